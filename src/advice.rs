@@ -0,0 +1,68 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+
+/// Expected access pattern of the file data, see `advise()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// No special treatment (the default)
+    Normal,
+    /// Data is read sequentially, so read-ahead window can be larger
+    Sequential,
+    /// Data is read in random order, so read-ahead is disabled
+    Random,
+    /// Data will be read only once
+    NoReuse,
+    /// Data will be needed soon, so start reading it in background
+    WillNeed,
+    /// Data will not be needed soon, so page cache can be freed
+    DontNeed,
+}
+
+/// Announce the access pattern of the file data to the kernel
+///
+/// This is a wrapper around `posix_fadvise`. The `len` of zero means
+/// until the end of the file. For example use `Advice::Sequential` for
+/// large scans and `Advice::DontNeed` after the data is processed to avoid
+/// evicting more useful data from the page cache.
+///
+/// The advice is only a hint, so on systems that don't have
+/// `posix_fadvise` (i.e. macOS) this function does nothing.
+#[cfg(any(target_os="linux", target_os="android", target_os="freebsd"))]
+pub fn advise<F: AsRawFd>(file: &F, offset: u64, len: u64, advice: Advice)
+    -> io::Result<()>
+{
+    let advice = match advice {
+        Advice::Normal => libc::POSIX_FADV_NORMAL,
+        Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+        Advice::Random => libc::POSIX_FADV_RANDOM,
+        Advice::NoReuse => libc::POSIX_FADV_NOREUSE,
+        Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+        Advice::DontNeed => libc::POSIX_FADV_DONTNEED,
+    };
+    // Note: posix_fadvise returns error instead of setting errno
+    let res = unsafe {
+        libc::posix_fadvise(file.as_raw_fd(),
+            offset as libc::off_t, len as libc::off_t, advice)
+    };
+    if res != 0 {
+        Err(io::Error::from_raw_os_error(res))
+    } else {
+        Ok(())
+    }
+}
+
+/// Announce the access pattern of the file data to the kernel
+///
+/// This is a wrapper around `posix_fadvise`. The `len` of zero means
+/// until the end of the file.
+///
+/// The advice is only a hint, so on systems that don't have
+/// `posix_fadvise` (i.e. macOS) this function does nothing.
+#[cfg(not(any(target_os="linux", target_os="android", target_os="freebsd")))]
+pub fn advise<F: AsRawFd>(_file: &F, _offset: u64, _len: u64,
+    _advice: Advice)
+    -> io::Result<()>
+{
+    Ok(())
+}
@@ -0,0 +1,453 @@
+use std::io;
+use std::mem;
+use std::fs::File;
+use std::ffi::{CStr, CString, OsStr};
+use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{RawFd, FromRawFd};
+
+use crate::{Dir, AsPath};
+use crate::dir::to_cstr;
+use crate::error::raw_os_error;
+use crate::syscall::{syscall, unchecked_syscall};
+#[cfg(target_os="linux")]
+use crate::capability::check_path;
+#[cfg(target_os="linux")]
+use crate::probe::{Feature, is_supported, note_error};
+
+#[cfg(target_os="linux")]
+const OPEN_FLAGS: libc::c_int =
+    libc::O_PATH|libc::O_DIRECTORY|libc::O_CLOEXEC;
+#[cfg(not(target_os="linux"))]
+const OPEN_FLAGS: libc::c_int = libc::O_DIRECTORY|libc::O_CLOEXEC;
+
+// same limit as Linux has for a single path resolution
+const MAX_SYMLINKS: usize = 40;
+
+impl Dir {
+    /// Returns `true` if `other` is this directory or any of its descendants
+    ///
+    /// This walks `..` from `other` up to the root of the filesystem
+    /// comparing device and inode numbers, so it works regardless of how
+    /// `other` was opened (i.e. following any symlinks or absolute paths).
+    /// Note that the answer may change if directories are moved
+    /// concurrently.
+    pub fn contains(&self, other: &Dir) -> io::Result<bool> {
+        let root = file_id(self.0)?;
+        let mut cur = file_id(other.0)?;
+        if cur == root {
+            return Ok(true);
+        }
+        let mut dir = open_raw(other.0, Path::new(".."))?;
+        loop {
+            let id = file_id(dir.0)?;
+            if id == root {
+                return Ok(true);
+            }
+            if id == cur {
+                // `..` of the root directory is the directory itself
+                return Ok(false);
+            }
+            cur = id;
+            dir = open_raw(dir.0, Path::new(".."))?;
+        }
+    }
+
+    /// Returns `true` if the path resolves to an entry inside this directory
+    ///
+    /// The path is resolved one component at a time, following symlinks
+    /// (including the last component) and `..` the same way kernel would
+    /// do, and every directory visited on the way is checked with
+    /// `contains()`. So a path that leaves the directory and comes back
+    /// (e.g. through a symlink to the outside followed by `..`) is rejected.
+    /// The only exception is an absolute path or symlink target, which may
+    /// pass through ancestors of this directory until it gets inside. The
+    /// last component doesn't have to exist, in this case the directory
+    /// where it would be created is checked.
+    ///
+    /// This is useful to check a user-supplied path before following it:
+    ///
+    /// ```rust,no_run
+    /// # use openat::Dir;
+    /// # let dir = Dir::open(".").unwrap();
+    /// # let user_path = "uploads/avatar.png";
+    /// if dir.verify_beneath(user_path).unwrap() {
+    ///     let file = dir.open_file(user_path).unwrap();
+    /// }
+    /// ```
+    ///
+    /// Note: unlike all other methods of `Dir`, this method follows
+    /// symlinks and doesn't honor `capability-mode` (it only inspects the
+    /// path).
+    pub fn verify_beneath<P: AsPath>(&self, path: P) -> io::Result<bool> {
+        let path = to_cstr(path)?;
+        let bytes = path.as_ref().to_bytes();
+        self._verify_beneath(Path::new(std::ffi::OsStr::from_bytes(bytes)))
+    }
+
+    /// Open file for reading, failing if resolution escapes this directory
+    ///
+    /// Unlike `open_file` symlinks are followed, but only as long as they
+    /// point inside this directory. Absolute paths, `..` that leaves the
+    /// directory and such symlinks are rejected by the kernel (`EXDEV` on
+    /// Linux, `ENOTCAPABLE` on FreeBSD), so there is no race with
+    /// concurrent renames unlike with `verify_beneath()`.
+    ///
+    /// Uses `openat2` with `RESOLVE_BENEATH` on Linux 5.6+ and
+    /// `O_RESOLVE_BENEATH` on FreeBSD 13+. On older Linux kernels (see
+    /// `is_supported()`) the path is resolved one component at a time
+    /// instead, with the same rules. Fails with `ENOSYS` on other systems.
+    pub fn open_beneath<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self._open_beneath(to_cstr(path)?.as_ref())
+    }
+
+    #[cfg(target_os="linux")]
+    fn _open_beneath(&self, path: &CStr) -> io::Result<File> {
+        if !is_supported(Feature::Openat2) {
+            return self._open_beneath_fallback(path);
+        }
+        let call = syscall("openat2", self.0, path)?;
+        let mut how: libc::open_how = unsafe { mem::zeroed() };
+        how.flags = (libc::O_RDONLY|libc::O_CLOEXEC) as u64;
+        how.resolve = libc::RESOLVE_BENEATH;
+        let fd = unsafe {
+            libc::syscall(libc::SYS_openat2, self.0, path.as_ptr(),
+                &how as *const libc::open_how,
+                mem::size_of::<libc::open_how>())
+        };
+        if fd < 0 {
+            let err = call.error();
+            if raw_os_error(&err) == Some(libc::ENOSYS) {
+                note_error(Feature::Openat2, &err);
+                drop(call);
+                return self._open_beneath_fallback(path);
+            }
+            Err(err)
+        } else {
+            Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+        }
+    }
+
+    // Emulates `RESOLVE_BENEATH` for kernels without `openat2`: symlinks
+    // are read and resolved relative to their directory, `..` goes back to
+    // the previously opened directory, so only descriptors opened here are
+    // used and there is no race with renames
+    #[cfg(target_os="linux")]
+    fn _open_beneath_fallback(&self, path: &CStr) -> io::Result<File> {
+        check_path(self.0, path)?;
+        let exdev = || io::Error::from_raw_os_error(libc::EXDEV);
+        let bytes = path.to_bytes();
+        if bytes.is_empty() {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        if bytes.starts_with(b"/") {
+            return Err(exdev());
+        }
+        // components left to resolve, the next one is at the end
+        let mut todo = bytes.split(|&b| b == b'/').rev()
+            .map(|c| c.to_vec()).collect::<Vec<_>>();
+        let mut stack: Vec<Dir> = Vec::new();
+        let mut links = 0;
+        while let Some(name) = todo.pop() {
+            let last = todo.is_empty();
+            if name == b".." {
+                stack.pop().ok_or_else(exdev)?;
+            }
+            let parent = stack.last().unwrap_or(self);
+            if name.is_empty() || name == b"." || name == b".." {
+                if last {
+                    let dot = unsafe {
+                        CStr::from_bytes_with_nul_unchecked(b".\0")
+                    };
+                    return parent._open_file(dot, libc::O_RDONLY, 0);
+                }
+                continue;
+            }
+            // nul bytes can't be in the `CStr` nor in a symlink
+            let name = CString::new(name).unwrap();
+            let err = if last {
+                match parent._open_file(&name, libc::O_RDONLY, 0) {
+                    Err(e) => e,
+                    res => return res,
+                }
+            } else {
+                match parent._sub_dir(&name, libc::O_DIRECTORY) {
+                    Ok(dir) => {
+                        stack.push(dir);
+                        continue;
+                    }
+                    Err(e) => e,
+                }
+            };
+            // `O_NOFOLLOW` fails with one of these on symlinks
+            if raw_os_error(&err) != Some(libc::ELOOP) &&
+               raw_os_error(&err) != Some(libc::ENOTDIR)
+            {
+                return Err(err);
+            }
+            let target = match parent.read_link(name.as_c_str()) {
+                Ok(target) => target,
+                Err(_) => return Err(err),
+            };
+            links += 1;
+            if links > MAX_SYMLINKS {
+                return Err(io::Error::from_raw_os_error(libc::ELOOP));
+            }
+            let target = target.as_os_str().as_bytes();
+            if target.starts_with(b"/") {
+                return Err(exdev());
+            }
+            todo.extend(target.split(|&b| b == b'/').rev()
+                .map(|c| c.to_vec()));
+        }
+        unreachable!("the last component always returns");
+    }
+
+    #[cfg(target_os="freebsd")]
+    fn _open_beneath(&self, path: &CStr) -> io::Result<File> {
+        let call = syscall("openat", self.0, path)?;
+        let fd = unsafe {
+            libc::openat(self.0, path.as_ptr(),
+                libc::O_RDONLY|libc::O_CLOEXEC|libc::O_RESOLVE_BENEATH)
+        };
+        if fd < 0 {
+            Err(call.error())
+        } else {
+            Ok(unsafe { File::from_raw_fd(fd) })
+        }
+    }
+
+    #[cfg(not(any(target_os="linux", target_os="freebsd")))]
+    fn _open_beneath(&self, _path: &CStr) -> io::Result<File> {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+
+    fn _verify_beneath(&self, path: &Path) -> io::Result<bool> {
+        let bytes = path.as_os_str().as_bytes();
+        // components left to resolve, the next one is at the end
+        let mut todo = Vec::new();
+        let mut cur = open_raw(self.0, Path::new("."))?;
+        // resolving an absolute path which hasn't got inside yet
+        let mut outside = false;
+        if bytes.starts_with(b"/") {
+            cur = open_raw(self.0, Path::new("/"))?;
+            outside = true;
+        }
+        push_components(&mut todo, bytes);
+        let mut links = 0;
+        while let Some(name) = todo.pop() {
+            let last = todo.is_empty();
+            if name == b".." {
+                cur = open_raw(cur.0, Path::new(".."))?;
+                if !self._visit(&cur, &mut outside)? {
+                    return Ok(false);
+                }
+            } else if !name.is_empty() && name != b"." {
+                let name = Path::new(OsStr::from_bytes(&name));
+                match cur.read_link(name) {
+                    Ok(target) => {
+                        links += 1;
+                        if links > MAX_SYMLINKS {
+                            return Err(
+                                io::Error::from_raw_os_error(libc::ELOOP));
+                        }
+                        let target = target.as_os_str().as_bytes();
+                        if target.starts_with(b"/") {
+                            cur = open_raw(cur.0, Path::new("/"))?;
+                            outside = true;
+                        }
+                        push_components(&mut todo, target);
+                        continue;
+                    }
+                    Err(ref e) if raw_os_error(e) == Some(libc::EINVAL) ||
+                                  raw_os_error(e) == Some(libc::ENOENT)
+                    => {
+                        if !last {
+                            cur = open_raw(cur.0, name)?;
+                            if !self._visit(&cur, &mut outside)? {
+                                return Ok(false);
+                            }
+                        }
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            if last {
+                return self.contains(&cur);
+            }
+        }
+        unreachable!("the last component always returns");
+    }
+
+    // Returns `false` if resolution has left this directory
+    fn _visit(&self, dir: &Dir, outside: &mut bool) -> io::Result<bool> {
+        if self.contains(dir)? {
+            *outside = false;
+            Ok(true)
+        } else if *outside {
+            dir.contains(self)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+fn push_components(todo: &mut Vec<Vec<u8>>, path: &[u8]) {
+    todo.extend(path.split(|&b| b == b'/').rev().map(|c| c.to_vec()));
+}
+
+fn file_id(fd: RawFd) -> io::Result<(libc::dev_t, libc::ino_t)> {
+    unsafe {
+        let mut stat: libc::stat = mem::zeroed();
+        if libc::fstat(fd, &mut stat) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((stat.st_dev, stat.st_ino))
+    }
+}
+
+// Opens a directory following symlinks, absolute paths are allowed
+fn open_raw(dirfd: RawFd, path: &Path) -> io::Result<Dir> {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                 "nul byte in file name"))?;
+    let call = unchecked_syscall("openat", dirfd, &path);
+    let fd = unsafe { libc::openat(dirfd, path.as_ptr(), OPEN_FLAGS) };
+    if fd < 0 {
+        Err(call.error())
+    } else {
+        Ok(Dir(fd))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Dir;
+
+    #[test]
+    fn contains() {
+        let tmp = tempfile::tempdir().unwrap();
+        let top = Dir::open(tmp.path()).unwrap();
+        top.create_dir("root", 0o755).unwrap();
+        top.create_dir("root/a", 0o755).unwrap();
+        top.create_dir("other", 0o755).unwrap();
+        let root = top.sub_dir("root").unwrap();
+        assert!(root.contains(&root).unwrap());
+        assert!(root.contains(&root.sub_dir("a").unwrap()).unwrap());
+        assert!(top.contains(&root.sub_dir("a").unwrap()).unwrap());
+        assert!(!root.contains(&top).unwrap());
+        assert!(!root.contains(&top.sub_dir("other").unwrap()).unwrap());
+    }
+
+    #[test]
+    fn verify_beneath() {
+        let tmp = tempfile::tempdir().unwrap();
+        let top = Dir::open(tmp.path()).unwrap();
+        top.create_dir("root", 0o755).unwrap();
+        top.create_dir("root/a", 0o755).unwrap();
+        top.write_file("secret", 0o600).unwrap();
+        let root = top.sub_dir("root").unwrap();
+        root.write_file("a/file", 0o644).unwrap();
+        root.symlink("inside", "a/file").unwrap();
+        root.symlink("a/up", "..").unwrap();
+        root.symlink("escape", "../secret").unwrap();
+        root.symlink("abs", &tmp.path().join("root/a")).unwrap();
+        root.symlink("abs_escape", &tmp.path().join("secret")).unwrap();
+        root.symlink("loop", "loop").unwrap();
+        top.create_dir("other", 0o755).unwrap();
+        root.symlink("outlink", "../other").unwrap();
+        for path in &["a", "a/file", "a/../a/file", "inside", "a/up/inside",
+                      "abs/file", "a/nonexistent", "."]
+        {
+            assert!(root.verify_beneath(*path).unwrap(), "{}", path);
+        }
+        for path in &["..", "../secret", "escape", "a/up/..", "abs_escape",
+                      "/", "a/../../root/../secret", "outlink/../root/a/file",
+                      "../root/a/file"]
+        {
+            assert!(!root.verify_beneath(*path).unwrap(), "{}", path);
+        }
+        assert_eq!(root.verify_beneath("loop").unwrap_err().raw_os_error(),
+                   Some(libc::ELOOP));
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn open_beneath() {
+        let tmp = tempfile::tempdir().unwrap();
+        let top = Dir::open(tmp.path()).unwrap();
+        top.create_dir("root", 0o755).unwrap();
+        top.write_file("secret", 0o600).unwrap();
+        let root = top.sub_dir("root").unwrap();
+        root.write_file("file", 0o644).unwrap();
+        root.symlink("inside", "file").unwrap();
+        root.symlink("escape", "../secret").unwrap();
+        root.open_beneath("inside").unwrap();
+        let err = root.open_beneath("escape").unwrap_err();
+        assert_eq!(crate::raw_os_error(&err), Some(libc::EXDEV));
+        // rejected early with `capability-mode`
+        assert!(root.open_beneath("../secret").is_err());
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn open_beneath_fallback() {
+        use std::io::{Read, Write};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let top = Dir::open(tmp.path()).unwrap();
+        top.create_dir("root", 0o755).unwrap();
+        top.create_dir("root/a", 0o755).unwrap();
+        top.write_file("secret", 0o600).unwrap();
+        let root = top.sub_dir("root").unwrap();
+        root.write_file("a/file", 0o644).unwrap().write_all(b"x").unwrap();
+        root.symlink("inside", "a/file").unwrap();
+        root.symlink("a/up", "..").unwrap();
+        root.symlink("a/dir", "../a").unwrap();
+        root.symlink("escape", "../secret").unwrap();
+        root.symlink("parent", "..").unwrap();
+        root.symlink("abs", &tmp.path().join("root/a/file")).unwrap();
+        root.symlink("loop", "loop").unwrap();
+        for path in &["a/file", "inside", "a/up/inside", "a/dir/dir/file",
+                      "./a//file"]
+        {
+            let mut buf = String::new();
+            root._open_beneath_fallback(&cstr(path)).unwrap()
+                .read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, "x", "{}", path);
+        }
+        assert!(root._open_beneath_fallback(&cstr("a/up")).unwrap()
+            .metadata().unwrap().is_dir());
+        for path in &["escape", "abs", "parent", "a/up/parent/root"] {
+            let err = root._open_beneath_fallback(&cstr(path)).unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EXDEV), "{}", path);
+        }
+        // these are rejected early with `capability-mode`
+        #[cfg(not(feature="capability-mode"))]
+        {
+            assert!(root._open_beneath_fallback(&cstr("a/../a/./file")).is_ok());
+            assert!(root._open_beneath_fallback(&cstr("a/..")).is_ok());
+            for path in &["..", "a/up/..", "a/up/../secret", "/etc"] {
+                let err = root._open_beneath_fallback(&cstr(path))
+                    .unwrap_err();
+                assert_eq!(err.raw_os_error(), Some(libc::EXDEV), "{}", path);
+            }
+        }
+        for (path, errno) in &[("loop", libc::ELOOP), ("missing", libc::ENOENT),
+                               ("a/file/x", libc::ENOTDIR), ("", libc::ENOENT)]
+        {
+            let err = root._open_beneath_fallback(&cstr(path)).unwrap_err();
+            assert_eq!(crate::raw_os_error(&err), Some(*errno), "{}", path);
+        }
+    }
+
+    #[cfg(target_os="linux")]
+    fn cstr(path: &str) -> std::ffi::CString {
+        std::ffi::CString::new(path).unwrap()
+    }
+}
@@ -0,0 +1,61 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+/// A flag to abort long recursive operations from another thread
+///
+/// Clones share the same flag. Operations check it between entries, so
+/// after cancellation the tree is left in a consistent state: every entry
+/// is either fully processed or untouched. Cancelled operations fail with
+/// `ErrorKind::Interrupted`.
+///
+/// Supported by `Dir::remove_recursive_cancellable` and `Walk::cancel_on`.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Create a new token that isn't cancelled yet
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+    /// Request cancellation of all operations using this token
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+    /// Returns `true` if `cancel()` was called
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+    /// Returns `Interrupted` error if `cancel()` was called
+    pub fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            Err(cancelled())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub(crate) fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "operation cancelled")
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use super::CancelToken;
+
+    #[test]
+    fn token() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(token.check().is_ok());
+        clone.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(token.check().unwrap_err().kind(),
+                   io::ErrorKind::Interrupted);
+    }
+}
@@ -0,0 +1,83 @@
+use std::io;
+use std::ffi::CStr;
+use std::os::unix::io::RawFd;
+
+
+/// Check that the path can't escape the directory (if enforced)
+///
+/// With the `capability-mode` feature enabled every path passed to methods
+/// of `Dir` (and to `rename`, `hardlink` and similar functions) must be
+/// relative and must not contain `..` components, and the directory must
+/// not be the deprecated `Dir::cwd()` handle. Otherwise the error of kind
+/// `PermissionDenied` is returned, before any system call is made.
+///
+/// `Dir::open()` is the only way to get a handle from an arbitrary path.
+///
+/// The check is process-wide: cargo unifies features, so enabling it in any
+/// crate enables it for all users of `openat` in the build.
+///
+/// Note: symlinks can still point outside of the directory, so either
+/// open one component at a time (all methods use `O_NOFOLLOW` for the last
+/// component), or use `RESOLVE_BENEATH` on Linux.
+#[cfg(feature="capability-mode")]
+pub fn check_path(dirfd: RawFd, path: &CStr) -> io::Result<()> {
+    let bytes = path.to_bytes();
+    if dirfd == libc::AT_FDCWD {
+        Err(denied("current directory handle is not allowed"))
+    } else if bytes.starts_with(b"/") {
+        Err(denied("absolute paths are not allowed"))
+    } else if bytes.split(|&b| b == b'/').any(|c| c == b"..") {
+        Err(denied("parent directory components are not allowed"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check that the path can't escape the directory (if enforced)
+///
+/// This is a no-op unless `capability-mode` feature is enabled.
+#[cfg(not(feature="capability-mode"))]
+#[inline(always)]
+pub fn check_path(_dirfd: RawFd, _path: &CStr) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(feature="capability-mode")]
+fn denied(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied,
+        format!("{} in capability mode", message))
+}
+
+#[cfg(all(test, feature="capability-mode"))]
+mod test {
+    use std::io;
+    use crate::Dir;
+
+    #[test]
+    fn rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        for path in &["/etc/passwd", "..", "sub/../../x", "sub/.."] {
+            assert_eq!(dir.metadata(*path).err().unwrap().kind(),
+                       io::ErrorKind::PermissionDenied);
+        }
+        assert_eq!(dir.local_rename("sub", "../sub").unwrap_err().kind(),
+                   io::ErrorKind::PermissionDenied);
+        #[allow(deprecated)]
+        let cwd = Dir::cwd();
+        assert_eq!(cwd.metadata("Cargo.toml").err().unwrap().kind(),
+                   io::ErrorKind::PermissionDenied);
+        std::mem::forget(cwd);
+    }
+
+    #[test]
+    fn allowed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("sub/..file", 0o644).unwrap();
+        dir.local_rename("sub/..file", "./file").unwrap();
+        assert!(dir.metadata("file").unwrap().is_file());
+    }
+}
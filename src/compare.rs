@@ -0,0 +1,252 @@
+use std::io::{self, Read};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+use crate::{Dir, Entry, SimpleType, SortBy};
+
+
+/// A difference between two directory trees found by `compare`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Difference {
+    /// Entry exists only in the new tree
+    ///
+    /// Contents of the added directory are not reported separately.
+    Added(PathBuf),
+    /// Entry exists only in the old tree
+    ///
+    /// Contents of the removed directory are not reported separately.
+    Removed(PathBuf),
+    /// Entry has different type, size, modification time, contents or
+    /// symlink target (depending on the options)
+    Modified(PathBuf),
+}
+
+/// Options for comparing two directory trees
+///
+/// By default entries are compared by type, size and modification time,
+/// and targets of symlinks are compared.
+#[derive(Debug, Clone)]
+pub struct Compare {
+    mtime: bool,
+    content: bool,
+}
+
+impl Difference {
+    /// Returns path of the entry relative to the compared directories
+    pub fn path(&self) -> &Path {
+        match self {
+            Difference::Added(path) => path,
+            Difference::Removed(path) => path,
+            Difference::Modified(path) => path,
+        }
+    }
+}
+
+/// Compare two directory trees with default options, see `Compare`
+///
+/// Differences are returned sorted by path.
+pub fn compare(old: &Dir, new: &Dir) -> io::Result<Vec<Difference>> {
+    Compare::new().run(old, new)
+}
+
+impl Compare {
+    /// Create default options
+    pub fn new() -> Compare {
+        Compare { mtime: true, content: false }
+    }
+    /// Whether to treat files with different modification time as modified
+    ///
+    /// Default is `true`. Disable it when comparing copies that don't
+    /// preserve times, probably along with enabling `content`.
+    pub fn mtime(&mut self, value: bool) -> &mut Compare {
+        self.mtime = value;
+        self
+    }
+    /// Whether to compare contents of files having the same size
+    ///
+    /// Default is `false`. This reads both files fully, so it's slow.
+    pub fn content(&mut self, value: bool) -> &mut Compare {
+        self.content = value;
+        self
+    }
+    /// Compare two directory trees
+    ///
+    /// Symlinks are never followed. Differences are returned sorted by
+    /// path.
+    pub fn run(&self, old: &Dir, new: &Dir) -> io::Result<Vec<Difference>> {
+        let mut result = Vec::new();
+        self.compare_dirs(old, new, Path::new(""), &mut result)?;
+        Ok(result)
+    }
+
+    fn compare_dirs(&self, old: &Dir, new: &Dir, prefix: &Path,
+        result: &mut Vec<Difference>)
+        -> io::Result<()>
+    {
+        let old_list = old.list_sorted(".", SortBy::Name)?;
+        let new_list = new.list_sorted(".", SortBy::Name)?;
+        let mut old_iter = old_list.iter().peekable();
+        let mut new_iter = new_list.iter().peekable();
+        loop {
+            let order = match (old_iter.peek(), new_iter.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(a), Some(b)) => a.name.cmp(&b.name),
+            };
+            match order {
+                Ordering::Less => {
+                    let entry = old_iter.next().expect("peeked");
+                    let path = prefix.join(entry.file_name());
+                    result.push(Difference::Removed(path));
+                }
+                Ordering::Greater => {
+                    let entry = new_iter.next().expect("peeked");
+                    let path = prefix.join(entry.file_name());
+                    result.push(Difference::Added(path));
+                }
+                Ordering::Equal => {
+                    let a = old_iter.next().expect("peeked");
+                    let b = new_iter.next().expect("peeked");
+                    self.compare_entries(old, new, a, b, prefix, result)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn compare_entries(&self, old: &Dir, new: &Dir, a: &Entry, b: &Entry,
+        prefix: &Path, result: &mut Vec<Difference>)
+        -> io::Result<()>
+    {
+        let path = prefix.join(a.file_name());
+        let flags = libc::AT_SYMLINK_NOFOLLOW;
+        let ma = old._stat(&a.name, flags)?;
+        let mb = new._stat(&b.name, flags)?;
+        let typ = ma.simple_type();
+        let modified = if typ != mb.simple_type() {
+            true
+        } else {
+            match typ {
+                SimpleType::Dir => {
+                    let sub_a = old._sub_dir(&a.name, libc::O_DIRECTORY)?;
+                    let sub_b = new._sub_dir(&b.name, libc::O_DIRECTORY)?;
+                    return self.compare_dirs(&sub_a, &sub_b, &path, result);
+                }
+                SimpleType::Symlink => {
+                    old.read_link(a)? != new.read_link(b)?
+                }
+                SimpleType::File => {
+                    let (sa, sb) = (ma.stat(), mb.stat());
+                    ma.len() != mb.len()
+                    || self.mtime && (sa.st_mtime, sa.st_mtime_nsec)
+                                  != (sb.st_mtime, sb.st_mtime_nsec)
+                    || self.content && !same_content(old, new, a, b)?
+                }
+                _ => {
+                    ma.stat().st_mode != mb.stat().st_mode
+                    || ma.stat().st_rdev != mb.stat().st_rdev
+                }
+            }
+        };
+        if modified {
+            result.push(Difference::Modified(path));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Compare {
+    fn default() -> Compare {
+        Compare::new()
+    }
+}
+
+fn same_content(old: &Dir, new: &Dir, a: &Entry, b: &Entry)
+    -> io::Result<bool>
+{
+    let mut fa = old.open_file(a)?;
+    let mut fb = new.open_file(b)?;
+    let mut buf_a = [0u8; 8192];
+    let mut buf_b = [0u8; 8192];
+    loop {
+        let n = read_full(&mut fa, &mut buf_a)?;
+        let m = read_full(&mut fb, &mut buf_b)?;
+        if buf_a[..n] != buf_b[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+fn read_full<R: Read>(file: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match file.read(&mut buf[pos..]) {
+            Ok(0) => break,
+            Ok(n) => pos += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(pos)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+    use crate::Dir;
+    use super::{compare, Compare, Difference};
+
+    fn tree(dir: &Dir, time: SystemTime) {
+        dir.create_dir("sub", 0o755).unwrap();
+        for name in ["same", "sub/file", "removed", "changed", "touched"] {
+            let mut f = dir.write_file(name, 0o644).unwrap();
+            f.write_all(b"hello").unwrap();
+            f.set_modified(time).unwrap();
+        }
+        dir.symlink("link", "same").unwrap();
+    }
+
+    #[test]
+    fn compare_trees() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Dir::open(tmp.path()).unwrap();
+        root.create_dir("a", 0o755).unwrap();
+        root.create_dir("b", 0o755).unwrap();
+        let a = root.sub_dir("a").unwrap();
+        let b = root.sub_dir("b").unwrap();
+        let time = SystemTime::now() - Duration::from_secs(100);
+        tree(&a, time);
+        tree(&b, time);
+        assert_eq!(compare(&a, &b).unwrap(), []);
+
+        b.remove_file("removed").unwrap();
+        b.write_file("sub/added", 0o644).unwrap();
+        let mut f = b.update_file("changed", 0o644).unwrap();
+        f.write_all(b"HELLO").unwrap();
+        f.set_modified(time).unwrap();
+        b.update_file("touched", 0o644).unwrap()
+            .set_modified(SystemTime::now()).unwrap();
+        b.remove_file("link").unwrap();
+        b.symlink("link", "changed").unwrap();
+        let p = PathBuf::from;
+        assert_eq!(compare(&a, &b).unwrap(), [
+            Difference::Modified(p("link")),
+            Difference::Removed(p("removed")),
+            Difference::Added(p("sub/added")),
+            Difference::Modified(p("touched")),
+        ]);
+        assert_eq!(Compare::new().mtime(false).content(true)
+                   .run(&a, &b).unwrap(), [
+            Difference::Modified(p("changed")),
+            Difference::Modified(p("link")),
+            Difference::Removed(p("removed")),
+            Difference::Added(p("sub/added")),
+        ]);
+    }
+}
@@ -0,0 +1,193 @@
+use std::io;
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::Watcher;
+use crate::watch::{Event, EventKind, wait_readable};
+
+
+/// Coalesces bursts of `Watcher` events, created by `Watcher::debounce`
+///
+/// Events are collected per path, and a single event is returned for the
+/// path only when no new events for it arrived during the quiet period.
+/// The returned event describes the net change:
+///
+/// * `Created` if the entry didn't exist before the burst (or was
+///   replaced, e.g. by renaming a temporary file over it)
+/// * `Removed` if the entry existed before the burst and is gone
+/// * `Modified` or `MetadataChanged` if the entry was only changed
+///
+/// Entries that were created and removed within the burst (like temporary
+/// files of editors) are not reported at all. Renames are reported as
+/// `Removed` of the old path and `Created` of the new one. `Overflow` and
+/// `WatchedRemoved` are returned immediately.
+#[derive(Debug)]
+pub struct Debouncer {
+    watcher: Watcher,
+    quiet: Duration,
+    pending: HashMap<PathBuf, Pending>,
+    ready: Vec<Event>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    first: Instant,
+    last: Instant,
+    existed: bool,
+    replaced: bool,
+    exists: bool,
+    modified: bool,
+    is_dir: bool,
+}
+
+impl Watcher {
+    /// Coalesce events that are less than `quiet` apart, see `Debouncer`
+    pub fn debounce(self, quiet: Duration) -> Debouncer {
+        Debouncer {
+            watcher: self,
+            quiet,
+            pending: HashMap::new(),
+            ready: Vec::new(),
+        }
+    }
+}
+
+impl Debouncer {
+    /// Wait until some changes settle and return them
+    ///
+    /// Blocks until at least one path has had no events for the quiet
+    /// period. Events are ordered by the time of the first change.
+    pub fn read_events(&mut self) -> io::Result<Vec<Event>> {
+        loop {
+            let now = Instant::now();
+            let mut settled = Vec::new();
+            let mut next = None::<Instant>;
+            for (path, pending) in &self.pending {
+                let deadline = pending.last + self.quiet;
+                if deadline <= now {
+                    settled.push((pending.first, path.clone()));
+                } else if next.is_none_or(|next| deadline < next) {
+                    next = Some(deadline);
+                }
+            }
+            settled.sort();
+            for (_, path) in settled {
+                let pending = self.pending.remove(&path)
+                    .expect("pending path exists");
+                if let Some(kind) = pending.kind() {
+                    self.ready.push(Event {
+                        kind, path, is_dir: pending.is_dir,
+                    });
+                }
+            }
+            if !self.ready.is_empty() {
+                return Ok(std::mem::take(&mut self.ready));
+            }
+            let timeout = next.map(|next| next.saturating_duration_since(now));
+            if wait_readable(self.watcher.as_raw_fd(), timeout)? {
+                for event in self.watcher.read_events()? {
+                    self.add(event);
+                }
+            }
+        }
+    }
+
+    /// Returns the underlying watcher
+    pub fn into_inner(self) -> Watcher {
+        self.watcher
+    }
+
+    fn add(&mut self, event: Event) {
+        let now = Instant::now();
+        let (exists, created, modified) = match event.kind {
+            EventKind::Created | EventKind::MovedTo(_) => (true, true, false),
+            EventKind::Removed | EventKind::MovedFrom(_) => {
+                (false, false, false)
+            }
+            EventKind::Modified => (true, false, true),
+            EventKind::MetadataChanged => (true, false, false),
+            EventKind::WatchedRemoved | EventKind::Overflow => {
+                self.ready.push(event);
+                return;
+            }
+        };
+        let is_dir = event.is_dir;
+        let pending = self.pending.entry(event.path)
+            .or_insert_with(|| Pending {
+                first: now,
+                last: now,
+                // the first event tells whether the entry was there before
+                existed: !created,
+                replaced: false,
+                exists: true,
+                modified: false,
+                is_dir,
+            });
+        pending.last = now;
+        pending.is_dir = is_dir;
+        pending.replaced |= created;
+        pending.exists = exists;
+        pending.modified |= modified;
+    }
+}
+
+impl Pending {
+    fn kind(&self) -> Option<EventKind> {
+        if !self.exists {
+            return if self.existed { Some(EventKind::Removed) } else { None };
+        }
+        if self.replaced {
+            Some(EventKind::Created)
+        } else if self.modified {
+            Some(EventKind::Modified)
+        } else {
+            Some(EventKind::MetadataChanged)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::Duration;
+    use crate::{Dir, EventKind};
+
+    #[test]
+    fn debounce() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("old", 0o644).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let mut watcher = dir.watch().unwrap()
+            .debounce(Duration::from_millis(100));
+        // editor-style save: write temporary file and rename it over
+        dir.write_file("file.tmp", 0o644).unwrap();
+        dir.local_rename("file.tmp", "file").unwrap();
+        dir.write_file("new", 0o644).unwrap();
+        dir.write_file("new", 0o644).unwrap();
+        dir.remove_file("old").unwrap();
+        dir.write_file("transient", 0o644).unwrap();
+        dir.remove_file("transient").unwrap();
+        let mut events = Vec::new();
+        while events.len() < 3 {
+            events.extend(watcher.read_events().unwrap());
+        }
+        let summary = events.iter()
+            .map(|e| (e.path().to_str().unwrap(), e.kind()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, [
+            ("file", EventKind::Created),
+            ("new", EventKind::Created),
+            ("old", EventKind::Removed),
+        ]);
+        dir.update_file("new", 0o644).unwrap().write_all(b"a").unwrap();
+        dir.append_file("new", 0o644).unwrap().write_all(b"b").unwrap();
+        let events = watcher.read_events().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].path(), Path::new("new"));
+        assert_eq!(events[0].kind(), EventKind::Modified);
+    }
+}
@@ -1,16 +1,28 @@
 use std::io;
 use std::mem;
-use std::ffi::{OsString, CStr};
+use std::ffi::{OsStr, OsString, CStr};
 use std::fs::{File, read_link};
 use std::os::unix::io::{AsRawFd, RawFd, FromRawFd, IntoRawFd};
-use std::os::unix::ffi::{OsStringExt};
-use std::path::{PathBuf};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::FileExt;
+use std::env::current_dir;
+use std::path::{Path, PathBuf};
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use libc;
 use crate::metadata::{self, Metadata};
 use crate::list::{DirIter, open_dir, open_dirfd};
+use crate::temp::create_temp;
+use crate::error::{raw_os_error, with_path, dangling_symlink};
+use crate::strict::validate_name;
+use crate::syscall::{syscall, unchecked_syscall};
+use crate::capability::check_path;
+use crate::cancel::CancelToken;
+#[cfg(target_os="linux")]
+use crate::probe::{Feature, is_supported, check_supported, note_error};
 
-use crate::{Dir, AsPath};
+use crate::{Dir, AsPath, SimpleType, IntoMode};
 
 #[cfg(target_os="linux")]
 const BASE_OPEN_FLAGS: libc::c_int = libc::O_PATH|libc::O_CLOEXEC;
@@ -19,6 +31,13 @@ const BASE_OPEN_FLAGS: libc::c_int = libc::O_DIRECTORY|libc::O_CLOEXEC;
 #[cfg(not(any(target_os="linux", target_os="freebsd")))]
 const BASE_OPEN_FLAGS: libc::c_int = libc::O_CLOEXEC;
 
+#[cfg(target_os="freebsd")]
+extern "C" {
+    // not in libc crate yet
+    fn funlinkat(dfd: libc::c_int, path: *const libc::c_char,
+                 fd: libc::c_int, flag: libc::c_int) -> libc::c_int;
+}
+
 impl Dir {
     /// Creates a directory descriptor that resolves paths relative to current
     /// working directory (AT_FDCWD)
@@ -40,11 +59,12 @@ impl Dir {
     }
 
     fn _open(path: &CStr) -> io::Result<Dir> {
+        let call = unchecked_syscall("open", libc::AT_FDCWD, path);
         let fd = unsafe {
             libc::open(path.as_ptr(), BASE_OPEN_FLAGS)
         };
         if fd < 0 {
-            Err(io::Error::last_os_error())
+            Err(call.error())
         } else {
             Ok(Dir(fd))
         }
@@ -54,7 +74,7 @@ impl Dir {
     ///
     /// You can list directory itself with `list_self`.
     pub fn list_dir<P: AsPath>(&self, path: P) -> io::Result<DirIter> {
-        open_dir(self, to_cstr(path)?.as_ref())
+        open_dir(self, to_cstr(path)?.as_ref(), 0)
     }
 
     /// List this dir
@@ -64,6 +84,33 @@ impl Dir {
         }
     }
 
+    /// Returns `true` if the subdirectory has no entries
+    ///
+    /// Reads at most one entry, so this is cheap even for huge directories.
+    pub fn is_empty<P: AsPath>(&self, path: P) -> io::Result<bool> {
+        Ok(!open_dir(self, to_cstr(path)?.as_ref(), 0)?.skip_entry()?)
+    }
+
+    /// Returns `true` if this directory has no entries, see `is_empty`
+    pub fn self_is_empty(&self) -> io::Result<bool> {
+        self.is_empty(".")
+    }
+
+    /// Returns the number of entries in the subdirectory
+    ///
+    /// Unlike `list_dir(path)?.count()` this doesn't allocate anything for
+    /// entries, so it's suitable for showing "N items" for huge
+    /// directories. The number is approximate in the sense that directory
+    /// may be modified while it's being read.
+    pub fn count_entries<P: AsPath>(&self, path: P) -> io::Result<usize> {
+        let mut iter = open_dir(self, to_cstr(path)?.as_ref(), 0)?;
+        let mut count = 0;
+        while iter.skip_entry()? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
     /// Open subdirectory
     ///
     /// Note that this method does not resolve symlinks by default, so you may have to call
@@ -71,28 +118,112 @@ impl Dir {
     ///
     /// [`read_link`]: #method.read_link
     pub fn sub_dir<P: AsPath>(&self, path: P) -> io::Result<Dir> {
-        self._sub_dir(to_cstr(path)?.as_ref())
+        self._sub_dir(to_cstr(path)?.as_ref(), 0)
     }
 
-    fn _sub_dir(&self, path: &CStr) -> io::Result<Dir> {
+    pub(crate) fn _sub_dir(&self, path: &CStr, flags: libc::c_int)
+        -> io::Result<Dir>
+    {
+        let call = syscall("openat", self.0, path)?;
         let fd = unsafe {
             libc::openat(self.0,
                         path.as_ptr(),
-                        BASE_OPEN_FLAGS|libc::O_NOFOLLOW)
+                        BASE_OPEN_FLAGS|libc::O_NOFOLLOW|flags)
         };
         if fd < 0 {
-            Err(io::Error::last_os_error())
+            Err(call.error())
         } else {
             Ok(Dir(fd))
         }
     }
 
+    /// Open nested subdirectory one component at a time
+    ///
+    /// Each item must be a single path component (see `validate_name()`),
+    /// it is opened relative to the previous one with `O_NOFOLLOW`, so
+    /// symlinks are never followed and `..` can't escape this directory:
+    ///
+    /// ```rust,no_run
+    /// # use openat::Dir;
+    /// # let dir = Dir::open(".").unwrap();
+    /// let sub = dir.open_nested(["var", "lib", "app"]).unwrap();
+    /// let file = sub.open_file("state.json").unwrap();
+    /// ```
+    ///
+    /// On failure the returned error contains `openat::Error` with the
+    /// path up to and including the failed component (without calling
+    /// `ErrorContext::context()`). Empty iterator opens this directory again.
+    ///
+    /// Use `open_nested_file` if the last component is a file.
+    pub fn open_nested<I>(&self, components: I) -> io::Result<Dir>
+        where I: IntoIterator,
+              I::Item: AsPath,
+    {
+        let dir = self._open_nested(components,
+            |parent, name| parent._sub_dir(name, libc::O_DIRECTORY))?;
+        match dir {
+            Some(dir) => Ok(dir),
+            None => self.sub_dir("."),
+        }
+    }
+
+    /// Open a file for reading in a nested subdirectory
+    ///
+    /// Same as `open_nested` but the last component is opened as a file
+    /// (like `open_file`, i.e. read-only and not following symlinks):
+    ///
+    /// ```rust,no_run
+    /// # use openat::Dir;
+    /// # let dir = Dir::open(".").unwrap();
+    /// let file = dir.open_nested_file(["var", "lib", "state.json"]).unwrap();
+    /// ```
+    ///
+    /// Empty iterator results in error of kind `InvalidInput`.
+    pub fn open_nested_file<I>(&self, components: I) -> io::Result<File>
+        where I: IntoIterator,
+              I::Item: AsPath,
+    {
+        let file = self._open_nested(components,
+            |parent, name| parent._open_file(name, libc::O_RDONLY, 0))?;
+        file.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput,
+            "no path components to open"))
+    }
+
+    fn _open_nested<I, T, F>(&self, components: I, open_last: F)
+        -> io::Result<Option<T>>
+        where I: IntoIterator,
+              I::Item: AsPath,
+              F: FnOnce(&Dir, &CStr) -> io::Result<T>,
+    {
+        let mut path = PathBuf::new();
+        let mut cur: Option<Dir> = None;
+        let mut components = components.into_iter().peekable();
+        while let Some(item) = components.next() {
+            let name = to_cstr(item)?;
+            let name = name.as_ref();
+            path.push(OsStr::from_bytes(name.to_bytes()));
+            validate_name(name.to_bytes())
+                .map_err(|e| with_path(e, "openat", &path))?;
+            let parent = cur.as_ref().unwrap_or(self);
+            if components.peek().is_none() {
+                return open_last(parent, name)
+                    .map(Some)
+                    .map_err(|e| with_path(e, "openat", &path));
+            }
+            let next = parent._sub_dir(name, libc::O_DIRECTORY)
+                .map_err(|e| with_path(e, "openat", &path))?;
+            cur = Some(next);
+        }
+        Ok(None)
+    }
+
     /// Read link in this directory
     pub fn read_link<P: AsPath>(&self, path: P) -> io::Result<PathBuf> {
         self._read_link(to_cstr(path)?.as_ref())
     }
 
     fn _read_link(&self, path: &CStr) -> io::Result<PathBuf> {
+        let call = syscall("readlinkat", self.0, path)?;
         let mut buf = vec![0u8; 4096];
         let res = unsafe {
             libc::readlinkat(self.0,
@@ -100,7 +231,7 @@ impl Dir {
                         buf.as_mut_ptr() as *mut libc::c_char, buf.len())
         };
         if res < 0 {
-            Err(io::Error::last_os_error())
+            Err(call.error())
         } else {
             buf.truncate(res as usize);
             Ok(OsString::from_vec(buf).into())
@@ -126,12 +257,12 @@ impl Dir {
     /// clobbering the symlink at the destination.
     ///
     /// [`new_unnamed_file`]: #method.new_unnamed_file
-    pub fn write_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+    pub fn write_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
         -> io::Result<File>
     {
         self._open_file(to_cstr(path)?.as_ref(),
             libc::O_CREAT|libc::O_WRONLY|libc::O_TRUNC,
-            mode)
+            mode.into_mode())
     }
 
     /// Open file for append, create if necessary
@@ -140,12 +271,12 @@ impl Dir {
     /// will need to call [`read_link`] to resolve the real path first.
     ///
     /// [`read_link`]: #method.read_link
-    pub fn append_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+    pub fn append_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
         -> io::Result<File>
     {
         self._open_file(to_cstr(path)?.as_ref(),
             libc::O_CREAT|libc::O_WRONLY|libc::O_APPEND,
-            mode)
+            mode.into_mode())
     }
 
     /// Create file for writing (and truncate) in this directory
@@ -159,12 +290,12 @@ impl Dir {
     ///
     /// [`new_unnamed_file`]: #method.new_unnamed_file
     #[deprecated(since="0.1.7", note="please use `write_file` instead")]
-    pub fn create_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+    pub fn create_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
         -> io::Result<File>
     {
         self._open_file(to_cstr(path)?.as_ref(),
             libc::O_CREAT|libc::O_WRONLY|libc::O_TRUNC,
-            mode)
+            mode.into_mode())
     }
 
     /// Create a tmpfile in this directory which isn't linked to any filename
@@ -191,12 +322,12 @@ impl Dir {
     /// can't be accomplished rather than relying on specific error codes,
     /// because semantics of errors are very ugly.
     #[cfg(target_os="linux")]
-    pub fn new_unnamed_file(&self, mode: libc::mode_t)
+    pub fn new_unnamed_file<M: IntoMode>(&self, mode: M)
         -> io::Result<File>
     {
         self._open_file(unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") },
             libc::O_TMPFILE|libc::O_WRONLY,
-            mode)
+            mode.into_mode())
     }
 
     /// Create a tmpfile in this directory which isn't linked to any filename
@@ -220,18 +351,67 @@ impl Dir {
     /// can't be accomplished rather than relying on specific error codes,
     /// because semantics of errors are very ugly.
     #[cfg(not(target_os="linux"))]
-    pub fn new_unnamed_file<P: AsPath>(&self, _mode: libc::mode_t)
+    pub fn new_unnamed_file<M: IntoMode>(&self, _mode: M)
         -> io::Result<File>
     {
         Err(io::Error::new(io::ErrorKind::Other,
             "creating unnamed tmpfiles is only supported on linux"))
     }
 
+    /// Create an anonymous file, falling back if `O_TMPFILE` doesn't work
+    ///
+    /// This tries ``new_unnamed_file()`` first. If it fails (non-linux
+    /// system, old kernel or a filesystem that doesn't support `O_TMPFILE`)
+    /// a file with a random name is created in this directory and is
+    /// unlinked immediately. If that fails too (e.g. directory is read-only),
+    /// on linux the file is created with `memfd_create`, which doesn't
+    /// occupy space on the filesystem of this directory.
+    ///
+    /// **Note:** only a file created by `O_TMPFILE` can be linked by
+    /// ``link_file_at``. For others ``link_file_at`` returns an error, so
+    /// your code should be able to fallback to copying the data.
+    pub fn new_unnamed_file_fallback<M: IntoMode>(&self, mode: M)
+        -> io::Result<File>
+    {
+        let mode = mode.into_mode();
+        #[cfg(target_os="linux")]
+        {
+            if is_supported(Feature::TmpFile) {
+                if let Ok(file) = self.new_unnamed_file(mode) {
+                    return Ok(file);
+                }
+            }
+        }
+        match self._new_unlinked_file(mode) {
+            #[cfg(target_os="linux")]
+            Err(_) => _memfd_file(mode),
+            res => res,
+        }
+    }
+
+    fn _new_unlinked_file(&self, mode: libc::mode_t) -> io::Result<File> {
+        let (file, name) = create_temp(self,
+            OsStr::new(".tmp"), OsStr::new(""), mode)?;
+        match self._unlink(&name, 0) {
+            Ok(()) => Ok(file),
+            Err(e) => {
+                // some filesystems refuse to remove open files (EBUSY),
+                // so close it and retry to not leave the file behind
+                drop(file);
+                self._unlink(&name, 0).ok();
+                Err(e)
+            }
+        }
+    }
+
     /// Link open file to a specified path
     ///
     /// This is used with ``new_unnamed_file()`` to create and initialize the
-    /// file before linking it into a filesystem. This requires `/proc` to be
-    /// mounted and works **only on linux**.
+    /// file before linking it into a filesystem. This works **only on linux**.
+    ///
+    /// The file is linked with `linkat(AT_EMPTY_PATH)` if the process has
+    /// `CAP_DAC_READ_SEARCH` capability. Otherwise, this requires `/proc` to
+    /// be mounted.
     ///
     /// On systems other than linux this always returns error. It's expected
     /// that in most cases this methos is not called if ``new_unnamed_file``
@@ -242,9 +422,18 @@ impl Dir {
     pub fn link_file_at<F: AsRawFd, P: AsPath>(&self, file: &F, path: P)
         -> io::Result<()>
     {
-        let fd_path = format!("/proc/self/fd/{}", file.as_raw_fd());
-        _hardlink(&Dir(libc::AT_FDCWD), to_cstr(fd_path)?.as_ref(),
-            &self, to_cstr(path)?.as_ref(),
+        let path = to_cstr(path)?;
+        let empty = unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") };
+        match _hardlink(file.as_raw_fd(), empty, self.0, path.as_ref(),
+                        libc::AT_EMPTY_PATH)
+        {
+            // ENOENT means we don't have CAP_DAC_READ_SEARCH
+            Err(ref e) if raw_os_error(e) == Some(libc::ENOENT) => {}
+            res => return res,
+        }
+        let fd_path = fd_path(file.as_raw_fd());
+        _hardlink(libc::AT_FDCWD, to_cstr(&fd_path)?.as_ref(),
+            self.0, path.as_ref(),
             libc::AT_SYMLINK_FOLLOW)
     }
 
@@ -273,12 +462,12 @@ impl Dir {
     /// respect to other threads and processes.
     ///
     /// Technically it means passing `O_EXCL` flag to open.
-    pub fn new_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+    pub fn new_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
         -> io::Result<File>
     {
         self._open_file(to_cstr(path)?.as_ref(),
             libc::O_CREAT|libc::O_EXCL|libc::O_WRONLY,
-            mode)
+            mode.into_mode())
     }
 
     /// Open file for reading and writing without truncation, create if needed
@@ -287,17 +476,70 @@ impl Dir {
     /// will need to call [`read_link`] to resolve the real path first.
     ///
     /// [`read_link`]: #method.read_link
-    pub fn update_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+    pub fn update_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
         -> io::Result<File>
     {
         self._open_file(to_cstr(path)?.as_ref(),
             libc::O_CREAT|libc::O_RDWR,
-            mode)
+            mode.into_mode())
+    }
+
+    /// Truncate (or extend) an existing file to the specified length
+    ///
+    /// This opens the file for writing (symlinks are not followed), calls
+    /// `ftruncate` and closes the file, so it's not created if it doesn't
+    /// exist.
+    pub fn truncate<P: AsPath>(&self, path: P, len: u64) -> io::Result<()> {
+        let file = self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_WRONLY,
+            0)?;
+        file.set_len(len)
     }
 
-    fn _open_file(&self, path: &CStr, flags: libc::c_int, mode: libc::mode_t)
+    /// Read up to `len` bytes of the file starting at `offset`
+    ///
+    /// The file is opened for the call, read with `pread` and closed, so
+    /// this is convenient for small random reads. Less than `len` bytes
+    /// are returned if the end of the file is reached.
+    pub fn read_range<P: AsPath>(&self, path: P, offset: u64, len: usize)
+        -> io::Result<Vec<u8>>
+    {
+        let file = self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_RDONLY,
+            0)?;
+        let mut buf = vec![0u8; len];
+        let mut filled = 0;
+        while filled < len {
+            match file.read_at(&mut buf[filled..], offset + filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        buf.truncate(filled);
+        Ok(buf)
+    }
+
+    /// Write `data` into an existing file at `offset`
+    ///
+    /// The file is opened for writing (it's neither created nor truncated),
+    /// written with `pwrite` and closed. Writing past the end of the file
+    /// extends it (the gap reads as zeros).
+    pub fn write_at<P: AsPath>(&self, path: P, offset: u64, data: &[u8])
+        -> io::Result<()>
+    {
+        let file = self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_WRONLY,
+            0)?;
+        file.write_all_at(data, offset)
+    }
+
+    pub(crate) fn _open_file(&self, path: &CStr, flags: libc::c_int,
+        mode: libc::mode_t)
         -> io::Result<File>
     {
+        let call = syscall("openat", self.0, path)?;
         unsafe {
             // Note: In below call to `openat`, *mode* must be cast to
             // `unsigned` because the optional `mode` argument to `openat` is
@@ -308,7 +550,7 @@ impl Dir {
                             flags|libc::O_CLOEXEC|libc::O_NOFOLLOW,
                             mode as libc::c_uint);
             if res < 0 {
-                Err(io::Error::last_os_error())
+                Err(call.error())
             } else {
                 Ok(File::from_raw_fd(res))
             }
@@ -323,12 +565,59 @@ impl Dir {
     {
         self._symlink(to_cstr(path)?.as_ref(), to_cstr(value)?.as_ref())
     }
+    /// Make a symlink in this directory, verifying that the target exists
+    ///
+    /// The symlink is created and then resolved: the target (and every
+    /// symlink on the way) must exist and stay inside this directory (see
+    /// `verify_beneath`). Otherwise the symlink is removed and the error
+    /// of kind `NotFound` is returned, for which `error_kind()` returns
+    /// `ErrorKind::DanglingSymlink`, so it can be distinguished from the
+    /// errors of creating the symlink itself.
+    ///
+    /// Use it where a broken link indicates a bug. Note that the target
+    /// may still be removed (or moved) after the check.
+    pub fn symlink_verified<P: AsPath, R: AsPath>(&self, path: P, value: R)
+        -> io::Result<()>
+    {
+        let path = to_cstr(path)?;
+        let value = to_cstr(value)?;
+        self._symlink(path.as_ref(), value.as_ref())?;
+        let resolved = self._symlink_resolves(path.as_ref());
+        match resolved {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self._unlink(path.as_ref(), 0)?;
+                let value = value.as_ref().to_bytes();
+                Err(dangling_symlink(Path::new(OsStr::from_bytes(value))))
+            }
+            Err(e) => {
+                self._unlink(path.as_ref(), 0)?;
+                Err(e)
+            }
+        }
+    }
+    fn _symlink_resolves(&self, path: &CStr) -> io::Result<bool> {
+        let is_dangling = |e: &io::Error| matches!(raw_os_error(e),
+            Some(libc::ENOENT) | Some(libc::ENOTDIR) | Some(libc::ELOOP));
+        match self.verify_beneath(path) {
+            Ok(true) => {}
+            Ok(false) => return Ok(false),
+            Err(ref e) if is_dangling(e) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        match self._stat(path, 0) {
+            Ok(_) => Ok(true),
+            Err(ref e) if is_dangling(e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
     fn _symlink(&self, path: &CStr, link: &CStr) -> io::Result<()> {
+        let call = syscall("symlinkat", self.0, path)?;
         unsafe {
             let res = libc::symlinkat(link.as_ptr(),
                 self.0, path.as_ptr());
             if res < 0 {
-                Err(io::Error::last_os_error())
+                Err(call.error())
             } else {
                 Ok(())
             }
@@ -336,16 +625,27 @@ impl Dir {
     }
 
     /// Create a subdirectory in this directory
-    pub fn create_dir<P: AsPath>(&self, path: P, mode: libc::mode_t)
+    pub fn create_dir<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
         -> io::Result<()>
     {
-        self._create_dir(to_cstr(path)?.as_ref(), mode)
+        self._create_dir(to_cstr(path)?.as_ref(), mode.into_mode())
     }
-    fn _create_dir(&self, path: &CStr, mode: libc::mode_t) -> io::Result<()> {
+    /// Create a subdirectory with default permissions
+    ///
+    /// Same as `std::fs::create_dir`, i.e. the directory is created with
+    /// `0o777` mode with the process umask applied (see `umask()`).
+    pub fn create_dir_default<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self._create_dir(to_cstr(path)?.as_ref(), 0o777)
+    }
+
+    pub(crate) fn _create_dir(&self, path: &CStr, mode: libc::mode_t)
+        -> io::Result<()>
+    {
+        let call = syscall("mkdirat", self.0, path)?;
         unsafe {
             let res = libc::mkdirat(self.0, path.as_ptr(), mode);
             if res < 0 {
-                Err(io::Error::last_os_error())
+                Err(call.error())
             } else {
                 Ok(())
             }
@@ -390,30 +690,270 @@ impl Dir {
     {
         self._unlink(to_cstr(path)?.as_ref(), 0)
     }
-    fn _unlink(&self, path: &CStr, flags: libc::c_int) -> io::Result<()> {
+    /// Remove a file only if the name still refers to the given open file
+    ///
+    /// This closes the race between checking the file (e.g. its
+    /// modification time in a cache eviction code) and removing it: if the
+    /// name was replaced in between, the call fails with `EDEADLK` and
+    /// nothing is removed.
+    ///
+    /// Uses `funlinkat` which is only available on FreeBSD 13 and newer.
+    #[cfg(target_os="freebsd")]
+    pub fn remove_file_verified<P: AsPath>(&self, path: P, file: &File)
+        -> io::Result<()>
+    {
+        let path = to_cstr(path)?;
+        let path = path.as_ref();
+        let call = syscall("funlinkat", self.0, path)?;
+        unsafe {
+            let res = funlinkat(self.0, path.as_ptr(), file.as_raw_fd(), 0);
+            if res < 0 {
+                Err(call.error())
+            } else {
+                Ok(())
+            }
+        }
+    }
+    pub(crate) fn _unlink(&self, path: &CStr, flags: libc::c_int)
+        -> io::Result<()>
+    {
+        let call = syscall("unlinkat", self.0, path)?;
         unsafe {
             let res = libc::unlinkat(self.0, path.as_ptr(), flags);
             if res < 0 {
-                Err(io::Error::last_os_error())
+                Err(call.error())
             } else {
                 Ok(())
             }
         }
     }
 
+    /// Remove a subdirectory in this directory with all its contents
+    ///
+    /// Symlinks are never followed, i.e. symlinks themselves are removed
+    /// rather than files they point to.
+    pub fn remove_recursive<P: AsPath>(&self, path: P)
+        -> io::Result<()>
+    {
+        self._remove_recursive(to_cstr(path)?.as_ref(), None)
+    }
+    /// Remove a subdirectory with all its contents, unless cancelled
+    ///
+    /// Same as `remove_recursive` but checks `cancel` before removing each
+    /// entry and fails with `ErrorKind::Interrupted` when it's cancelled.
+    /// Entries that were already removed are not restored, the rest of the
+    /// tree stays intact.
+    pub fn remove_recursive_cancellable<P: AsPath>(&self, path: P,
+        cancel: &CancelToken)
+        -> io::Result<()>
+    {
+        self._remove_recursive(to_cstr(path)?.as_ref(), Some(cancel))
+    }
+    /// Remove all contents of this directory but keep the directory itself
+    ///
+    /// Unlike removing and recreating the directory this preserves its
+    /// mode, ownership and anything mounted onto it. Symlinks are never
+    /// followed.
+    pub fn clear(&self) -> io::Result<()> {
+        self._remove_contents(None)
+    }
+    /// Remove all contents of a subdirectory but keep the subdirectory
+    ///
+    /// See `clear()`. The subdirectory itself must not be a symlink.
+    pub fn remove_recursive_keep_root<P: AsPath>(&self, path: P)
+        -> io::Result<()>
+    {
+        self._sub_dir(to_cstr(path)?.as_ref(), libc::O_DIRECTORY)?
+            ._remove_contents(None)
+    }
+    /// Remove a subdirectory with all its contents, continuing on errors
+    ///
+    /// Unlike `remove_recursive`, which stops at the first failure, this
+    /// removes as much as possible (like `rm -rf`) and returns all the
+    /// errors encountered along with paths (relative to this directory) of
+    /// entries that couldn't be removed. Empty vector means the directory
+    /// is removed completely.
+    ///
+    /// Parent directories of the entries that failed are not reported
+    /// separately, even though they can't be removed either.
+    pub fn remove_recursive_tolerant<P: AsPath>(&self, path: P)
+        -> Vec<(PathBuf, io::Error)>
+    {
+        let mut errors = Vec::new();
+        match to_cstr(path) {
+            Ok(path) => {
+                let path = path.as_ref();
+                let name = Path::new(OsStr::from_bytes(path.to_bytes()));
+                self._remove_tolerant(path, name, &mut errors);
+            }
+            Err(e) => errors.push((PathBuf::new(), e)),
+        }
+        errors
+    }
+    fn _remove_tolerant(&self, path: &CStr, name: &Path,
+        errors: &mut Vec<(PathBuf, io::Error)>)
+    {
+        let before = errors.len();
+        match self._sub_dir(path, libc::O_DIRECTORY) {
+            Ok(dir) => dir._remove_contents_tolerant(name, errors),
+            Err(e) => return errors.push((name.to_path_buf(), e)),
+        }
+        if let Err(e) = self._unlink(path, libc::AT_REMOVEDIR) {
+            if errors.len() == before ||
+                raw_os_error(&e) != Some(libc::ENOTEMPTY)
+            {
+                errors.push((name.to_path_buf(), e));
+            }
+        }
+    }
+    fn _remove_contents_tolerant(&self, prefix: &Path,
+        errors: &mut Vec<(PathBuf, io::Error)>)
+    {
+        let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+        let iter = match open_dir(self, dot, 0) {
+            Ok(iter) => iter,
+            Err(e) => return errors.push((prefix.to_path_buf(), e)),
+        };
+        for entry in iter {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return errors.push((prefix.to_path_buf(), e)),
+            };
+            let name = prefix.join(entry.file_name());
+            let is_dir = match entry.simple_type() {
+                Some(typ) => typ == SimpleType::Dir,
+                None => match self.metadata(&entry) {
+                    Ok(meta) => meta.is_dir(),
+                    Err(e) => {
+                        errors.push((name, e));
+                        continue;
+                    }
+                },
+            };
+            if is_dir {
+                self._remove_tolerant(&entry.name, &name, errors);
+            } else if let Err(e) = self._unlink(&entry.name, 0) {
+                errors.push((name, e));
+            }
+        }
+    }
+    fn _remove_recursive(&self, path: &CStr, cancel: Option<&CancelToken>)
+        -> io::Result<()>
+    {
+        self._sub_dir(path, libc::O_DIRECTORY)?._remove_contents(cancel)?;
+        self._unlink(path, libc::AT_REMOVEDIR)
+    }
+    // Removes everything in this directory, descending into subdirectories
+    // through the already opened file descriptor, so the path is never
+    // resolved twice
+    fn _remove_contents(&self, cancel: Option<&CancelToken>)
+        -> io::Result<()>
+    {
+        let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+        for entry in open_dir(self, dot, 0)? {
+            if let Some(cancel) = cancel {
+                cancel.check()?;
+            }
+            let entry = entry?;
+            let is_dir = match entry.simple_type() {
+                Some(typ) => typ == SimpleType::Dir,
+                None => self.metadata(&entry)?.is_dir(),
+            };
+            if is_dir {
+                self._remove_recursive(&entry.name, cancel)?;
+            } else {
+                self._unlink(&entry.name, 0)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Get the path of this directory (if possible)
     ///
-    /// This uses symlinks in `/proc/self`, they sometimes may not be
-    /// available so use with care.
+    /// This uses symlinks in `/proc/self`, if `/proc` is not mounted it
+    /// falls back to `recover_path_slow()`.
+    #[cfg(not(any(target_os="macos", target_os="ios")))]
     pub fn recover_path(&self) -> io::Result<PathBuf> {
         let fd = self.0;
         if fd != libc::AT_FDCWD {
-            read_link(format!("/proc/self/fd/{}", fd))
-        } else {
+            if Path::new("/proc/self/fd").exists() {
+                read_link(fd_path(fd))
+            } else {
+                self.recover_path_slow()
+            }
+        } else if Path::new("/proc/self/cwd").exists() {
             read_link("/proc/self/cwd")
+        } else {
+            current_dir()
         }
     }
 
+    /// Get the path of this directory (if possible)
+    ///
+    /// This uses `fcntl(F_GETPATH)`, note that path may be stale if the
+    /// directory was moved or removed.
+    #[cfg(any(target_os="macos", target_os="ios"))]
+    pub fn recover_path(&self) -> io::Result<PathBuf> {
+        if self.0 == libc::AT_FDCWD {
+            return current_dir();
+        }
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+        let res = unsafe {
+            libc::fcntl(self.0, libc::F_GETPATH, buf.as_mut_ptr())
+        };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            buf.truncate(len);
+            Ok(OsString::from_vec(buf).into())
+        }
+    }
+
+    /// Get the path of this directory by walking parent directories
+    ///
+    /// This is a classic `getcwd` algorithm: at each level parent directory
+    /// is listed to find an entry with the same device and inode number.
+    /// Unlike ``recover_path()`` it needs neither `/proc` nor `F_GETPATH`,
+    /// but it's slow: O(depth × directory size). Also it requires the
+    /// permission to list every parent directory, and doesn't work with
+    /// `capability-mode` feature (as parent directories are opened by `..`).
+    pub fn recover_path_slow(&self) -> io::Result<PathBuf> {
+        let mut names = Vec::new();
+        let mut cur = self.sub_dir(".")?;
+        let mut cur_meta = cur.self_metadata()?;
+        loop {
+            let parent = cur.sub_dir("..")?;
+            let parent_meta = parent.self_metadata()?;
+            if same_file(&cur_meta, &parent_meta) {
+                break;
+            }
+            names.push(parent._find_entry(&cur_meta)?);
+            cur = parent;
+            cur_meta = parent_meta;
+        }
+        let mut path = PathBuf::from("/");
+        path.extend(names.iter().rev());
+        Ok(path)
+    }
+
+    pub(crate) fn _find_entry(&self, target: &Metadata) -> io::Result<OsString> {
+        for entry in self.list_dir(".")? {
+            let entry = entry?;
+            match entry.simple_type() {
+                Some(SimpleType::Dir) | None => {}
+                Some(_) => continue,
+            }
+            // entry may be removed in the meantime, just skip it
+            if let Ok(meta) = self.metadata(&entry) {
+                if same_file(&meta, target) {
+                    return Ok(entry.file_name().to_os_string());
+                }
+            }
+        }
+        Err(io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
     /// Returns metadata of an entry in this directory
     ///
     /// If the destination path is a symlink, this will return the metadata of the symlink itself.
@@ -424,13 +964,16 @@ impl Dir {
     pub fn metadata<P: AsPath>(&self, path: P) -> io::Result<Metadata> {
         self._stat(to_cstr(path)?.as_ref(), libc::AT_SYMLINK_NOFOLLOW)
     }
-    fn _stat(&self, path: &CStr, flags: libc::c_int) -> io::Result<Metadata> {
+    pub(crate) fn _stat(&self, path: &CStr, flags: libc::c_int)
+        -> io::Result<Metadata>
+    {
+        let call = syscall("fstatat", self.0, path)?;
         unsafe {
             let mut stat = mem::zeroed();
             let res = libc::fstatat(self.0, path.as_ptr(),
                 &mut stat, flags);
             if res < 0 {
-                Err(io::Error::last_os_error())
+                Err(call.error())
             } else {
                 Ok(metadata::new(stat))
             }
@@ -439,15 +982,63 @@ impl Dir {
 
     /// Returns the metadata of the directory itself.
     pub fn self_metadata(&self) -> io::Result<Metadata> {
+        fstat(self.0)
+    }
+
+    /// Change permissions of the directory itself
+    ///
+    /// Unlike other methods umask is not applied. This (and the other
+    /// `set_self_*` methods) works via the `.` entry, so it works on
+    /// `O_PATH` descriptors which `fchmod` rejects.
+    pub fn set_self_permissions<M: IntoMode>(&self, mode: M)
+        -> io::Result<()>
+    {
+        let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+        let call = syscall("fchmodat", self.0, dot)?;
         unsafe {
-            let mut stat = mem::zeroed();
-            let res = libc::fstat(self.0, &mut stat);
-            if res < 0 {
-                Err(io::Error::last_os_error())
-            } else {
-                Ok(metadata::new(stat))
+            if libc::fchmodat(self.0, dot.as_ptr(), mode.into_mode(), 0) < 0 {
+                return Err(call.error());
             }
         }
+        Ok(())
+    }
+
+    /// Change owner and/or group of the directory itself
+    ///
+    /// `None` leaves the respective id unchanged.
+    pub fn set_self_owner(&self, uid: Option<libc::uid_t>,
+        gid: Option<libc::gid_t>)
+        -> io::Result<()>
+    {
+        let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+        let call = syscall("fchownat", self.0, dot)?;
+        // -1 means "don't change"
+        let uid = uid.unwrap_or(!0);
+        let gid = gid.unwrap_or(!0);
+        unsafe {
+            if libc::fchownat(self.0, dot.as_ptr(), uid, gid, 0) < 0 {
+                return Err(call.error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Change access and/or modification time of the directory itself
+    ///
+    /// `None` leaves the respective time unchanged.
+    pub fn set_self_times(&self, accessed: Option<SystemTime>,
+        modified: Option<SystemTime>)
+        -> io::Result<()>
+    {
+        let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+        let times = [to_timespec(accessed)?, to_timespec(modified)?];
+        let call = syscall("utimensat", self.0, dot)?;
+        unsafe {
+            if libc::utimensat(self.0, dot.as_ptr(), times.as_ptr(), 0) < 0 {
+                return Err(call.error());
+            }
+        }
+        Ok(())
     }
 
     /// Constructs a new `Dir` from a given raw file descriptor,
@@ -494,11 +1085,13 @@ pub fn rename<P, R>(old_dir: &Dir, old: P, new_dir: &Dir, new: R)
 fn _rename(old_dir: &Dir, old: &CStr, new_dir: &Dir, new: &CStr)
     -> io::Result<()>
 {
+    let call = syscall("renameat", old_dir.0, old)?;
+    check_path(new_dir.0, new)?;
     unsafe {
         let res = libc::renameat(old_dir.0, old.as_ptr(),
             new_dir.0, new.as_ptr());
         if res < 0 {
-            Err(io::Error::last_os_error())
+            Err(call.error())
         } else {
             Ok(())
         }
@@ -517,20 +1110,88 @@ pub fn hardlink<P, R>(old_dir: &Dir, old: P, new_dir: &Dir, new: R)
     -> io::Result<()>
     where P: AsPath, R: AsPath,
 {
-    _hardlink(old_dir, to_cstr(old)?.as_ref(),
-              new_dir, to_cstr(new)?.as_ref(),
+    let old = to_cstr(old)?;
+    check_path(old_dir.0, old.as_ref())?;
+    _hardlink(old_dir.0, old.as_ref(),
+              new_dir.0, to_cstr(new)?.as_ref(),
               0)
 }
 
-fn _hardlink(old_dir: &Dir, old: &CStr, new_dir: &Dir, new: &CStr,
-             flags: libc::c_int)
+fn to_timespec(time: Option<SystemTime>) -> io::Result<libc::timespec> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput,
+        "time is out of range");
+    let time = match time {
+        Some(time) => time,
+        None => return Ok(libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        }),
+    };
+    let (sec, nsec) = match time.duration_since(UNIX_EPOCH) {
+        Ok(dur) => (libc::time_t::try_from(dur.as_secs())
+                    .map_err(|_| invalid())?,
+                    dur.subsec_nanos()),
+        Err(e) => {
+            // round towards negative infinity, nanoseconds are positive
+            let dur = e.duration();
+            let mut sec = libc::time_t::try_from(dur.as_secs())
+                .map_err(|_| invalid())?;
+            let mut nsec = dur.subsec_nanos();
+            if nsec > 0 {
+                sec += 1;
+                nsec = 1_000_000_000 - nsec;
+            }
+            (-sec, nsec)
+        }
+    };
+    Ok(libc::timespec { tv_sec: sec, tv_nsec: nsec as _ })
+}
+
+pub(crate) fn _hardlink(old_dir: RawFd, old: &CStr,
+    new_dir: RawFd, new: &CStr, flags: libc::c_int)
     -> io::Result<()>
 {
+    let call = syscall("linkat", new_dir, new)?;
     unsafe {
-        let res = libc::linkat(old_dir.0, old.as_ptr(),
-            new_dir.0, new.as_ptr(), flags);
+        let res = libc::linkat(old_dir, old.as_ptr(),
+            new_dir, new.as_ptr(), flags);
         if res < 0 {
-            Err(io::Error::last_os_error())
+            Err(call.error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Copy a file using copy-on-write clone (`clonefileat`)
+///
+/// The copy is instant and doesn't take additional space until either file
+/// is modified. Files must be on a single APFS volume, otherwise the call
+/// fails with `EXDEV` or `ENOTSUP` (there is no fallback to copying data).
+/// The destination must not exist. Directories are cloned recursively.
+///
+/// Symlinks are not followed (the symlink itself is cloned).
+///
+/// Only available on macOS and iOS.
+#[cfg(any(target_os="macos", target_os="ios"))]
+pub fn clone_file<P, R>(old_dir: &Dir, old: P, new_dir: &Dir, new: R)
+    -> io::Result<()>
+    where P: AsPath, R: AsPath,
+{
+    // not in libc crate yet
+    const CLONE_NOFOLLOW: u32 = 0x0001;
+
+    let old = to_cstr(old)?;
+    let old = old.as_ref();
+    let new = to_cstr(new)?;
+    let new = new.as_ref();
+    check_path(old_dir.0, old)?;
+    let call = syscall("clonefileat", new_dir.0, new)?;
+    unsafe {
+        let res = libc::clonefileat(old_dir.0, old.as_ptr(),
+            new_dir.0, new.as_ptr(), CLONE_NOFOLLOW);
+        if res < 0 {
+            Err(call.error())
         } else {
             Ok(())
         }
@@ -542,6 +1203,9 @@ fn _hardlink(old_dir: &Dir, old: &CStr, new_dir: &Dir, new: &CStr,
 /// Files must be on a single filesystem anyway. This funtion does **not**
 /// fallback to copying if needed.
 ///
+/// On kernels without `renameat2` (see `is_supported()`) zero `flags` fall
+/// back to the plain `renameat`, other flags fail with `ENOSYS`.
+///
 /// Only supported on Linux.
 #[cfg(target_os="linux")]
 pub fn rename_flags<P, R>(old_dir: &Dir, old: P, new_dir: &Dir, new: R,
@@ -559,19 +1223,43 @@ fn _rename_flags(old_dir: &Dir, old: &CStr, new_dir: &Dir, new: &CStr,
     flags: libc::c_int)
     -> io::Result<()>
 {
+    if flags == 0 && !is_supported(Feature::Renameat2) {
+        return _rename(old_dir, old, new_dir, new);
+    }
+    let call = syscall("renameat2", old_dir.0, old)?;
+    check_path(new_dir.0, new)?;
+    check_supported(Feature::Renameat2)?;
     unsafe {
         let res = libc::syscall(
             libc::SYS_renameat2,
             old_dir.0, old.as_ptr(),
             new_dir.0, new.as_ptr(), flags);
         if res < 0 {
-            Err(io::Error::last_os_error())
+            let err = call.error();
+            note_error(Feature::Renameat2, &err);
+            Err(err)
         } else {
             Ok(())
         }
     }
 }
 
+#[cfg(target_os="linux")]
+fn _memfd_file(mode: libc::mode_t) -> io::Result<File> {
+    unsafe {
+        let name = CStr::from_bytes_with_nul_unchecked(b"openat-unnamed\0");
+        let res = libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC);
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = File::from_raw_fd(res);
+        if libc::fchmod(res, mode) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file)
+    }
+}
+
 impl AsRawFd for Dir {
     #[inline]
     fn as_raw_fd(&self) -> RawFd {
@@ -608,7 +1296,29 @@ impl Drop for Dir {
     }
 }
 
-fn to_cstr<P: AsPath>(path: P) -> io::Result<P::Buffer> {
+pub fn fstat(fd: RawFd) -> io::Result<Metadata> {
+    unsafe {
+        let mut stat = mem::zeroed();
+        let res = libc::fstat(fd, &mut stat);
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(metadata::new(stat))
+        }
+    }
+}
+
+pub fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.identity() == b.identity()
+}
+
+/// Returns a path that refers to the file descriptor: `/proc/self/fd/<fd>`
+#[cfg(not(any(target_os="macos", target_os="ios")))]
+pub fn fd_path(fd: RawFd) -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{}", fd))
+}
+
+pub fn to_cstr<P: AsPath>(path: P) -> io::Result<P::Buffer> {
     path.to_path()
     .ok_or_else(|| {
         io::Error::new(io::ErrorKind::InvalidInput,
@@ -618,10 +1328,12 @@ fn to_cstr<P: AsPath>(path: P) -> io::Result<P::Buffer> {
 
 #[cfg(test)]
 mod test {
-    use std::io::{Read};
+    use std::fs;
+    use std::io::{self, Read, Write};
     use std::path::Path;
+    use std::os::unix::fs::PermissionsExt;
     use std::os::unix::io::{FromRawFd, IntoRawFd};
-    use crate::{Dir};
+    use crate::{Dir, CancelToken};
 
     #[test]
     fn test_open_ok() {
@@ -681,6 +1393,209 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_unnamed_file_fallback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut f = dir.new_unnamed_file_fallback(0o644).unwrap();
+        f.write_all(b"hello").unwrap();
+        assert_eq!(dir.list_dir(".").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_unlinked_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut f = dir._new_unlinked_file(0o644).unwrap();
+        f.write_all(b"hello").unwrap();
+        assert_eq!(dir.list_dir(".").unwrap().count(), 0);
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn test_memfd_file() {
+        let mut f = super::_memfd_file(0o600).unwrap();
+        f.write_all(b"hello").unwrap();
+    }
+
+    #[test]
+    fn test_recover_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        assert_eq!(dir.recover_path().unwrap(),
+                   tmp.path().canonicalize().unwrap());
+    }
+
+    #[test]
+    #[cfg(not(feature="capability-mode"))]
+    fn test_recover_path_slow() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        let sub = dir.sub_dir("sub").unwrap();
+        assert_eq!(sub.recover_path_slow().unwrap(),
+                   tmp.path().canonicalize().unwrap().join("sub"));
+        assert_eq!(Dir::open("/").unwrap().recover_path_slow().unwrap(),
+                   Path::new("/"));
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn test_link_file_at() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut f = dir.new_file("a.txt", 0o644).unwrap();
+        f.write_all(b"hello").unwrap();
+        dir.link_file_at(&f, "b.txt").unwrap();
+        let mut buf = String::new();
+        dir.open_file("b.txt").unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+    }
+
+    #[test]
+    fn test_symlink_verified() {
+        use crate::{ErrorKind, error_kind};
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.write_file("a/file", 0o644).unwrap();
+        dir.symlink_verified("link", "a/file").unwrap();
+        dir.symlink_verified("a/link", "file").unwrap();
+        dir.symlink_verified("chain", "link").unwrap();
+        for (path, value) in &[("bad", "a/missing"), ("a/bad", "../file"),
+                               ("escape", "/etc/passwd"), ("loop", "loop"),
+                               ("notdir", "a/file/x")]
+        {
+            let err = dir.symlink_verified(*path, *value).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+            assert_eq!(error_kind(&err), ErrorKind::DanglingSymlink);
+            assert!(dir.metadata(*path).is_err());
+        }
+        let err = dir.symlink_verified("link", "a/file").unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::AlreadyExists);
+        assert!(dir.metadata("link").is_ok());
+    }
+
+    #[test]
+    fn test_remove_recursive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.create_dir("a/b", 0o755).unwrap();
+        dir.write_file("a/b/file", 0o644).unwrap();
+        dir.write_file("a/file", 0o644).unwrap();
+        dir.symlink("a/link", "/").unwrap();
+        dir.symlink("link", "a").unwrap();
+        // symlink to a directory is not followed
+        assert!(dir.remove_recursive("link").is_err());
+        assert!(dir.metadata("a/file").is_ok());
+        dir.remove_file("link").unwrap();
+        dir.remove_recursive("a").unwrap();
+        assert_eq!(dir.list_dir(".").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_clear() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o700).unwrap();
+        dir.create_dir("a/b", 0o755).unwrap();
+        dir.write_file("a/b/file", 0o644).unwrap();
+        dir.write_file("a/file", 0o644).unwrap();
+        dir.remove_recursive_keep_root("a").unwrap();
+        assert_eq!(dir.list_dir("a").unwrap().count(), 0);
+        assert_eq!(dir.metadata("a").unwrap().stat().st_mode & 0o777, 0o700);
+        dir.write_file("file", 0o644).unwrap();
+        dir.clear().unwrap();
+        assert_eq!(dir.list_dir(".").unwrap().count(), 0);
+        assert!(tmp.path().is_dir());
+    }
+
+    #[test]
+    fn test_remove_recursive_tolerant() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.create_dir("a/b", 0o755).unwrap();
+        dir.create_dir("a/c", 0o755).unwrap();
+        dir.write_file("a/b/file", 0o644).unwrap();
+        dir.write_file("a/c/file", 0o644).unwrap();
+        dir.write_file("a/file", 0o644).unwrap();
+        // directory which can't be listed (root ignores permissions)
+        let b = tmp.path().join("a/b");
+        fs::set_permissions(&b, fs::Permissions::from_mode(0o000)).unwrap();
+        let errors = dir.remove_recursive_tolerant("a");
+        fs::set_permissions(&b, fs::Permissions::from_mode(0o755)).ok();
+        if unsafe { libc::geteuid() } == 0 {
+            assert!(errors.is_empty());
+            assert!(dir.metadata("a").is_err());
+        } else {
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].0, Path::new("a/b"));
+            assert!(dir.metadata("a/c").is_err());
+            assert!(dir.metadata("a/file").is_err());
+            assert!(dir.metadata("a/b/file").is_ok());
+        }
+        let errors = dir.remove_recursive_tolerant("nonexistent");
+        assert_eq!(errors[0].0, Path::new("nonexistent"));
+        assert_eq!(errors[0].1.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_remove_recursive_cancellable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.write_file("a/file", 0o644).unwrap();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let err = dir.remove_recursive_cancellable("a", &cancel).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(dir.metadata("a/file").is_ok());
+        dir.remove_recursive_cancellable("a", &CancelToken::new()).unwrap();
+        assert!(dir.metadata("a").is_err());
+    }
+
+    #[test]
+    fn test_is_empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        assert!(dir.self_is_empty().unwrap());
+        dir.create_dir("a", 0o755).unwrap();
+        assert!(!dir.self_is_empty().unwrap());
+        assert!(dir.is_empty("a").unwrap());
+        dir.write_file("a/file", 0o644).unwrap();
+        assert!(!dir.is_empty("a").unwrap());
+        assert!(dir.is_empty("a/file").is_err());
+    }
+
+    #[test]
+    fn test_count_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        assert_eq!(dir.count_entries(".").unwrap(), 0);
+        for i in 0..100 {
+            dir.write_file(format!("file{}", i), 0o644).unwrap();
+        }
+        dir.create_dir("sub", 0o755).unwrap();
+        assert_eq!(dir.count_entries(".").unwrap(), 101);
+        assert_eq!(dir.count_entries("sub").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_truncate() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap().write_all(b"hello").unwrap();
+        dir.truncate("file", 2).unwrap();
+        assert_eq!(dir.metadata("file").unwrap().len(), 2);
+        assert_eq!(dir.truncate("nonexistent", 0).unwrap_err().kind(),
+                   std::io::ErrorKind::NotFound);
+        dir.symlink("link", "file").unwrap();
+        assert!(dir.truncate("link", 0).is_err());
+        assert_eq!(dir.metadata("file").unwrap().len(), 2);
+    }
+
     #[test]
     fn test_try_clone() {
         let d = Dir::open(".").unwrap();
@@ -688,4 +1603,120 @@ mod test {
         drop(d);
         let _file = d2.open_file("src/lib.rs").unwrap();
     }
+
+    #[test]
+    fn test_open_nested() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.create_dir("a/b", 0o755).unwrap();
+        dir.symlink("a/link", "b").unwrap();
+        dir.open_nested(["a", "b"]).unwrap();
+        let err = dir.open_nested(["a", "c", "d"]).unwrap_err();
+        assert_eq!(crate::raw_os_error(&err), Some(libc::ENOENT));
+        assert_eq!(crate::context(&err).unwrap().path(),
+                   Path::new("a/c"));
+        let err = dir.open_nested(["a", "link"]).unwrap_err();
+        assert_eq!(crate::raw_os_error(&err), Some(libc::ENOTDIR));
+        assert_eq!(crate::context(&err).unwrap().path(),
+                   Path::new("a/link"));
+        let err = dir.open_nested(["a", ".."]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(crate::context(&err).unwrap().path(),
+                   Path::new("a/.."));
+    }
+
+    #[test]
+    fn test_open_nested_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.write_file("a/file", 0o644).unwrap().write_all(b"x").unwrap();
+        dir.symlink("a/link", "file").unwrap();
+        let mut buf = String::new();
+        dir.open_nested_file(["a", "file"]).unwrap()
+            .read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "x");
+        let err = dir.open_nested_file(["a", "link"]).unwrap_err();
+        assert_eq!(crate::raw_os_error(&err), Some(libc::ELOOP));
+        assert_eq!(crate::context(&err).unwrap().path(),
+                   Path::new("a/link"));
+        let err = dir.open_nested_file(["a", "file", "x"]).unwrap_err();
+        assert_eq!(crate::raw_os_error(&err), Some(libc::ENOTDIR));
+        let err = dir.open_nested_file(Vec::<&str>::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    #[cfg(target_os="freebsd")]
+    fn test_remove_file_verified() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let old = dir.write_file("file", 0o644).unwrap();
+        dir.write_file("other", 0o644).unwrap();
+        dir.local_rename("other", "file").unwrap();
+        let err = dir.remove_file_verified("file", &old).unwrap_err();
+        assert_eq!(crate::raw_os_error(&err), Some(libc::EDEADLK));
+        let new = dir.open_file("file").unwrap();
+        dir.remove_file_verified("file", &new).unwrap();
+    }
+
+    #[test]
+    fn test_read_range_write_at() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap()
+            .write_all(b"hello world").unwrap();
+        assert_eq!(dir.read_range("file", 6, 5).unwrap(), b"world");
+        assert_eq!(dir.read_range("file", 9, 100).unwrap(), b"ld");
+        assert_eq!(dir.read_range("file", 100, 10).unwrap(), b"");
+        dir.write_at("file", 0, b"HELLO").unwrap();
+        dir.write_at("file", 13, b"!").unwrap();
+        assert_eq!(dir.read_range("file", 0, 100).unwrap(),
+                   b"HELLO world\0\0!");
+        assert!(dir.write_at("missing", 0, b"x").is_err());
+    }
+
+    #[test]
+    fn test_set_self_attributes() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.set_self_permissions(0o700).unwrap();
+        let meta = dir.self_metadata().unwrap();
+        assert_eq!(meta.stat().st_mode & 0o7777, 0o700);
+        let time = UNIX_EPOCH + Duration::new(1_000_000_000, 500);
+        let before = UNIX_EPOCH - Duration::from_millis(1500);
+        dir.set_self_times(Some(before), Some(time)).unwrap();
+        let meta = dir.self_metadata().unwrap();
+        assert_eq!(meta.modified().unwrap(), time);
+        assert_eq!(meta.accessed().unwrap(), before);
+        dir.set_self_times(None, Some(before)).unwrap();
+        let meta = dir.self_metadata().unwrap();
+        assert_eq!(meta.modified().unwrap(), before);
+        assert_eq!(meta.accessed().unwrap(), before);
+        let uid = meta.stat().st_uid;
+        dir.set_self_owner(Some(uid), None).unwrap();
+        assert_eq!(dir.self_metadata().unwrap().stat().st_uid, uid);
+    }
+
+    #[test]
+    #[cfg(any(target_os="macos", target_os="ios"))]
+    fn test_clone_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap().write_all(b"data").unwrap();
+        match super::clone_file(&dir, "file", &dir, "copy") {
+            Ok(()) => {}
+            // not on APFS
+            Err(ref e) if crate::raw_os_error(e) == Some(libc::ENOTSUP) => {
+                return;
+            }
+            Err(e) => panic!("clone_file: {}", e),
+        }
+        let mut buf = String::new();
+        dir.open_file("copy").unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "data");
+        assert!(super::clone_file(&dir, "file", &dir, "copy").is_err());
+    }
 }
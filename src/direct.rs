@@ -0,0 +1,142 @@
+use std::io;
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::AsRawFd;
+use std::slice;
+
+use crate::dir::fstat;
+
+
+/// A zero-initialized heap buffer with the specified alignment
+///
+/// Direct I/O (`O_DIRECT`, see `DirMethodFlags::direct()`) requires buffer
+/// address, file offset and length to be aligned to the logical block size
+/// of the underlying device, otherwise `read` and `write` fail with
+/// `EINVAL`. Use `AlignedBuffer::for_file()` to get suitable buffer.
+pub struct AlignedBuffer {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+// it's just an owned allocation, like `Box<[u8]>`
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+/// Returns the alignment that is suitable for direct I/O on the file
+///
+/// This is the preferred I/O block size of the file (`st_blksize`), which
+/// is a multiple of the logical block size of the device on all common
+/// filesystems, so it's a safe (if a little bit conservative) choice.
+pub fn block_size<F: AsRawFd>(file: &F) -> io::Result<usize> {
+    let size = fstat(file.as_raw_fd())?.stat().st_blksize as usize;
+    if size.is_power_of_two() {
+        Ok(size)
+    } else {
+        // never seen in practice, but we can't align to such value
+        Ok(4096)
+    }
+}
+
+impl AlignedBuffer {
+    /// Allocate a buffer of `len` bytes aligned to `align` bytes
+    ///
+    /// Returns error of kind `InvalidInput` if `align` is not a power of two.
+    pub fn new(len: usize, align: usize) -> io::Result<AlignedBuffer> {
+        // zero-sized allocations are not allowed
+        let layout = Layout::from_size_align(len.max(1), align)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                "invalid buffer alignment"))?;
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        Ok(AlignedBuffer {
+            ptr,
+            layout: Layout::from_size_align(len, align)
+                .expect("layout is checked above"),
+        })
+    }
+    /// Allocate a buffer suitable for direct I/O on the file
+    ///
+    /// The `len` is rounded up to the multiple of the `block_size()`.
+    pub fn for_file<F: AsRawFd>(file: &F, len: usize)
+        -> io::Result<AlignedBuffer>
+    {
+        let align = block_size(file)?;
+        let len = (len + align - 1) & !(align - 1);
+        AlignedBuffer::new(len, align)
+    }
+    /// Returns the alignment of the buffer
+    pub fn align(&self) -> usize {
+        self.layout.align()
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.layout.size().max(1),
+                                             self.layout.align())
+            .expect("layout is checked on allocation");
+        unsafe { alloc::dealloc(self.ptr, layout) }
+    }
+}
+
+impl std::fmt::Debug for AlignedBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AlignedBuffer")
+            .field("len", &self.layout.size())
+            .field("align", &self.layout.align())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use crate::Dir;
+    use super::AlignedBuffer;
+
+    #[test]
+    fn alignment() {
+        let buf = AlignedBuffer::new(100, 4096).unwrap();
+        assert_eq!(buf.as_ptr() as usize % 4096, 0);
+        assert_eq!(buf.len(), 100);
+        assert!(buf.iter().all(|&x| x == 0));
+        assert!(AlignedBuffer::new(100, 3).is_err());
+        assert_eq!(AlignedBuffer::new(0, 512).unwrap().len(), 0);
+    }
+
+    #[test]
+    #[cfg(any(target_os="linux", target_os="android", target_os="freebsd"))]
+    fn direct_io() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut file = match dir.with(0).direct().write_file("file") {
+            Ok(file) => file,
+            // filesystem doesn't support direct I/O (e.g. old tmpfs)
+            Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) => return,
+            Err(e) => panic!("{}", e),
+        };
+        let mut buf = AlignedBuffer::for_file(&file, 1000).unwrap();
+        assert_eq!(buf.len() % buf.align(), 0);
+        buf[..5].copy_from_slice(b"hello");
+        file.write_all(&buf).unwrap();
+        let mut file = dir.with(0).direct().open_file("file").unwrap();
+        let mut buf2 = AlignedBuffer::for_file(&file, 1).unwrap();
+        file.read_exact(&mut buf2).unwrap();
+        assert_eq!(&buf2[..5], b"hello");
+    }
+}
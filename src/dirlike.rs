@@ -0,0 +1,166 @@
+use std::io::{self, Read, Write, Seek};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+use crate::{Dir, Entry, Metadata};
+
+
+/// A file opened by `DirLike` methods
+///
+/// This is implemented for every readable, writable and seekable type, in
+/// particular for `std::fs::File`.
+pub trait FileLike: Read + Write + Seek + Send + Debug {}
+
+impl<T: Read + Write + Seek + Send + Debug> FileLike for T {}
+
+/// Iterator over directory entries returned by `DirLike::list_dir()`
+pub type EntryIter<'a> = Box<dyn Iterator<Item=io::Result<Entry>> + 'a>;
+
+/// Object-safe version of the operations of `Dir`
+///
+/// This allows to write code against `&dyn DirLike` (or
+/// `Box<dyn DirLike>`), and use a different implementation in place of a
+/// real directory: a mock (`MemoryDir` with `mock` feature), a read-only
+/// overlay, an auditing proxy and so on.
+///
+/// Methods have the same semantics as the ones of `Dir`, but take `&Path`
+/// instead of generic arguments, and return boxed files and directories.
+pub trait DirLike: Send + Sync + Debug {
+    /// Open file for reading, see `Dir::open_file`
+    fn open_file(&self, path: &Path) -> io::Result<Box<dyn FileLike>>;
+    /// Open file for writing and truncate it, see `Dir::write_file`
+    fn write_file(&self, path: &Path, mode: libc::mode_t)
+        -> io::Result<Box<dyn FileLike>>;
+    /// Open file for append, see `Dir::append_file`
+    fn append_file(&self, path: &Path, mode: libc::mode_t)
+        -> io::Result<Box<dyn FileLike>>;
+    /// Create a new file, fail if exists, see `Dir::new_file`
+    fn new_file(&self, path: &Path, mode: libc::mode_t)
+        -> io::Result<Box<dyn FileLike>>;
+    /// Open file for reading and writing, see `Dir::update_file`
+    fn update_file(&self, path: &Path, mode: libc::mode_t)
+        -> io::Result<Box<dyn FileLike>>;
+    /// Open subdirectory, see `Dir::sub_dir`
+    fn sub_dir(&self, path: &Path) -> io::Result<Box<dyn DirLike>>;
+    /// List subdirectory, see `Dir::list_dir`
+    fn list_dir(&self, path: &Path) -> io::Result<EntryIter<'_>>;
+    /// Returns metadata of an entry, see `Dir::metadata`
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+    /// Create a subdirectory, see `Dir::create_dir`
+    fn create_dir(&self, path: &Path, mode: libc::mode_t) -> io::Result<()>;
+    /// Make a symlink, see `Dir::symlink`
+    fn symlink(&self, path: &Path, value: &Path) -> io::Result<()>;
+    /// Read a symlink, see `Dir::read_link`
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    /// Rename an entry, see `Dir::local_rename`
+    fn local_rename(&self, old: &Path, new: &Path) -> io::Result<()>;
+    /// Remove a file, see `Dir::remove_file`
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    /// Remove an empty directory, see `Dir::remove_dir`
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    /// Remove a directory with all its contents, see `Dir::remove_recursive`
+    fn remove_recursive(&self, path: &Path) -> io::Result<()>;
+}
+
+macro_rules! impl_dir_like {
+    ($typ:ty) => {
+        impl DirLike for $typ {
+            fn open_file(&self, path: &Path) -> io::Result<Box<dyn FileLike>> {
+                Ok(Box::new(<$typ>::open_file(self, path)?))
+            }
+            fn write_file(&self, path: &Path, mode: libc::mode_t)
+                -> io::Result<Box<dyn FileLike>>
+            {
+                Ok(Box::new(<$typ>::write_file(self, path, mode)?))
+            }
+            fn append_file(&self, path: &Path, mode: libc::mode_t)
+                -> io::Result<Box<dyn FileLike>>
+            {
+                Ok(Box::new(<$typ>::append_file(self, path, mode)?))
+            }
+            fn new_file(&self, path: &Path, mode: libc::mode_t)
+                -> io::Result<Box<dyn FileLike>>
+            {
+                Ok(Box::new(<$typ>::new_file(self, path, mode)?))
+            }
+            fn update_file(&self, path: &Path, mode: libc::mode_t)
+                -> io::Result<Box<dyn FileLike>>
+            {
+                Ok(Box::new(<$typ>::update_file(self, path, mode)?))
+            }
+            fn sub_dir(&self, path: &Path) -> io::Result<Box<dyn DirLike>> {
+                Ok(Box::new(<$typ>::sub_dir(self, path)?))
+            }
+            fn list_dir(&self, path: &Path) -> io::Result<EntryIter<'_>> {
+                Ok(Box::new(<$typ>::list_dir(self, path)?))
+            }
+            fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+                <$typ>::metadata(self, path)
+            }
+            fn create_dir(&self, path: &Path, mode: libc::mode_t)
+                -> io::Result<()>
+            {
+                <$typ>::create_dir(self, path, mode)
+            }
+            fn symlink(&self, path: &Path, value: &Path) -> io::Result<()> {
+                <$typ>::symlink(self, path, value)
+            }
+            fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+                <$typ>::read_link(self, path)
+            }
+            fn local_rename(&self, old: &Path, new: &Path) -> io::Result<()> {
+                <$typ>::local_rename(self, old, new)
+            }
+            fn remove_file(&self, path: &Path) -> io::Result<()> {
+                <$typ>::remove_file(self, path)
+            }
+            fn remove_dir(&self, path: &Path) -> io::Result<()> {
+                <$typ>::remove_dir(self, path)
+            }
+            fn remove_recursive(&self, path: &Path) -> io::Result<()> {
+                <$typ>::remove_recursive(self, path)
+            }
+        }
+    }
+}
+
+impl_dir_like!(Dir);
+#[cfg(feature="mock")]
+impl_dir_like!(crate::MemoryDir);
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use crate::Dir;
+    use super::DirLike;
+
+    fn exercise(dir: &dyn DirLike) {
+        dir.create_dir(Path::new("sub"), 0o755).unwrap();
+        dir.write_file(Path::new("sub/file"), 0o644).unwrap()
+            .write_all(b"hello").unwrap();
+        let sub = dir.sub_dir(Path::new("sub")).unwrap();
+        let mut buf = String::new();
+        sub.open_file(Path::new("file")).unwrap()
+            .read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+        sub.local_rename(Path::new("file"), Path::new("renamed")).unwrap();
+        assert_eq!(sub.metadata(Path::new("renamed")).unwrap().len(), 5);
+        assert_eq!(dir.list_dir(Path::new("sub")).unwrap().count(), 1);
+        dir.remove_recursive(Path::new("sub")).unwrap();
+        assert_eq!(dir.list_dir(Path::new(".")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn real_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        exercise(&Dir::open(tmp.path()).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature="mock")]
+    fn memory_dir() {
+        let dir: Box<dyn DirLike> = Box::new(crate::MemoryDir::new());
+        exercise(&*dir);
+    }
+}
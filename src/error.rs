@@ -0,0 +1,358 @@
+use std::io;
+use std::fmt;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+
+/// An error with the name of the failed system call and the path
+///
+/// Errors of this type are produced by `ErrorContext::context()`, and by a
+/// few methods that always report the path (e.g. `Dir::open_nested`). They
+/// are still returned as `io::Error` (with the same `kind()`), use
+/// `context()` to get to the context:
+///
+/// ```rust,no_run
+/// # use openat::{Dir, ErrorContext};
+/// # let dir = Dir::open(".").unwrap();
+/// if let Err(e) = dir.open_file("config.toml").context() {
+///     match openat::context(&e) {
+///         // prints: openat "config.toml": No such file or directory ...
+///         Some(ctx) => eprintln!("{}", ctx),
+///         None => eprintln!("{}", e),
+///     }
+/// }
+/// ```
+///
+/// Note: `io::Error::raw_os_error()` returns `None` for wrapped errors,
+/// use `openat::raw_os_error()` which works in both cases.
+#[derive(Debug)]
+pub struct Error {
+    operation: &'static str,
+    path: PathBuf,
+    error: io::Error,
+}
+
+impl Error {
+    /// Returns the name of the system call that failed (e.g. `openat`)
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+    /// Returns the path passed to the system call
+    ///
+    /// This is usually relative to some directory descriptor.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Returns the original error
+    pub fn into_inner(self) -> io::Error {
+        self.error
+    }
+}
+
+impl Deref for Error {
+    type Target = io::Error;
+    fn deref(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:?}: {}", self.operation, self.path, self.error)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Detailed kind of the error returned by filesystem operations
+///
+/// This is more fine-grained than `io::ErrorKind` on older Rust versions
+/// and is based on the OS error code, see `error_kind()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Entry is not found (`ENOENT`)
+    NotFound,
+    /// Permission denied (`EACCES`, `EPERM`)
+    PermissionDenied,
+    /// Entry already exists (`EEXIST`)
+    AlreadyExists,
+    /// Path component (or the target) is not a directory (`ENOTDIR`)
+    NotADirectory,
+    /// A directory was found where a file was expected (`EISDIR`)
+    IsADirectory,
+    /// Directory is not empty (`ENOTEMPTY`)
+    DirectoryNotEmpty,
+    /// Link or rename across filesystems (`EXDEV`)
+    CrossesDevices,
+    /// Too many symlinks or a symlink with `O_NOFOLLOW` (`ELOOP`)
+    FilesystemLoop,
+    /// Filesystem is mounted read-only (`EROFS`)
+    ReadOnlyFilesystem,
+    /// File name or path is too long (`ENAMETOOLONG`)
+    FilenameTooLong,
+    /// Too many hard links or directory entries (`EMLINK`)
+    TooManyLinks,
+    /// No space left on device or quota exceeded (`ENOSPC`, `EDQUOT`)
+    StorageFull,
+    /// File or directory is busy (`EBUSY`, `ETXTBSY`)
+    ResourceBusy,
+    /// Stale network filesystem handle (`ESTALE`)
+    StaleHandle,
+    /// Operation is not supported (`EOPNOTSUPP`, `ENOSYS`)
+    Unsupported,
+    /// Invalid argument (`EINVAL`) or nul byte in the path
+    InvalidInput,
+    /// Interrupted by signal (`EINTR`)
+    Interrupted,
+    /// Operation would block (`EAGAIN`)
+    WouldBlock,
+    /// Target of the symlink doesn't exist (or is outside of the
+    /// directory), returned by `Dir::symlink_verified`
+    DanglingSymlink,
+    /// Any other error
+    Other,
+}
+
+/// Returns detailed kind of the error
+///
+/// This looks at `raw_os_error()` (even if error is wrapped, see `Error`),
+/// so it doesn't depend on what `io::ErrorKind` variants are stable in the
+/// Rust version used:
+///
+/// ```rust,no_run
+/// # use openat::{Dir, ErrorKind, error_kind};
+/// # let dir = Dir::open(".").unwrap();
+/// match dir.remove_dir("data") {
+///     Err(ref e) if error_kind(e) == ErrorKind::DirectoryNotEmpty => {
+///         dir.remove_recursive("data").unwrap();
+///     }
+///     res => res.unwrap(),
+/// }
+/// ```
+pub fn error_kind(err: &io::Error) -> ErrorKind {
+    if err.get_ref().is_some_and(|e| e.is::<DanglingSymlink>()) {
+        return ErrorKind::DanglingSymlink;
+    }
+    match raw_os_error(err) {
+        Some(code) => errno_kind(code),
+        None => match err.kind() {
+            io::ErrorKind::NotFound => ErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+            io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+            io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+            io::ErrorKind::WouldBlock => ErrorKind::WouldBlock,
+            _ => ErrorKind::Other,
+        },
+    }
+}
+
+fn errno_kind(code: i32) -> ErrorKind {
+    use self::ErrorKind::*;
+    match code {
+        libc::ENOENT => NotFound,
+        libc::EACCES | libc::EPERM => PermissionDenied,
+        libc::EEXIST => AlreadyExists,
+        libc::ENOTDIR => NotADirectory,
+        libc::EISDIR => IsADirectory,
+        libc::ENOTEMPTY => DirectoryNotEmpty,
+        libc::EXDEV => CrossesDevices,
+        libc::ELOOP => FilesystemLoop,
+        libc::EROFS => ReadOnlyFilesystem,
+        libc::ENAMETOOLONG => FilenameTooLong,
+        libc::EMLINK => TooManyLinks,
+        libc::ENOSPC | libc::EDQUOT => StorageFull,
+        libc::EBUSY | libc::ETXTBSY => ResourceBusy,
+        libc::ESTALE => StaleHandle,
+        libc::EOPNOTSUPP | libc::ENOSYS => Unsupported,
+        libc::EINVAL => InvalidInput,
+        libc::EINTR => Interrupted,
+        libc::EAGAIN => WouldBlock,
+        _ => Other,
+    }
+}
+
+#[derive(Debug)]
+struct DanglingSymlink {
+    target: PathBuf,
+}
+
+impl fmt::Display for DanglingSymlink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "symlink target {:?} doesn't exist in the directory",
+               self.target)
+    }
+}
+
+impl std::error::Error for DanglingSymlink {}
+
+/// Returns the error for a symlink which doesn't resolve to `target`
+pub fn dangling_symlink(target: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound,
+        DanglingSymlink { target: target.to_path_buf() })
+}
+
+/// Returns the context of the error if there is one
+///
+/// See `Error` for more info.
+pub fn context(err: &io::Error) -> Option<&Error> {
+    err.get_ref().and_then(|e| e.downcast_ref::<Error>())
+}
+
+/// Returns OS error code of the error, even if it's wrapped into `Error`
+pub fn raw_os_error(err: &io::Error) -> Option<i32> {
+    match context(err) {
+        Some(ctx) => ctx.error.raw_os_error(),
+        None => err.raw_os_error(),
+    }
+}
+
+thread_local! {
+    static LAST_FAILED: RefCell<Option<(&'static str, CString, i32)>> =
+        const { RefCell::new(None) };
+}
+
+/// Attaches system call name and path to the error, see `Error`
+///
+/// Errors returned by methods of `Dir` are plain OS errors, so
+/// `io::Error::raw_os_error()` works on them. Call `context()` on the result
+/// right after the call to attach the name and the path of the system call
+/// that failed in this thread:
+///
+/// ```rust,no_run
+/// # use openat::{Dir, ErrorContext};
+/// # fn main() -> std::io::Result<()> {
+/// let dir = Dir::open("/etc")?;
+/// // the error displays as: openat "hostname": No such file or directory ...
+/// let file = dir.open_file("hostname").context()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The context is attached only if the error code matches the last failed
+/// system call, errors that have not come from a system call are returned
+/// unchanged.
+pub trait ErrorContext {
+    /// Attach context of the last failed system call to the error
+    fn context(self) -> Self;
+}
+
+impl<T> ErrorContext for io::Result<T> {
+    fn context(self) -> io::Result<T> {
+        self.map_err(add_context)
+    }
+}
+
+impl ErrorContext for io::Error {
+    fn context(self) -> io::Error {
+        add_context(self)
+    }
+}
+
+/// Remember the failed system call for `ErrorContext`
+pub fn record_failure(operation: &'static str, path: &CStr, code: i32) {
+    LAST_FAILED.with(|last| {
+        *last.borrow_mut() = Some((operation, path.to_owned(), code));
+    });
+}
+
+fn add_context(error: io::Error) -> io::Error {
+    let code = match error.raw_os_error() {
+        Some(code) => code,
+        None => return error,
+    };
+    let last = LAST_FAILED.with(|last| last.borrow_mut().take());
+    match last {
+        Some((operation, path, last_code)) if last_code == code => {
+            let path = Path::new(OsStr::from_bytes(path.to_bytes()));
+            with_path(error, operation, path)
+        }
+        _ => error,
+    }
+}
+
+/// Attach context to the error unconditionally
+///
+/// If the error already has a context, it's replaced.
+pub fn with_path(error: io::Error, operation: &'static str, path: &Path)
+    -> io::Error
+{
+    let error = if context(&error).is_some() {
+        // checked by context() above
+        error.into_inner().unwrap().downcast::<Error>().unwrap().error
+    } else {
+        error
+    };
+    io::Error::new(error.kind(), Error {
+        operation,
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use crate::Dir;
+    use super::{context, raw_os_error, ErrorContext};
+
+    #[test]
+    fn raw_error() {
+        let dir = Dir::open(".").unwrap();
+        let err = dir.open_file("nonexistent").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(raw_os_error(&err), Some(libc::ENOENT));
+    }
+
+    #[test]
+    fn kinds() {
+        use super::{error_kind, ErrorKind};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("sub/file", 0o644).unwrap();
+        dir.symlink("link", "sub").unwrap();
+        let kind = |res: io::Result<()>| error_kind(&res.unwrap_err());
+        assert_eq!(kind(dir.remove_dir("sub")),
+                   ErrorKind::DirectoryNotEmpty);
+        assert_eq!(kind(dir.create_dir("sub", 0o755)),
+                   ErrorKind::AlreadyExists);
+        assert_eq!(kind(dir.open_file("sub/file/x").map(|_| ())),
+                   ErrorKind::NotADirectory);
+        assert_eq!(kind(dir.open_file("link").map(|_| ())),
+                   ErrorKind::FilesystemLoop);
+        assert_eq!(kind(dir.remove_file("nonexistent")), ErrorKind::NotFound);
+        assert_eq!(kind(dir.remove_file("a\0b")), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn with_context() {
+        let dir = Dir::open(".").unwrap();
+        let err = dir.sub_dir("src/nonexistent").context().unwrap_err();
+        assert_eq!(raw_os_error(&err), Some(libc::ENOENT));
+        let ctx = context(&err).unwrap();
+        assert_eq!(ctx.operation(), "openat");
+        assert_eq!(ctx.path(), std::path::Path::new("src/nonexistent"));
+        assert!(err.to_string().starts_with("openat \"src/nonexistent\": "));
+    }
+
+    #[test]
+    fn without_context() {
+        let dir = Dir::open(".").unwrap();
+        let err = dir.sub_dir("src/nonexistent").unwrap_err();
+        assert!(context(&err).is_none());
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+        // not a system call error
+        let err = dir.sub_dir("a\0b").context().unwrap_err();
+        assert!(context(&err).is_none());
+    }
+}
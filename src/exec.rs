@@ -0,0 +1,143 @@
+use std::io;
+use std::ptr;
+use std::ffi::{CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::{Dir, AsPath};
+use crate::dir::to_cstr;
+use crate::syscall::syscall;
+
+
+impl Dir {
+    /// Execute a program located relative to this directory
+    ///
+    /// This is a wrapper around `execveat`. The `args` are passed as `argv`
+    /// (so the first one is conventionally a program name) and `env` items
+    /// are passed as `envp`, each of them should be in `KEY=value` form.
+    ///
+    /// Like `std::os::unix::process::CommandExt::exec` this method only
+    /// returns if an error happens, so the return value is always an error.
+    ///
+    /// Note: symlinks are not followed for the last component of the `path`,
+    /// like in other methods of this crate.
+    ///
+    /// Only supported on Linux.
+    pub fn exec_at<P, A, E>(&self, path: P, args: A, env: E) -> io::Error
+        where P: AsPath,
+              A: IntoIterator, A::Item: AsRef<OsStr>,
+              E: IntoIterator, E::Item: AsRef<OsStr>,
+    {
+        let path = match to_cstr(path) {
+            Ok(path) => path,
+            Err(e) => return e,
+        };
+        _execveat(self.0, path.as_ref(), args, env, libc::AT_SYMLINK_NOFOLLOW)
+    }
+}
+
+/// Execute a program referred by an already open file descriptor
+///
+/// This is a wrapper around `execveat` with `AT_EMPTY_PATH` flag, i.e. it
+/// is an equivalent of `fexecve`, but doesn't need `/proc` to be mounted.
+/// Arguments are the same as for `Dir::exec_at`.
+///
+/// Note: if the file is a script (starts with `#!`) and the descriptor is
+/// opened with `O_CLOEXEC` (which is the case for all files opened by this
+/// crate) the interpreter will be unable to open the script and the call
+/// fails with `ENOENT`.
+///
+/// Only supported on Linux.
+pub fn exec_fd<F, A, E>(file: &F, args: A, env: E) -> io::Error
+    where F: AsRawFd,
+          A: IntoIterator, A::Item: AsRef<OsStr>,
+          E: IntoIterator, E::Item: AsRef<OsStr>,
+{
+    let empty = unsafe { CStr::from_bytes_with_nul_unchecked(b"\0") };
+    _execveat(file.as_raw_fd(), empty, args, env, libc::AT_EMPTY_PATH)
+}
+
+fn _execveat<A, E>(fd: RawFd, path: &CStr, args: A, env: E,
+    flags: libc::c_int)
+    -> io::Error
+    where A: IntoIterator, A::Item: AsRef<OsStr>,
+          E: IntoIterator, E::Item: AsRef<OsStr>,
+{
+    let args = match to_cstr_vec(args) {
+        Ok(args) => args,
+        Err(e) => return e,
+    };
+    let env = match to_cstr_vec(env) {
+        Ok(env) => env,
+        Err(e) => return e,
+    };
+    let argv = to_ptr_vec(&args);
+    let envp = to_ptr_vec(&env);
+    let call = match syscall("execveat", fd, path) {
+        Ok(call) => call,
+        Err(e) => return e,
+    };
+    unsafe {
+        libc::syscall(libc::SYS_execveat,
+            fd, path.as_ptr(), argv.as_ptr(), envp.as_ptr(), flags);
+    }
+    call.error()
+}
+
+fn to_cstr_vec<I>(items: I) -> io::Result<Vec<CString>>
+    where I: IntoIterator, I::Item: AsRef<OsStr>,
+{
+    items.into_iter()
+    .map(|item| {
+        CString::new(item.as_ref().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput,
+                                    "nul byte in argument"))
+    })
+    .collect()
+}
+
+fn to_ptr_vec(items: &[CString]) -> Vec<*const libc::c_char> {
+    items.iter()
+        .map(|item| item.as_ptr())
+        .chain(Some(ptr::null()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+    use crate::Dir;
+
+    const NO_ENV: [&OsStr; 0] = [];
+
+    #[test]
+    fn exec_missing() {
+        let dir = Dir::open("src").unwrap();
+        let err = dir.exec_at("some-non-existent-file", &["x"], NO_ENV);
+        assert_eq!(crate::raw_os_error(&err), Some(libc::ENOENT));
+    }
+
+    #[test]
+    fn exec_not_executable() {
+        let dir = Dir::open("src").unwrap();
+        let err = dir.exec_at("lib.rs", &["lib.rs"], NO_ENV);
+        assert_eq!(crate::raw_os_error(&err), Some(libc::EACCES));
+    }
+
+    #[test]
+    fn exec_nul_in_argument() {
+        let dir = Dir::open("src").unwrap();
+        let err = dir.exec_at("lib.rs", &["a\0b"], NO_ENV);
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    #[cfg(feature="capability-mode")]
+    fn exec_capability_mode() {
+        let dir = Dir::open("src").unwrap();
+        for path in &["../non-existent-file", "/non-existent-file"] {
+            let err = dir.exec_at(*path, &["x"], NO_ENV);
+            assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+        }
+    }
+}
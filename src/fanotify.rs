@@ -0,0 +1,352 @@
+use std::io;
+use std::mem;
+use std::ffi::CStr;
+use std::fs::File;
+use std::ptr;
+use std::ops::{BitOr, BitOrAssign};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::PathBuf;
+
+use crate::Dir;
+use crate::dir::fd_path;
+
+
+const METADATA: usize = mem::size_of::<libc::fanotify_event_metadata>();
+const BUFFER: usize = 4096;
+
+/// Set of events for `Fanotify::mark`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FanotifyMask(u64);
+
+impl FanotifyMask {
+    /// File was read
+    pub const ACCESS: FanotifyMask = FanotifyMask(libc::FAN_ACCESS);
+    /// File was written to
+    pub const MODIFY: FanotifyMask = FanotifyMask(libc::FAN_MODIFY);
+    /// File opened for writing was closed
+    pub const CLOSE_WRITE: FanotifyMask = FanotifyMask(libc::FAN_CLOSE_WRITE);
+    /// File opened read-only was closed
+    pub const CLOSE_NOWRITE: FanotifyMask =
+        FanotifyMask(libc::FAN_CLOSE_NOWRITE);
+    /// File was opened
+    pub const OPEN: FanotifyMask = FanotifyMask(libc::FAN_OPEN);
+    /// File was opened for execution
+    pub const OPEN_EXEC: FanotifyMask = FanotifyMask(libc::FAN_OPEN_EXEC);
+    /// Permission to open a file is requested
+    pub const OPEN_PERM: FanotifyMask = FanotifyMask(libc::FAN_OPEN_PERM);
+    /// Permission to read a file is requested
+    pub const ACCESS_PERM: FanotifyMask = FanotifyMask(libc::FAN_ACCESS_PERM);
+    /// Permission to open a file for execution is requested
+    pub const OPEN_EXEC_PERM: FanotifyMask =
+        FanotifyMask(libc::FAN_OPEN_EXEC_PERM);
+    /// Report events for directories too
+    pub const ON_DIR: FanotifyMask = FanotifyMask(libc::FAN_ONDIR);
+    /// Report events for entries of the marked directory (only for
+    /// `MarkScope::Inode`)
+    pub const EVENT_ON_CHILD: FanotifyMask =
+        FanotifyMask(libc::FAN_EVENT_ON_CHILD);
+
+    /// No events at all
+    pub fn empty() -> FanotifyMask {
+        FanotifyMask(0)
+    }
+    /// Returns raw `FAN_*` bits
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+    /// Returns `true` if all events of `other` are set
+    pub fn contains(&self, other: FanotifyMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// Returns `true` if any of the permission events is set
+    pub fn is_permission(&self) -> bool {
+        self.0 & (libc::FAN_OPEN_PERM | libc::FAN_ACCESS_PERM
+                  | libc::FAN_OPEN_EXEC_PERM) != 0
+    }
+}
+
+impl BitOr for FanotifyMask {
+    type Output = FanotifyMask;
+    fn bitor(self, other: FanotifyMask) -> FanotifyMask {
+        FanotifyMask(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for FanotifyMask {
+    fn bitor_assign(&mut self, other: FanotifyMask) {
+        self.0 |= other.0;
+    }
+}
+
+/// What is monitored by a `Fanotify::mark`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MarkScope {
+    /// The directory itself (and its entries with `EVENT_ON_CHILD`)
+    Inode,
+    /// The whole mount containing the directory
+    Mount,
+    /// The whole filesystem containing the directory (Linux 4.20)
+    Filesystem,
+}
+
+/// A fanotify group, monitoring filesystem access
+///
+/// Unlike inotify, fanotify can monitor whole mounts and filesystems,
+/// reports the accessing process, and gives an open descriptor of the
+/// accessed file. With `permissions` enabled it can also allow or deny
+/// access (e.g. for antivirus-style scanning).
+///
+/// Requires `CAP_SYS_ADMIN`, `fanotify` feature, Linux only.
+///
+/// ```rust,no_run
+/// # use openat::{Dir, Fanotify, FanotifyMask, MarkScope};
+/// let dir = Dir::open("/srv").unwrap();
+/// let fan = Fanotify::new(true).unwrap();
+/// fan.mark(&dir, MarkScope::Mount, FanotifyMask::OPEN_PERM).unwrap();
+/// loop {
+///     for event in fan.read_events().unwrap() {
+///         println!("{:?} opened by {}", event.path(), event.pid());
+///         if event.is_permission() {
+///             fan.respond(&event, true).unwrap();
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Fanotify {
+    fd: RawFd,
+}
+
+/// An event read from `Fanotify`
+#[derive(Debug)]
+pub struct FanotifyEvent {
+    mask: FanotifyMask,
+    file: Option<File>,
+    pid: i32,
+}
+
+impl Fanotify {
+    /// Create a fanotify group
+    ///
+    /// If `permissions` is true, permission events (`*_PERM`) can be used
+    /// and must be answered with `respond()`.
+    pub fn new(permissions: bool) -> io::Result<Fanotify> {
+        let class = if permissions {
+            libc::FAN_CLASS_CONTENT
+        } else {
+            libc::FAN_CLASS_NOTIF
+        };
+        let fd = unsafe {
+            libc::fanotify_init(libc::FAN_CLOEXEC | class,
+                (libc::O_RDONLY | libc::O_CLOEXEC | libc::O_LARGEFILE)
+                as libc::c_uint)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Fanotify { fd })
+    }
+
+    /// Start monitoring events of `mask` in `scope` of the directory
+    ///
+    /// The directory is referred to by its descriptor (as `"."` relative
+    /// to it, since the kernel doesn't accept `O_PATH` descriptors
+    /// directly), so renaming the directory doesn't matter.
+    pub fn mark(&self, dir: &Dir, scope: MarkScope, mask: FanotifyMask)
+        -> io::Result<()>
+    {
+        let flags = libc::FAN_MARK_ADD | match scope {
+            MarkScope::Inode => libc::FAN_MARK_INODE,
+            MarkScope::Mount => libc::FAN_MARK_MOUNT,
+            MarkScope::Filesystem => libc::FAN_MARK_FILESYSTEM,
+        };
+        let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+        let res = unsafe {
+            libc::fanotify_mark(self.fd, flags, mask.0, dir.0, dot.as_ptr())
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Wait for events and return them
+    ///
+    /// Blocks until at least one event is available.
+    pub fn read_events(&self) -> io::Result<Vec<FanotifyEvent>> {
+        let mut buf = vec![0u64; BUFFER / 8];
+        let bytes = loop {
+            let res = unsafe {
+                libc::read(self.fd, buf.as_mut_ptr() as *mut _, BUFFER)
+            };
+            if res < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            break res as usize;
+        };
+        let buf = unsafe {
+            std::slice::from_raw_parts(buf.as_ptr() as *const u8, bytes)
+        };
+        let mut events = Vec::new();
+        let mut pos = 0;
+        while pos + METADATA <= bytes {
+            let raw = unsafe {
+                ptr::read_unaligned(buf[pos..].as_ptr()
+                    as *const libc::fanotify_event_metadata)
+            };
+            if raw.event_len < METADATA as u32 {
+                break;
+            }
+            pos += raw.event_len as usize;
+            if raw.vers != libc::FANOTIFY_METADATA_VERSION {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    "unsupported fanotify metadata version"));
+            }
+            let file = if raw.fd >= 0 {
+                Some(unsafe { File::from_raw_fd(raw.fd) })
+            } else {
+                None
+            };
+            events.push(FanotifyEvent {
+                mask: FanotifyMask(raw.mask),
+                file,
+                pid: raw.pid,
+            });
+        }
+        Ok(events)
+    }
+
+    /// Allow or deny access for a permission event
+    ///
+    /// Every permission event must be answered, otherwise the process
+    /// accessing the file hangs until this group is closed.
+    pub fn respond(&self, event: &FanotifyEvent, allow: bool)
+        -> io::Result<()>
+    {
+        let file = event.file.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           "event has no file descriptor")
+        })?;
+        let response = libc::fanotify_response {
+            fd: file.as_raw_fd(),
+            response: if allow { libc::FAN_ALLOW } else { libc::FAN_DENY },
+        };
+        let size = mem::size_of::<libc::fanotify_response>();
+        let res = unsafe {
+            libc::write(self.fd,
+                &response as *const libc::fanotify_response as *const _, size)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl FanotifyEvent {
+    /// Returns the events that happened
+    pub fn mask(&self) -> FanotifyMask {
+        self.mask
+    }
+    /// Returns `true` if this event must be answered with `respond()`
+    pub fn is_permission(&self) -> bool {
+        self.mask.is_permission()
+    }
+    /// Returns read-only descriptor of the accessed file
+    ///
+    /// `None` if the queue overflowed.
+    pub fn file(&self) -> Option<&File> {
+        self.file.as_ref()
+    }
+    /// Returns pid of the process that accessed the file
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+    /// Returns the current path of the accessed file (if possible)
+    ///
+    /// Uses `/proc/self/fd`, see `Dir::recover_path`.
+    pub fn path(&self) -> io::Result<PathBuf> {
+        let file = self.file.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound,
+                           "event has no file descriptor")
+        })?;
+        std::fs::read_link(fd_path(file.as_raw_fd()))
+    }
+}
+
+impl AsRawFd for Fanotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Fanotify {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use crate::Dir;
+    use super::{Fanotify, FanotifyMask, MarkScope};
+
+    #[test]
+    fn mask() {
+        let mask = FanotifyMask::OPEN | FanotifyMask::CLOSE_WRITE;
+        assert!(mask.contains(FanotifyMask::OPEN));
+        assert!(!mask.contains(FanotifyMask::MODIFY));
+        assert!(!mask.is_permission());
+        assert!((mask | FanotifyMask::OPEN_PERM).is_permission());
+    }
+
+    #[test]
+    fn events() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let fan = match Fanotify::new(false) {
+            Ok(fan) => fan,
+            // requires CAP_SYS_ADMIN
+            Err(_) => return,
+        };
+        fan.mark(&dir, MarkScope::Inode,
+                 FanotifyMask::CLOSE_WRITE | FanotifyMask::EVENT_ON_CHILD)
+            .unwrap();
+        dir.write_file("file", 0o644).unwrap().write_all(b"x").unwrap();
+        let events = fan.read_events().unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].mask().contains(FanotifyMask::CLOSE_WRITE));
+        assert_eq!(events[0].pid(), std::process::id() as i32);
+        assert_eq!(events[0].path().unwrap(), tmp.path().join("file"));
+    }
+
+    #[test]
+    fn deny() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let fan = match Fanotify::new(true) {
+            Ok(fan) => fan,
+            // requires CAP_SYS_ADMIN
+            Err(_) => return,
+        };
+        fan.mark(&dir, MarkScope::Inode,
+                 FanotifyMask::OPEN_PERM | FanotifyMask::EVENT_ON_CHILD)
+            .unwrap();
+        let path = tmp.path().join("file");
+        let opener = std::thread::spawn(move || std::fs::File::open(path));
+        let events = fan.read_events().unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].is_permission());
+        fan.respond(&events[0], false).unwrap();
+        let err = opener.join().unwrap().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    }
+}
@@ -0,0 +1,188 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+
+use crate::{Dir, AsPath};
+use crate::dir::to_cstr;
+
+// _IOWR('f', 11, struct fiemap), not exposed by libc crate
+const FS_IOC_FIEMAP: u32 = 0xC020_660B;
+const BATCH: usize = 64;
+
+const EXTENT_LAST: u32 = 0x0001;
+const EXTENT_UNKNOWN: u32 = 0x0002;
+const EXTENT_DELALLOC: u32 = 0x0004;
+const EXTENT_ENCODED: u32 = 0x0008;
+const EXTENT_DATA_INLINE: u32 = 0x0200;
+const EXTENT_UNWRITTEN: u32 = 0x0800;
+const EXTENT_SHARED: u32 = 0x2000;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawExtent {
+    logical: u64,
+    physical: u64,
+    length: u64,
+    reserved64: [u64; 2],
+    flags: u32,
+    reserved: [u32; 3],
+}
+
+#[repr(C)]
+struct Request {
+    start: u64,
+    length: u64,
+    flags: u32,
+    mapped_extents: u32,
+    extent_count: u32,
+    reserved: u32,
+    extents: [RawExtent; BATCH],
+}
+
+/// A physical extent of a file, as returned by `extents()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Extent {
+    logical: u64,
+    physical: u64,
+    length: u64,
+    flags: u32,
+}
+
+impl Extent {
+    /// Returns offset of the extent in the file
+    pub fn logical(&self) -> u64 {
+        self.logical
+    }
+    /// Returns offset of the extent on the device
+    ///
+    /// Meaningless if `is_unknown()`.
+    pub fn physical(&self) -> u64 {
+        self.physical
+    }
+    /// Returns length of the extent in bytes
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+    /// Returns raw `FIEMAP_EXTENT_*` flags
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+    /// Returns `true` if location of the extent is not known yet
+    pub fn is_unknown(&self) -> bool {
+        self.flags & EXTENT_UNKNOWN != 0
+    }
+    /// Returns `true` if the extent is not allocated on disk yet (data is
+    /// in the page cache), implies `is_unknown()`
+    pub fn is_delalloc(&self) -> bool {
+        self.flags & EXTENT_DELALLOC != 0
+    }
+    /// Returns `true` if data is compressed or otherwise encoded, so it
+    /// can't be read directly from the device
+    pub fn is_encoded(&self) -> bool {
+        self.flags & EXTENT_ENCODED != 0
+    }
+    /// Returns `true` if data is stored together with metadata
+    pub fn is_inline(&self) -> bool {
+        self.flags & EXTENT_DATA_INLINE != 0
+    }
+    /// Returns `true` if space is allocated but not written (reads as
+    /// zeros), e.g. after `fallocate`
+    pub fn is_unwritten(&self) -> bool {
+        self.flags & EXTENT_UNWRITTEN != 0
+    }
+    /// Returns `true` if the extent is shared with other files (reflinks
+    /// or snapshots)
+    pub fn is_shared(&self) -> bool {
+        self.flags & EXTENT_SHARED != 0
+    }
+}
+
+impl Dir {
+    /// Returns physical extents of a file in this directory
+    ///
+    /// The file is opened read-only for the call. See `extents` for more
+    /// info.
+    ///
+    /// Requires `fiemap` feature, only supported on Linux.
+    pub fn extents<P: AsPath>(&self, path: P) -> io::Result<Vec<Extent>> {
+        let file = self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_RDONLY, 0)?;
+        extents(&file)
+    }
+}
+
+/// Returns physical extents of an open file (`FS_IOC_FIEMAP`)
+///
+/// Holes are not reported. Extents which are not written to disk yet are
+/// returned with `is_delalloc()` flag, call `sync_data()` on the file
+/// first if physical location of all the data is needed. Copy tools
+/// usually look at `is_shared()` (to decide whether to reflink) and at
+/// the number of extents (to decide whether to defragment).
+///
+/// Returns `EOPNOTSUPP` error if the filesystem doesn't support the call
+/// (e.g. tmpfs, NFS).
+///
+/// Requires `fiemap` feature, only supported on Linux.
+pub fn extents<F: AsRawFd>(file: &F) -> io::Result<Vec<Extent>> {
+    let mut result = Vec::new();
+    let mut request: Request = unsafe { mem::zeroed() };
+    let mut start = 0;
+    loop {
+        request.start = start;
+        request.length = u64::MAX - start;
+        request.flags = 0;
+        request.mapped_extents = 0;
+        request.extent_count = BATCH as u32;
+        let res = unsafe {
+            libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut request)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mapped = &request.extents[..request.mapped_extents as usize];
+        let last = match mapped.last() {
+            Some(last) => *last,
+            None => return Ok(result),
+        };
+        result.extend(mapped.iter().map(|e| Extent {
+            logical: e.logical,
+            physical: e.physical,
+            length: e.length,
+            flags: e.flags,
+        }));
+        if last.flags & EXTENT_LAST != 0 {
+            return Ok(result);
+        }
+        start = last.logical + last.length;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use crate::Dir;
+
+    #[test]
+    fn extents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("empty", 0o644).unwrap();
+        match dir.extents("empty") {
+            Ok(extents) => assert_eq!(extents, []),
+            // filesystem doesn't support fiemap
+            Err(_) => return,
+        }
+        let mut file = dir.write_file("file", 0o644).unwrap();
+        for _ in 0..100 {
+            file.write_all(&[1u8; 4096]).unwrap();
+        }
+        file.sync_data().unwrap();
+        let extents = super::extents(&file).unwrap();
+        assert!(!extents.is_empty());
+        assert_eq!(extents[0].logical(), 0);
+        assert!(!extents[0].is_delalloc());
+        let total = extents.iter().map(|e| e.length()).sum::<u64>();
+        assert!(total >= 409600);
+        assert_eq!(extents.last().unwrap().flags() & 1, 1);
+    }
+}
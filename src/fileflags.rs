@@ -0,0 +1,143 @@
+use std::io;
+use std::ffi::CStr;
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::{Dir, AsPath, Metadata};
+use crate::dir::to_cstr;
+use crate::syscall::syscall;
+
+
+/// BSD file flags (`st_flags`), as changed by `chflags`
+///
+/// Flags starting with `USER_` can be changed by the owner of the file,
+/// `SYSTEM_` flags can only be changed by root (and, depending on the
+/// securelevel, only in single-user mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileFlags(u32);
+
+impl FileFlags {
+    /// Do not dump the file (honored by `dump` and many backup tools)
+    pub const NODUMP: FileFlags = FileFlags(libc::UF_NODUMP as u32);
+    /// The file may not be changed, renamed or removed
+    pub const USER_IMMUTABLE: FileFlags =
+        FileFlags(libc::UF_IMMUTABLE as u32);
+    /// The file may only be appended to
+    pub const USER_APPEND: FileFlags = FileFlags(libc::UF_APPEND as u32);
+    /// The entry is hidden from GUI file browsers
+    pub const HIDDEN: FileFlags = FileFlags(libc::UF_HIDDEN as u32);
+    /// The file is archived
+    pub const ARCHIVED: FileFlags = FileFlags(libc::SF_ARCHIVED as u32);
+    /// Same as `USER_IMMUTABLE`, but can only be cleared by root
+    pub const SYSTEM_IMMUTABLE: FileFlags =
+        FileFlags(libc::SF_IMMUTABLE as u32);
+    /// Same as `USER_APPEND`, but can only be cleared by root
+    pub const SYSTEM_APPEND: FileFlags = FileFlags(libc::SF_APPEND as u32);
+
+    /// No flags set
+    pub fn empty() -> FileFlags {
+        FileFlags(0)
+    }
+    /// Creates flags from raw `st_flags` bits
+    ///
+    /// Bits unknown to this crate are kept as is.
+    pub fn from_raw(bits: u32) -> FileFlags {
+        FileFlags(bits)
+    }
+    /// Returns raw `st_flags` bits
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+    /// Returns `true` if all flags of `other` are set
+    pub fn contains(&self, other: FileFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for FileFlags {
+    type Output = FileFlags;
+    fn bitor(self, other: FileFlags) -> FileFlags {
+        FileFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for FileFlags {
+    fn bitor_assign(&mut self, other: FileFlags) {
+        self.0 |= other.0;
+    }
+}
+
+impl Metadata {
+    /// Returns file flags (`st_flags`)
+    ///
+    /// Only available on macOS and FreeBSD.
+    pub fn flags(&self) -> FileFlags {
+        FileFlags(self.stat().st_flags as u32)
+    }
+}
+
+impl Dir {
+    /// Set file flags of the entry at `path`, replacing existing flags
+    ///
+    /// Symlinks are not followed: flags of the link itself are changed. To
+    /// add a flag, read the existing ones via `metadata(path)?.flags()`
+    /// first.
+    ///
+    /// Only available on macOS and FreeBSD.
+    pub fn set_flags<P: AsPath>(&self, path: P, flags: FileFlags)
+        -> io::Result<()>
+    {
+        self._set_flags(to_cstr(path)?.as_ref(), flags)
+    }
+
+    #[cfg(target_os="freebsd")]
+    fn _set_flags(&self, path: &CStr, flags: FileFlags) -> io::Result<()> {
+        let call = syscall("chflagsat", self.0, path)?;
+        let res = unsafe {
+            libc::chflagsat(self.0, path.as_ptr(),
+                flags.0 as libc::c_ulong, libc::AT_SYMLINK_NOFOLLOW)
+        };
+        if res < 0 {
+            return Err(call.error());
+        }
+        Ok(())
+    }
+
+    // there is no chflagsat on macOS, so the entry is opened (without
+    // requiring any access to the file) and fchflags is used instead
+    #[cfg(target_os="macos")]
+    fn _set_flags(&self, path: &CStr, flags: FileFlags) -> io::Result<()> {
+        let call = syscall("fchflags", self.0, path)?;
+        unsafe {
+            let fd = libc::openat(self.0, path.as_ptr(),
+                libc::O_EVTONLY|libc::O_SYMLINK|libc::O_NONBLOCK|
+                libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(call.error());
+            }
+            let res = libc::fchflags(fd, flags.0 as libc::c_uint);
+            let result = if res < 0 { Err(call.error()) } else { Ok(()) };
+            libc::close(fd);
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Dir, FileFlags};
+
+    #[test]
+    fn nodump() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        assert!(!dir.metadata("file").unwrap().flags()
+            .contains(FileFlags::NODUMP));
+        dir.set_flags("file", FileFlags::NODUMP | FileFlags::HIDDEN)
+            .unwrap();
+        let flags = dir.metadata("file").unwrap().flags();
+        assert!(flags.contains(FileFlags::NODUMP | FileFlags::HIDDEN));
+        dir.set_flags("file", FileFlags::empty()).unwrap();
+        assert_eq!(dir.metadata("file").unwrap().flags(), FileFlags::empty());
+    }
+}
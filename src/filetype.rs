@@ -1,11 +1,13 @@
 use std::fs::Metadata;
+use std::os::unix::fs::FileTypeExt;
 
 /// This is a simplified file type enum that is easy to match
 ///
 /// It doesn't represent all the options, because that enum needs to extensible
 /// but most application do not actually need that power, so we provide
 /// this simplified enum that works for many appalications.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
 pub enum SimpleType {
     /// Entry is a symlink
     Symlink,
@@ -13,7 +15,15 @@ pub enum SimpleType {
     Dir,
     /// Entry is a regular file
     File,
-    /// Entry is neither a symlink, directory nor a regular file
+    /// Entry is a unix domain socket
+    Socket,
+    /// Entry is a named pipe
+    Fifo,
+    /// Entry is a character device
+    CharDevice,
+    /// Entry is a block device
+    BlockDevice,
+    /// Entry is of some other (system-specific) type, e.g. a whiteout
     Other,
 }
 
@@ -26,8 +36,46 @@ impl SimpleType {
             SimpleType::Dir
         } else if stat.is_file() {
             SimpleType::File
+        } else if stat.file_type().is_socket() {
+            SimpleType::Socket
+        } else if stat.file_type().is_fifo() {
+            SimpleType::Fifo
+        } else if stat.file_type().is_char_device() {
+            SimpleType::CharDevice
+        } else if stat.file_type().is_block_device() {
+            SimpleType::BlockDevice
         } else {
             SimpleType::Other
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+    use std::os::unix::net::UnixListener;
+    use crate::{Dir, SimpleType};
+
+    #[test]
+    fn special_types() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let fifo = CString::new(tmp.path().join("fifo").to_str().unwrap())
+            .unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+        let _sock = UnixListener::bind(tmp.path().join("sock")).unwrap();
+        let mut entries = dir.list_dir(".").unwrap().resolve_types(true)
+            .collect_entries().unwrap();
+        entries.sort();
+        let types = entries.iter().map(|e| e.simple_type().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(types, [SimpleType::Fifo, SimpleType::Socket]);
+        assert_eq!(dir.metadata("fifo").unwrap().simple_type(),
+                   SimpleType::Fifo);
+        let dev = Dir::open("/dev").unwrap();
+        assert_eq!(dev.metadata("null").unwrap().simple_type(),
+                   SimpleType::CharDevice);
+        let std = std::fs::symlink_metadata("/dev/null").unwrap();
+        assert_eq!(SimpleType::extract(&std), SimpleType::CharDevice);
+    }
+}
@@ -0,0 +1,227 @@
+use std::io;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, Component};
+
+use crate::{Entry, DirIter, SimpleType, Walk, WalkEntry};
+
+
+/// Filtering adapter over `DirIter` or `Walk`
+///
+/// Created by `files_only()`, `dirs_only()`, `skip_hidden()` or
+/// `with_extension()` methods of either iterator, which can also be
+/// chained on the adapter itself:
+///
+/// ```rust,no_run
+/// # use openat::Dir;
+/// # let dir = Dir::open(".").unwrap();
+/// for entry in dir.walk("src").unwrap().files_only().with_extension("rs") {
+///     println!("{:?}", entry.unwrap().path());
+/// }
+/// ```
+///
+/// File type is taken from the directory entry when the filesystem
+/// provides it, and the entry is stat'ed only if it doesn't. Errors are
+/// passed through. For `Walk` filtering only affects entries returned,
+/// directories that don't match are still descended into.
+#[derive(Debug)]
+pub struct Filter<I> {
+    inner: I,
+    file_type: Option<SimpleType>,
+    skip_hidden: bool,
+    extension: Option<OsString>,
+}
+
+mod private {
+    use std::io;
+    use std::ffi::OsStr;
+    use crate::SimpleType;
+
+    pub trait Source: Iterator<Item=io::Result<Self::Entry>> {
+        type Entry;
+        fn file_name(entry: &Self::Entry) -> &OsStr;
+        fn is_hidden(entry: &Self::Entry) -> bool;
+        fn file_type(&self, entry: &Self::Entry) -> io::Result<SimpleType>;
+    }
+}
+
+use self::private::Source;
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.as_bytes().first() == Some(&b'.')
+}
+
+impl Source for DirIter {
+    type Entry = Entry;
+    fn file_name(entry: &Entry) -> &OsStr {
+        entry.file_name()
+    }
+    fn is_hidden(entry: &Entry) -> bool {
+        is_hidden(entry.file_name())
+    }
+    fn file_type(&self, entry: &Entry) -> io::Result<SimpleType> {
+        self.entry_type(entry)
+    }
+}
+
+impl Source for Walk {
+    type Entry = WalkEntry;
+    fn file_name(entry: &WalkEntry) -> &OsStr {
+        entry.file_name()
+    }
+    // entries within hidden directories are hidden too
+    fn is_hidden(entry: &WalkEntry) -> bool {
+        entry.path().components().any(|c| match c {
+            Component::Normal(name) => is_hidden(name),
+            _ => false,
+        })
+    }
+    fn file_type(&self, entry: &WalkEntry) -> io::Result<SimpleType> {
+        match entry.simple_type() {
+            Some(typ) => Ok(typ),
+            None => Ok(entry.metadata()?.simple_type()),
+        }
+    }
+}
+
+impl<I: Source> Filter<I> {
+    fn new(inner: I) -> Filter<I> {
+        Filter { inner, file_type: None, skip_hidden: false, extension: None }
+    }
+    /// Only return regular files (symlinks are not followed)
+    pub fn files_only(mut self) -> Filter<I> {
+        self.file_type = Some(SimpleType::File);
+        self
+    }
+    /// Only return directories (symlinks are not followed)
+    pub fn dirs_only(mut self) -> Filter<I> {
+        self.file_type = Some(SimpleType::Dir);
+        self
+    }
+    /// Skip entries whose name starts with a dot
+    ///
+    /// For `Walk` this also skips everything inside hidden directories.
+    pub fn skip_hidden(mut self) -> Filter<I> {
+        self.skip_hidden = true;
+        self
+    }
+    /// Only return entries with the specified extension (without a dot)
+    pub fn with_extension<S: AsRef<OsStr>>(mut self, ext: S) -> Filter<I> {
+        self.extension = Some(ext.as_ref().to_owned());
+        self
+    }
+    fn matches(&self, entry: &I::Entry) -> io::Result<bool> {
+        if self.skip_hidden && I::is_hidden(entry) {
+            return Ok(false);
+        }
+        if let Some(ref ext) = self.extension {
+            let name = Path::new(I::file_name(entry));
+            if name.extension() != Some(ext.as_os_str()) {
+                return Ok(false);
+            }
+        }
+        if let Some(typ) = self.file_type {
+            return Ok(self.inner.file_type(entry)? == typ);
+        }
+        Ok(true)
+    }
+}
+
+impl<I: Source> Iterator for Filter<I> {
+    type Item = io::Result<I::Entry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match self.inner.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.matches(&entry) {
+                Ok(true) => return Some(Ok(entry)),
+                Ok(false) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+macro_rules! filter_methods {
+    ($typ:ty) => {
+        impl $typ {
+            /// Only return regular files, see `Filter`
+            pub fn files_only(self) -> Filter<$typ> {
+                Filter::new(self).files_only()
+            }
+            /// Only return directories, see `Filter`
+            pub fn dirs_only(self) -> Filter<$typ> {
+                Filter::new(self).dirs_only()
+            }
+            /// Skip entries whose name starts with a dot, see `Filter`
+            pub fn skip_hidden(self) -> Filter<$typ> {
+                Filter::new(self).skip_hidden()
+            }
+            /// Only return entries with the extension, see `Filter`
+            pub fn with_extension<S: AsRef<OsStr>>(self, ext: S)
+                -> Filter<$typ>
+            {
+                Filter::new(self).with_extension(ext)
+            }
+        }
+    }
+}
+
+filter_methods!(DirIter);
+filter_methods!(Walk);
+
+#[cfg(test)]
+mod test {
+    use crate::Dir;
+
+    fn tree() -> (tempfile::TempDir, Dir) {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("src", 0o755).unwrap();
+        dir.create_dir(".git", 0o755).unwrap();
+        dir.write_file(".git/config.rs", 0o644).unwrap();
+        dir.write_file("src/lib.rs", 0o644).unwrap();
+        dir.write_file("src/.hidden.rs", 0o644).unwrap();
+        dir.write_file("README.md", 0o644).unwrap();
+        dir.symlink("link.rs", "src/lib.rs").unwrap();
+        (tmp, dir)
+    }
+
+    fn names<I, E>(iter: I, f: fn(&E) -> String) -> Vec<String>
+        where I: Iterator<Item=std::io::Result<E>>
+    {
+        let mut names = iter.map(|e| f(&e.unwrap())).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn dir_iter() {
+        let (_tmp, dir) = tree();
+        let name = |e: &crate::Entry| e.file_name().to_str().unwrap().into();
+        assert_eq!(names(dir.list_dir(".").unwrap().files_only(), name),
+                   ["README.md"]);
+        assert_eq!(names(dir.list_dir(".").unwrap().dirs_only(), name),
+                   [".git", "src"]);
+        assert_eq!(names(dir.list_dir(".").unwrap().skip_hidden()
+                         .dirs_only(), name),
+                   ["src"]);
+        assert_eq!(names(dir.list_dir(".").unwrap().with_extension("rs"),
+                         name),
+                   ["link.rs"]);
+    }
+
+    #[test]
+    fn walk() {
+        let (_tmp, dir) = tree();
+        let path = |e: &crate::WalkEntry| e.path().to_str().unwrap().into();
+        assert_eq!(names(dir.walk(".").unwrap().files_only()
+                         .with_extension("rs"), path),
+                   [".git/config.rs", "src/.hidden.rs", "src/lib.rs"]);
+        assert_eq!(names(dir.walk(".").unwrap().skip_hidden()
+                         .with_extension("rs"), path),
+                   ["link.rs", "src/lib.rs"]);
+    }
+}
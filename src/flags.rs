@@ -0,0 +1,404 @@
+use std::io;
+use std::fs::File;
+use std::ffi::CStr;
+
+use crate::{Dir, AsPath, DirIter, Metadata, IntoMode};
+use crate::advice::{Advice, advise};
+use crate::dir::to_cstr;
+use crate::list::open_dir;
+#[cfg(target_os="linux")]
+use crate::error::raw_os_error;
+
+
+#[cfg(any(target_os="linux", target_os="android",
+          target_os="macos", target_os="ios",
+          target_os="freebsd", target_os="netbsd", target_os="openbsd"))]
+const O_DSYNC: libc::c_int = libc::O_DSYNC;
+#[cfg(not(any(target_os="linux", target_os="android",
+              target_os="macos", target_os="ios",
+              target_os="freebsd", target_os="netbsd", target_os="openbsd")))]
+const O_DSYNC: libc::c_int = libc::O_SYNC;
+
+#[cfg(target_os="freebsd")]
+const O_RESOLVE_BENEATH: libc::c_int = libc::O_RESOLVE_BENEATH;
+#[cfg(target_os="freebsd")]
+const AT_RESOLVE_BENEATH: libc::c_int = libc::AT_RESOLVE_BENEATH;
+#[cfg(not(target_os="freebsd"))]
+const O_RESOLVE_BENEATH: libc::c_int = 0;
+#[cfg(not(target_os="freebsd"))]
+const AT_RESOLVE_BENEATH: libc::c_int = 0;
+
+/// Default permissions of files created using `DirMethodFlags`
+///
+/// Read and write for the owner and read-only for everyone else, the
+/// process umask is applied on top of that.
+pub const DEFAULT_FILE_MODE: libc::mode_t = 0o644;
+
+/// Methods of `Dir` with customized flags, created by `Dir::with()`
+#[derive(Debug, Clone, Copy)]
+pub struct DirMethodFlags<'a> {
+    object: &'a Dir,
+    flags: libc::c_int,
+    // `O_*` flags set by builder methods, not valid for `fstatat`/`unlinkat`
+    open_only: libc::c_int,
+    advice: Option<Advice>,
+    noatime: bool,
+    beneath: bool,
+    mode: libc::mode_t,
+}
+
+impl Dir {
+    /// Customize flags passed to the underlying system calls
+    ///
+    /// The `flags` are added to ones that are used by the respective methods
+    /// of `Dir` (including `O_NOFOLLOW` and `O_CLOEXEC`), so you can't remove
+    /// any flags using this, only add.
+    ///
+    /// Methods that don't open anything (`metadata`, `remove_file` and
+    /// `remove_dir`) pass `flags` as `AT_*` flags to `fstatat` and `unlinkat`
+    /// respectively, so use separate `with()` calls for them. Flags added by
+    /// `direct()`, `sync()` and `dsync()` are only used for opening files.
+    /// For example:
+    ///
+    /// ```rust,no_run
+    /// # use openat::Dir;
+    /// # let dir = Dir::open(".").unwrap();
+    /// let file = dir.with(libc::O_NONBLOCK).open_file("fifo").unwrap();
+    /// ```
+    pub fn with(&self, flags: libc::c_int) -> DirMethodFlags<'_> {
+        DirMethodFlags {
+            object: self,
+            flags,
+            open_only: 0,
+            advice: None,
+            noatime: false,
+            beneath: false,
+            mode: DEFAULT_FILE_MODE,
+        }
+    }
+}
+
+impl<'a> DirMethodFlags<'a> {
+    /// Apply `posix_fadvise` to every opened file
+    ///
+    /// The advice is applied to the whole file, see `advise()` for more info.
+    pub fn advise(mut self, advice: Advice) -> Self {
+        self.advice = Some(advice);
+        self
+    }
+
+    /// Set permissions for newly created files
+    ///
+    /// Used by `write_file`, `append_file`, `update_file` and `new_file`.
+    /// The default is `DEFAULT_FILE_MODE` (`0o644`). The process umask is
+    /// applied on top of this.
+    pub fn mode<M: IntoMode>(mut self, mode: M) -> Self {
+        self.mode = mode.into_mode();
+        self
+    }
+
+    /// Open files with `O_DIRECT`, bypassing the page cache
+    ///
+    /// All reads and writes on such files must use buffers, offsets and
+    /// lengths aligned to the logical block size, use `AlignedBuffer` for
+    /// that. Not all filesystems support direct I/O, the open fails with
+    /// `EINVAL` in that case.
+    ///
+    /// Supported on Linux, Android and FreeBSD.
+    #[cfg(any(target_os="linux", target_os="android", target_os="freebsd"))]
+    pub fn direct(mut self) -> Self {
+        self.open_only |= libc::O_DIRECT;
+        self
+    }
+
+    /// Open files with `O_SYNC`, so every write is durable on return
+    ///
+    /// Each `write` waits until both data and metadata needed to retrieve
+    /// the data are written to the disk, like if `sync_all()` was called
+    /// after each write. Only makes sense with `write_file`, `append_file`
+    /// and similar methods.
+    pub fn sync(mut self) -> Self {
+        self.open_only |= libc::O_SYNC;
+        self
+    }
+
+    /// Open files with `O_DSYNC`, so every write of data is durable on return
+    ///
+    /// Like `sync()` but metadata that is not needed to read the data back
+    /// (such as modification time) may be not flushed, like if `sync_data()`
+    /// was called after each write. This is usually what write-ahead logs
+    /// need.
+    ///
+    /// On systems that don't have `O_DSYNC`, `O_SYNC` is used instead
+    /// (it's a strictly stronger guarantee).
+    pub fn dsync(mut self) -> Self {
+        self.open_only |= O_DSYNC;
+        self
+    }
+
+    /// Open files with `O_NOATIME`, so reading doesn't update access time
+    ///
+    /// The flag is only permitted if the caller owns the file (or has
+    /// `CAP_FOWNER`), so if open fails with `EPERM` it's retried without the
+    /// flag. This makes it usable for backup tools which can't know the
+    /// owner of the file up front.
+    ///
+    /// Only supported on Linux, on other systems this option is a no-op.
+    pub fn noatime(mut self) -> Self {
+        self.noatime = true;
+        self
+    }
+
+    /// Fail if the path resolution escapes the directory
+    ///
+    /// Adds `O_RESOLVE_BENEATH` to all opens, and `AT_RESOLVE_BENEATH` to
+    /// `metadata`, `remove_file` and `remove_dir`. Absolute paths, `..` and
+    /// symlinks that point outside of the directory fail with
+    /// `ENOTCAPABLE`. See also `Dir::open_beneath` which is available on
+    /// more systems.
+    ///
+    /// Supported on FreeBSD 13 and newer.
+    #[cfg(target_os="freebsd")]
+    pub fn resolve_beneath(mut self) -> Self {
+        self.beneath = true;
+        self
+    }
+
+    fn open_flags(&self) -> libc::c_int {
+        let flags = self.flags | self.open_only;
+        if self.beneath {
+            flags | O_RESOLVE_BENEATH
+        } else {
+            flags
+        }
+    }
+
+    fn at_flags(&self) -> libc::c_int {
+        if self.beneath {
+            self.flags | AT_RESOLVE_BENEATH
+        } else {
+            self.flags
+        }
+    }
+
+    /// Open file for reading in this directory
+    ///
+    /// See `Dir::open_file` for more info.
+    pub fn open_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self._open_file(path, libc::O_RDONLY, 0)
+    }
+
+    /// Open file for writing, create if necessary, truncate on open
+    ///
+    /// See `Dir::write_file` for more info.
+    pub fn write_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self._open_file(path,
+            libc::O_CREAT|libc::O_WRONLY|libc::O_TRUNC,
+            self.mode)
+    }
+
+    /// Open file for append, create if necessary
+    ///
+    /// See `Dir::append_file` for more info.
+    pub fn append_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self._open_file(path,
+            libc::O_CREAT|libc::O_WRONLY|libc::O_APPEND,
+            self.mode)
+    }
+
+    /// Open file for reading and writing without truncation, create if needed
+    ///
+    /// See `Dir::update_file` for more info.
+    pub fn update_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self._open_file(path, libc::O_CREAT|libc::O_RDWR, self.mode)
+    }
+
+    /// Create file if not exists, fail if exists
+    ///
+    /// See `Dir::new_file` for more info.
+    pub fn new_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self._open_file(path,
+            libc::O_CREAT|libc::O_EXCL|libc::O_WRONLY,
+            self.mode)
+    }
+
+    /// List subdirectory of this dir
+    ///
+    /// See `Dir::list_dir` for more info.
+    pub fn list_dir<P: AsPath>(&self, path: P) -> io::Result<DirIter> {
+        open_dir(self.object, to_cstr(path)?.as_ref(), self.open_flags())
+    }
+
+    /// Returns metadata of the file (`flags` are passed to `fstatat`)
+    ///
+    /// See `Dir::metadata` for more info.
+    pub fn metadata<P: AsPath>(&self, path: P) -> io::Result<Metadata> {
+        self.object._stat(to_cstr(path)?.as_ref(),
+            libc::AT_SYMLINK_NOFOLLOW|self.at_flags())
+    }
+
+    /// Remove a file (`flags` are passed to `unlinkat`)
+    ///
+    /// See `Dir::remove_file` for more info.
+    pub fn remove_file<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self.object._unlink(to_cstr(path)?.as_ref(), self.at_flags())
+    }
+
+    /// Remove an empty directory (`flags` are passed to `unlinkat`)
+    ///
+    /// See `Dir::remove_dir` for more info.
+    pub fn remove_dir<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self.object._unlink(to_cstr(path)?.as_ref(),
+            libc::AT_REMOVEDIR|self.at_flags())
+    }
+
+    /// Open subdirectory
+    ///
+    /// See `Dir::sub_dir` for more info.
+    pub fn sub_dir<P: AsPath>(&self, path: P) -> io::Result<Dir> {
+        self.object._sub_dir(to_cstr(path)?.as_ref(), self.open_flags())
+    }
+
+    fn _open_file<P: AsPath>(&self, path: P, flags: libc::c_int,
+        mode: libc::mode_t)
+        -> io::Result<File>
+    {
+        let path = to_cstr(path)?;
+        let file = self.__open_file(path.as_ref(),
+            flags|self.open_flags(), mode)?;
+        if let Some(advice) = self.advice {
+            advise(&file, 0, 0, advice)?;
+        }
+        Ok(file)
+    }
+
+    #[cfg(target_os="linux")]
+    fn __open_file(&self, path: &CStr, flags: libc::c_int,
+        mode: libc::mode_t)
+        -> io::Result<File>
+    {
+        if self.noatime {
+            match self.object._open_file(path, flags|libc::O_NOATIME, mode) {
+                Err(ref e) if raw_os_error(e) == Some(libc::EPERM) => {}
+                res => return res,
+            }
+        }
+        self.object._open_file(path, flags, mode)
+    }
+
+    #[cfg(not(target_os="linux"))]
+    fn __open_file(&self, path: &CStr, flags: libc::c_int,
+        mode: libc::mode_t)
+        -> io::Result<File>
+    {
+        self.object._open_file(path, flags, mode)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use crate::{Dir, Advice};
+
+    #[test]
+    fn open_file() {
+        let dir = Dir::open("src").unwrap();
+        let mut buf = String::new();
+        dir.with(libc::O_NONBLOCK).advise(Advice::Sequential)
+            .open_file("lib.rs").unwrap()
+            .read_to_string(&mut buf).unwrap();
+        assert!(buf.find("extern crate libc;").is_some());
+    }
+
+    #[test]
+    fn sub_dir() {
+        let dir = Dir::open(".").unwrap();
+        dir.with(0).sub_dir("src").unwrap();
+        let err = dir.with(libc::O_DIRECTORY).sub_dir("Cargo.toml")
+            .unwrap_err();
+        assert_eq!(crate::raw_os_error(&err), Some(libc::ENOTDIR));
+    }
+
+    fn open_flags(file: &std::fs::File) -> libc::c_int {
+        use std::os::unix::io::AsRawFd;
+        unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) }
+    }
+
+    #[test]
+    fn sync_and_dsync() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let file = dir.write_file("plain", 0o644).unwrap();
+        assert_eq!(open_flags(&file) & libc::O_SYNC, 0);
+        let file = dir.with(0).sync().write_file("sync").unwrap();
+        assert_eq!(open_flags(&file) & libc::O_SYNC, libc::O_SYNC);
+        let file = dir.with(0).dsync().append_file("dsync").unwrap();
+        assert_eq!(open_flags(&file) & super::O_DSYNC, super::O_DSYNC);
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn noatime() {
+        let dir = Dir::open("src").unwrap();
+        let file = dir.with(0).noatime().open_file("lib.rs").unwrap();
+        // flag is only set if we own the file
+        if dir.metadata("lib.rs").unwrap().stat().st_uid
+            == unsafe { libc::geteuid() }
+        {
+            assert_eq!(open_flags(&file) & libc::O_NOATIME, libc::O_NOATIME);
+        }
+        // not owned by us, falls back to opening without the flag
+        if unsafe { libc::geteuid() } != 0 {
+            Dir::open("/etc").unwrap()
+                .with(0).noatime().open_file("passwd").unwrap();
+        }
+    }
+
+    #[test]
+    fn other_methods() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let flags = dir.with(libc::O_NONBLOCK);
+        flags.new_file("file").unwrap();
+        assert!(flags.new_file("file").is_err());
+        flags.update_file("file").unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        assert_eq!(flags.list_dir(".").unwrap().count(), 2);
+        let at = dir.with(0);
+        assert!(at.metadata("file").unwrap().is_file());
+        at.remove_file("file").unwrap();
+        assert!(at.remove_file("sub").is_err());
+        at.remove_dir("sub").unwrap();
+        assert_eq!(dir.list_dir(".").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn sync_with_other_methods() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        // `O_DSYNC` is the same bit as `AT_EMPTY_PATH` on linux
+        for flags in &[dir.with(0).sync(), dir.with(0).dsync()] {
+            assert!(flags.metadata("file").unwrap().is_file());
+            assert!(flags.metadata("").is_err());
+        }
+        dir.with(0).sync().remove_file("file").unwrap();
+        dir.with(0).dsync().remove_dir("sub").unwrap();
+        assert_eq!(dir.list_dir(".").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn write_and_append() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let flags = dir.with(libc::O_SYNC).advise(Advice::DontNeed);
+        flags.write_file("file").unwrap();
+        assert_eq!(dir.metadata("file").unwrap().stat().st_mode & 0o022, 0);
+        dir.with(0).mode(0o600).write_file("private").unwrap();
+        assert_eq!(dir.metadata("private").unwrap().stat().st_mode & 0o777,
+                   0o600);
+        flags.append_file("file").unwrap();
+        assert!(dir.metadata("file").unwrap().is_file());
+    }
+}
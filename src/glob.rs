@@ -0,0 +1,136 @@
+use std::io;
+use std::path::PathBuf;
+
+use ::glob::{Pattern, MatchOptions};
+
+use crate::{Dir, Walk, WalkEntry};
+use crate::error::raw_os_error;
+
+
+/// Iterator over entries matching a glob pattern, created by `Dir::glob`
+#[derive(Debug)]
+pub struct Glob {
+    walk: Option<Walk>,
+    pattern: Pattern,
+    prefix: PathBuf,
+}
+
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+fn is_literal(component: &str) -> bool {
+    !component.contains(['*', '?', '['])
+}
+
+impl Dir {
+    /// Find entries matching a glob pattern relative to this directory
+    ///
+    /// Supports `*`, `?`, `[...]` and `**` (any number of directories),
+    /// see the `glob` crate for the syntax. Like in the shell, wildcards
+    /// don't match names starting with a dot. Entries are returned in
+    /// sorted order with paths relative to this directory:
+    ///
+    /// ```rust,no_run
+    /// # use openat::Dir;
+    /// # let dir = Dir::open(".").unwrap();
+    /// for entry in dir.glob("logs/**/*.log").unwrap() {
+    ///     println!("{:?}", entry.unwrap().path());  // e.g. "logs/a/x.log"
+    /// }
+    /// ```
+    ///
+    /// Only the directories below the literal prefix of the pattern
+    /// (`logs` above) are walked, and only as deep as the pattern can
+    /// match. Symlinks are never followed. Returns an empty iterator if
+    /// the prefix directory doesn't exist.
+    pub fn glob(&self, pattern: &str) -> io::Result<Glob> {
+        let compiled = Pattern::new(pattern).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, e.msg)
+        })?;
+        let components = pattern.split('/').collect::<Vec<_>>();
+        let literal = components[..components.len() - 1].iter()
+            .take_while(|c| is_literal(c))
+            .count();
+        let mut prefix = components[..literal].join("/");
+        if literal > 0 && prefix.is_empty() {
+            prefix.push('/');  // pattern like "/*"
+        }
+        let rest = &components[literal..];
+        let depth = if rest.iter().any(|c| c.contains("**")) {
+            usize::MAX
+        } else {
+            rest.len()
+        };
+        let root = if prefix.is_empty() { "." } else { &prefix[..] };
+        let walk = match self.walk(root) {
+            Ok(walk) => Some(walk.sorted().max_depth(depth)),
+            Err(ref e) if raw_os_error(e) == Some(libc::ENOENT) => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Glob {
+            walk,
+            pattern: compiled,
+            prefix: PathBuf::from(prefix),
+        })
+    }
+}
+
+impl Iterator for Glob {
+    type Item = io::Result<WalkEntry>;
+    fn next(&mut self) -> Option<io::Result<WalkEntry>> {
+        let walk = self.walk.as_mut()?;
+        for item in walk {
+            let mut entry = match item {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            if !self.prefix.as_os_str().is_empty() {
+                entry.add_prefix(&self.prefix);
+            }
+            if self.pattern.matches_path_with(&entry.path, OPTIONS) {
+                return Some(Ok(entry));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use crate::Dir;
+
+    fn paths(dir: &Dir, pattern: &str) -> Vec<PathBuf> {
+        dir.glob(pattern).unwrap()
+            .map(|e| e.unwrap().path().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn glob() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("logs", 0o755).unwrap();
+        dir.create_dir("logs/a", 0o755).unwrap();
+        dir.write_file("logs/x.log", 0o644).unwrap();
+        dir.write_file("logs/a/y.log", 0o644).unwrap();
+        dir.write_file("logs/a/z.txt", 0o644).unwrap();
+        dir.write_file("logs/.hidden.log", 0o644).unwrap();
+        dir.write_file("top.log", 0o644).unwrap();
+        assert_eq!(paths(&dir, "*.log"), [PathBuf::from("top.log")]);
+        assert_eq!(paths(&dir, "logs/*.log"), [PathBuf::from("logs/x.log")]);
+        assert_eq!(paths(&dir, "logs/**/*.log"),
+                   [PathBuf::from("logs/a/y.log"),
+                    PathBuf::from("logs/x.log")]);
+        assert_eq!(paths(&dir, "**/*.log"),
+                   [PathBuf::from("logs/a/y.log"),
+                    PathBuf::from("logs/x.log"),
+                    PathBuf::from("top.log")]);
+        assert_eq!(paths(&dir, "*/?/z.*"), [PathBuf::from("logs/a/z.txt")]);
+        assert_eq!(paths(&dir, "./top.[lx]og"), [PathBuf::from("./top.log")]);
+        assert!(paths(&dir, "nonexistent/*").is_empty());
+        assert!(dir.glob("[").is_err());
+    }
+}
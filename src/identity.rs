@@ -0,0 +1,140 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::{Dir, Metadata};
+
+
+/// Identity of a file: device and inode numbers
+///
+/// Two entries with the same identity are the same file (or directory),
+/// e.g. hard links of the same file or the same directory opened via
+/// different paths. Use it as a `HashMap` or `HashSet` key to skip
+/// already visited directories or deduplicate files.
+///
+/// Note: inode numbers are reused after a file is removed, so identity
+/// only makes sense while the file is known to exist (e.g. while it's
+/// opened).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Identity {
+    dev: libc::dev_t,
+    ino: libc::ino_t,
+}
+
+impl Identity {
+    /// Returns device number (`st_dev`)
+    pub fn dev(&self) -> libc::dev_t {
+        self.dev
+    }
+    /// Returns inode number (`st_ino`)
+    pub fn ino(&self) -> libc::ino_t {
+        self.ino
+    }
+}
+
+impl Metadata {
+    /// Returns identity of the file
+    pub fn identity(&self) -> Identity {
+        Identity {
+            dev: self.stat().st_dev,
+            ino: self.stat().st_ino,
+        }
+    }
+}
+
+impl Dir {
+    /// Returns identity of the directory itself
+    pub fn identity(&self) -> io::Result<Identity> {
+        Ok(self.self_metadata()?.identity())
+    }
+
+    /// Returns `true` if both handles refer to the same directory
+    pub fn same_as(&self, other: &Dir) -> io::Result<bool> {
+        Ok(self.identity()? == other.identity()?)
+    }
+}
+
+/// Returns path of `descendant` relative to `ancestor`
+///
+/// The path is found by opening `..` of `descendant` repeatedly until the
+/// directory with the identity of `ancestor` is reached, at each level the
+/// parent directory is listed to find the name of the child (like
+/// `Dir::recover_path_slow` does). So it works without `/proc` and for
+/// directories opened in any way (e.g. received from another process),
+/// and the result is a portable relative path, empty if both are the same
+/// directory.
+///
+/// Returns the error of kind `InvalidInput` if the root directory is
+/// reached, i.e. `descendant` is not inside `ancestor`. Doesn't work with
+/// `capability-mode` feature (as parent directories are opened by `..`).
+pub fn relative_path_from(ancestor: &Dir, descendant: &Dir)
+    -> io::Result<PathBuf>
+{
+    let target = ancestor.identity()?;
+    let mut names = Vec::new();
+    let mut cur = descendant.try_clone()?;
+    let mut cur_meta = cur.self_metadata()?;
+    while cur_meta.identity() != target {
+        let parent = cur.sub_dir("..")?;
+        let parent_meta = parent.self_metadata()?;
+        if parent_meta.identity() == cur_meta.identity() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "directory is not a descendant of the ancestor"));
+        }
+        names.push(parent._find_entry(&cur_meta)?);
+        cur = parent;
+        cur_meta = parent_meta;
+    }
+    Ok(names.iter().rev().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use crate::Dir;
+
+    #[test]
+    fn same_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        let sub = dir.sub_dir("sub").unwrap();
+        let again = Dir::open(&tmp.path().join("sub")).unwrap();
+        assert!(sub.same_as(&again).unwrap());
+        assert!(!sub.same_as(&dir).unwrap());
+        assert!(dir.same_as(&dir.sub_dir(".").unwrap()).unwrap());
+        let mut seen = HashSet::new();
+        assert!(seen.insert(sub.identity().unwrap()));
+        assert!(!seen.insert(again.identity().unwrap()));
+        dir.write_file("file", 0o644).unwrap();
+        crate::hardlink(&dir, "file", &dir, "link").unwrap();
+        assert_eq!(dir.metadata("file").unwrap().identity(),
+                   dir.metadata("link").unwrap().identity());
+    }
+
+    #[test]
+    #[cfg(not(feature="capability-mode"))]
+    fn relative_path_from() {
+        use std::io;
+        use std::path::Path;
+        use super::relative_path_from;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.create_dir("a/b", 0o755).unwrap();
+        dir.create_dir("c", 0o755).unwrap();
+        let b = dir.sub_dir("a/b").unwrap();
+        let c = dir.sub_dir("c").unwrap();
+        assert_eq!(relative_path_from(&dir, &b).unwrap(), Path::new("a/b"));
+        assert_eq!(relative_path_from(&dir, &dir).unwrap(), Path::new(""));
+        assert_eq!(relative_path_from(&b, &dir).unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        assert_eq!(relative_path_from(&c, &b).unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        // follows the directory when it's moved
+        dir.local_rename("a", "c/d").unwrap();
+        assert_eq!(relative_path_from(&dir, &b).unwrap(),
+                   Path::new("c/d/b"));
+        assert_eq!(relative_path_from(&c, &b).unwrap(), Path::new("d/b"));
+    }
+}
@@ -0,0 +1,169 @@
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+use std::os::unix::io::AsRawFd;
+
+use crate::{Dir, AsPath};
+use crate::dir::to_cstr;
+
+
+/// Linux inode flags, as shown by `lsattr` and changed by `chattr`
+///
+/// Support for the flags depends on the filesystem, unsupported ones are
+/// either ignored or rejected with `EOPNOTSUPP`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InodeFlags(u32);
+
+impl InodeFlags {
+    /// Synchronous updates (`S`)
+    pub const SYNC: InodeFlags = InodeFlags(0x0000_0008);
+    /// The file may not be changed, renamed or removed, requires
+    /// `CAP_LINUX_IMMUTABLE` (`i`)
+    pub const IMMUTABLE: InodeFlags = InodeFlags(0x0000_0010);
+    /// The file may only be appended to, requires `CAP_LINUX_IMMUTABLE`
+    /// (`a`)
+    pub const APPEND: InodeFlags = InodeFlags(0x0000_0020);
+    /// Do not dump the file (`d`)
+    pub const NODUMP: InodeFlags = InodeFlags(0x0000_0040);
+    /// Do not update access time (`A`)
+    pub const NOATIME: InodeFlags = InodeFlags(0x0000_0080);
+    /// Synchronous directory updates (`D`)
+    pub const DIRSYNC: InodeFlags = InodeFlags(0x0001_0000);
+    /// Disable copy-on-write, e.g. for database files on btrfs (`C`)
+    ///
+    /// Only takes effect on empty files, for directories it's inherited by
+    /// the new files.
+    pub const NOCOW: InodeFlags = InodeFlags(0x0080_0000);
+    /// Subdirectories inherit the project id (`P`)
+    pub const PROJINHERIT: InodeFlags = InodeFlags(0x2000_0000);
+
+    /// No flags set
+    pub fn empty() -> InodeFlags {
+        InodeFlags(0)
+    }
+    /// Creates flags from raw `FS_*_FL` bits
+    ///
+    /// Bits unknown to this crate are kept as is.
+    pub fn from_raw(bits: u32) -> InodeFlags {
+        InodeFlags(bits)
+    }
+    /// Returns raw `FS_*_FL` bits
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+    /// Returns `true` if all flags of `other` are set
+    pub fn contains(&self, other: InodeFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// Returns these flags with flags of `other` cleared
+    pub fn without(self, other: InodeFlags) -> InodeFlags {
+        InodeFlags(self.0 & !other.0)
+    }
+}
+
+impl BitOr for InodeFlags {
+    type Output = InodeFlags;
+    fn bitor(self, other: InodeFlags) -> InodeFlags {
+        InodeFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for InodeFlags {
+    fn bitor_assign(&mut self, other: InodeFlags) {
+        self.0 |= other.0;
+    }
+}
+
+impl Dir {
+    /// Returns inode flags of a file or directory
+    ///
+    /// The entry is opened read-only for the call, symlinks are not
+    /// followed (and have no flags). See `inode_flags` for more info.
+    ///
+    /// Only supported on Linux.
+    pub fn inode_flags<P: AsPath>(&self, path: P) -> io::Result<InodeFlags> {
+        let file = self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_RDONLY|libc::O_NONBLOCK, 0)?;
+        inode_flags(&file)
+    }
+
+    /// Set inode flags of a file or directory, replacing existing flags
+    ///
+    /// To change a single flag, read the existing flags first:
+    ///
+    /// ```rust,no_run
+    /// # use openat::{Dir, InodeFlags};
+    /// # let dir = Dir::open(".").unwrap();
+    /// let flags = dir.inode_flags("db").unwrap();
+    /// dir.set_inode_flags("db", flags | InodeFlags::NOCOW).unwrap();
+    /// ```
+    ///
+    /// Only supported on Linux.
+    pub fn set_inode_flags<P: AsPath>(&self, path: P, flags: InodeFlags)
+        -> io::Result<()>
+    {
+        let file = self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_RDONLY|libc::O_NONBLOCK, 0)?;
+        set_inode_flags(&file, flags)
+    }
+}
+
+/// Returns inode flags of an open file or directory (`FS_IOC_GETFLAGS`)
+///
+/// Only supported on Linux.
+pub fn inode_flags<F: AsRawFd>(file: &F) -> io::Result<InodeFlags> {
+    // the ioctl is declared with `long` but the kernel uses `int`
+    let mut flags: libc::c_int = 0;
+    let res = unsafe {
+        libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags)
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(InodeFlags(flags as u32))
+}
+
+/// Set inode flags of an open file or directory (`FS_IOC_SETFLAGS`)
+///
+/// Works on read-only descriptors too, but requires the file to be owned
+/// by the user (or `CAP_FOWNER`).
+///
+/// Only supported on Linux.
+pub fn set_inode_flags<F: AsRawFd>(file: &F, flags: InodeFlags)
+    -> io::Result<()>
+{
+    let flags = flags.0 as libc::c_int;
+    let res = unsafe {
+        libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags)
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Dir, InodeFlags};
+
+    #[test]
+    fn noatime() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let flags = match dir.inode_flags("file") {
+            Ok(flags) => flags,
+            // tmpfs before Linux 6.0 doesn't support the ioctl
+            Err(_) => return,
+        };
+        assert!(!flags.contains(InodeFlags::NOATIME));
+        if dir.set_inode_flags("file", flags | InodeFlags::NOATIME).is_err() {
+            return;
+        }
+        let flags = dir.inode_flags("file").unwrap();
+        assert!(flags.contains(InodeFlags::NOATIME));
+        dir.set_inode_flags("file", flags.without(InodeFlags::NOATIME))
+            .unwrap();
+        assert!(!dir.inode_flags("file").unwrap()
+            .contains(InodeFlags::NOATIME));
+    }
+}
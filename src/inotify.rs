@@ -0,0 +1,265 @@
+use std::io;
+use std::mem;
+use std::ffi::{CStr, OsStr};
+use std::collections::HashMap;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use crate::{Dir, SimpleType};
+use crate::watch::{Event, EventKind};
+use crate::dir::{fd_path, to_cstr};
+
+
+const MASK: u32 = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MODIFY
+    | libc::IN_ATTRIB | libc::IN_MOVED_FROM | libc::IN_MOVED_TO
+    | libc::IN_DELETE_SELF | libc::IN_ONLYDIR;
+const HEADER: usize = mem::size_of::<libc::inotify_event>();
+const BUFFER: usize = 64 * (HEADER + 256);
+
+/// Watches a directory for changes using inotify, created by `Dir::watch`
+///
+/// Implements `AsRawFd`, so it can be registered in an event loop: the
+/// descriptor is readable when `read_events()` wouldn't block.
+#[derive(Debug)]
+pub struct Watcher {
+    fd: RawFd,
+    root: Dir,
+    recursive: bool,
+    watches: HashMap<i32, PathBuf>,
+    buffer: Vec<u64>,
+}
+
+impl Dir {
+    /// Watch this directory for changes in its entries
+    ///
+    /// The watch is set up on the directory itself (via its descriptor),
+    /// so it keeps working if the directory is renamed or moved.
+    ///
+    /// Requires `inotify` feature, Linux only.
+    pub fn watch(&self) -> io::Result<Watcher> {
+        let mut watcher = Watcher::new(self, false)?;
+        watcher.add(self, PathBuf::new())?;
+        Ok(watcher)
+    }
+
+    /// Watch this directory and all its subdirectories for changes
+    ///
+    /// Directories created (or moved in) later are watched too. Note that
+    /// there is an inherent race: entries created in a new directory before
+    /// the watch is added to it are not reported.
+    ///
+    /// Paths are tracked per watched directory, so events in a
+    /// subdirectory renamed after it was watched are reported with its
+    /// old path (the rename itself is reported, though).
+    ///
+    /// Each directory uses one inotify watch, which are limited by
+    /// `fs.inotify.max_user_watches` sysctl.
+    pub fn watch_recursive(&self) -> io::Result<Watcher> {
+        let mut watcher = Watcher::new(self, true)?;
+        watcher.add_tree(self, PathBuf::new())?;
+        Ok(watcher)
+    }
+}
+
+impl Watcher {
+    fn new(dir: &Dir, recursive: bool) -> io::Result<Watcher> {
+        let fd = unsafe {
+            libc::inotify_init1(libc::IN_CLOEXEC)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let root = match dir.try_clone() {
+            Ok(root) => root,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        };
+        Ok(Watcher {
+            fd, root, recursive,
+            watches: HashMap::new(),
+            buffer: vec![0; BUFFER / 8],
+        })
+    }
+
+    fn add(&mut self, dir: &Dir, path: PathBuf) -> io::Result<()> {
+        // inotify has no *at variant, but it follows magic links
+        let proc_path = to_cstr(&fd_path(dir.as_raw_fd()))?;
+        let wd = unsafe {
+            libc::inotify_add_watch(self.fd, proc_path.as_ref().as_ptr(),
+                                    MASK)
+        };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.watches.insert(wd, path);
+        Ok(())
+    }
+
+    fn add_tree(&mut self, dir: &Dir, path: PathBuf) -> io::Result<()> {
+        self.add(dir, path.clone())?;
+        for entry in dir.walk(".")? {
+            let entry = entry?;
+            if entry.simple_type() == Some(SimpleType::Dir) {
+                let sub = entry.sub_dir()?;
+                self.add(&sub, path.join(entry.path()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_created(&mut self, path: &Path) -> io::Result<()> {
+        let dir = match self.root.sub_dir(path) {
+            Ok(dir) => dir,
+            // removed or replaced in the meantime
+            Err(_) => return Ok(()),
+        };
+        self.add_tree(&dir, path.to_path_buf())
+    }
+
+    /// Wait for changes and return them
+    ///
+    /// Blocks until at least one event is available. Paths of the events
+    /// are relative to the watched directory.
+    pub fn read_events(&mut self) -> io::Result<Vec<Event>> {
+        let bytes = loop {
+            let res = unsafe {
+                libc::read(self.fd, self.buffer.as_mut_ptr() as *mut _,
+                           BUFFER)
+            };
+            if res < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            break res as usize;
+        };
+        let buf = unsafe {
+            std::slice::from_raw_parts(self.buffer.as_ptr() as *const u8,
+                                       bytes)
+        };
+        let mut events = Vec::new();
+        let mut created = Vec::new();
+        let mut pos = 0;
+        while pos + HEADER <= bytes {
+            let raw = unsafe {
+                std::ptr::read_unaligned(
+                    buf[pos..].as_ptr() as *const libc::inotify_event)
+            };
+            let end = pos + HEADER + raw.len as usize;
+            let name_bytes = &buf[pos + HEADER..end];
+            pos = end;
+            let name = match CStr::from_bytes_until_nul(name_bytes) {
+                Ok(name) => OsStr::from_bytes(name.to_bytes()),
+                Err(_) => OsStr::from_bytes(name_bytes),
+            };
+            if raw.mask & libc::IN_Q_OVERFLOW != 0 {
+                events.push(Event {
+                    kind: EventKind::Overflow,
+                    path: PathBuf::new(),
+                    is_dir: false,
+                });
+                continue;
+            }
+            if raw.mask & libc::IN_IGNORED != 0 {
+                self.watches.remove(&raw.wd);
+                continue;
+            }
+            let dir = match self.watches.get(&raw.wd) {
+                Some(dir) => dir,
+                None => continue,
+            };
+            let path = if name.is_empty() {
+                dir.clone()
+            } else {
+                dir.join(name)
+            };
+            let is_dir = raw.mask & libc::IN_ISDIR != 0;
+            let kind = match raw.mask & MASK {
+                libc::IN_CREATE => EventKind::Created,
+                libc::IN_DELETE => EventKind::Removed,
+                libc::IN_MODIFY => EventKind::Modified,
+                libc::IN_ATTRIB => EventKind::MetadataChanged,
+                libc::IN_MOVED_FROM => EventKind::MovedFrom(raw.cookie),
+                libc::IN_MOVED_TO => EventKind::MovedTo(raw.cookie),
+                libc::IN_DELETE_SELF => EventKind::WatchedRemoved,
+                _ => continue,
+            };
+            if self.recursive && is_dir && matches!(kind,
+                EventKind::Created | EventKind::MovedTo(_))
+            {
+                created.push(path.clone());
+            }
+            events.push(Event { kind, path, is_dir });
+        }
+        for path in created {
+            self.add_created(&path)?;
+        }
+        Ok(events)
+    }
+}
+
+impl AsRawFd for Watcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use crate::Dir;
+    use crate::EventKind;
+
+    #[test]
+    fn watch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut watcher = dir.watch().unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        dir.local_rename("file", "file2").unwrap();
+        dir.remove_file("file2").unwrap();
+        let events = watcher.read_events().unwrap();
+        let kinds = events.iter().map(|e| e.kind()).collect::<Vec<_>>();
+        assert_eq!(kinds[0], EventKind::Created);
+        assert_eq!(events[0].path(), Path::new("file"));
+        assert!(!events[0].is_dir());
+        let from = events.iter().position(|e| e.path() == Path::new("file")
+            && matches!(e.kind(), EventKind::MovedFrom(_))).unwrap();
+        let to = events.iter().position(|e| e.path() == Path::new("file2")
+            && matches!(e.kind(), EventKind::MovedTo(_))).unwrap();
+        assert!(from < to);
+        assert_eq!(events.last().unwrap().kind(), EventKind::Removed);
+    }
+
+    #[test]
+    fn watch_recursive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        let mut watcher = dir.watch_recursive().unwrap();
+        dir.write_file("a/file", 0o644).unwrap();
+        dir.create_dir("b", 0o755).unwrap();
+        let events = watcher.read_events().unwrap();
+        assert_eq!(events[0].path(), Path::new("a/file"));
+        assert_eq!(events[1].path(), Path::new("b"));
+        assert!(events[1].is_dir());
+        // new directory is watched too
+        dir.write_file("b/file", 0o644).unwrap();
+        let events = watcher.read_events().unwrap();
+        assert_eq!(events[0].kind(), EventKind::Created);
+        assert_eq!(events[0].path(), Path::new("b/file"));
+    }
+}
@@ -0,0 +1,289 @@
+use std::io;
+use std::mem;
+use std::ptr;
+use std::ffi::{CStr, CString, OsStr};
+use std::collections::HashMap;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+
+use crate::{Dir, SimpleType};
+use crate::watch::{Event, EventKind};
+
+
+const NOTES: u32 = libc::NOTE_WRITE | libc::NOTE_DELETE | libc::NOTE_ATTRIB
+    | libc::NOTE_RENAME | libc::NOTE_REVOKE;
+const EVENTS: usize = 64;
+
+/// Watches a directory for changes using kqueue, created by `Dir::watch`
+///
+/// kqueue only reports that a directory has changed, so entries are
+/// listed again on every change and compared to the previous listing.
+/// This means that renames are reported as `Removed` and `Created` pairs,
+/// and writes to files are not reported at all (only `Created`, `Removed`,
+/// `MetadataChanged` of the directories and `WatchedRemoved` are).
+///
+/// Implements `AsRawFd`, so it can be registered in an event loop: the
+/// descriptor is readable when `read_events()` wouldn't block.
+#[derive(Debug)]
+pub struct Watcher {
+    fd: RawFd,
+    recursive: bool,
+    watches: HashMap<RawFd, Watched>,
+}
+
+#[derive(Debug)]
+struct Watched {
+    dir: Dir,
+    path: PathBuf,
+    entries: HashMap<CString, (u64, bool)>,
+}
+
+impl Dir {
+    /// Watch this directory for changes in its entries
+    ///
+    /// The watch is set up on the directory itself (via its descriptor),
+    /// so it keeps working if the directory is renamed or moved.
+    ///
+    /// Requires `kqueue` feature, macOS and FreeBSD only. See `Watcher`
+    /// for the differences from the inotify-based implementation.
+    pub fn watch(&self) -> io::Result<Watcher> {
+        let mut watcher = Watcher::new(false)?;
+        watcher.add(self.try_clone()?, PathBuf::new())?;
+        Ok(watcher)
+    }
+
+    /// Watch this directory and all its subdirectories for changes
+    ///
+    /// Directories created (or moved in) later are watched too. Note that
+    /// there is an inherent race: entries created in a new directory before
+    /// the watch is added to it are not reported.
+    ///
+    /// Each directory keeps an open descriptor, which are limited by
+    /// `ulimit -n`.
+    pub fn watch_recursive(&self) -> io::Result<Watcher> {
+        let mut watcher = Watcher::new(true)?;
+        watcher.add_tree(self.try_clone()?, PathBuf::new())?;
+        Ok(watcher)
+    }
+}
+
+fn list(dir: &Dir) -> io::Result<HashMap<CString, (u64, bool)>> {
+    let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+    let mut entries = HashMap::new();
+    for entry in dir.list_dir(dot)? {
+        let entry = entry?;
+        let is_dir = match entry.simple_type() {
+            Some(typ) => typ == SimpleType::Dir,
+            None => match dir._stat(&entry.name, libc::AT_SYMLINK_NOFOLLOW) {
+                Ok(meta) => meta.simple_type() == SimpleType::Dir,
+                // removed in the meantime
+                Err(_) => continue,
+            },
+        };
+        entries.insert(entry.name.clone(), (entry.inode(), is_dir));
+    }
+    Ok(entries)
+}
+
+impl Watcher {
+    fn new(recursive: bool) -> io::Result<Watcher> {
+        let fd = unsafe { libc::kqueue() };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        Ok(Watcher { fd, recursive, watches: HashMap::new() })
+    }
+
+    fn add(&mut self, dir: Dir, path: PathBuf) -> io::Result<()> {
+        let entries = list(&dir)?;
+        let mut change: libc::kevent = unsafe { mem::zeroed() };
+        change.ident = dir.0 as libc::uintptr_t;
+        change.filter = libc::EVFILT_VNODE;
+        change.flags = libc::EV_ADD | libc::EV_CLEAR;
+        change.fflags = NOTES;
+        let res = unsafe {
+            libc::kevent(self.fd, &change, 1, ptr::null_mut(), 0, ptr::null())
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.watches.insert(dir.0, Watched { dir, path, entries });
+        Ok(())
+    }
+
+    fn add_tree(&mut self, dir: Dir, path: PathBuf) -> io::Result<()> {
+        let mut subdirs = Vec::new();
+        for entry in dir.walk(".")? {
+            let entry = entry?;
+            if entry.simple_type() == Some(SimpleType::Dir) {
+                let sub = entry.sub_dir()?;
+                subdirs.push((sub, path.join(entry.path())));
+            }
+        }
+        self.add(dir, path)?;
+        for (sub, path) in subdirs {
+            self.add(sub, path)?;
+        }
+        Ok(())
+    }
+
+    fn add_created(&mut self, parent: RawFd, name: &CStr, path: PathBuf)
+        -> io::Result<()>
+    {
+        let dir = match self.watches.get(&parent) {
+            Some(watched) => watched.dir._sub_dir(name, libc::O_DIRECTORY),
+            None => return Ok(()),
+        };
+        match dir {
+            Ok(dir) => self.add_tree(dir, path),
+            // removed or replaced in the meantime
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Wait for changes and return them
+    ///
+    /// Blocks until at least one event is available. Paths of the events
+    /// are relative to the watched directory. May return an empty list if
+    /// the directory changed but the listing is the same (e.g. a file was
+    /// created and removed in between).
+    pub fn read_events(&mut self) -> io::Result<Vec<Event>> {
+        let mut buf: [libc::kevent; EVENTS] = unsafe { mem::zeroed() };
+        let count = loop {
+            let res = unsafe {
+                libc::kevent(self.fd, ptr::null(), 0,
+                             buf.as_mut_ptr(), EVENTS as libc::c_int,
+                             ptr::null())
+            };
+            if res < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            break res as usize;
+        };
+        let mut events = Vec::new();
+        let mut created = Vec::new();
+        for raw in &buf[..count] {
+            let fd = raw.ident as RawFd;
+            let watched = match self.watches.get_mut(&fd) {
+                Some(watched) => watched,
+                None => continue,
+            };
+            if raw.fflags & (libc::NOTE_DELETE | libc::NOTE_REVOKE) != 0 {
+                events.push(Event {
+                    kind: EventKind::WatchedRemoved,
+                    path: watched.path.clone(),
+                    is_dir: true,
+                });
+                // closing the descriptor removes it from the kqueue
+                self.watches.remove(&fd);
+                continue;
+            }
+            if raw.fflags & libc::NOTE_ATTRIB != 0 {
+                events.push(Event {
+                    kind: EventKind::MetadataChanged,
+                    path: watched.path.clone(),
+                    is_dir: true,
+                });
+            }
+            if raw.fflags & libc::NOTE_WRITE == 0 {
+                continue;
+            }
+            let entries = list(&watched.dir)?;
+            for (name, &(ino, is_dir)) in &watched.entries {
+                if entries.get(name).map(|x| x.0) != Some(ino) {
+                    events.push(Event {
+                        kind: EventKind::Removed,
+                        path: watched.path.join(name_path(name)),
+                        is_dir,
+                    });
+                }
+            }
+            for (name, &(ino, is_dir)) in &entries {
+                if watched.entries.get(name).map(|x| x.0) != Some(ino) {
+                    let path = watched.path.join(name_path(name));
+                    if self.recursive && is_dir {
+                        created.push((fd, name.clone(), path.clone()));
+                    }
+                    events.push(Event {
+                        kind: EventKind::Created,
+                        path,
+                        is_dir,
+                    });
+                }
+            }
+            watched.entries = entries;
+        }
+        for (parent, name, path) in created {
+            self.add_created(parent, &name, path)?;
+        }
+        Ok(events)
+    }
+}
+
+fn name_path(name: &CStr) -> PathBuf {
+    OsStr::from_bytes(name.to_bytes()).into()
+}
+
+impl AsRawFd for Watcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use crate::{Dir, EventKind};
+
+    #[test]
+    fn watch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut watcher = dir.watch().unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let events = watcher.read_events().unwrap();
+        assert_eq!(events[0].kind(), EventKind::Created);
+        assert_eq!(events[0].path(), Path::new("file"));
+        assert!(!events[0].is_dir());
+        dir.remove_file("file").unwrap();
+        let events = watcher.read_events().unwrap();
+        assert_eq!(events[0].kind(), EventKind::Removed);
+        assert_eq!(events[0].path(), Path::new("file"));
+    }
+
+    #[test]
+    fn watch_recursive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("a", 0o755).unwrap();
+        let mut watcher = dir.watch_recursive().unwrap();
+        dir.write_file("a/file", 0o644).unwrap();
+        let events = watcher.read_events().unwrap();
+        assert_eq!(events[0].path(), Path::new("a/file"));
+        dir.create_dir("b", 0o755).unwrap();
+        let events = watcher.read_events().unwrap();
+        assert_eq!(events[0].path(), Path::new("b"));
+        assert!(events[0].is_dir());
+        // new directory is watched too
+        dir.write_file("b/file", 0o644).unwrap();
+        let events = watcher.read_events().unwrap();
+        assert_eq!(events[0].kind(), EventKind::Created);
+        assert_eq!(events[0].path(), Path::new("b/file"));
+    }
+}
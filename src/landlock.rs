@@ -0,0 +1,245 @@
+use std::io;
+use std::ptr;
+use std::mem;
+use std::ops::{BitOr, BitOrAssign};
+use std::os::unix::io::RawFd;
+
+use crate::Dir;
+
+const CREATE_RULESET_VERSION: libc::c_uint = 1;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Filesystem access rights for the Landlock rules
+///
+/// Rights that are not supported by the running kernel are silently
+/// dropped (e.g. `REFER` needs Linux 5.19 and `TRUNCATE` needs 6.2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LandlockAccess(u64);
+
+impl LandlockAccess {
+    /// Execute a file
+    pub const EXECUTE: LandlockAccess = LandlockAccess(1 << 0);
+    /// Open a file with write access
+    pub const WRITE_FILE: LandlockAccess = LandlockAccess(1 << 1);
+    /// Open a file with read access
+    pub const READ_FILE: LandlockAccess = LandlockAccess(1 << 2);
+    /// Open a directory or list its content
+    pub const READ_DIR: LandlockAccess = LandlockAccess(1 << 3);
+    /// Remove an empty directory or rename one
+    pub const REMOVE_DIR: LandlockAccess = LandlockAccess(1 << 4);
+    /// Unlink (or rename) a file
+    pub const REMOVE_FILE: LandlockAccess = LandlockAccess(1 << 5);
+    /// Create (or rename or link) a character device
+    pub const MAKE_CHAR: LandlockAccess = LandlockAccess(1 << 6);
+    /// Create (or rename) a directory
+    pub const MAKE_DIR: LandlockAccess = LandlockAccess(1 << 7);
+    /// Create (or rename or link) a regular file
+    pub const MAKE_REG: LandlockAccess = LandlockAccess(1 << 8);
+    /// Create (or rename or link) a unix socket
+    pub const MAKE_SOCK: LandlockAccess = LandlockAccess(1 << 9);
+    /// Create (or rename or link) a named pipe
+    pub const MAKE_FIFO: LandlockAccess = LandlockAccess(1 << 10);
+    /// Create (or rename or link) a block device
+    pub const MAKE_BLOCK: LandlockAccess = LandlockAccess(1 << 11);
+    /// Create (or rename or link) a symbolic link
+    pub const MAKE_SYM: LandlockAccess = LandlockAccess(1 << 12);
+    /// Link or rename a file from or to a different directory
+    pub const REFER: LandlockAccess = LandlockAccess(1 << 13);
+    /// Truncate a file
+    pub const TRUNCATE: LandlockAccess = LandlockAccess(1 << 14);
+
+    /// Read files and directories and execute files
+    pub const READ_ONLY: LandlockAccess = LandlockAccess(0b1101);
+    /// All the rights known to this crate
+    pub const ALL: LandlockAccess = LandlockAccess((1 << 15) - 1);
+
+    /// No rights at all
+    pub fn empty() -> LandlockAccess {
+        LandlockAccess(0)
+    }
+    /// Returns raw `LANDLOCK_ACCESS_FS_*` bits
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+    /// Returns `true` if all rights of `other` are set
+    pub fn contains(&self, other: LandlockAccess) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for LandlockAccess {
+    type Output = LandlockAccess;
+    fn bitor(self, other: LandlockAccess) -> LandlockAccess {
+        LandlockAccess(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for LandlockAccess {
+    fn bitor_assign(&mut self, other: LandlockAccess) {
+        self.0 |= other.0;
+    }
+}
+
+/// A set of Landlock rules restricting the process to some directories
+///
+/// ```rust,no_run
+/// # use openat::{Dir, Landlock, LandlockAccess};
+/// let data = Dir::open("/var/lib/app").unwrap();
+/// let config = Dir::open("/etc/app").unwrap();
+/// let mut rules = Landlock::new().unwrap();
+/// rules.allow(&data, LandlockAccess::ALL).unwrap();
+/// rules.allow(&config, LandlockAccess::READ_ONLY).unwrap();
+/// rules.restrict_self().unwrap();
+/// // now the process can only access these two directories
+/// ```
+///
+/// All access rights supported by the kernel are handled, i.e. everything
+/// not allowed by the rules is forbidden. Note that the restriction
+/// applies to the calling thread and its future children only, so it's
+/// usually done before spawning any threads.
+#[derive(Debug)]
+pub struct Landlock {
+    fd: RawFd,
+    handled: u64,
+}
+
+impl Landlock {
+    /// Returns the Landlock ABI version supported by the kernel
+    ///
+    /// Returns error of kind `Unsupported` (`ENOSYS` or `EOPNOTSUPP`) if
+    /// Landlock is not available.
+    pub fn abi_version() -> io::Result<u32> {
+        let res = unsafe {
+            libc::syscall(libc::SYS_landlock_create_ruleset,
+                ptr::null::<RulesetAttr>(), 0 as libc::size_t,
+                CREATE_RULESET_VERSION)
+        };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(res as u32)
+        }
+    }
+
+    /// Create an empty ruleset that handles all supported rights
+    pub fn new() -> io::Result<Landlock> {
+        let handled = match Landlock::abi_version()? {
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            _ => LandlockAccess::ALL.0,
+        };
+        let attr = RulesetAttr { handled_access_fs: handled };
+        let fd = unsafe {
+            libc::syscall(libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                mem::size_of::<RulesetAttr>() as libc::size_t,
+                0 as libc::c_uint)
+        };
+        if fd < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(Landlock { fd: fd as RawFd, handled })
+        }
+    }
+
+    /// Allow access to the directory and everything beneath it
+    pub fn allow(&mut self, dir: &Dir, access: LandlockAccess)
+        -> io::Result<&mut Landlock>
+    {
+        let attr = PathBeneathAttr {
+            allowed_access: access.0 & self.handled,
+            parent_fd: dir.0,
+        };
+        let res = unsafe {
+            libc::syscall(libc::SYS_landlock_add_rule, self.fd,
+                RULE_PATH_BENEATH, &attr as *const PathBeneathAttr,
+                0 as libc::c_uint)
+        };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// Enforce the rules on the current thread
+    ///
+    /// This also sets `no_new_privs` flag on the thread, which is required
+    /// by the kernel for unprivileged processes.
+    pub fn restrict_self(self) -> io::Result<()> {
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::syscall(libc::SYS_landlock_restrict_self,
+                             self.fd, 0 as libc::c_uint) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Landlock {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Restrict the current thread to the given directories
+///
+/// This is a shortcut for `Landlock::new()`, `allow()` for each item and
+/// `restrict_self()`.
+pub fn restrict_to<'a, I>(dirs: I) -> io::Result<()>
+    where I: IntoIterator<Item=(&'a Dir, LandlockAccess)>
+{
+    let mut rules = Landlock::new()?;
+    for (dir, access) in dirs {
+        rules.allow(dir, access)?;
+    }
+    rules.restrict_self()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Dir;
+    use super::{Landlock, LandlockAccess};
+
+    #[test]
+    fn access() {
+        assert_eq!(LandlockAccess::READ_ONLY,
+            LandlockAccess::EXECUTE | LandlockAccess::READ_FILE
+            | LandlockAccess::READ_DIR);
+        assert!(LandlockAccess::ALL.contains(LandlockAccess::TRUNCATE));
+        assert!(!LandlockAccess::READ_ONLY
+            .contains(LandlockAccess::WRITE_FILE));
+    }
+
+    #[test]
+    fn ruleset() {
+        // don't call `restrict_self()` as it would affect other tests
+        let mut rules = match Landlock::new() {
+            Ok(rules) => rules,
+            Err(e) if e.raw_os_error() == Some(libc::ENOSYS) ||
+                      e.raw_os_error() == Some(libc::EOPNOTSUPP)
+            => return,
+            Err(e) => panic!("landlock: {}", e),
+        };
+        let dir = Dir::open(".").unwrap();
+        rules.allow(&dir, LandlockAccess::READ_ONLY).unwrap();
+    }
+}
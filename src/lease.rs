@@ -0,0 +1,130 @@
+use std::io;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+use crate::{Dir, AsPath};
+use crate::dir::to_cstr;
+
+// Not exposed by libc crate, it's the same on all linux architectures
+const F_SETSIG: libc::c_int = 10;
+
+/// Type of a file lease
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lease {
+    /// Read lease, the holder is notified when file is opened for writing
+    /// or truncated by another process
+    Read,
+    /// Write lease, the holder is notified when file is opened by another
+    /// process
+    Write,
+}
+
+impl Dir {
+    /// Open a file and take a lease on it
+    ///
+    /// For `Lease::Read` the file is opened read-only (read lease can't be
+    /// taken on a writable descriptor), for `Lease::Write` the file is opened
+    /// for reading and writing (write lease can only be taken when no other
+    /// process has the file open). See `set_lease` for more info.
+    ///
+    /// Only supported on Linux.
+    pub fn open_file_leased<P: AsPath>(&self, path: P, lease: Lease)
+        -> io::Result<File>
+    {
+        let flags = match lease {
+            Lease::Read => libc::O_RDONLY,
+            Lease::Write => libc::O_RDWR,
+        };
+        let file = self._open_file(to_cstr(path)?.as_ref(), flags, 0)?;
+        set_lease(&file, lease)?;
+        Ok(file)
+    }
+}
+
+/// Take (or change) a lease on an open file
+///
+/// When another process opens the file in a way conflicting with the lease,
+/// its `open` call is blocked and the lease holder is notified by a signal
+/// (`SIGIO` by default, see `set_lease_signal`). The holder should then
+/// flush caches, and remove (or downgrade) the lease, otherwise it's broken
+/// forcibly after `/proc/sys/fs/lease-break-time` seconds.
+///
+/// Only supported on Linux.
+pub fn set_lease<F: AsRawFd>(file: &F, lease: Lease) -> io::Result<()> {
+    let arg = match lease {
+        Lease::Read => libc::F_RDLCK,
+        Lease::Write => libc::F_WRLCK,
+    };
+    _fcntl(file, libc::F_SETLEASE, arg).map(|_| ())
+}
+
+/// Remove a lease from a file
+///
+/// Only supported on Linux.
+pub fn remove_lease<F: AsRawFd>(file: &F) -> io::Result<()> {
+    _fcntl(file, libc::F_SETLEASE, libc::F_UNLCK).map(|_| ())
+}
+
+/// Returns the lease currently held on the file
+///
+/// Only supported on Linux.
+pub fn get_lease<F: AsRawFd>(file: &F) -> io::Result<Option<Lease>> {
+    match _fcntl(file, libc::F_GETLEASE, 0)? {
+        libc::F_RDLCK => Ok(Some(Lease::Read)),
+        libc::F_WRLCK => Ok(Some(Lease::Write)),
+        _ => Ok(None),
+    }
+}
+
+/// Set the signal that is sent when the lease on the file is being broken
+///
+/// If a real-time signal is used and the handler is installed with
+/// `SA_SIGINFO`, the `si_fd` field of `siginfo_t` contains the file
+/// descriptor of the leased file, so single handler can serve many leases.
+/// Zero restores the default (`SIGIO`).
+///
+/// Only supported on Linux.
+pub fn set_lease_signal<F: AsRawFd>(file: &F, signal: libc::c_int)
+    -> io::Result<()>
+{
+    _fcntl(file, F_SETSIG, signal).map(|_| ())
+}
+
+fn _fcntl<F: AsRawFd>(file: &F, cmd: libc::c_int, arg: libc::c_int)
+    -> io::Result<libc::c_int>
+{
+    let res = unsafe { libc::fcntl(file.as_raw_fd(), cmd, arg) };
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Dir;
+    use super::*;
+
+    #[test]
+    fn read_lease() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let file = dir.open_file_leased("file", Lease::Read).unwrap();
+        assert_eq!(get_lease(&file).unwrap(), Some(Lease::Read));
+        set_lease_signal(&file, libc::SIGRTMIN()).unwrap();
+        remove_lease(&file).unwrap();
+        assert_eq!(get_lease(&file).unwrap(), None);
+    }
+
+    #[test]
+    fn write_lease_on_read_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let file = dir.open_file("file").unwrap();
+        let _other = dir.open_file("file").unwrap();
+        assert!(set_lease(&file, Lease::Write).is_err());
+    }
+}
@@ -44,38 +44,223 @@
 //!
 #![warn(missing_docs)]
 
+#[cfg(not(any(unix, target_os="wasi", windows)))]
+compile_error!("openat crate only supports unix systems, WASI and Windows");
+#[cfg(all(windows, not(feature="windows")))]
+compile_error!("openat crate requires `windows` feature on Windows \
+                (only a subset of the API is supported, see README)");
+
 extern crate libc;
 
-mod dir;
-mod list;
-mod name;
-mod filetype;
-mod metadata;
+// Everything except the WASI and Windows backends is unix-only
+macro_rules! unix {
+    ($($item:item)*) => { $( #[cfg(unix)] $item )* }
+}
+
+unix! {
+    mod dir;
+    mod list;
+    mod name;
+    mod pathstack;
+    mod filetype;
+    mod metadata;
+    mod identity;
+    mod temp;
+    mod lock;
+    mod space;
+    mod advice;
+    mod flags;
+    mod direct;
+    mod options;
+    mod mode;
+    mod error;
+    mod syscall;
+    mod capability;
+    mod strict;
+    mod beneath;
+    mod probe;
+    mod walk;
+    mod cancel;
+    mod sort;
+    mod filter;
+    mod compare;
+    mod snapshot;
+    mod wait;
+    #[cfg(any(target_os="linux", target_os="android"))]
+    mod page;
+    #[cfg(feature="manifest")]
+    mod manifest;
+    #[cfg(feature="tar")]
+    mod tar;
+    #[cfg(any(all(feature="inotify", target_os="linux"),
+              all(feature="kqueue",
+                  any(target_os="macos", target_os="freebsd"))))]
+    mod watch;
+    #[cfg(all(feature="inotify", target_os="linux"))]
+    mod inotify;
+    #[cfg(all(feature="kqueue", any(target_os="macos", target_os="freebsd")))]
+    mod kqueue;
+    #[cfg(any(all(feature="inotify", target_os="linux"),
+              all(feature="kqueue",
+                  any(target_os="macos", target_os="freebsd"))))]
+    mod debounce;
+    #[cfg(all(feature="fanotify", target_os="linux"))]
+    mod fanotify;
+    #[cfg(feature="fd-passing")]
+    mod passing;
+    #[cfg(all(feature="pidfd", target_os="linux"))]
+    mod pidfd;
+    #[cfg(feature="mmap")]
+    mod mmap;
+    #[cfg(feature="mock")]
+    mod memory;
+    mod dirlike;
+    #[cfg(any(target_os="linux", target_os="android", target_os="freebsd",
+              target_os="macos", target_os="ios"))]
+    mod sparse;
+    #[cfg(any(target_os="macos", target_os="ios"))]
+    mod bulk;
+    #[cfg(any(target_os="macos", target_os="freebsd"))]
+    mod fileflags;
+    #[cfg(target_os="linux")]
+    mod exec;
+    #[cfg(target_os="linux")]
+    mod socket;
+    #[cfg(target_os="linux")]
+    mod lease;
+    #[cfg(target_os="linux")]
+    mod inodeflags;
+    #[cfg(all(feature="fiemap", target_os="linux"))]
+    mod fiemap;
+    #[cfg(all(feature="landlock", target_os="linux"))]
+    mod landlock;
+    #[cfg(any(feature="unblock", feature="tokio"))]
+    pub mod unblock;
+    #[cfg(feature="tokio")]
+    pub mod tokio;
+    #[cfg(target_os="linux")]
+    mod statx;
+    #[cfg(all(feature="io-uring", target_os="linux"))]
+    mod uring;
+    #[cfg(feature="glob")]
+    mod glob;
 
-pub use crate::list::DirIter;
-pub use crate::name::AsPath;
-pub use crate::dir::{rename, hardlink};
-pub use crate::filetype::SimpleType;
-pub use crate::metadata::Metadata;
+    pub use crate::list::{DirIter, DirEntries};
+    #[cfg(any(target_os="linux", target_os="android"))]
+    pub use crate::list::DirCookie;
+    #[cfg(any(target_os="linux", target_os="android"))]
+    pub use crate::page::DirPage;
+    pub use crate::name::{AsPath, FileName};
+    pub use crate::pathstack::PathStack;
+    pub use crate::dir::{rename, hardlink};
+    pub use crate::filetype::SimpleType;
+    pub use crate::metadata::Metadata;
+    pub use crate::identity::{Identity, relative_path_from};
+    pub use crate::temp::{TempFile, TempDir, ScopedDir};
+    pub use crate::lock::{DirLock, LockFile};
+    pub use crate::space::allocate;
+    pub use crate::advice::{Advice, advise};
+    pub use crate::flags::{DirMethodFlags, DEFAULT_FILE_MODE};
+    pub use crate::direct::{AlignedBuffer, block_size};
+    pub use crate::options::OpenOptions;
+    pub use crate::mode::{Mode, IntoMode, umask};
+    pub use crate::error::{Error, ErrorContext, context, raw_os_error};
+    pub use crate::error::{ErrorKind, error_kind};
+    pub use crate::syscall::{Observer, set_observer, clear_observer};
+    pub use crate::strict::{StrictDir, validate_name};
+    pub use crate::probe::{Feature, is_supported};
+    pub use crate::walk::{Walk, WalkEntry};
+    pub use crate::cancel::CancelToken;
+    pub use crate::sort::SortBy;
+    pub use crate::filter::Filter;
+    pub use crate::compare::{compare, Compare, Difference};
+    pub use crate::snapshot::snapshot_linked;
+    #[cfg(feature="manifest")]
+    pub use crate::manifest::{Manifest, ManifestEntry, HashAlgo};
+    #[cfg(any(all(feature="inotify", target_os="linux"),
+              all(feature="kqueue",
+                  any(target_os="macos", target_os="freebsd"))))]
+    pub use crate::watch::{Event, EventKind};
+    #[cfg(all(feature="inotify", target_os="linux"))]
+    pub use crate::inotify::Watcher;
+    #[cfg(all(feature="kqueue", any(target_os="macos", target_os="freebsd")))]
+    pub use crate::kqueue::Watcher;
+    #[cfg(any(all(feature="inotify", target_os="linux"),
+              all(feature="kqueue",
+                  any(target_os="macos", target_os="freebsd"))))]
+    pub use crate::debounce::Debouncer;
+    #[cfg(all(feature="fanotify", target_os="linux"))]
+    pub use crate::fanotify::{Fanotify, FanotifyMask, FanotifyEvent, MarkScope};
+    #[cfg(feature="glob")]
+    pub use crate::glob::Glob;
+    pub use crate::dirlike::{DirLike, FileLike, EntryIter};
+    #[cfg(feature="mmap")]
+    pub use crate::mmap::{MapOptions, Mmap};
+    #[cfg(feature="mock")]
+    pub use crate::memory::{MemoryDir, MemoryFile, MemoryDirIter};
+    #[cfg(target_os="linux")]
+    pub use crate::space::{punch_hole, zero_range};
+    #[cfg(any(target_os="linux", target_os="android", target_os="freebsd",
+              target_os="macos", target_os="ios"))]
+    pub use crate::sparse::{segments, Segments, Segment};
+    #[cfg(any(target_os="macos", target_os="ios"))]
+    pub use crate::bulk::BulkIter;
+    #[cfg(any(target_os="macos", target_os="freebsd"))]
+    pub use crate::fileflags::FileFlags;
+    #[cfg(any(target_os="macos", target_os="ios"))]
+    pub use crate::dir::clone_file;
+    #[cfg(target_os="linux")]
+    pub use crate::exec::exec_fd;
+    #[cfg(target_os="linux")]
+    pub use crate::statx::{StatxMask, StatxSync};
+    #[cfg(target_os="linux")]
+    pub use crate::lease::{Lease, set_lease, remove_lease, get_lease};
+    #[cfg(target_os="linux")]
+    pub use crate::inodeflags::{InodeFlags, inode_flags, set_inode_flags};
+    #[cfg(all(feature="fiemap", target_os="linux"))]
+    pub use crate::fiemap::{Extent, extents};
+    #[cfg(target_os="linux")]
+    pub use crate::lease::set_lease_signal;
+    #[cfg(all(feature="landlock", target_os="linux"))]
+    pub use crate::landlock::{Landlock, LandlockAccess, restrict_to};
 
-use std::ffi::CString;
-use std::os::unix::io::RawFd;
+    use std::ffi::CString;
+    use std::os::unix::io::RawFd;
 
-/// A safe wrapper around directory file descriptor
-///
-/// Construct it either with ``Dir::cwd()`` or ``Dir::open(path)``
-///
-#[derive(Debug)]
-pub struct Dir(RawFd);
+    /// A safe wrapper around directory file descriptor
+    ///
+    /// Construct it either with ``Dir::cwd()`` or ``Dir::open(path)``
+    ///
+    #[derive(Debug)]
+    pub struct Dir(RawFd);
 
-/// Entry returned by iterating over `DirIter` iterator
-#[derive(Debug)]
-pub struct Entry {
-    name: CString,
-    file_type: Option<SimpleType>,
+    /// Entry returned by iterating over `DirIter` iterator
+    ///
+    /// Entries are compared, ordered and hashed by name only (bytewise), so
+    /// entries of different directories with the same name are equal.
+    #[derive(Debug, Clone)]
+    pub struct Entry {
+        name: CString,
+        file_type: Option<SimpleType>,
+        ino: u64,
+        dir: Option<std::sync::Arc<crate::list::DirHandle>>,
+    }
 }
 
-#[cfg(test)]
+#[cfg(target_os="wasi")]
+mod wasi;
+#[cfg(target_os="wasi")]
+pub use crate::wasi::{Dir, DirIter, Entry, Metadata, SimpleType, AsPath};
+#[cfg(target_os="wasi")]
+pub use crate::wasi::{rename, hardlink};
+#[cfg(all(windows, feature="windows"))]
+mod windows;
+#[cfg(all(windows, feature="windows"))]
+pub use crate::windows::{Dir, DirIter, Entry, Metadata, SimpleType, AsPath};
+#[cfg(all(windows, feature="windows"))]
+pub use crate::windows::rename;
+
+#[cfg(all(test, unix))]
 mod test {
     use std::mem;
     use super::Dir;
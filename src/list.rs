@@ -1,11 +1,21 @@
 use std::io;
-use std::ptr;
+use std::fs::File;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::mem::ManuallyDrop;
 use std::ffi::{CStr, OsStr};
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
 
 use libc;
 
-use crate::{Dir, Entry, SimpleType};
+use crate::{Dir, Entry, AsPath, SimpleType, OpenOptions};
+use crate::{rename, validate_name};
+use crate::dir::{to_cstr, fstat};
+use crate::syscall::syscall;
+use crate::error::raw_os_error;
 
 
 // We have such weird constants because C types are ugly
@@ -16,9 +26,75 @@ const DOTDOT: [libc::c_char; 3] = [b'.' as libc::c_char, b'.' as libc::c_char, 0
 /// Iterator over directory entries
 ///
 /// Created using `Dir::list_dir()`
+///
+/// The iterator is `Send` and `Sync`: reading the directory stream is
+/// protected by an internal mutex, so `&DirIter` is an iterator too and
+/// can be shared by several threads, each entry is returned to exactly one
+/// of them.
 #[derive(Debug)]
 pub struct DirIter {
-    dir: *mut libc::DIR,
+    handle: Arc<DirHandle>,
+    // entries returned so far that might be directories, for `size_hint`
+    // (`usize::MAX` after `seek`, as we don't know what's left)
+    seen_dirs: AtomicUsize,
+    include_dots: bool,
+    resolve_types: bool,
+}
+
+/// Directory stream shared by `DirIter` and the entries it returns
+///
+/// Keeps the directory open while any of the entries is alive, so they
+/// can open files relative to it.
+#[derive(Debug)]
+pub(crate) struct DirHandle {
+    stream: Mutex<Stream>,
+    fd: RawFd,
+}
+
+// null if the descriptor is not owned by a directory stream
+#[derive(Debug)]
+struct Stream(*mut libc::DIR);
+
+// readdir and friends are not thread-safe, but `Stream` is only accessed
+// under the mutex in `DirHandle`
+unsafe impl Send for Stream {}
+
+impl DirHandle {
+    #[cfg(any(target_os="macos", target_os="ios"))]
+    pub(crate) fn from_dir(dir: Dir) -> DirHandle {
+        use std::os::unix::io::IntoRawFd;
+        DirHandle {
+            stream: Mutex::new(Stream(std::ptr::null_mut())),
+            fd: dir.into_raw_fd(),
+        }
+    }
+    #[cfg(any(target_os="macos", target_os="ios"))]
+    pub(crate) fn fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for DirHandle {
+    fn drop(&mut self) {
+        let dir = self.stream.get_mut().unwrap_or_else(|e| e.into_inner()).0;
+        unsafe {
+            if dir.is_null() {
+                libc::close(self.fd);
+            } else {
+                libc::closedir(dir);
+            }
+        }
+    }
+}
+
+/// Iterator over entries of a directory, created by `for entry in &dir`
+///
+/// Same as `DirIter`, but an error opening the directory for listing is
+/// returned as the first (and the only) item.
+#[derive(Debug)]
+pub struct DirEntries {
+    iter: Option<DirIter>,
+    error: Option<io::Error>,
 }
 
 /// Position in a DirIter as obtained by 'DirIter::current_position()'
@@ -28,15 +104,158 @@ pub struct DirPosition {
     pos: libc::c_long,
 }
 
+/// Position in a directory that stays valid across processes
+///
+/// Obtained by `DirIter::cookie()`, this is the offset of the filesystem
+/// (`d_off` of the last returned entry), so it can be stored (see `raw`)
+/// and used to resume listing of the same directory by another iterator,
+/// e.g. after a restart. Entries added or removed in the meantime may or
+/// may not be returned, but the remaining entries are not skipped on
+/// filesystems with stable offsets (ext4, xfs, btrfs and most others).
+///
+/// Only supported on Linux and Android, where `telldir` returns the
+/// filesystem offset rather than an index into the stream.
+#[cfg(any(target_os="linux", target_os="android"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DirCookie(i64);
+
+#[cfg(any(target_os="linux", target_os="android"))]
+impl DirCookie {
+    /// Cookie pointing to the start of the directory
+    pub fn start() -> DirCookie {
+        DirCookie(0)
+    }
+    /// Restore the cookie from the value returned by `raw`
+    pub fn from_raw(value: i64) -> DirCookie {
+        DirCookie(value)
+    }
+    /// Returns the raw offset to store the cookie
+    pub fn raw(&self) -> i64 {
+        self.0
+    }
+}
+
 impl Entry {
     /// Returns the file name of this entry
     pub fn file_name(&self) -> &OsStr {
         OsStr::from_bytes(self.name.to_bytes())
     }
+    /// Returns the file name as a nul-terminated C string
+    ///
+    /// Useful to pass the name to libc functions without a copy.
+    pub fn name_cstr(&self) -> &CStr {
+        &self.name
+    }
+    /// Returns the file name as raw bytes (without the nul terminator)
+    pub fn name_bytes(&self) -> &[u8] {
+        self.name.to_bytes()
+    }
     /// Returns the simplified type of this entry
     pub fn simple_type(&self) -> Option<SimpleType> {
         self.file_type
     }
+    /// Returns the inode number of this entry
+    ///
+    /// Note: for mount points this is the inode of the directory
+    /// underneath the mount rather than the root of the mounted filesystem.
+    pub fn inode(&self) -> u64 {
+        self.ino
+    }
+
+    // borrows descriptor of the directory this entry was listed from
+    fn parent(&self) -> io::Result<ManuallyDrop<Dir>> {
+        match self.dir {
+            Some(ref handle) => Ok(ManuallyDrop::new(Dir(handle.fd))),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "entry is not bound to a directory descriptor")),
+        }
+    }
+    /// Open this entry as a file for reading, see `Dir::open_file`
+    ///
+    /// The file is opened relative to the directory descriptor this entry
+    /// was listed from, so it works even if the directory was renamed
+    /// since. Entries keep the directory open while they are alive.
+    ///
+    /// Fails with `InvalidInput` for entries that weren't produced by
+    /// listing a real directory (e.g. `MemoryDir`).
+    pub fn open_file(&self) -> io::Result<File> {
+        self.parent()?.open_file(self)
+    }
+    /// Open this entry as a file with the specified options
+    ///
+    /// See `open_file` and `OpenOptions::open_at`.
+    pub fn open(&self, options: &OpenOptions) -> io::Result<File> {
+        options.open_at(&*self.parent()?, self)
+    }
+    /// Open this entry as a directory, see `Dir::sub_dir` and `open_file`
+    pub fn sub_dir(&self) -> io::Result<Dir> {
+        self.parent()?.sub_dir(self)
+    }
+    /// Remove this entry from the directory it was listed from
+    ///
+    /// Directories are removed with `AT_REMOVEDIR` (so they must be empty),
+    /// everything else is unlinked. If the type isn't known from the
+    /// listing, the entry is stat'ed first.
+    ///
+    /// Unlike going through a path, this can't remove an entry from a
+    /// different directory if the parent was renamed or replaced in the
+    /// meantime.
+    pub fn remove(&self) -> io::Result<()> {
+        let parent = self.parent()?;
+        let typ = match self.file_type {
+            Some(typ) => typ,
+            None => {
+                parent._stat(&self.name, libc::AT_SYMLINK_NOFOLLOW)?
+                    .simple_type()
+            }
+        };
+        if typ == SimpleType::Dir {
+            parent._unlink(&self.name, libc::AT_REMOVEDIR)
+        } else {
+            parent._unlink(&self.name, 0)
+        }
+    }
+    /// Rename this entry within the directory it was listed from
+    ///
+    /// `new_name` must be a single path component (see `validate_name`).
+    /// An existing entry with this name is replaced, as with `rename(2)`.
+    /// On success the name of this entry is updated, so it can still be
+    /// opened or removed.
+    pub fn rename_to<P: AsPath>(&mut self, new_name: P) -> io::Result<()> {
+        let new_name = to_cstr(new_name)?;
+        let new_name = new_name.as_ref();
+        validate_name(new_name.to_bytes())?;
+        let parent = self.parent()?;
+        rename(&parent, self.name.as_c_str(), &parent, new_name)?;
+        self.name = new_name.to_owned();
+        Ok(())
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        self.name.cmp(&other.name)
+    }
+}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state)
+    }
 }
 
 #[cfg(any(target_os="linux", target_os="fuchsia"))]
@@ -56,25 +275,139 @@ unsafe fn errno_location() -> *mut libc::c_int {
 
 impl DirIter {
 
-    unsafe fn next_entry(&mut self) -> io::Result<Option<&libc::dirent>>
+    fn stream(&self) -> MutexGuard<'_, Stream> {
+        // stream is still valid if other thread panicked
+        self.handle.stream.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Reads the next entry (skipping `.` and `..` unless `include_dots`),
+    // and converts it with `f` while the stream is locked
+    fn read_with<T, F>(&self, f: F) -> io::Result<Option<T>>
+        where F: FnOnce(&libc::dirent) -> T,
     {
-        // Reset errno to detect if error occurred
-        *errno_location() = 0;
+        let stream = self.stream();
+        loop {
+            let entry = unsafe {
+                // Reset errno to detect if error occurred
+                *errno_location() = 0;
+                libc::readdir(stream.0)
+            };
+            if entry.is_null() {
+                if unsafe { *errno_location() } == 0 {
+                    return Ok(None);
+                } else {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            let entry = unsafe { &*entry };
+            if !self.include_dots &&
+                (entry.d_name[..2] == DOT || entry.d_name[..3] == DOTDOT)
+            {
+                continue;
+            }
+            return Ok(Some(f(entry)));
+        }
+    }
 
-        let entry = libc::readdir(self.dir);
-        if entry == ptr::null_mut() {
-            if *errno_location() == 0 {
-                return Ok(None)
-            } else {
-                return Err(io::Error::last_os_error());
+    // d_ino is u32 on some systems
+    #[allow(clippy::useless_conversion)]
+    fn read_entry(&self) -> Option<io::Result<Entry>> {
+        loop {
+            let entry = self.read_with(|e| Entry {
+                name: unsafe { CStr::from_ptr((e.d_name).as_ptr()) }
+                    .to_owned(),
+                file_type: match e.d_type {
+                    0 => None,
+                    libc::DT_REG => Some(SimpleType::File),
+                    libc::DT_DIR => Some(SimpleType::Dir),
+                    libc::DT_LNK => Some(SimpleType::Symlink),
+                    libc::DT_SOCK => Some(SimpleType::Socket),
+                    libc::DT_FIFO => Some(SimpleType::Fifo),
+                    libc::DT_CHR => Some(SimpleType::CharDevice),
+                    libc::DT_BLK => Some(SimpleType::BlockDevice),
+                    _ => Some(SimpleType::Other),
+                },
+                ino: u64::from(e.d_ino),
+                dir: Some(self.handle.clone()),
+            });
+            let mut entry = match entry {
+                Ok(Some(entry)) => entry,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.resolve_types && entry.file_type.is_none() {
+                match self.entry_type(&entry) {
+                    Ok(typ) => entry.file_type = Some(typ),
+                    // removed in the meantime
+                    Err(ref e) if raw_os_error(e) == Some(libc::ENOENT) => {
+                        continue;
+                    }
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            if matches!(entry.file_type, None | Some(SimpleType::Dir)) {
+                self.count_dir();
             }
+            return Some(Ok(entry));
         }
-        return Ok(Some(&*entry));
+    }
+
+    fn count_dir(&self) {
+        let _ = self.seen_dirs.fetch_update(Relaxed, Relaxed,
+            |seen| Some(seen.saturating_add(1)));
+    }
+
+    // Skips to the next entry other than `.` and `..` without allocating
+    pub(crate) fn skip_entry(&mut self) -> io::Result<bool> {
+        let found = self.read_with(|_| ())?.is_some();
+        if found {
+            // type is unknown, so count it for `size_hint`
+            self.count_dir();
+        }
+        Ok(found)
+    }
+
+    // Returns type of the entry, stats it if `d_type` is unknown
+    pub(crate) fn entry_type(&self, entry: &Entry) -> io::Result<SimpleType> {
+        if let Some(typ) = entry.file_type {
+            return Ok(typ);
+        }
+        let dir = self.borrow_dir();
+        Ok(dir._stat(&entry.name, libc::AT_SYMLINK_NOFOLLOW)?.simple_type())
+    }
+
+    // Borrows the descriptor of the iterator, it's closed by closedir
+    pub(crate) fn borrow_dir(&self) -> ManuallyDrop<Dir> {
+        ManuallyDrop::new(Dir(self.handle.fd))
+    }
+
+    /// Also return `.` and `..` entries (they are skipped by default)
+    ///
+    /// Some tools (e.g. archivers or filesystem checkers) need to see them.
+    /// Both have the type of a directory if the filesystem reports types.
+    /// Note that `..` of the root of a mount point refers to the mount
+    /// point's parent, and its inode number is that of the underlying
+    /// filesystem.
+    pub fn include_dots(mut self, include: bool) -> DirIter {
+        self.include_dots = include;
+        self
+    }
+
+    /// Stat entries whose type isn't reported by the filesystem
+    ///
+    /// Some filesystems (e.g. older XFS, some network and FUSE ones) don't
+    /// fill in `d_type`, so `Entry::simple_type()` returns `None`. With
+    /// this option such entries are stat'ed (without following symlinks)
+    /// when returned, so `simple_type()` is always `Some`. Entries removed
+    /// before they are stat'ed are skipped.
+    pub fn resolve_types(mut self, resolve: bool) -> DirIter {
+        self.resolve_types = resolve;
+        self
     }
 
     /// Returns the current directory iterator position. The result should be handled as opaque value
     pub fn current_position(&self) -> io::Result<DirPosition> {
-        let pos = unsafe { libc::telldir(self.dir) };
+        let pos = unsafe { libc::telldir(self.stream().0) };
 
         if pos == -1 {
             Err(io::Error::last_os_error())
@@ -86,12 +419,58 @@ impl DirIter {
     // note the C-API does not report errors for seekdir/rewinddir, thus we don't do as well.
     /// Sets the current directory iterator position to some location queried by 'current_position()'
     pub fn seek(&self, position: DirPosition) {
-        unsafe { libc::seekdir(self.dir, position.pos) };
+        unsafe { libc::seekdir(self.stream().0, position.pos) };
+        self.seen_dirs.store(usize::MAX, Relaxed);
+    }
+
+    /// Returns a position that can be used by another iterator
+    ///
+    /// See `DirCookie` for more info.
+    #[cfg(any(target_os="linux", target_os="android"))]
+    // c_long is i32 on 32-bit systems
+    #[allow(clippy::useless_conversion)]
+    pub fn cookie(&self) -> io::Result<DirCookie> {
+        let pos = unsafe { libc::telldir(self.stream().0) };
+        if pos == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(DirCookie(pos.into()))
+        }
+    }
+
+    /// Continue listing from the position returned by `cookie()`
+    ///
+    /// The cookie may come from another iterator (or process) listing the
+    /// same directory. A cookie from a different directory results in
+    /// arbitrary entries of this directory being skipped.
+    #[cfg(any(target_os="linux", target_os="android"))]
+    pub fn seek_cookie(&self, cookie: DirCookie) -> io::Result<()> {
+        use std::convert::TryFrom;
+        let pos = libc::c_long::try_from(cookie.0).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                           "directory cookie is out of range")
+        })?;
+        unsafe { libc::seekdir(self.stream().0, pos) };
+        self.seen_dirs.store(usize::MAX, Relaxed);
+        Ok(())
     }
 
     /// Resets the current directory iterator position to the beginning
     pub fn rewind(&self) {
-        unsafe { libc::rewinddir(self.dir) };
+        unsafe { libc::rewinddir(self.stream().0) };
+        self.seen_dirs.store(0, Relaxed);
+    }
+
+    /// Read all remaining entries, failing on the first error
+    ///
+    /// Same as `collect::<io::Result<Vec<_>>>()`, but preallocates the
+    /// vector using `size_hint`.
+    pub fn collect_entries(self) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::with_capacity(self.size_hint().0);
+        for entry in self {
+            entries.push(entry?);
+        }
+        Ok(entries)
     }
 }
 
@@ -100,16 +479,28 @@ pub fn open_dirfd(fd: libc::c_int) -> io::Result<DirIter> {
     if dir == std::ptr::null_mut() {
         Err(io::Error::last_os_error())
     } else {
-        Ok(DirIter { dir: dir })
+        Ok(DirIter {
+            handle: Arc::new(DirHandle {
+                stream: Mutex::new(Stream(dir)),
+                fd,
+            }),
+            seen_dirs: AtomicUsize::new(0),
+            include_dots: false,
+            resolve_types: false,
+        })
     }
 }
 
-pub fn open_dir(dir: &Dir, path: &CStr) -> io::Result<DirIter> {
+pub fn open_dir(dir: &Dir, path: &CStr, flags: libc::c_int)
+    -> io::Result<DirIter>
+{
+    let call = syscall("openat", dir.0, path)?;
     let dir_fd = unsafe {
-        libc::openat(dir.0, path.as_ptr(), libc::O_DIRECTORY|libc::O_CLOEXEC)
+        libc::openat(dir.0, path.as_ptr(),
+                     libc::O_DIRECTORY|libc::O_CLOEXEC|flags)
     };
     if dir_fd < 0 {
-        Err(io::Error::last_os_error())
+        Err(call.error())
     } else {
         open_dirfd(dir_fd)
     }
@@ -118,36 +509,272 @@ pub fn open_dir(dir: &Dir, path: &CStr) -> io::Result<DirIter> {
 impl Iterator for DirIter {
     type Item = io::Result<Entry>;
     fn next(&mut self) -> Option<Self::Item> {
-        unsafe {
-            loop {
-                match self.next_entry() {
-                    Err(e) => return Some(Err(e)),
-                    Ok(None) => return None,
-                    Ok(Some(e)) if e.d_name[..2] == DOT => continue,
-                    Ok(Some(e)) if e.d_name[..3] == DOTDOT => continue,
-                    Ok(Some(e)) => {
-                        return Some(Ok(Entry {
-                            name: CStr::from_ptr((e.d_name).as_ptr())
-                                .to_owned(),
-                            file_type: match e.d_type {
-                                0 => None,
-                                libc::DT_REG => Some(SimpleType::File),
-                                libc::DT_DIR => Some(SimpleType::Dir),
-                                libc::DT_LNK => Some(SimpleType::Symlink),
-                                _ => Some(SimpleType::Other),
-                            },
-                        }));
-                    }
-                }
-            }
+        self.read_entry()
+    }
+
+    /// Best-effort estimate of remaining entries
+    ///
+    /// On most filesystems the link count of a directory is two plus the
+    /// number of subdirectories, so the lower bound is the number of
+    /// subdirectories not returned yet. It's zero on filesystems that
+    /// don't count subdirectories (e.g. btrfs) and after `seek`. The bound
+    /// may be wrong if subdirectories are removed concurrently.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let nlink = match fstat(self.handle.fd) {
+            Ok(meta) => meta.stat().st_nlink as usize,
+            Err(_) => return (0, None),
+        };
+        let seen = self.seen_dirs.load(Relaxed);
+        (nlink.saturating_sub(2).saturating_sub(seen), None)
+    }
+}
+
+impl Iterator for &DirIter {
+    type Item = io::Result<Entry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (**self).size_hint()
+    }
+}
+
+impl IntoIterator for &Dir {
+    type Item = io::Result<Entry>;
+    type IntoIter = DirEntries;
+    /// Lists this directory (like `list_self`)
+    fn into_iter(self) -> DirEntries {
+        let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+        match open_dir(self, dot, 0) {
+            Ok(iter) => DirEntries { iter: Some(iter), error: None },
+            Err(e) => DirEntries { iter: None, error: Some(e) },
         }
     }
 }
 
-impl Drop for DirIter {
-    fn drop(&mut self) {
-        unsafe {
-            libc::closedir(self.dir);
+impl Iterator for DirEntries {
+    type Item = io::Result<Entry>;
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        self.iter.as_mut()?.next()
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match (&self.iter, &self.error) {
+            (_, Some(_)) => (1, Some(1)),
+            (Some(iter), None) => iter.size_hint(),
+            (None, None) => (0, Some(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::collections::{BTreeSet, HashSet};
+    use std::os::unix::io::{FromRawFd, IntoRawFd};
+    use crate::{Dir, Entry, OpenOptions, SimpleType};
+
+    fn assert_send_sync<T: Send + Sync>(x: T) -> T { x }
+
+    #[test]
+    fn entry_open() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("sub/file", 0o644).unwrap()
+            .write_all(b"hello").unwrap();
+        dir.create_dir("sub/nested", 0o755).unwrap();
+        let mut entries = dir.list_dir("sub").unwrap()
+            .collect::<Result<Vec<Entry>, _>>().unwrap();
+        entries.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+        // entries keep working after the directory is moved
+        dir.local_rename("sub", "moved").unwrap();
+        let file = assert_send_sync(entries.remove(0));
+        let mut buf = String::new();
+        file.open_file().unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+        file.open(OpenOptions::new().append(true)).unwrap()
+            .write_all(b"!").unwrap();
+        assert_eq!(dir.metadata("moved/file").unwrap().len(), 6);
+        let nested = entries[0].sub_dir().unwrap();
+        nested.create_dir("x", 0o755).unwrap();
+        assert!(dir.metadata("moved/nested/x").unwrap().is_dir());
+    }
+
+    #[test]
+    fn entry_remove_rename() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("sub/file", 0o644).unwrap();
+        dir.create_dir("sub/empty", 0o755).unwrap();
+        let mut entries = dir.list_dir("sub").unwrap()
+            .collect::<Result<Vec<Entry>, _>>().unwrap();
+        entries.sort();
+        let (empty, file) = match &mut entries[..] {
+            [empty, file] => (empty, file),
+            _ => unreachable!(),
+        };
+        assert!(file.rename_to("a/b").is_err());
+        file.rename_to("renamed").unwrap();
+        assert_eq!(file.file_name(), "renamed");
+        assert!(dir.metadata("sub/file").is_err());
+        file.open_file().unwrap();
+        file.remove().unwrap();
+        empty.remove().unwrap();
+        assert_eq!(dir.list_dir("sub").unwrap().count(), 0);
+    }
+
+    #[test]
+    // the mutex in the directory handle of `Entry` is not a part of the
+    // key: entries are compared and hashed by name only
+    #[allow(clippy::mutable_key_type)]
+    fn entry_traits() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        for name in &["b", "a", "sub/a", "c"] {
+            dir.write_file(*name, 0o644).unwrap();
+        }
+        let mut entries = dir.list_dir(".").unwrap()
+            .collect::<Result<BTreeSet<Entry>, _>>().unwrap();
+        let names = entries.iter().map(|e| e.file_name().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c", "sub"]);
+        let nested = dir.list_dir("sub").unwrap().next().unwrap().unwrap();
+        // same name in different directories
+        assert!(!entries.insert(nested.clone()));
+        let set = entries.iter().cloned().collect::<HashSet<_>>();
+        assert!(set.contains(&nested));
+        assert_eq!(nested.clone(), nested);
+        assert_eq!(nested.name_bytes(), b"a");
+        assert_eq!(nested.name_cstr().to_bytes_with_nul(), b"a\0");
+    }
+
+    #[test]
+    fn collect_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        for name in &["a", "b", "c"] {
+            dir.create_dir(*name, 0o755).unwrap();
+        }
+        dir.write_file("file", 0o644).unwrap();
+        let iter = dir.list_dir(".").unwrap();
+        let (lower, upper) = iter.size_hint();
+        // zero on filesystems that don't count subdirectories
+        assert!(lower == 3 || lower == 0);
+        assert_eq!(upper, None);
+        let mut entries = iter.collect_entries().unwrap();
+        entries.sort();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[3].file_name(), "file");
+        let mut iter = dir.list_dir(".").unwrap();
+        for _ in &mut iter {}
+        assert_eq!(iter.size_hint(), (0, None));
+    }
+
+    #[test]
+    fn shared_iter() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        for i in 0..200 {
+            dir.write_file(format!("file{}", i), 0o644).unwrap();
+        }
+        let iter = assert_send_sync(dir.list_dir(".").unwrap());
+        let mut names = std::thread::scope(|scope| {
+            let threads = (0..4).map(|_| scope.spawn(|| {
+                (&iter).map(|e| e.unwrap().file_name().to_owned())
+                    .collect::<Vec<_>>()
+            })).collect::<Vec<_>>();
+            threads.into_iter().flat_map(|t| t.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 200);
+    }
+
+    #[cfg(any(target_os="linux", target_os="android"))]
+    #[test]
+    fn cookie() {
+        use crate::DirCookie;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        for i in 0..100 {
+            dir.write_file(format!("file{}", i), 0o644).unwrap();
+        }
+        let mut iter = dir.list_dir(".").unwrap();
+        let mut names = (&mut iter).take(30)
+            .map(|e| e.unwrap().file_name().to_owned())
+            .collect::<Vec<_>>();
+        let raw = iter.cookie().unwrap().raw();
+        drop(iter);
+        let iter = dir.list_dir(".").unwrap();
+        iter.seek_cookie(DirCookie::from_raw(raw)).unwrap();
+        names.extend(iter.map(|e| e.unwrap().file_name().to_owned()));
+        assert_eq!(names.len(), 100);
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 100);
+        let iter = dir.list_dir(".").unwrap();
+        iter.seek_cookie(DirCookie::start()).unwrap();
+        assert_eq!(iter.count(), 100);
+    }
+
+    #[test]
+    fn include_dots() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let mut entries = dir.list_dir(".").unwrap().include_dots(true)
+            .collect_entries().unwrap();
+        entries.sort();
+        let names = entries.iter().map(|e| e.file_name().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, [".", "..", "file"]);
+        assert_eq!(entries[0].inode(),
+                   dir.self_metadata().unwrap().stat().st_ino);
+        assert_eq!(dir.list_dir(".").unwrap().include_dots(false).count(), 1);
+    }
+
+    #[test]
+    fn resolve_types() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        dir.symlink("link", "sub").unwrap();
+        let mut iter = dir.list_dir(".").unwrap().resolve_types(true);
+        let mut entry = iter.next().unwrap().unwrap();
+        // simulate filesystem without `d_type`
+        entry.file_type = None;
+        assert_eq!(iter.entry_type(&entry).unwrap(),
+                   dir.metadata(&entry).unwrap().simple_type());
+        let entries = iter.collect_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.simple_type().is_some()));
+        assert_eq!(dir.metadata("link").unwrap().simple_type(),
+                   SimpleType::Symlink);
+    }
+
+    #[test]
+    fn iterate_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let mut names = Vec::new();
+        for entry in &dir {
+            names.push(entry.unwrap().file_name().to_owned());
         }
+        assert_eq!(names, ["file"]);
+        // not a directory
+        let file = dir.open_file("file").unwrap();
+        let dir = unsafe { Dir::from_raw_fd(file.into_raw_fd()) };
+        let mut iter = (&dir).into_iter();
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
     }
 }
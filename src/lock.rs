@@ -0,0 +1,248 @@
+use std::io::{self, Read, Write};
+use std::process;
+use std::fs::File;
+use std::ffi::{CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd, FromRawFd};
+
+use crate::{Dir, AsPath};
+use crate::dir::{to_cstr, fstat, same_file};
+
+
+/// A lock on a directory, acquired by `Dir::lock_exclusive()` and similar
+///
+/// The lock is released when this object is dropped.
+#[derive(Debug)]
+pub struct DirLock {
+    file: File,
+}
+
+/// A lock file, created by `Dir::acquire_lockfile()`
+///
+/// The file is removed when this object is dropped.
+#[derive(Debug)]
+pub struct LockFile<'a> {
+    dir: &'a Dir,
+    // `None` only after lock is released
+    name: Option<CString>,
+}
+
+impl Dir {
+    /// Create a lock file containing the pid of the current process
+    ///
+    /// The file is created with `O_EXCL`, so only one process can hold the
+    /// lock. If the file exists and the process mentioned in the file is
+    /// not alive anymore, the file is considered stale and is replaced.
+    /// Otherwise, error of kind `AlreadyExists` is returned. A file which
+    /// doesn't contain a valid pid is never considered stale (it may be
+    /// being written by another process right now).
+    ///
+    /// Note: stale lock detection has a small race window when two processes
+    /// replace the same stale lock at the same time. Also pid check doesn't
+    /// work across pid namespaces (containers) or network filesystems.
+    pub fn acquire_lockfile<P: AsPath>(&self, name: P)
+        -> io::Result<LockFile<'_>>
+    {
+        let name = to_cstr(name)?.as_ref().to_owned();
+        loop {
+            match self.new_file(&*name, 0o644) {
+                Ok(mut file) => {
+                    let res = writeln!(file, "{}", process::id())
+                        .and_then(|()| file.sync_all());
+                    if let Err(e) = res {
+                        self.remove_file(&*name).ok();
+                        return Err(e);
+                    }
+                    return Ok(LockFile { dir: self, name: Some(name) });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+            if !self._remove_stale_lock(&name)? {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                    "lock file is held by another process"));
+            }
+        }
+    }
+
+    fn _remove_stale_lock(&self, name: &CStr) -> io::Result<bool> {
+        let mut file = match self.open_file(name) {
+            Ok(file) => file,
+            // removed in the meantime, retry
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(true);
+            }
+            Err(e) => return Err(e),
+        };
+        let mut buf = String::with_capacity(16);
+        file.read_to_string(&mut buf)?;
+        let pid = match buf.trim().parse::<libc::pid_t>() {
+            Ok(pid) if pid > 0 => pid,
+            // probably the file is being written right now
+            _ => return Ok(false),
+        };
+        let res = unsafe { libc::kill(pid, 0) };
+        if res == 0 || io::Error::last_os_error().raw_os_error()
+                       != Some(libc::ESRCH)
+        {
+            return Ok(false);
+        }
+        // make sure we remove the same file we have checked
+        let opened = fstat(file.as_raw_fd())?;
+        match self.metadata(name) {
+            Ok(ref meta) if same_file(meta, &opened) => {
+                match self.remove_file(name) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        Ok(true)
+                    }
+                    res => res.map(|()| true),
+                }
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Acquire an exclusive lock on this directory, wait if it's locked
+    ///
+    /// This uses `flock` on a freshly opened descriptor for the directory
+    /// (so it works even if `Dir` itself was opened using `O_PATH`). Because
+    /// `flock` locks are attached to the open file description, this locks
+    /// out other threads of the same process too.
+    ///
+    /// Note: this lock is advisory, it only serializes processes (and
+    /// threads) that use locks.
+    pub fn lock_exclusive(&self) -> io::Result<DirLock> {
+        self._lock(libc::LOCK_EX)
+    }
+
+    /// Acquire a shared lock on this directory, wait if it's locked
+    /// exclusively
+    ///
+    /// See `lock_exclusive` for more info.
+    pub fn lock_shared(&self) -> io::Result<DirLock> {
+        self._lock(libc::LOCK_SH)
+    }
+
+    /// Acquire an exclusive lock on this directory if it's not locked
+    ///
+    /// Returns error of kind `WouldBlock` if directory is already locked.
+    pub fn try_lock_exclusive(&self) -> io::Result<DirLock> {
+        self._lock(libc::LOCK_EX|libc::LOCK_NB)
+    }
+
+    /// Acquire a shared lock on this directory if it's not locked
+    /// exclusively
+    ///
+    /// Returns error of kind `WouldBlock` if directory is locked exclusively.
+    pub fn try_lock_shared(&self) -> io::Result<DirLock> {
+        self._lock(libc::LOCK_SH|libc::LOCK_NB)
+    }
+
+    fn _lock(&self, operation: libc::c_int) -> io::Result<DirLock> {
+        let file = unsafe {
+            let fd = libc::openat(self.0, b".\0".as_ptr() as *const _,
+                libc::O_RDONLY|libc::O_DIRECTORY|libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            File::from_raw_fd(fd)
+        };
+        loop {
+            let res = unsafe { libc::flock(file.as_raw_fd(), operation) };
+            if res == 0 {
+                return Ok(DirLock { file });
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        }
+    }
+}
+
+impl<'a> LockFile<'a> {
+    /// Returns the name of the lock file
+    pub fn name(&self) -> &OsStr {
+        let name = self.name.as_ref().expect("lock is not released");
+        OsStr::from_bytes(name.to_bytes())
+    }
+    /// Release the lock, returning an error if lock file can't be removed
+    pub fn release(mut self) -> io::Result<()> {
+        let name = self.name.take().expect("lock is not released");
+        self.dir.remove_file(&*name)
+    }
+}
+
+impl<'a> Drop for LockFile<'a> {
+    fn drop(&mut self) {
+        if let Some(ref name) = self.name {
+            self.dir.remove_file(&**name).ok();
+        }
+    }
+}
+
+impl AsRawFd for DirLock {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use crate::Dir;
+
+    #[test]
+    fn exclusive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let lock = dir.lock_exclusive().unwrap();
+        assert_eq!(dir.try_lock_exclusive().unwrap_err().kind(),
+                   io::ErrorKind::WouldBlock);
+        assert_eq!(dir.try_lock_shared().unwrap_err().kind(),
+                   io::ErrorKind::WouldBlock);
+        drop(lock);
+        dir.try_lock_exclusive().unwrap();
+    }
+
+    #[test]
+    fn lockfile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let lock = dir.acquire_lockfile("app.lock").unwrap();
+        let mut buf = String::new();
+        dir.open_file("app.lock").unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, format!("{}\n", std::process::id()));
+        assert_eq!(dir.acquire_lockfile("app.lock").unwrap_err().kind(),
+                   io::ErrorKind::AlreadyExists);
+        lock.release().unwrap();
+        assert!(dir.metadata("app.lock").is_err());
+        dir.acquire_lockfile("app.lock").unwrap();
+    }
+
+    #[test]
+    fn stale_lockfile() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        writeln!(dir.new_file("app.lock", 0o644).unwrap(), "{}", pid)
+            .unwrap();
+        let _lock = dir.acquire_lockfile("app.lock").unwrap();
+        let mut buf = String::new();
+        dir.open_file("app.lock").unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, format!("{}\n", std::process::id()));
+    }
+
+    #[test]
+    fn shared() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let _lock1 = dir.lock_shared().unwrap();
+        let _lock2 = dir.try_lock_shared().unwrap();
+        assert_eq!(dir.try_lock_exclusive().unwrap_err().kind(),
+                   io::ErrorKind::WouldBlock);
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::{self, Read};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{Dir, AsPath, Walk, SimpleType};
+
+
+/// Hash algorithm used by `Dir::manifest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum HashAlgo {
+    /// SHA-256
+    Sha256,
+    /// SHA-512
+    Sha512,
+}
+
+/// Iterator over files with their checksums, created by `Dir::manifest`
+#[derive(Debug)]
+pub struct Manifest {
+    walk: Walk,
+    algo: HashAlgo,
+}
+
+/// A single file in the `Manifest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    path: PathBuf,
+    size: u64,
+    mtime: SystemTime,
+    digest: Vec<u8>,
+}
+
+impl Dir {
+    /// List all regular files in the tree with their size, modification
+    /// time and checksum
+    ///
+    /// Files are returned one by one in sorted order (see `Walk::sorted`),
+    /// so the whole tree isn't held in memory. Symlinks, directories and
+    /// special files are skipped. Size and modification time are taken
+    /// from the opened file, so they are consistent with the digest unless
+    /// the file is being modified concurrently.
+    ///
+    /// Requires `manifest` feature.
+    pub fn manifest<P: AsPath>(&self, path: P, algo: HashAlgo)
+        -> io::Result<Manifest>
+    {
+        Ok(Manifest { walk: self.walk(path)?.sorted(), algo })
+    }
+}
+
+fn digest<D: Digest, R: Read>(mut file: R) -> io::Result<Vec<u8>> {
+    let mut hasher = D::new();
+    let mut buf = [0u8; 65536];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+impl Iterator for Manifest {
+    type Item = io::Result<ManifestEntry>;
+    fn next(&mut self) -> Option<io::Result<ManifestEntry>> {
+        for entry in &mut self.walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            if entry.simple_type() != Some(SimpleType::File) {
+                continue;
+            }
+            let result = entry.open_file()
+                .and_then(|file| {
+                    let meta = file.metadata()?;
+                    let digest = match self.algo {
+                        HashAlgo::Sha256 => digest::<Sha256, _>(&file)?,
+                        HashAlgo::Sha512 => digest::<Sha512, _>(&file)?,
+                    };
+                    Ok(ManifestEntry {
+                        path: entry.path().to_path_buf(),
+                        size: meta.len(),
+                        mtime: meta.modified()?,
+                        digest,
+                    })
+                });
+            return Some(result);
+        }
+        None
+    }
+}
+
+impl ManifestEntry {
+    /// Returns path relative to the directory passed to `Dir::manifest`
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Returns size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    /// Returns modification time of the file
+    pub fn mtime(&self) -> SystemTime {
+        self.mtime
+    }
+    /// Returns modification time as duration since unix epoch
+    ///
+    /// Returns zero duration for files modified before the epoch.
+    pub fn mtime_since_epoch(&self) -> Duration {
+        self.mtime.duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+    /// Returns raw digest of the file contents
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+    /// Returns digest as lowercase hex string (as printed by `sha256sum`)
+    pub fn hex_digest(&self) -> String {
+        let mut result = String::with_capacity(self.digest.len() * 2);
+        for byte in &self.digest {
+            write!(result, "{:02x}", byte).expect("writing to string");
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::path::Path;
+    use crate::Dir;
+    use super::HashAlgo;
+
+    #[test]
+    fn manifest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("sub/b", 0o644).unwrap().write_all(b"abc").unwrap();
+        dir.write_file("a", 0o644).unwrap();
+        dir.symlink("link", "a").unwrap();
+        let items = dir.manifest(".", HashAlgo::Sha256).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path(), Path::new("a"));
+        assert_eq!(items[0].hex_digest(), "e3b0c44298fc1c149afbf4c8996fb924\
+                                           27ae41e4649b934ca495991b7852b855");
+        assert_eq!(items[1].path(), Path::new("sub/b"));
+        assert_eq!(items[1].size(), 3);
+        assert_eq!(items[1].hex_digest(), "ba7816bf8f01cfea414140de5dae2223\
+                                           b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(items[1].mtime(),
+                   std::fs::metadata(tmp.path().join("sub/b")).unwrap()
+                   .modified().unwrap());
+        let items = dir.manifest("sub", HashAlgo::Sha512).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(items[0].path(), Path::new("b"));
+        assert_eq!(items[0].digest().len(), 64);
+    }
+}
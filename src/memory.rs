@@ -0,0 +1,650 @@
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::mem;
+use std::collections::BTreeMap;
+use std::ffi::{CString, OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+use std::os::unix::ffi::OsStrExt;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{AsPath, Entry, Metadata, SimpleType, IntoMode};
+use crate::dir::to_cstr;
+use crate::metadata;
+
+
+static INODE: AtomicU64 = AtomicU64::new(1);
+
+type NodeRef = Arc<Mutex<Node>>;
+
+#[derive(Debug)]
+struct Node {
+    ino: u64,
+    mode: libc::mode_t,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Kind {
+    File(Vec<u8>),
+    Dir {
+        entries: BTreeMap<OsString, NodeRef>,
+        parent: Weak<Mutex<Node>>,
+    },
+    Symlink(PathBuf),
+}
+
+/// An in-memory directory tree with the same methods as `Dir`
+///
+/// This is useful for unit tests of code written against this crate, so
+/// they don't need to touch the real filesystem. Enabled by `mock` feature.
+///
+/// The semantics follow `Dir` as close as practical (i.e. symlinks at the
+/// last path component are never followed, errors have the same OS error
+/// codes), with the following limitations:
+///
+/// * Symlinks in the middle of the path are not followed either, such
+///   paths fail with `ELOOP`
+/// * There are no owners, permissions are stored but not checked, and
+///   umask is not applied
+/// * Absolute paths are resolved against the root of the tree
+///
+/// Cloning `MemoryDir` creates a new handle to the same directory.
+#[derive(Debug, Clone)]
+pub struct MemoryDir {
+    root: NodeRef,
+    node: NodeRef,
+}
+
+/// A file opened in `MemoryDir`
+///
+/// Multiple opened files (and the tree itself) share the same contents,
+/// like real files do.
+#[derive(Debug)]
+pub struct MemoryFile {
+    node: NodeRef,
+    pos: u64,
+    read: bool,
+    write: bool,
+    append: bool,
+}
+
+/// Iterator over entries of `MemoryDir`, created by `MemoryDir::list_dir()`
+#[derive(Debug)]
+pub struct MemoryDirIter {
+    entries: std::vec::IntoIter<Entry>,
+}
+
+fn error(code: libc::c_int) -> io::Error {
+    io::Error::from_raw_os_error(code)
+}
+
+fn lock(node: &NodeRef) -> MutexGuard<'_, Node> {
+    node.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn new_node(mode: libc::mode_t, kind: Kind) -> NodeRef {
+    Arc::new(Mutex::new(Node {
+        ino: INODE.fetch_add(1, Ordering::Relaxed),
+        mode: mode & 0o7777,
+        kind,
+    }))
+}
+
+fn to_path<P: AsPath>(path: P) -> io::Result<PathBuf> {
+    let path = to_cstr(path)?;
+    Ok(Path::new(OsStr::from_bytes(path.as_ref().to_bytes())).to_path_buf())
+}
+
+impl Node {
+    fn simple_type(&self) -> SimpleType {
+        match self.kind {
+            Kind::File(_) => SimpleType::File,
+            Kind::Dir { .. } => SimpleType::Dir,
+            Kind::Symlink(_) => SimpleType::Symlink,
+        }
+    }
+    fn entries(&mut self) -> io::Result<&mut BTreeMap<OsString, NodeRef>> {
+        match self.kind {
+            Kind::Dir { ref mut entries, .. } => Ok(entries),
+            _ => Err(error(libc::ENOTDIR)),
+        }
+    }
+}
+
+impl MemoryDir {
+    /// Create a new empty tree and return its root directory
+    pub fn new() -> MemoryDir {
+        let root = new_node(0o755, Kind::Dir {
+            entries: BTreeMap::new(),
+            parent: Weak::new(),
+        });
+        MemoryDir { root: root.clone(), node: root }
+    }
+
+    /// Walk all components of the path except the last one
+    fn parent(&self, path: &Path) -> io::Result<(NodeRef, Option<OsString>)> {
+        let mut components = path.components().peekable();
+        if components.peek().is_none() {
+            return Err(error(libc::ENOENT));
+        }
+        let mut node = self.node.clone();
+        while let Some(component) = components.next() {
+            let last = components.peek().is_none();
+            let next = match component {
+                Component::RootDir => self.root.clone(),
+                Component::CurDir => node.clone(),
+                Component::ParentDir => match lock(&node).kind {
+                    Kind::Dir { ref parent, .. } => {
+                        parent.upgrade().unwrap_or_else(|| node.clone())
+                    }
+                    _ => return Err(error(libc::ENOTDIR)),
+                },
+                Component::Normal(name) if last => {
+                    lock(&node).entries()?;
+                    return Ok((node, Some(name.to_owned())));
+                }
+                Component::Normal(name) => {
+                    lock(&node).entries()?.get(name).cloned()
+                        .ok_or_else(|| error(libc::ENOENT))?
+                }
+                Component::Prefix(_) => unreachable!(),
+            };
+            node = next;
+            match lock(&node).kind {
+                Kind::Dir { .. } => {}
+                Kind::Symlink(_) => return Err(error(libc::ELOOP)),
+                Kind::File(_) => return Err(error(libc::ENOTDIR)),
+            }
+        }
+        Ok((node, None))
+    }
+
+    /// Find the node at path (the last symlink is not followed)
+    fn lookup(&self, path: &Path) -> io::Result<NodeRef> {
+        match self.parent(path)? {
+            (dir, Some(name)) => {
+                lock(&dir).entries()?.get(&name).cloned()
+                    .ok_or_else(|| error(libc::ENOENT))
+            }
+            (dir, None) => Ok(dir),
+        }
+    }
+
+    /// Find the parent directory and the name of the last component
+    fn parent_and_name(&self, path: &Path) -> io::Result<(NodeRef, OsString)>
+    {
+        match self.parent(path)? {
+            (dir, Some(name)) => Ok((dir, name)),
+            // like `mkdir(".")` or `rmdir("..")`
+            (_, None) => Err(error(libc::EINVAL)),
+        }
+    }
+
+    /// Open subdirectory
+    pub fn sub_dir<P: AsPath>(&self, path: P) -> io::Result<MemoryDir> {
+        let node = self.lookup(&to_path(path)?)?;
+        let is_dir = lock(&node).entries().is_ok();
+        if is_dir {
+            Ok(MemoryDir { root: self.root.clone(), node })
+        } else {
+            Err(error(libc::ENOTDIR))
+        }
+    }
+
+    /// List subdirectory of this dir
+    pub fn list_dir<P: AsPath>(&self, path: P) -> io::Result<MemoryDirIter> {
+        self.sub_dir(path)?.list_self()
+    }
+
+    /// List this dir
+    pub fn list_self(&self) -> io::Result<MemoryDirIter> {
+        let mut node = lock(&self.node);
+        let entries = node.entries()?.iter()
+            .map(|(name, child)| {
+                let child = lock(child);
+                Entry {
+                    name: CString::new(name.as_bytes())
+                        .expect("names never contain nul"),
+                    file_type: Some(child.simple_type()),
+                    ino: child.ino,
+                    dir: None,
+                }
+            })
+            .collect::<Vec<_>>();
+        Ok(MemoryDirIter { entries: entries.into_iter() })
+    }
+
+    /// Returns metadata of an entry in this directory
+    ///
+    /// Only file type, permissions, size, inode number and link count are
+    /// filled in.
+    pub fn metadata<P: AsPath>(&self, path: P) -> io::Result<Metadata> {
+        let node = self.lookup(&to_path(path)?)?;
+        let node = lock(&node);
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        let (typ, size, nlink) = match node.kind {
+            Kind::File(ref data) => (libc::S_IFREG, data.len(), 1),
+            Kind::Dir { ref entries, .. } => {
+                (libc::S_IFDIR, 0, 2 + entries.len())
+            }
+            Kind::Symlink(ref target) => {
+                (libc::S_IFLNK, target.as_os_str().len(), 1)
+            }
+        };
+        stat.st_mode = typ | node.mode;
+        stat.st_size = size as libc::off_t;
+        stat.st_ino = node.ino as libc::ino_t;
+        stat.st_nlink = nlink as libc::nlink_t;
+        Ok(metadata::new(stat))
+    }
+
+    /// Open file for reading in this directory
+    pub fn open_file<P: AsPath>(&self, path: P) -> io::Result<MemoryFile> {
+        self._open_file(&to_path(path)?, libc::O_RDONLY, 0)
+    }
+
+    /// Open file for writing, create if necessary, truncate on open
+    pub fn write_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<MemoryFile>
+    {
+        self._open_file(&to_path(path)?,
+            libc::O_CREAT|libc::O_WRONLY|libc::O_TRUNC,
+            mode.into_mode())
+    }
+
+    /// Open file for append, create if necessary
+    pub fn append_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<MemoryFile>
+    {
+        self._open_file(&to_path(path)?,
+            libc::O_CREAT|libc::O_WRONLY|libc::O_APPEND,
+            mode.into_mode())
+    }
+
+    /// Create file if not exists, fail if exists
+    pub fn new_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<MemoryFile>
+    {
+        self._open_file(&to_path(path)?,
+            libc::O_CREAT|libc::O_EXCL|libc::O_WRONLY,
+            mode.into_mode())
+    }
+
+    /// Open file for reading and writing without truncation, create if needed
+    pub fn update_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<MemoryFile>
+    {
+        self._open_file(&to_path(path)?,
+            libc::O_CREAT|libc::O_RDWR,
+            mode.into_mode())
+    }
+
+    fn _open_file(&self, path: &Path, flags: libc::c_int, mode: libc::mode_t)
+        -> io::Result<MemoryFile>
+    {
+        let (dir, name) = self.parent_and_name(path)?;
+        let node = {
+            let mut dir = lock(&dir);
+            let entries = dir.entries()?;
+            match entries.get(&name) {
+                Some(_) if flags & libc::O_EXCL != 0 => {
+                    return Err(error(libc::EEXIST));
+                }
+                Some(node) => node.clone(),
+                None if flags & libc::O_CREAT != 0 => {
+                    let node = new_node(mode, Kind::File(Vec::new()));
+                    entries.insert(name, node.clone());
+                    node
+                }
+                None => return Err(error(libc::ENOENT)),
+            }
+        };
+        match lock(&node).kind {
+            Kind::File(ref mut data) => {
+                if flags & libc::O_TRUNC != 0 {
+                    data.clear();
+                }
+            }
+            Kind::Dir { .. } => return Err(error(libc::EISDIR)),
+            Kind::Symlink(_) => return Err(error(libc::ELOOP)),
+        }
+        let access = flags & libc::O_ACCMODE;
+        Ok(MemoryFile {
+            node,
+            pos: 0,
+            read: access == libc::O_RDONLY || access == libc::O_RDWR,
+            write: access == libc::O_WRONLY || access == libc::O_RDWR,
+            append: flags & libc::O_APPEND != 0,
+        })
+    }
+
+    /// Create a subdirectory in this directory
+    pub fn create_dir<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<()>
+    {
+        let (dir, name) = self.parent_and_name(&to_path(path)?)?;
+        let node = new_node(mode.into_mode(), Kind::Dir {
+            entries: BTreeMap::new(),
+            parent: Arc::downgrade(&dir),
+        });
+        self.insert(&dir, name, node)
+    }
+
+    /// Make a symlink in this directory
+    pub fn symlink<P: AsPath, R: AsPath>(&self, path: P, value: R)
+        -> io::Result<()>
+    {
+        let (dir, name) = self.parent_and_name(&to_path(path)?)?;
+        let node = new_node(0o777, Kind::Symlink(to_path(value)?));
+        self.insert(&dir, name, node)
+    }
+
+    fn insert(&self, dir: &NodeRef, name: OsString, node: NodeRef)
+        -> io::Result<()>
+    {
+        let mut dir = lock(dir);
+        let entries = dir.entries()?;
+        if entries.contains_key(&name) {
+            return Err(error(libc::EEXIST));
+        }
+        entries.insert(name, node);
+        Ok(())
+    }
+
+    /// Read link in this directory
+    pub fn read_link<P: AsPath>(&self, path: P) -> io::Result<PathBuf> {
+        let node = self.lookup(&to_path(path)?)?;
+        let node = lock(&node);
+        match node.kind {
+            Kind::Symlink(ref target) => Ok(target.clone()),
+            _ => Err(error(libc::EINVAL)),
+        }
+    }
+
+    /// Rename a file in this directory to another name (keeping same dir)
+    ///
+    /// Like `rename(2)` it replaces the target if it's a file, or an empty
+    /// directory (if the source is a directory too).
+    pub fn local_rename<P: AsPath, R: AsPath>(&self, old: P, new: R)
+        -> io::Result<()>
+    {
+        let (old_dir, old_name) = self.parent_and_name(&to_path(old)?)?;
+        let (new_dir, new_name) = self.parent_and_name(&to_path(new)?)?;
+        let node = lock(&old_dir).entries()?.get(&old_name).cloned()
+            .ok_or_else(|| error(libc::ENOENT))?;
+        let is_dir = lock(&node).entries().is_ok();
+        // directory can't be moved into itself
+        if is_dir {
+            let mut cur = Some(new_dir.clone());
+            while let Some(dir) = cur {
+                if Arc::ptr_eq(&dir, &node) {
+                    return Err(error(libc::EINVAL));
+                }
+                cur = match lock(&dir).kind {
+                    Kind::Dir { ref parent, .. } => parent.upgrade(),
+                    _ => None,
+                };
+            }
+        }
+        if let Some(target) = lock(&new_dir).entries()?.get(&new_name) {
+            if Arc::ptr_eq(target, &node) {
+                return Ok(());
+            }
+            let mut target = lock(target);
+            match (is_dir, target.entries()) {
+                (true, Ok(entries)) if !entries.is_empty() => {
+                    return Err(error(libc::ENOTEMPTY));
+                }
+                (true, Ok(_)) | (false, Err(_)) => {}
+                (true, Err(_)) => return Err(error(libc::ENOTDIR)),
+                (false, Ok(_)) => return Err(error(libc::EISDIR)),
+            }
+        }
+        lock(&old_dir).entries()?.remove(&old_name);
+        if let Kind::Dir { ref mut parent, .. } = lock(&node).kind {
+            *parent = Arc::downgrade(&new_dir);
+        }
+        lock(&new_dir).entries()?.insert(new_name, node);
+        Ok(())
+    }
+
+    /// Remove a file in this directory
+    pub fn remove_file<P: AsPath>(&self, path: P) -> io::Result<()> {
+        let (dir, name) = self.parent_and_name(&to_path(path)?)?;
+        let mut dir = lock(&dir);
+        let entries = dir.entries()?;
+        match entries.get(&name) {
+            None => return Err(error(libc::ENOENT)),
+            Some(node) if lock(node).entries().is_ok() => {
+                return Err(error(libc::EISDIR));
+            }
+            Some(_) => {}
+        }
+        entries.remove(&name);
+        Ok(())
+    }
+
+    /// Remove a subdirectory in this directory
+    ///
+    /// Note only empty directory may be removed
+    pub fn remove_dir<P: AsPath>(&self, path: P) -> io::Result<()> {
+        let (dir, name) = self.parent_and_name(&to_path(path)?)?;
+        let mut dir = lock(&dir);
+        let entries = dir.entries()?;
+        match entries.get(&name) {
+            None => return Err(error(libc::ENOENT)),
+            Some(node) => match lock(node).entries() {
+                Ok(children) if !children.is_empty() => {
+                    return Err(error(libc::ENOTEMPTY));
+                }
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        entries.remove(&name);
+        Ok(())
+    }
+
+    /// Remove a subdirectory in this directory with all its contents
+    pub fn remove_recursive<P: AsPath>(&self, path: P) -> io::Result<()> {
+        let (dir, name) = self.parent_and_name(&to_path(path)?)?;
+        let mut dir = lock(&dir);
+        let entries = dir.entries()?;
+        match entries.get(&name) {
+            None => return Err(error(libc::ENOENT)),
+            Some(node) => { lock(node).entries()?; }
+        }
+        entries.remove(&name);
+        Ok(())
+    }
+}
+
+impl Default for MemoryDir {
+    fn default() -> MemoryDir {
+        MemoryDir::new()
+    }
+}
+
+impl MemoryFile {
+    /// Returns metadata of the opened file
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        let node = lock(&self.node);
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        if let Kind::File(ref data) = node.kind {
+            stat.st_size = data.len() as libc::off_t;
+        }
+        stat.st_mode = libc::S_IFREG | node.mode;
+        stat.st_ino = node.ino as libc::ino_t;
+        stat.st_nlink = 1;
+        Ok(metadata::new(stat))
+    }
+    /// Truncate or extend the file
+    pub fn set_len(&self, len: u64) -> io::Result<()> {
+        if !self.write {
+            return Err(error(libc::EINVAL));
+        }
+        self.with_data(|data| data.resize(len as usize, 0));
+        Ok(())
+    }
+    fn with_data<T, F: FnOnce(&mut Vec<u8>) -> T>(&self, f: F) -> T {
+        match lock(&self.node).kind {
+            Kind::File(ref mut data) => f(data),
+            _ => unreachable!("only files can be opened"),
+        }
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.read {
+            return Err(error(libc::EBADF));
+        }
+        let pos = self.pos as usize;
+        let n = self.with_data(|data| {
+            if pos >= data.len() {
+                return 0;
+            }
+            let n = buf.len().min(data.len() - pos);
+            buf[..n].copy_from_slice(&data[pos..pos+n]);
+            n
+        });
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.write {
+            return Err(error(libc::EBADF));
+        }
+        let append = self.append;
+        let pos = self.pos as usize;
+        self.pos = self.with_data(|data| {
+            let pos = if append { data.len() } else { pos };
+            if data.len() < pos + buf.len() {
+                data.resize(pos + buf.len(), 0);
+            }
+            data[pos..pos+buf.len()].copy_from_slice(buf);
+            (pos + buf.len()) as u64
+        });
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.with_data(|data| data.len() as u64);
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(off) => (len as i64).checked_add(off)
+                .filter(|&x| x >= 0).map(|x| x as u64),
+            SeekFrom::Current(off) => (self.pos as i64).checked_add(off)
+                .filter(|&x| x >= 0).map(|x| x as u64),
+        };
+        self.pos = pos.ok_or_else(|| error(libc::EINVAL))?;
+        Ok(self.pos)
+    }
+}
+
+impl Iterator for MemoryDirIter {
+    type Item = io::Result<Entry>;
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        self.entries.next().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write, Seek, SeekFrom};
+    use std::path::Path;
+    use crate::{SimpleType, ErrorKind, error_kind};
+    use super::MemoryDir;
+
+    fn read(dir: &MemoryDir, path: &str) -> String {
+        let mut buf = String::new();
+        dir.open_file(path).unwrap().read_to_string(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn files() {
+        let dir = MemoryDir::new();
+        dir.write_file("file", 0o644).unwrap().write_all(b"hello").unwrap();
+        dir.append_file("file", 0o644).unwrap().write_all(b" world").unwrap();
+        assert_eq!(read(&dir, "file"), "hello world");
+        let mut file = dir.update_file("file", 0o644).unwrap();
+        file.seek(SeekFrom::Start(6)).unwrap();
+        file.write_all(b"there").unwrap();
+        assert_eq!(read(&dir, "file"), "hello there");
+        let meta = dir.metadata("file").unwrap();
+        assert!(meta.is_file());
+        assert_eq!(meta.len(), 11);
+        assert_eq!(meta.stat().st_mode & 0o777, 0o644);
+        let err = dir.new_file("file", 0o644).unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::AlreadyExists);
+        dir.remove_file("file").unwrap();
+        let err = dir.open_file("file").unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn dirs() {
+        let dir = MemoryDir::new();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.create_dir("a/b", 0o755).unwrap();
+        dir.write_file("a/b/file", 0o644).unwrap();
+        let sub = dir.sub_dir("a/b").unwrap();
+        assert_eq!(read(&sub, "../../a/b/file"), "");
+        assert_eq!(read(&sub, "/a/b/file"), "");
+        assert!(dir.metadata("a").unwrap().is_dir());
+        let names = dir.list_dir("a").unwrap()
+            .map(|e| e.unwrap().file_name().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["b"]);
+        let err = dir.remove_dir("a").unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::DirectoryNotEmpty);
+        let err = dir.open_file("a/b/file/x").unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::NotADirectory);
+        dir.remove_recursive("a").unwrap();
+        assert_eq!(dir.list_dir(".").unwrap().count(), 0);
+        // handle to removed directory still works
+        sub.write_file("other", 0o644).unwrap();
+    }
+
+    #[test]
+    fn symlinks() {
+        let dir = MemoryDir::new();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.symlink("link", "a").unwrap();
+        assert_eq!(dir.read_link("link").unwrap(), Path::new("a"));
+        let entry = dir.list_dir(".").unwrap()
+            .find(|e| e.as_ref().unwrap().file_name() == "link")
+            .unwrap().unwrap();
+        assert_eq!(entry.simple_type(), Some(SimpleType::Symlink));
+        let err = dir.open_file("link").unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::FilesystemLoop);
+        let err = dir.write_file("link/file", 0o644).unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::FilesystemLoop);
+    }
+
+    #[test]
+    fn rename() {
+        let dir = MemoryDir::new();
+        dir.create_dir("a", 0o755).unwrap();
+        dir.create_dir("b", 0o755).unwrap();
+        dir.write_file("a/file", 0o644).unwrap().write_all(b"x").unwrap();
+        dir.local_rename("a/file", "b/file").unwrap();
+        assert_eq!(read(&dir, "b/file"), "x");
+        let err = dir.local_rename("a", "a/sub").unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::InvalidInput);
+        let err = dir.local_rename("a", "b").unwrap_err();
+        assert_eq!(error_kind(&err), ErrorKind::DirectoryNotEmpty);
+        dir.local_rename("b", "a").unwrap();
+        assert_eq!(read(&dir, "a/file"), "x");
+        assert_eq!(read(&dir, "a/../a/file"), "x");
+    }
+}
@@ -1,9 +1,13 @@
+use std::io;
 use std::fs::Permissions;
 use std::os::unix::fs::PermissionsExt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use libc;
 
 use crate::SimpleType;
+#[cfg(target_os="linux")]
+use crate::StatxMask;
 
 
 /// A file metadata
@@ -12,20 +16,36 @@ use crate::SimpleType;
 /// implement our own structure.
 pub struct Metadata {
     stat: libc::stat,
+    // fields filled in (`STATX_*` bits), all the basic ones for `stat`
+    #[cfg(target_os="linux")]
+    mask: u32,
+    #[cfg(target_os="linux")]
+    btime: Option<SystemTime>,
 }
 
 impl Metadata {
     /// Returns simplified type of the directory entry
+    ///
+    /// On Linux it's `Other` if metadata is fetched using `statx` and the
+    /// type isn't provided, see `provided()`. Same for the `is_*` methods
+    /// which return `false` then.
     pub fn simple_type(&self) -> SimpleType {
         let typ = self.stat.st_mode & libc::S_IFMT;
         match typ {
             libc::S_IFREG => SimpleType::File,
             libc::S_IFDIR => SimpleType::Dir,
             libc::S_IFLNK => SimpleType::Symlink,
+            libc::S_IFSOCK => SimpleType::Socket,
+            libc::S_IFIFO => SimpleType::Fifo,
+            libc::S_IFCHR => SimpleType::CharDevice,
+            libc::S_IFBLK => SimpleType::BlockDevice,
             _ => SimpleType::Other,
         }
     }
     /// Returns underlying stat structure
+    ///
+    /// On Linux, fields that `statx` didn't provide are zero, see
+    /// `provided()`.
     pub fn stat(&self) -> &libc::stat {
         &self.stat
     }
@@ -37,18 +57,169 @@ impl Metadata {
     pub fn is_dir(&self) -> bool {
         self.simple_type() == SimpleType::Dir
     }
+    /// Returns `true` if the entry is a symbolic link
+    pub fn is_symlink(&self) -> bool {
+        self.simple_type() == SimpleType::Symlink
+    }
+    /// Returns `true` if the entry is a block device
+    pub fn is_block_device(&self) -> bool {
+        self.simple_type() == SimpleType::BlockDevice
+    }
+    /// Returns `true` if the entry is a character device
+    pub fn is_char_device(&self) -> bool {
+        self.simple_type() == SimpleType::CharDevice
+    }
+    /// Returns `true` if the entry is a unix socket
+    pub fn is_socket(&self) -> bool {
+        self.simple_type() == SimpleType::Socket
+    }
+    /// Returns `true` if the entry is a named pipe
+    pub fn is_fifo(&self) -> bool {
+        self.simple_type() == SimpleType::Fifo
+    }
     /// Returns permissions of the entry
+    ///
+    /// On Linux the mode is zero if metadata is fetched using `statx` and
+    /// the filesystem didn't provide it, see `provided()`.
     pub fn permissions(&self) -> Permissions {
         Permissions::from_mode(self.stat.st_mode as u32)
     }
     /// Returns file size
+    ///
+    /// On Linux it's zero if metadata is fetched using `statx` and the
+    /// filesystem didn't provide the size, see `provided()`.
     pub fn len(&self) -> u64 {
         self.stat.st_size as u64
     }
+    /// Returns last access time
+    ///
+    /// Same as `std::fs::Metadata::accessed`, it only fails if metadata
+    /// is fetched using `statx` and the filesystem didn't provide the time.
+    pub fn accessed(&self) -> io::Result<SystemTime> {
+        #[cfg(target_os="linux")]
+        self.require(libc::STATX_ATIME, "access time")?;
+        Ok(to_time(self.stat.st_atime, self.stat.st_atime_nsec))
+    }
+    /// Returns last modification time
+    ///
+    /// Fails in the same cases as `accessed()`.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        #[cfg(target_os="linux")]
+        self.require(libc::STATX_MTIME, "modification time")?;
+        Ok(to_time(self.stat.st_mtime, self.stat.st_mtime_nsec))
+    }
+    /// Returns creation time of the entry
+    ///
+    /// Returns the error of kind `Unsupported` where `btime()` is `None`.
+    /// On Linux this means that metadata wasn't fetched using `statx` or
+    /// the filesystem didn't provide the time.
+    #[cfg(target_os="linux")]
+    pub fn created(&self) -> io::Result<SystemTime> {
+        self.btime().ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported,
+            "creation time is not returned by statx \
+             (and never returned by stat)"))
+    }
+    /// Returns creation time of the entry
+    ///
+    /// Returns the error of kind `Unsupported` where `btime()` is `None`.
+    #[cfg(not(target_os="linux"))]
+    pub fn created(&self) -> io::Result<SystemTime> {
+        self.btime().ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported,
+            "creation time is not available on this platform"))
+    }
+    /// Returns birth (creation) time of the entry
+    ///
+    /// It's only available in `stat` on macOS and FreeBSD, `None` is
+    /// returned on other systems.
+    #[cfg(any(target_os="macos", target_os="freebsd"))]
+    pub fn btime(&self) -> Option<SystemTime> {
+        Some(to_time(self.stat.st_birthtime, self.stat.st_birthtime_nsec))
+    }
+    /// Returns birth (creation) time of the entry
+    ///
+    /// On Linux it's only available if metadata is fetched using `statx`
+    /// (see `Dir::statx`) and the filesystem supports it.
+    #[cfg(target_os="linux")]
+    pub fn btime(&self) -> Option<SystemTime> {
+        self.btime
+    }
+    /// Returns birth (creation) time of the entry
+    ///
+    /// It's only available in `stat` on macOS and FreeBSD, `None` is
+    /// returned on other systems.
+    #[cfg(not(any(target_os="linux",
+                  target_os="macos", target_os="freebsd")))]
+    pub fn btime(&self) -> Option<SystemTime> {
+        None
+    }
+    /// Returns `true` if all the fields in `mask` were filled in
+    ///
+    /// Metadata fetched by `stat` always has all the basic fields, but
+    /// `statx` (used by `Dir::statx` and `Dir::stat_many`) reports which
+    /// fields the filesystem actually provided. Fields which are not
+    /// provided are zero (so `len()`, `permissions()`, `stat()` and similar
+    /// getters return zeros for them), and time getters return an error.
+    #[cfg(target_os="linux")]
+    pub fn provided(&self, mask: StatxMask) -> bool {
+        self.mask & mask.raw() == mask.raw()
+    }
+    #[cfg(target_os="linux")]
+    fn require(&self, bit: u32, field: &'static str) -> io::Result<()> {
+        if self.mask & bit == bit {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::Unsupported,
+                format!("{} is not provided by the filesystem", field)))
+        }
+    }
+}
+
+// c_long and time_t are i32 on 32-bit systems
+#[allow(clippy::useless_conversion)]
+fn to_time(sec: libc::time_t, nsec: libc::c_long) -> SystemTime {
+    let nsec = Duration::from_nanos(i64::from(nsec) as u64);
+    if sec >= 0 {
+        UNIX_EPOCH + Duration::from_secs(sec as u64) + nsec
+    } else {
+        UNIX_EPOCH - Duration::from_secs(u64::from(sec.unsigned_abs())) + nsec
+    }
 }
 
 pub fn new(stat: libc::stat) -> Metadata {
-    Metadata { stat: stat }
+    Metadata {
+        stat: stat,
+        #[cfg(target_os="linux")]
+        mask: libc::STATX_BASIC_STATS,
+        #[cfg(target_os="linux")]
+        btime: None,
+    }
+}
+
+#[cfg(target_os="linux")]
+pub fn from_statx(stx: &libc::statx) -> Metadata {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    stat.st_dev = libc::makedev(stx.stx_dev_major, stx.stx_dev_minor);
+    stat.st_ino = stx.stx_ino as _;
+    stat.st_mode = stx.stx_mode as _;
+    stat.st_nlink = stx.stx_nlink as _;
+    stat.st_uid = stx.stx_uid;
+    stat.st_gid = stx.stx_gid;
+    stat.st_rdev = libc::makedev(stx.stx_rdev_major, stx.stx_rdev_minor);
+    stat.st_size = stx.stx_size as _;
+    stat.st_blksize = stx.stx_blksize as _;
+    stat.st_blocks = stx.stx_blocks as _;
+    stat.st_atime = stx.stx_atime.tv_sec as _;
+    stat.st_atime_nsec = stx.stx_atime.tv_nsec as _;
+    stat.st_mtime = stx.stx_mtime.tv_sec as _;
+    stat.st_mtime_nsec = stx.stx_mtime.tv_nsec as _;
+    stat.st_ctime = stx.stx_ctime.tv_sec as _;
+    stat.st_ctime_nsec = stx.stx_ctime.tv_nsec as _;
+    let btime = if stx.stx_mask & libc::STATX_BTIME != 0 {
+        Some(to_time(stx.stx_btime.tv_sec as _, stx.stx_btime.tv_nsec as _))
+    } else {
+        None
+    };
+    Metadata { stat, mask: stx.stx_mask, btime }
 }
 
 #[cfg(test)]
@@ -72,4 +243,33 @@ mod test {
         assert!(!m.is_dir());
         assert!(m.is_file());
     }
+
+    #[test]
+    fn std_parity() {
+        let d = crate::Dir::open("src").unwrap();
+        let m = d.metadata("lib.rs").unwrap();
+        let std = std::fs::metadata("src/lib.rs").unwrap();
+        assert_eq!(m.accessed().unwrap(), std.accessed().unwrap());
+        assert_eq!(m.modified().unwrap(), std.modified().unwrap());
+        assert!(!m.is_symlink());
+        assert!(!m.is_fifo());
+        let dev = crate::Dir::open("/dev").unwrap();
+        let null = dev.metadata("null").unwrap();
+        assert!(null.is_char_device());
+        assert!(!null.is_block_device());
+        assert!(!null.is_socket());
+        #[cfg(any(target_os="macos", target_os="freebsd"))]
+        assert_eq!(m.created().unwrap(), std.created().unwrap());
+        #[cfg(target_os="linux")]
+        assert!(m.btime().is_none());
+        #[cfg(target_os="linux")]
+        assert!(m.created().unwrap_err().to_string().contains("statx"));
+    }
+
+    #[test]
+    fn times_before_epoch() {
+        assert_eq!(to_time(-1, 500_000_000),
+                   UNIX_EPOCH - Duration::from_millis(500));
+        assert_eq!(to_time(1, 1), UNIX_EPOCH + Duration::new(1, 1));
+    }
 }
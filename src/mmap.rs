@@ -0,0 +1,217 @@
+use std::io;
+use std::convert::TryFrom;
+use std::ops::Deref;
+use std::ptr::{self, NonNull};
+use std::slice;
+use std::os::unix::io::AsRawFd;
+
+use crate::{Dir, AsPath};
+use crate::dir::to_cstr;
+
+
+/// Options for `Dir::map_file`
+///
+/// By default the whole file is mapped read-only.
+#[derive(Debug, Clone, Default)]
+pub struct MapOptions {
+    copy_on_write: bool,
+    offset: u64,
+    len: Option<usize>,
+}
+
+/// A memory-mapped file, created by `Dir::map_file`
+///
+/// The file is unmapped when this is dropped. Dereferences to the mapped
+/// bytes.
+#[derive(Debug)]
+pub struct Mmap {
+    // start of the mapping, aligned to the page size
+    base: NonNull<u8>,
+    // offset of the data requested from the `base`
+    delta: usize,
+    len: usize,
+    writable: bool,
+}
+
+// the memory is owned by the `Mmap` just like memory of a `Vec`
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl MapOptions {
+    /// Create default options: whole file, read-only
+    pub fn new() -> MapOptions {
+        MapOptions::default()
+    }
+    /// Map the file in copy-on-write mode
+    ///
+    /// The mapped memory is writable (see `Mmap::as_mut_slice`), but the
+    /// changes are private to the mapping and are never written to the
+    /// file. The file itself is still opened read-only.
+    pub fn copy_on_write(&mut self, copy_on_write: bool) -> &mut Self {
+        self.copy_on_write = copy_on_write;
+        self
+    }
+    /// Map the file starting from `offset` (doesn't have to be aligned)
+    pub fn offset(&mut self, offset: u64) -> &mut Self {
+        self.offset = offset;
+        self
+    }
+    /// Map `len` bytes instead of the rest of the file
+    ///
+    /// Accessing the bytes past the end of the file results in `SIGBUS`,
+    /// so the length is not allowed to exceed the file size.
+    pub fn len(&mut self, len: usize) -> &mut Self {
+        self.len = Some(len);
+        self
+    }
+}
+
+impl Dir {
+    /// Map a file in this directory into memory
+    ///
+    /// The file is opened read-only (symlinks are not followed) and is
+    /// closed right after mapping, it's not needed to keep the map
+    /// alive. Empty files (or zero length) are supported and result in an
+    /// empty map.
+    ///
+    /// Requires `mmap` feature.
+    ///
+    /// # Safety
+    ///
+    /// The mapped memory is shared with the file. If the file is modified
+    /// while the map is alive, the contents of the slice change under your
+    /// feet (this includes `copy_on_write` maps, for pages not written
+    /// to), and if the file is truncated, accessing the bytes past the new
+    /// end kills the process with `SIGBUS`. So the caller must make sure
+    /// the file is not modified, e.g. by having files replaced atomically
+    /// via rename instead of overwritten.
+    pub unsafe fn map_file<P: AsPath>(&self, path: P, options: &MapOptions)
+        -> io::Result<Mmap>
+    {
+        let file = self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_RDONLY, 0)?;
+        let size = file.metadata()?.len();
+        let out_of_range = || io::Error::new(io::ErrorKind::InvalidInput,
+            "mapped range is out of the file bounds");
+        let rest = size.checked_sub(options.offset).ok_or_else(out_of_range)?;
+        let len = match options.len {
+            Some(len) if len as u64 > rest => return Err(out_of_range()),
+            Some(len) => len,
+            None => usize::try_from(rest).map_err(|_| out_of_range())?,
+        };
+        if len == 0 {
+            return Ok(Mmap {
+                base: NonNull::dangling(),
+                delta: 0,
+                len: 0,
+                writable: options.copy_on_write,
+            });
+        }
+        let page = libc::sysconf(libc::_SC_PAGESIZE) as u64;
+        let aligned = options.offset - options.offset % page;
+        let delta = (options.offset - aligned) as usize;
+        let (prot, flags) = if options.copy_on_write {
+            (libc::PROT_READ|libc::PROT_WRITE, libc::MAP_PRIVATE)
+        } else {
+            (libc::PROT_READ, libc::MAP_SHARED)
+        };
+        let res = libc::mmap(ptr::null_mut(), len + delta, prot, flags,
+            file.as_raw_fd(), aligned as libc::off_t);
+        if res == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap {
+            base: NonNull::new_unchecked(res as *mut u8),
+            delta,
+            len,
+            writable: options.copy_on_write,
+        })
+    }
+}
+
+impl Mmap {
+    /// Returns `true` if the map is created in copy-on-write mode
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+    /// Returns mutable slice of a copy-on-write map, `None` if read-only
+    pub fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        if !self.writable {
+            return None;
+        }
+        unsafe {
+            Some(slice::from_raw_parts_mut(
+                self.base.as_ptr().add(self.delta), self.len))
+        }
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(self.base.as_ptr().add(self.delta), self.len)
+        }
+    }
+}
+
+impl AsRef<[u8]> for Mmap {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.base.as_ptr() as *mut libc::c_void,
+                    self.len + self.delta);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use crate::{Dir, MapOptions};
+
+    #[test]
+    fn map_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let data = (0..10000u32).map(|x| x as u8).collect::<Vec<_>>();
+        dir.write_file("file", 0o644).unwrap().write_all(&data).unwrap();
+        dir.write_file("empty", 0o644).unwrap();
+        unsafe {
+            let map = dir.map_file("file", &MapOptions::new()).unwrap();
+            assert_eq!(&map[..], &data[..]);
+            let mut map = dir.map_file("file", &MapOptions::new()).unwrap();
+            assert!(map.as_mut_slice().is_none());
+
+            let map = dir.map_file("file",
+                MapOptions::new().offset(5000).len(100)).unwrap();
+            assert_eq!(&map[..], &data[5000..5100]);
+
+            let mut map = dir.map_file("file",
+                MapOptions::new().copy_on_write(true)).unwrap();
+            map.as_mut_slice().unwrap()[0] = 77;
+            assert_eq!(map[0], 77);
+
+            let map = dir.map_file("empty", &MapOptions::new()).unwrap();
+            assert!(map.is_empty());
+
+            let err = dir.map_file("file", MapOptions::new().offset(10001))
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let err = dir.map_file("file",
+                MapOptions::new().offset(9000).len(1001)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        // copy-on-write changes are not written to the file
+        let mut buf = Vec::new();
+        dir.open_file("file").unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt;
+use std::fs::Permissions;
+use std::sync::Mutex;
+use std::os::unix::fs::PermissionsExt;
+use std::ops::{BitOr, BitOrAssign};
+
+
+/// Permission bits of a newly created file or directory
+///
+/// Unlike plain `mode_t` it can't be confused with a decimal number (it's
+/// a common mistake to write `644` instead of `0o644`):
+///
+/// ```rust,no_run
+/// # use openat::{Dir, Mode};
+/// # let dir = Dir::open(".").unwrap();
+/// dir.write_file("secret.key", Mode::RW_USER).unwrap();
+/// dir.create_dir("shared", Mode::RWX_USER | Mode::GROUP_READ
+///                          | Mode::GROUP_EXEC | Mode::SETGID).unwrap();
+/// let mode = Mode::empty().user(true, true, false).group(true, false, false);
+/// assert_eq!(mode.raw(), 0o640);
+/// ```
+///
+/// All methods that create files accept `Mode`, raw `mode_t` and
+/// `std::fs::Permissions` (see `IntoMode`), as usual the process umask is
+/// applied to the bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mode(libc::mode_t);
+
+/// A value that can be used as permissions of a newly created file
+pub trait IntoMode {
+    /// Returns raw permission bits
+    fn into_mode(self) -> libc::mode_t;
+}
+
+impl Mode {
+    /// Read permission for the owner
+    pub const USER_READ: Mode = Mode(0o400);
+    /// Write permission for the owner
+    pub const USER_WRITE: Mode = Mode(0o200);
+    /// Execute (search for directories) permission for the owner
+    pub const USER_EXEC: Mode = Mode(0o100);
+    /// Read permission for the group
+    pub const GROUP_READ: Mode = Mode(0o040);
+    /// Write permission for the group
+    pub const GROUP_WRITE: Mode = Mode(0o020);
+    /// Execute (search for directories) permission for the group
+    pub const GROUP_EXEC: Mode = Mode(0o010);
+    /// Read permission for others
+    pub const OTHER_READ: Mode = Mode(0o004);
+    /// Write permission for others
+    pub const OTHER_WRITE: Mode = Mode(0o002);
+    /// Execute (search for directories) permission for others
+    pub const OTHER_EXEC: Mode = Mode(0o001);
+    /// Set user id on execution
+    pub const SETUID: Mode = Mode(0o4000);
+    /// Set group id on execution (inherit group for directories)
+    pub const SETGID: Mode = Mode(0o2000);
+    /// Only owners can remove or rename files in the directory
+    pub const STICKY: Mode = Mode(0o1000);
+
+    /// Read and write for the owner only (`0o600`)
+    pub const RW_USER: Mode = Mode(0o600);
+    /// Read, write and execute for the owner only (`0o700`)
+    pub const RWX_USER: Mode = Mode(0o700);
+    /// Read and write for the owner, read for others (`0o644`)
+    pub const RW_USER_R_ALL: Mode = Mode(0o644);
+    /// Full access for the owner, read and execute for others (`0o755`)
+    pub const RWX_USER_RX_ALL: Mode = Mode(0o755);
+
+    /// No permissions at all
+    pub fn empty() -> Mode {
+        Mode(0)
+    }
+    /// Create mode from raw bits
+    ///
+    /// Bits other than permissions, setuid, setgid and sticky are dropped.
+    pub fn from_raw(mode: libc::mode_t) -> Mode {
+        Mode(mode & 0o7777)
+    }
+    /// Returns raw bits
+    pub fn raw(&self) -> libc::mode_t {
+        self.0
+    }
+    /// Returns `true` if all bits of `other` are set
+    pub fn contains(&self, other: Mode) -> bool {
+        self.0 & other.0 == other.0
+    }
+    /// Set read, write and execute permissions for the owner
+    pub fn user(self, read: bool, write: bool, exec: bool) -> Mode {
+        self.rwx(6, read, write, exec)
+    }
+    /// Set read, write and execute permissions for the group
+    pub fn group(self, read: bool, write: bool, exec: bool) -> Mode {
+        self.rwx(3, read, write, exec)
+    }
+    /// Set read, write and execute permissions for others
+    pub fn other(self, read: bool, write: bool, exec: bool) -> Mode {
+        self.rwx(0, read, write, exec)
+    }
+    fn rwx(self, shift: u32, read: bool, write: bool, exec: bool) -> Mode {
+        let bits = (read as libc::mode_t) << 2
+                 | (write as libc::mode_t) << 1
+                 | exec as libc::mode_t;
+        Mode(self.0 & !(0o7 << shift) | bits << shift)
+    }
+}
+
+impl BitOr for Mode {
+    type Output = Mode;
+    fn bitor(self, other: Mode) -> Mode {
+        Mode(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Mode {
+    fn bitor_assign(&mut self, other: Mode) {
+        self.0 |= other.0;
+    }
+}
+
+impl fmt::Display for Mode {
+    /// Formats mode like `ls -l` does, e.g. `rwxr-sr-t`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = self.0;
+        let exec = |x, special, set: char, unset: char| {
+            match (m & x != 0, m & special != 0) {
+                (true, true) => set,
+                (false, true) => unset,
+                (true, false) => 'x',
+                (false, false) => '-',
+            }
+        };
+        let bit = |b, c| if m & b != 0 { c } else { '-' };
+        let s: String = [
+            bit(0o400, 'r'), bit(0o200, 'w'), exec(0o100, 0o4000, 's', 'S'),
+            bit(0o040, 'r'), bit(0o020, 'w'), exec(0o010, 0o2000, 's', 'S'),
+            bit(0o004, 'r'), bit(0o002, 'w'), exec(0o001, 0o1000, 't', 'T'),
+        ].iter().collect();
+        f.pad(&s)
+    }
+}
+
+impl IntoMode for libc::mode_t {
+    fn into_mode(self) -> libc::mode_t {
+        self
+    }
+}
+
+impl IntoMode for Mode {
+    fn into_mode(self) -> libc::mode_t {
+        self.0
+    }
+}
+
+impl IntoMode for Permissions {
+    fn into_mode(self) -> libc::mode_t {
+        (&self).into_mode()
+    }
+}
+
+impl IntoMode for &Permissions {
+    fn into_mode(self) -> libc::mode_t {
+        (self.mode() & 0o7777) as libc::mode_t
+    }
+}
+
+impl From<Permissions> for Mode {
+    fn from(perm: Permissions) -> Mode {
+        Mode(perm.into_mode())
+    }
+}
+
+impl From<Mode> for Permissions {
+    // mode_t is u16 on some systems
+    #[allow(clippy::useless_conversion)]
+    fn from(mode: Mode) -> Permissions {
+        Permissions::from_mode(u32::from(mode.0))
+    }
+}
+
+impl From<Mode> for libc::mode_t {
+    fn from(mode: Mode) -> libc::mode_t {
+        mode.0
+    }
+}
+
+/// Returns the file mode creation mask of the current process
+///
+/// There is no system call to just read the umask, so on Linux it's read
+/// from `/proc/self/status` (kernels since 4.7). Otherwise the mask is
+/// temporarily reset and restored, which may race with threads that create
+/// files at the same time (calls of this function are serialized though).
+pub fn umask() -> Mode {
+    #[cfg(target_os="linux")]
+    {
+        if let Some(mask) = proc_umask() {
+            return Mode(mask);
+        }
+    }
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe {
+        let mask = libc::umask(0o022);
+        libc::umask(mask);
+        Mode(mask)
+    }
+}
+
+#[cfg(target_os="linux")]
+fn proc_umask() -> Option<libc::mode_t> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("Umask:"))?;
+    libc::mode_t::from_str_radix(line["Umask:".len()..].trim(), 8).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Dir;
+    use super::Mode;
+
+    #[test]
+    fn builder() {
+        assert_eq!(Mode::empty().user(true, true, true)
+                   .group(true, false, true).other(false, false, false).raw(),
+                   0o750);
+        assert_eq!(Mode::RWX_USER_RX_ALL.group(false, false, false).raw(),
+                   0o705);
+        assert_eq!((Mode::RW_USER | Mode::GROUP_READ).raw(), 0o640);
+        assert!(Mode::RW_USER_R_ALL.contains(Mode::OTHER_READ));
+        assert_eq!(Mode::from_raw(0o100644), Mode::RW_USER_R_ALL);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Mode::RW_USER_R_ALL.to_string(), "rw-r--r--");
+        assert_eq!((Mode::RWX_USER_RX_ALL | Mode::STICKY).to_string(),
+                   "rwxr-xr-t");
+        assert_eq!((Mode::RW_USER | Mode::SETUID | Mode::SETGID).to_string(),
+                   "rwS--S---");
+    }
+
+    #[test]
+    fn create() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", Mode::RW_USER).unwrap();
+        dir.create_dir("dir", Mode::RWX_USER).unwrap();
+        assert_eq!(dir.metadata("file").unwrap().stat().st_mode & 0o777,
+                   0o600);
+        assert_eq!(dir.metadata("dir").unwrap().stat().st_mode & 0o777,
+                   0o700);
+    }
+
+    #[test]
+    fn default_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir_default("dir").unwrap();
+        assert_eq!(dir.metadata("dir").unwrap().stat().st_mode & 0o777,
+                   0o777 & !super::umask().raw());
+    }
+
+    #[test]
+    fn umask() {
+        let mask = super::umask();
+        assert_eq!(super::umask(), mask);
+        #[cfg(target_os="linux")]
+        assert_eq!(super::proc_umask(), Some(mask.raw()));
+    }
+
+    #[test]
+    fn permissions() {
+        use std::fs::Permissions;
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let perm = Permissions::from_mode(0o100640);
+        dir.new_file("file", &perm).unwrap();
+        dir.create_dir("dir", perm.clone()).unwrap();
+        assert_eq!(dir.metadata("file").unwrap().stat().st_mode & 0o777,
+                   0o640);
+        assert_eq!(dir.metadata("dir").unwrap().stat().st_mode & 0o777,
+                   0o640);
+        assert_eq!(Mode::from(perm), Mode::RW_USER | Mode::GROUP_READ);
+        assert_eq!(Permissions::from(Mode::RW_USER).mode(), 0o600);
+    }
+}
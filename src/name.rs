@@ -1,10 +1,76 @@
-use std::ffi::{OsStr, CStr, CString};
+use std::io;
+use std::fmt;
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString, CStr, CString};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
 use std::os::unix::ffi::OsStrExt;
 
 use crate::{Entry};
+use crate::dir::to_cstr;
+use crate::strict::validate_name;
 
 
+/// A file name which is known to be a single path component
+///
+/// The name is validated once at construction (see `validate_name()`) and
+/// then can be passed to any method of `Dir` without copying or checking
+/// it again:
+///
+/// ```rust,no_run
+/// # use openat::{Dir, FileName};
+/// let name = FileName::new("data.json").unwrap();
+/// for path in &["/srv/a", "/srv/b", "/srv/c"] {
+///     let dir = Dir::open(*path).unwrap();
+///     dir.remove_file(&name).unwrap();
+/// }
+/// ```
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileName(CString);
+
+impl FileName {
+    /// Validate and copy the name
+    ///
+    /// Returns error of kind `InvalidInput` if the name is not a single path
+    /// component.
+    pub fn new<P: AsPath>(name: P) -> io::Result<FileName> {
+        let name = to_cstr(name)?;
+        validate_name(name.as_ref().to_bytes())?;
+        Ok(FileName(name.as_ref().to_owned()))
+    }
+    /// Returns the name as a C string
+    pub fn as_c_str(&self) -> &CStr {
+        &self.0
+    }
+    /// Returns the name as an `OsStr`
+    pub fn as_os_str(&self) -> &OsStr {
+        OsStr::from_bytes(self.0.to_bytes())
+    }
+    /// Returns the underlying C string
+    pub fn into_c_string(self) -> CString {
+        self.0
+    }
+}
+
+impl fmt::Debug for FileName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_os_str().fmt(f)
+    }
+}
+
+impl AsRef<CStr> for FileName {
+    fn as_ref(&self) -> &CStr {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for FileName {
+    fn as_ref(&self) -> &Path {
+        Path::new(self.as_os_str())
+    }
+}
+
 /// The purpose of this is similar to `AsRef<Path>` but it's optimized for
 /// things that can be directly used as `CStr` (which is type passed to
 /// the underlying system call).
@@ -40,6 +106,55 @@ impl<'a> AsPath for &'a OsStr {
     }
 }
 
+impl AsPath for &OsString {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_bytes()).ok()
+    }
+}
+
+impl AsPath for Cow<'_, Path> {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_os_str().as_bytes()).ok()
+    }
+}
+
+impl AsPath for Box<Path> {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_os_str().as_bytes()).ok()
+    }
+}
+
+impl AsPath for Arc<Path> {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_os_str().as_bytes()).ok()
+    }
+}
+
+impl AsPath for &Arc<Path> {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_os_str().as_bytes()).ok()
+    }
+}
+
+impl AsPath for Rc<Path> {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_os_str().as_bytes()).ok()
+    }
+}
+
+impl AsPath for &Rc<Path> {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_os_str().as_bytes()).ok()
+    }
+}
+
 impl<'a> AsPath for &'a str {
     type Buffer = CString;
     fn to_path(self) -> Option<CString> {
@@ -68,9 +183,64 @@ impl<'a> AsPath for &'a CStr {
     }
 }
 
+impl<'a> AsPath for &'a FileName {
+    type Buffer = &'a CStr;
+    fn to_path(self) -> Option<&'a CStr> {
+        Some(&self.0)
+    }
+}
+
 impl<'a> AsPath for &'a Entry {
     type Buffer = &'a CStr;
     fn to_path(self) -> Option<&'a CStr> {
         Some(&self.name)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::borrow::Cow;
+    use std::ffi::{CStr, OsString};
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use crate::Dir;
+    use super::FileName;
+
+    #[test]
+    fn file_name() {
+        let name = FileName::new("file.txt").unwrap();
+        assert_eq!(name.as_c_str(),
+                   CStr::from_bytes_with_nul(b"file.txt\0").unwrap());
+        assert_eq!(format!("{:?}", name), "\"file.txt\"");
+        for bad in &["", ".", "..", "a/b", "a\0b"] {
+            assert_eq!(FileName::new(*bad).unwrap_err().kind(),
+                       io::ErrorKind::InvalidInput);
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file(&name, 0o644).unwrap();
+        dir.strict().remove_file(&name).unwrap();
+        assert!(dir.metadata(&name).is_err());
+    }
+
+    #[test]
+    fn smart_pointers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let path = Path::new("file");
+        let arc: Arc<Path> = Arc::from(path);
+        let rc: Rc<Path> = Rc::from(path);
+        dir.metadata(Cow::Borrowed(path)).unwrap();
+        dir.metadata(Cow::<Path>::Owned(path.to_path_buf())).unwrap();
+        dir.metadata(Box::<Path>::from(path)).unwrap();
+        dir.metadata(&arc).unwrap();
+        dir.metadata(arc).unwrap();
+        dir.metadata(&rc).unwrap();
+        dir.metadata(rc).unwrap();
+        dir.metadata(&OsString::from("file")).unwrap();
+        assert!(dir.metadata(Box::<Path>::from(Path::new("a\0b"))).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::io;
+use std::fs::File;
+
+use crate::{Dir, AsPath, IntoMode};
+use crate::dir::to_cstr;
+
+
+/// Options for opening files relative to a directory
+///
+/// This mirrors `std::fs::OpenOptions`, so the code can be ported by
+/// replacing `options.open(path)` with `options.open_at(&dir, path)`. Like
+/// other methods of `Dir`, `O_NOFOLLOW` and `O_CLOEXEC` are always set.
+///
+/// ```rust,no_run
+/// # use openat::{Dir, OpenOptions};
+/// # let dir = Dir::open(".").unwrap();
+/// let file = OpenOptions::new()
+///     .append(true)
+///     .create(true)
+///     .mode(0o600)
+///     .open_at(&dir, "log.txt").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    mode: libc::mode_t,
+    custom_flags: libc::c_int,
+}
+
+impl OpenOptions {
+    /// Create a blank set of options, all options are `false` initially
+    pub fn new() -> OpenOptions {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            mode: 0o666,
+            custom_flags: 0,
+        }
+    }
+    /// Open the file for reading
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+    /// Open the file for writing
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+    /// Open the file for appending (implies writing)
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+    /// Truncate the file on open (requires writing)
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+    /// Create the file if it doesn't exist (requires writing)
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+    /// Create the file, fail if it exists (requires writing)
+    ///
+    /// If set, `create` and `truncate` are ignored.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+    /// Permissions for the newly created file (default is `0o666`)
+    ///
+    /// The mode is masked by process umask.
+    pub fn mode<M: IntoMode>(&mut self, mode: M) -> &mut Self {
+        self.mode = mode.into_mode();
+        self
+    }
+    /// Additional flags passed to `openat`
+    ///
+    /// Access mode bits (`O_ACCMODE`) are ignored.
+    pub fn custom_flags(&mut self, flags: libc::c_int) -> &mut Self {
+        self.custom_flags = flags;
+        self
+    }
+    /// Open the file at `path` relative to the `dir`
+    pub fn open_at<P: AsPath>(&self, dir: &Dir, path: P) -> io::Result<File> {
+        let flags = self.access_mode()? | self.creation_mode()?
+            | (self.custom_flags & !libc::O_ACCMODE);
+        dir._open_file(to_cstr(path)?.as_ref(), flags, self.mode)
+    }
+
+    fn access_mode(&self) -> io::Result<libc::c_int> {
+        match (self.read, self.write, self.append) {
+            (true, false, false) => Ok(libc::O_RDONLY),
+            (false, true, false) => Ok(libc::O_WRONLY),
+            (true, true, false) => Ok(libc::O_RDWR),
+            (false, _, true) => Ok(libc::O_WRONLY | libc::O_APPEND),
+            (true, _, true) => Ok(libc::O_RDWR | libc::O_APPEND),
+            (false, false, false) => Err(invalid()),
+        }
+    }
+
+    fn creation_mode(&self) -> io::Result<libc::c_int> {
+        match (self.write, self.append) {
+            (true, false) => {}
+            (false, false) => {
+                if self.truncate || self.create || self.create_new {
+                    return Err(invalid());
+                }
+            }
+            (_, true) => {
+                if self.truncate && !self.create_new {
+                    return Err(invalid());
+                }
+            }
+        }
+        Ok(match (self.create, self.truncate, self.create_new) {
+            (false, false, false) => 0,
+            (true, false, false) => libc::O_CREAT,
+            (false, true, false) => libc::O_TRUNC,
+            (true, true, false) => libc::O_CREAT | libc::O_TRUNC,
+            (_, _, true) => libc::O_CREAT | libc::O_EXCL,
+        })
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> OpenOptions {
+        OpenOptions::new()
+    }
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "invalid open options")
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use crate::Dir;
+    use super::OpenOptions;
+
+    #[test]
+    fn create_and_append() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        OpenOptions::new().write(true).create_new(true).mode(0o600)
+            .open_at(&dir, "file").unwrap()
+            .write_all(b"hello").unwrap();
+        assert_eq!(dir.metadata("file").unwrap().stat().st_mode & 0o777,
+                   0o600);
+        assert_eq!(OpenOptions::new().write(true).create_new(true)
+                   .open_at(&dir, "file").unwrap_err().kind(),
+                   io::ErrorKind::AlreadyExists);
+        OpenOptions::new().append(true)
+            .open_at(&dir, "file").unwrap()
+            .write_all(b" world").unwrap();
+        let mut buf = String::new();
+        OpenOptions::new().read(true)
+            .open_at(&dir, "file").unwrap()
+            .read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello world");
+    }
+
+    #[test]
+    fn truncate() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap().write_all(b"hello").unwrap();
+        OpenOptions::new().write(true).truncate(true)
+            .open_at(&dir, "file").unwrap();
+        assert_eq!(dir.metadata("file").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn invalid() {
+        let dir = Dir::open(".").unwrap();
+        for opt in &[
+            OpenOptions::new(),
+            OpenOptions::new().read(true).create(true).clone(),
+            OpenOptions::new().append(true).truncate(true).clone(),
+        ] {
+            assert_eq!(opt.open_at(&dir, "Cargo.toml").unwrap_err().kind(),
+                       io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::io;
+
+use crate::{Dir, Entry, AsPath, DirCookie};
+
+
+/// A bounded batch of entries returned by `Dir::list_page`
+#[derive(Debug, Clone)]
+pub struct DirPage {
+    entries: Vec<Entry>,
+    next: Option<DirCookie>,
+}
+
+impl Dir {
+    /// List at most `max_entries` entries of the subdirectory starting
+    /// from `cursor`
+    ///
+    /// Use `DirCookie::start()` for the first page, and the cursor
+    /// returned by `DirPage::next` for the following ones. No descriptor
+    /// is held between the calls, and the cursor can be stored or sent to
+    /// a client (see `DirCookie::raw`), so this is suitable for paginating
+    /// directory contents over an API.
+    ///
+    /// As with `DirCookie`, entries added or removed between the calls
+    /// may or may not be returned. Only supported on Linux and Android.
+    pub fn list_page<P: AsPath>(&self, path: P, cursor: DirCookie,
+        max_entries: usize)
+        -> io::Result<DirPage>
+    {
+        let mut iter = self.list_dir(path)?;
+        iter.seek_cookie(cursor)?;
+        let mut entries = Vec::with_capacity(max_entries.min(1024));
+        loop {
+            let position = iter.cookie()?;
+            match iter.next() {
+                Some(entry) if entries.len() < max_entries => {
+                    entries.push(entry?);
+                }
+                // there are more entries, continue from this one
+                Some(_) => {
+                    return Ok(DirPage { entries, next: Some(position) });
+                }
+                None => return Ok(DirPage { entries, next: None }),
+            }
+        }
+    }
+}
+
+impl DirPage {
+    /// Returns the entries of this page
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+    /// Returns the cursor of the next page, `None` if this page is the last
+    pub fn next(&self) -> Option<DirCookie> {
+        self.next
+    }
+    /// Returns the entries, consuming the page
+    pub fn into_entries(self) -> Vec<Entry> {
+        self.entries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Dir, DirCookie};
+
+    #[test]
+    fn pages() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        for i in 0..25 {
+            dir.write_file(format!("sub/file{}", i), 0o644).unwrap();
+        }
+        let mut cursor = DirCookie::start();
+        let mut names = Vec::new();
+        let mut pages = 0;
+        loop {
+            let page = dir.list_page("sub", cursor, 10).unwrap();
+            assert!(page.entries().len() <= 10);
+            pages += 1;
+            let next = page.next();
+            names.extend(page.into_entries());
+            match next {
+                Some(next) => cursor = DirCookie::from_raw(next.raw()),
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 25);
+        let page = dir.list_page("sub", DirCookie::start(), 25).unwrap();
+        assert_eq!(page.entries().len(), 25);
+        assert_eq!(page.next(), None);
+    }
+}
@@ -0,0 +1,169 @@
+use std::io;
+use std::mem;
+use std::ptr;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use crate::Dir;
+
+// enough for a single `SCM_RIGHTS` message with one descriptor, u64 is
+// for alignment of `cmsghdr`
+const CONTROL_LEN: usize = 4;
+
+impl Dir {
+    /// Send this directory handle to another process over a unix socket
+    ///
+    /// The descriptor is passed as `SCM_RIGHTS` ancillary data along with a
+    /// single byte of payload, and should be received using `recv_from`.
+    /// The receiver gets access to the directory even if it can't open it
+    /// by path (e.g. because it is sandboxed or runs as another user), so
+    /// this is the way to hand a capability to a worker process. The handle
+    /// in this process stays open.
+    ///
+    /// Requires `fd-passing` feature.
+    pub fn send_over(&self, socket: &UnixStream) -> io::Result<()> {
+        let mut payload = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+            iov_len: payload.len(),
+        };
+        let mut control = [0u64; CONTROL_LEN];
+        unsafe {
+            let space = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32);
+            let mut msg: libc::msghdr = mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = space as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len =
+                libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, self.0);
+            loop {
+                let res = libc::sendmsg(socket.as_raw_fd(), &msg, 0);
+                if res >= 0 {
+                    return Ok(());
+                }
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Receive a directory handle sent by `send_over`
+    ///
+    /// Returns `UnexpectedEof` error if the socket is closed by the peer,
+    /// and `InvalidData` if a message without a descriptor or with a
+    /// descriptor which is not a directory is received (the descriptor
+    /// is closed in this case). The descriptor is received with
+    /// close-on-exec flag.
+    ///
+    /// Requires `fd-passing` feature.
+    pub fn recv_from(socket: &UnixStream) -> io::Result<Dir> {
+        let mut payload = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+            iov_len: payload.len(),
+        };
+        let mut control = [0u64; CONTROL_LEN];
+        let fd = unsafe {
+            let mut msg: libc::msghdr = mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = mem::size_of_val(&control) as _;
+            let received = loop {
+                let res = libc::recvmsg(socket.as_raw_fd(), &mut msg,
+                                        RECV_FLAGS);
+                if res >= 0 {
+                    break res;
+                }
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            };
+            let fd = received_fd(&msg);
+            if received == 0 && fd.is_none() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                    "socket closed before directory was received"));
+            }
+            match fd {
+                Some(fd) => fd,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    "no file descriptor in the message")),
+            }
+        };
+        #[cfg(not(any(target_os="linux", target_os="android",
+                      target_os="freebsd")))]
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        unsafe { Dir::from_raw_fd_checked(fd) }.map_err(|e| {
+            unsafe { libc::close(fd) };
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        })
+    }
+}
+
+#[cfg(any(target_os="linux", target_os="android", target_os="freebsd"))]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os="linux", target_os="android", target_os="freebsd")))]
+const RECV_FLAGS: libc::c_int = 0;
+
+// Returns the first descriptor of the message, the others (if a malicious
+// peer sends more than one) are closed
+unsafe fn received_fd(msg: &libc::msghdr) -> Option<RawFd> {
+    let mut result = None;
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_SOCKET &&
+           (*cmsg).cmsg_type == libc::SCM_RIGHTS
+        {
+            let data = libc::CMSG_DATA(cmsg);
+            let len = (*cmsg).cmsg_len as usize
+                - (data as usize - cmsg as usize);
+            for idx in 0..len / mem::size_of::<RawFd>() {
+                let fd = ptr::read_unaligned(
+                    (data as *const RawFd).add(idx));
+                if result.is_none() {
+                    result = Some(fd);
+                } else {
+                    libc::close(fd);
+                }
+            }
+        }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Write};
+    use std::os::unix::net::UnixStream;
+    use crate::Dir;
+
+    #[test]
+    fn send_and_receive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let (a, b) = UnixStream::pair().unwrap();
+        dir.send_over(&a).unwrap();
+        let received = Dir::recv_from(&b).unwrap();
+        assert!(received.same_as(&dir).unwrap());
+        assert!(received.metadata("file").unwrap().is_file());
+
+        (&a).write_all(b"x").unwrap();
+        let err = Dir::recv_from(&b).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        drop(a);
+        let err = Dir::recv_from(&b).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
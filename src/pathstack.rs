@@ -0,0 +1,185 @@
+use std::io;
+use std::fmt;
+use std::ffi::{CStr, OsStr};
+use std::path::Path;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::AsPath;
+
+
+/// A reusable buffer for building nested relative paths
+///
+/// Components are pushed when descending into a directory and popped when
+/// leaving it, so a recursive traversal uses a single allocation for all
+/// the paths instead of a `PathBuf` per entry. The buffer is kept
+/// nul-terminated, so passing it to the methods of `Dir` doesn't copy it
+/// either:
+///
+/// ```rust,no_run
+/// # use std::io;
+/// # use openat::{Dir, PathStack, SimpleType};
+/// fn visit(dir: &Dir, path: &mut PathStack) -> io::Result<()> {
+///     for entry in dir.list_dir(&*path)? {
+///         let entry = entry?;
+///         path.push(entry.file_name())?;
+///         println!("{:?}", path.as_path());
+///         if entry.simple_type() == Some(SimpleType::Dir) {
+///             visit(dir, path)?;
+///         }
+///         path.pop();
+///     }
+///     Ok(())
+/// }
+/// # let dir = Dir::open(".").unwrap();
+/// visit(&dir, &mut PathStack::new()).unwrap();
+/// ```
+///
+/// An empty stack refers to the directory itself (it's passed as `.`).
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PathStack {
+    // path bytes followed by a nul byte
+    buf: Vec<u8>,
+    // length of the path before each push
+    lengths: Vec<usize>,
+}
+
+impl PathStack {
+    /// Create an empty stack
+    pub fn new() -> PathStack {
+        PathStack { buf: vec![0], lengths: Vec::new() }
+    }
+    /// Create an empty stack with `capacity` bytes preallocated
+    pub fn with_capacity(capacity: usize) -> PathStack {
+        let mut buf = Vec::with_capacity(capacity + 1);
+        buf.push(0);
+        PathStack { buf, lengths: Vec::new() }
+    }
+    /// Append a component (or a few, separated by slashes) to the path
+    ///
+    /// Returns error of kind `InvalidInput` if the `name` is empty,
+    /// absolute or contains a nul byte, the stack is unchanged in this case.
+    pub fn push<N: AsRef<OsStr>>(&mut self, name: N) -> io::Result<()> {
+        let name = name.as_ref().as_bytes();
+        if name.is_empty() || name[0] == b'/' || name.contains(&0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "path component must be non-empty, relative \
+                 and contain no nul bytes"));
+        }
+        let len = self.buf.len() - 1;
+        self.lengths.push(len);
+        self.buf.truncate(len);
+        if len > 0 {
+            self.buf.push(b'/');
+        }
+        self.buf.extend_from_slice(name);
+        self.buf.push(0);
+        Ok(())
+    }
+    /// Remove the last pushed component, returns `false` if the stack is
+    /// empty
+    pub fn pop(&mut self) -> bool {
+        match self.lengths.pop() {
+            Some(len) => {
+                self.buf.truncate(len);
+                self.buf.push(0);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Remove all components, keeping the allocated memory
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.buf.push(0);
+        self.lengths.clear();
+    }
+    /// Returns the number of pushed components
+    pub fn depth(&self) -> usize {
+        self.lengths.len()
+    }
+    /// Returns `true` if nothing is pushed
+    pub fn is_empty(&self) -> bool {
+        self.lengths.is_empty()
+    }
+    /// Returns the path (empty if nothing is pushed)
+    pub fn as_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(&self.buf[..self.buf.len() - 1]))
+    }
+    /// Returns the path as a C string (`.` if nothing is pushed)
+    pub fn as_c_str(&self) -> &CStr {
+        if self.is_empty() {
+            return unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+        }
+        // nul bytes are rejected by `push`
+        unsafe { CStr::from_bytes_with_nul_unchecked(&self.buf) }
+    }
+}
+
+impl Default for PathStack {
+    fn default() -> PathStack {
+        PathStack::new()
+    }
+}
+
+impl fmt::Debug for PathStack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_path().fmt(f)
+    }
+}
+
+impl AsRef<Path> for PathStack {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl<'a> AsPath for &'a PathStack {
+    type Buffer = &'a CStr;
+    fn to_path(self) -> Option<&'a CStr> {
+        Some(self.as_c_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::path::Path;
+    use crate::{Dir, PathStack};
+
+    #[test]
+    fn push_pop() {
+        let mut path = PathStack::new();
+        assert_eq!(path.as_path(), Path::new(""));
+        assert_eq!(path.as_c_str().to_bytes(), b".");
+        path.push("a").unwrap();
+        path.push("b/c").unwrap();
+        assert_eq!(path.as_path(), Path::new("a/b/c"));
+        assert_eq!(path.depth(), 2);
+        for bad in &["", "/x", "x\0y"] {
+            assert_eq!(path.push(bad).unwrap_err().kind(),
+                       io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(path.as_c_str().to_bytes(), b"a/b/c");
+        assert!(path.pop());
+        assert_eq!(path.as_path(), Path::new("a"));
+        assert!(path.pop());
+        assert!(!path.pop());
+        assert!(path.is_empty());
+        path.push("d").unwrap();
+        path.clear();
+        assert_eq!(format!("{:?}", path), "\"\"");
+    }
+
+    #[test]
+    fn as_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("sub/file", 0o644).unwrap();
+        let mut path = PathStack::new();
+        assert_eq!(dir.list_dir(&path).unwrap().count(), 1);
+        path.push("sub").unwrap();
+        path.push("file").unwrap();
+        assert!(dir.metadata(&path).unwrap().is_file());
+    }
+}
@@ -0,0 +1,69 @@
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::Dir;
+
+
+impl Dir {
+    /// Duplicate a directory descriptor out of another process
+    ///
+    /// `pidfd` refers to the process (see `pidfd_open(2)`), and
+    /// `remote_fd` is the number of the descriptor in that process. The
+    /// duplicate refers to the same open directory (and gets close-on-exec
+    /// flag), so a supervisor can inspect the directories a child has
+    /// pinned, even if they were renamed or their paths are not
+    /// accessible to the supervisor.
+    ///
+    /// Requires permission to ptrace the process. Returns `ENOTDIR` error
+    /// (and closes the duplicate) if the descriptor is not a directory.
+    ///
+    /// Requires `pidfd` feature and Linux 5.6.
+    pub fn from_pidfd<F: AsRawFd>(pidfd: &F, remote_fd: RawFd)
+        -> io::Result<Dir>
+    {
+        let fd = unsafe {
+            libc::syscall(libc::SYS_pidfd_getfd,
+                pidfd.as_raw_fd(), remote_fd, 0 as libc::c_uint)
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as RawFd;
+        unsafe { Dir::from_raw_fd_checked(fd) }.inspect_err(|_| {
+            unsafe { libc::close(fd) };
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use crate::Dir;
+
+    #[test]
+    fn from_pidfd() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        let pidfd = unsafe {
+            libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0)
+        };
+        if pidfd < 0 {
+            // kernel is older than 5.3 or seccomp forbids the call
+            return;
+        }
+        let pidfd = unsafe { File::from_raw_fd(pidfd as _) };
+        let copy = match Dir::from_pidfd(&pidfd, dir.as_raw_fd()) {
+            Ok(copy) => copy,
+            // older kernel or no ptrace permissions
+            Err(_) => return,
+        };
+        assert_ne!(copy.as_raw_fd(), dir.as_raw_fd());
+        assert!(copy.same_as(&dir).unwrap());
+        assert!(copy.metadata("file").unwrap().is_file());
+        let file = dir.open_file("file").unwrap();
+        let err = Dir::from_pidfd(&pidfd, file.as_raw_fd()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+    }
+}
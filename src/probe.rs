@@ -0,0 +1,145 @@
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::error::raw_os_error;
+
+const UNKNOWN: u8 = 0;
+const SUPPORTED: u8 = 1;
+const UNSUPPORTED: u8 = 2;
+
+/// Optional kernel feature used by this crate
+///
+/// See `is_supported()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Feature {
+    /// `openat2` system call (Linux 5.6), used by `Dir::open_beneath`
+    /// (which resolves the path in userspace without it)
+    Openat2,
+    /// `statx` system call (Linux 4.11), used by `Dir::statx` (which falls
+    /// back to `fstatat` without it)
+    Statx,
+    /// `renameat2` system call (Linux 3.15), used by `rename_flags` and
+    /// `Dir::local_exchange`
+    Renameat2,
+    /// `O_TMPFILE` flag (Linux 3.11), used by `Dir::new_unnamed_file`
+    ///
+    /// Note: the filesystem may still not support the flag.
+    TmpFile,
+    /// `copy_file_range` system call (Linux 4.5)
+    ///
+    /// Not used by this crate directly: files are copied with
+    /// `std::io::copy` which already falls back to `read`/`write`. The
+    /// probe is for callers choosing a copy strategy themselves.
+    CopyFileRange,
+}
+
+static STATE: [AtomicU8; 5] = [
+    AtomicU8::new(UNKNOWN), AtomicU8::new(UNKNOWN), AtomicU8::new(UNKNOWN),
+    AtomicU8::new(UNKNOWN), AtomicU8::new(UNKNOWN),
+];
+
+fn state(feature: Feature) -> &'static AtomicU8 {
+    &STATE[feature as usize]
+}
+
+/// Returns `true` if the running kernel supports the feature
+///
+/// The feature is probed on the first call (by issuing the system call
+/// with invalid arguments) and the result is cached for the lifetime of
+/// the process. So it's cheap to call this function every time.
+///
+/// This is about the kernel the binary runs on rather than the one it was
+/// built on, methods of this crate use it to skip the system calls known to
+/// fail and to choose a fallback where there is one. Always `false` on
+/// systems other than Linux.
+pub fn is_supported(feature: Feature) -> bool {
+    match state(feature).load(Ordering::Relaxed) {
+        SUPPORTED => true,
+        UNSUPPORTED => false,
+        _ => {
+            let result = probe(feature);
+            state(feature).store(if result { SUPPORTED } else { UNSUPPORTED },
+                                 Ordering::Relaxed);
+            result
+        }
+    }
+}
+
+/// Returns `ENOSYS` error if the feature is known to be unsupported
+pub fn check_supported(feature: Feature) -> io::Result<()> {
+    if is_supported(feature) {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+    }
+}
+
+/// Remember that feature is unsupported if the call failed with `ENOSYS`
+///
+/// This is for the case when system call is filtered out (e.g. by seccomp)
+/// after the probe was done.
+pub fn note_error(feature: Feature, err: &io::Error) {
+    if raw_os_error(err) == Some(libc::ENOSYS) {
+        state(feature).store(UNSUPPORTED, Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_os="linux")]
+fn probe(feature: Feature) -> bool {
+    use std::ptr::null;
+
+    let res = unsafe {
+        match feature {
+            Feature::Openat2 => libc::syscall(libc::SYS_openat2,
+                -1, null::<libc::c_char>(), null::<libc::open_how>(), 0),
+            Feature::Statx => libc::syscall(libc::SYS_statx,
+                -1, null::<libc::c_char>(), 0, 0, null::<libc::statx>()),
+            Feature::Renameat2 => libc::syscall(libc::SYS_renameat2,
+                -1, null::<libc::c_char>(), -1, null::<libc::c_char>(), 0),
+            Feature::CopyFileRange => libc::syscall(libc::SYS_copy_file_range,
+                -1, null::<libc::loff_t>(), -1, null::<libc::loff_t>(), 0, 0),
+            Feature::TmpFile => {
+                // `O_TMPFILE` requires write access, so kernels that know
+                // the flag return `EINVAL`, and older ones open the directory
+                let fd = libc::open(b"/\0".as_ptr() as *const libc::c_char,
+                    libc::O_TMPFILE|libc::O_RDONLY|libc::O_CLOEXEC);
+                if fd >= 0 {
+                    libc::close(fd);
+                    return false;
+                }
+                return io::Error::last_os_error().raw_os_error()
+                    == Some(libc::EINVAL);
+            }
+        }
+    };
+    // arguments are invalid, so the call never succeeds
+    res < 0 && io::Error::last_os_error().raw_os_error() != Some(libc::ENOSYS)
+}
+
+#[cfg(not(target_os="linux"))]
+fn probe(_feature: Feature) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_supported, Feature};
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn probe() {
+        // tests are run on reasonably modern kernels
+        assert!(is_supported(Feature::Renameat2));
+        assert!(is_supported(Feature::TmpFile));
+        assert!(is_supported(Feature::CopyFileRange));
+        // cached value is the same
+        assert!(is_supported(Feature::Renameat2));
+    }
+
+    #[test]
+    #[cfg(not(target_os="linux"))]
+    fn probe() {
+        assert!(!is_supported(Feature::Openat2));
+    }
+}
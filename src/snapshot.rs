@@ -0,0 +1,157 @@
+use std::io;
+use std::fs::File;
+use std::ffi::CStr;
+use std::os::unix::io::AsRawFd;
+
+use crate::{Dir, SimpleType, Metadata};
+use crate::dir::_hardlink;
+use crate::syscall::syscall;
+use crate::error::raw_os_error;
+
+
+/// Recreate the tree of `src` in `dst` using hardlinks instead of copies
+///
+/// This is the basis of incremental backups in the style of rsnapshot:
+/// directories are created anew (with the same permission bits), while
+/// files, symlinks and other entries are hardlinked, so unchanged files
+/// don't take any space in the new snapshot. If linking a file fails
+/// (e.g. `src` and `dst` are on different filesystems, or the link count
+/// limit is reached) the file is copied instead, preserving its mode and
+/// modification time. Symlinks are recreated in this case.
+///
+/// `dst` should be empty: existing entries are not overwritten and result
+/// in an error. Stops at the first error, leaving partially created tree.
+///
+/// Note: since files are shared, modifying a file in place (rather than
+/// replacing it) affects all snapshots.
+pub fn snapshot_linked(src: &Dir, dst: &Dir) -> io::Result<()> {
+    let dot = unsafe { CStr::from_bytes_with_nul_unchecked(b".\0") };
+    for entry in src.list_dir(dot)? {
+        let entry = entry?;
+        let name = &entry.name;
+        let meta = src._stat(name, libc::AT_SYMLINK_NOFOLLOW)?;
+        if meta.simple_type() == SimpleType::Dir {
+            dst._create_dir(name, 0o700)?;
+            let sub_src = src._sub_dir(name, libc::O_DIRECTORY)?;
+            let sub_dst = dst._sub_dir(name, libc::O_DIRECTORY)?;
+            snapshot_linked(&sub_src, &sub_dst)?;
+            // set permissions after filling, as mode may be read-only
+            chmod(dst, name, &meta)?;
+            continue;
+        }
+        match _hardlink(src.0, name, dst.0, name, 0) {
+            Ok(()) => {}
+            Err(e) => match (meta.simple_type(), raw_os_error(&e)) {
+                (_, Some(libc::EEXIST)) => return Err(e),
+                (SimpleType::File, _) => copy(src, dst, name, &meta)?,
+                (SimpleType::Symlink, _) => {
+                    dst.symlink(name.as_c_str(), &src.read_link(&entry)?)?
+                }
+                _ => return Err(e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn chmod(dir: &Dir, name: &CStr, meta: &Metadata) -> io::Result<()> {
+    let call = syscall("fchmodat", dir.0, name)?;
+    let mode = meta.stat().st_mode & 0o7777;
+    unsafe {
+        if libc::fchmodat(dir.0, name.as_ptr(), mode, 0) < 0 {
+            return Err(call.error());
+        }
+    }
+    Ok(())
+}
+
+fn copy(src: &Dir, dst: &Dir, name: &CStr, meta: &Metadata)
+    -> io::Result<()>
+{
+    let mut input = src._open_file(name,
+        libc::O_RDONLY|libc::O_NOFOLLOW, 0)?;
+    let mut output = dst._open_file(name,
+        libc::O_WRONLY|libc::O_CREAT|libc::O_EXCL|libc::O_NOFOLLOW, 0o600)?;
+    io::copy(&mut input, &mut output)?;
+    set_times(&output, meta)?;
+    let mode = meta.stat().st_mode & 0o7777;
+    unsafe {
+        if libc::fchmod(output.as_raw_fd(), mode) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn set_times(file: &File, meta: &Metadata) -> io::Result<()> {
+    let stat = meta.stat();
+    let times = [
+        libc::timespec {
+            tv_sec: stat.st_atime,
+            tv_nsec: stat.st_atime_nsec as _,
+        },
+        libc::timespec {
+            tv_sec: stat.st_mtime,
+            tv_nsec: stat.st_mtime_nsec as _,
+        },
+    ];
+    unsafe {
+        if libc::futimens(file.as_raw_fd(), times.as_ptr()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::ffi::CStr;
+    use crate::{Dir, compare};
+    use super::{snapshot_linked, copy};
+
+    #[test]
+    fn snapshot() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = Dir::open(tmp.path()).unwrap();
+        root.create_dir("src", 0o755).unwrap();
+        root.create_dir("dst", 0o755).unwrap();
+        let src = root.sub_dir("src").unwrap();
+        src.create_dir("sub", 0o750).unwrap();
+        src.write_file("sub/file", 0o600).unwrap()
+            .write_all(b"hello").unwrap();
+        src.symlink("link", "sub/file").unwrap();
+        let dst = root.sub_dir("dst").unwrap();
+        snapshot_linked(&src, &dst).unwrap();
+
+        assert_eq!(compare(&src, &dst).unwrap(), []);
+        let orig = src.metadata("sub/file").unwrap();
+        let copy = dst.metadata("sub/file").unwrap();
+        assert_eq!(orig.stat().st_ino, copy.stat().st_ino);
+        assert_eq!(copy.stat().st_nlink, 2);
+        assert_eq!(dst.metadata("sub").unwrap().stat().st_mode & 0o777,
+                   0o750);
+        assert_ne!(src.metadata("sub").unwrap().stat().st_ino,
+                   dst.metadata("sub").unwrap().stat().st_ino);
+        // destination is not empty
+        assert!(snapshot_linked(&src, &dst).is_err());
+    }
+
+    #[test]
+    fn copy_fallback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("src", 0o755).unwrap();
+        let src = dir.sub_dir("src").unwrap();
+        src.write_file("a", 0o640).unwrap().write_all(b"hello").unwrap();
+        let meta = src.metadata("a").unwrap();
+        let name = CStr::from_bytes_with_nul(b"a\0").unwrap();
+        copy(&src, &dir, name, &meta).unwrap();
+        let new = dir.metadata("a").unwrap();
+        assert_ne!(meta.stat().st_ino, new.stat().st_ino);
+        assert_eq!(new.len(), 5);
+        assert_eq!(new.stat().st_mode, meta.stat().st_mode);
+        assert_eq!(new.stat().st_mtime, meta.stat().st_mtime);
+        assert_eq!(new.stat().st_mtime_nsec, meta.stat().st_mtime_nsec);
+    }
+}
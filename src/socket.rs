@@ -0,0 +1,101 @@
+use std::io;
+use std::ffi::{CStr, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use crate::{Dir, AsPath};
+use crate::dir::{to_cstr, fd_path};
+use crate::capability::check_path;
+
+
+impl Dir {
+    /// Bind a unix socket to a name in this directory
+    ///
+    /// The socket is bound using `/proc/self/fd/<dirfd>/<name>` path, so the
+    /// length of the directory path doesn't count towards `sockaddr_un` limit
+    /// (only the `name` does), this requires `/proc` to be mounted. The `name` must be relative, an error of kind
+    /// `InvalidInput` is returned otherwise.
+    ///
+    /// Only supported on Linux.
+    pub fn bind_unix<P: AsPath>(&self, name: P) -> io::Result<UnixListener> {
+        UnixListener::bind(self.socket_path(to_cstr(name)?.as_ref())?)
+    }
+
+    /// Connect to a unix socket by a name in this directory
+    ///
+    /// See `bind_unix` for more info.
+    ///
+    /// Only supported on Linux.
+    pub fn connect_unix<P: AsPath>(&self, name: P) -> io::Result<UnixStream> {
+        UnixStream::connect(self.socket_path(to_cstr(name)?.as_ref())?)
+    }
+
+    fn socket_path(&self, name: &CStr) -> io::Result<PathBuf> {
+        // absolute name would replace the directory path when pushed
+        if name.to_bytes().starts_with(b"/") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                "socket name must be relative"));
+        }
+        check_path(self.0, name)?;
+        let mut path = fd_path(self.0);
+        path.push(OsStr::from_bytes(name.to_bytes()));
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use crate::Dir;
+
+    #[test]
+    fn bind_and_connect() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let listener = dir.bind_unix("sock").unwrap();
+        let mut client = dir.connect_unix("sock").unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(b"hello").unwrap();
+        drop(client);
+        let mut buf = String::new();
+        server.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+    }
+
+    #[test]
+    fn long_directory_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let long = tmp.path().join("x".repeat(100)).join("y".repeat(100));
+        std::fs::create_dir_all(&long).unwrap();
+        let dir = Dir::open(&long).unwrap();
+        let _listener = dir.bind_unix("sock").unwrap();
+        dir.connect_unix("sock").unwrap();
+    }
+
+    #[test]
+    fn absolute_name() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let path = tmp.path().join("sock");
+        assert_eq!(dir.bind_unix(&path).unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        assert_eq!(dir.connect_unix(&path).unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    #[cfg(feature="capability-mode")]
+    fn capability_mode() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        let sub = dir.sub_dir("sub").unwrap();
+        assert_eq!(sub.bind_unix("../sock").unwrap_err().kind(),
+                   io::ErrorKind::PermissionDenied);
+        assert_eq!(sub.connect_unix("../sock").unwrap_err().kind(),
+                   io::ErrorKind::PermissionDenied);
+        assert!(dir.metadata("sock").is_err());
+    }
+}
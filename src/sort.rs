@@ -0,0 +1,98 @@
+use std::io;
+
+use crate::{Dir, Entry, AsPath};
+use crate::dir::to_cstr;
+use crate::list::open_dir;
+use crate::error::raw_os_error;
+
+
+/// Order of entries returned by `Dir::list_sorted`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SortBy {
+    /// Byte-wise order of file names
+    Name,
+    /// Inode number, this is the fastest order to stat or open files on
+    /// most filesystems when the inode cache is cold
+    Inode,
+    /// Modification time, oldest first (symlinks are not followed)
+    Mtime,
+}
+
+impl Dir {
+    /// List subdirectory with entries sorted in a deterministic order
+    ///
+    /// Order of entries returned by `list_dir` is arbitrary and depends on
+    /// the filesystem. Ties are broken by name. Note that `SortBy::Mtime`
+    /// issues `fstatat` for every entry, entries removed in the meantime
+    /// are skipped.
+    pub fn list_sorted<P: AsPath>(&self, path: P, by: SortBy)
+        -> io::Result<Vec<Entry>>
+    {
+        let path = to_cstr(path)?;
+        let mut iter = open_dir(self, path.as_ref(), 0)?;
+        let mut entries = iter.by_ref().collect::<io::Result<Vec<_>>>()?;
+        match by {
+            SortBy::Name => entries.sort_by(|a, b| a.name.cmp(&b.name)),
+            SortBy::Inode => entries.sort_by(|a, b| {
+                (a.ino, &a.name).cmp(&(b.ino, &b.name))
+            }),
+            SortBy::Mtime => {
+                // stat relative to the listed descriptor, so the path is
+                // resolved only once
+                let dir = iter.borrow_dir();
+                let mut keyed = Vec::with_capacity(entries.len());
+                for entry in entries {
+                    match dir._stat(&entry.name, libc::AT_SYMLINK_NOFOLLOW) {
+                        Ok(meta) => {
+                            let stat = meta.stat();
+                            let key = (stat.st_mtime, stat.st_mtime_nsec);
+                            keyed.push((key, entry));
+                        }
+                        Err(ref e) if raw_os_error(e) == Some(libc::ENOENT)
+                        => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+                keyed.sort_by(|(ka, a), (kb, b)| {
+                    (ka, &a.name).cmp(&(kb, &b.name))
+                });
+                entries = keyed.into_iter().map(|(_, e)| e).collect();
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+    use crate::{Dir, SortBy};
+
+    fn names(entries: Vec<crate::Entry>) -> Vec<String> {
+        entries.iter()
+            .map(|e| e.file_name().to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sorted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let now = SystemTime::now();
+        for (i, name) in ["b", "c", "a"].iter().enumerate() {
+            let f: File = dir.write_file(*name, 0o644).unwrap();
+            f.set_modified(now - Duration::from_secs(i as u64)).unwrap();
+        }
+        assert_eq!(names(dir.list_sorted(".", SortBy::Name).unwrap()),
+                   ["a", "b", "c"]);
+        assert_eq!(names(dir.list_sorted(".", SortBy::Mtime).unwrap()),
+                   ["a", "c", "b"]);
+        let by_inode = dir.list_sorted(".", SortBy::Inode).unwrap();
+        let inodes = by_inode.iter().map(|e| e.inode()).collect::<Vec<_>>();
+        assert!(inodes.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(inodes[0],
+            dir.metadata(&by_inode[0]).unwrap().stat().st_ino as u64);
+    }
+}
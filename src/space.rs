@@ -0,0 +1,218 @@
+use std::io;
+use std::mem;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+use crate::{Dir, AsPath, IntoMode};
+use crate::dir::to_cstr;
+
+
+impl Dir {
+    /// Open file for writing (create if necessary) and preallocate space
+    ///
+    /// The file is opened in the same way as with ``update_file()``, i.e.
+    /// it's opened for reading and writing and isn't truncated. Then `len`
+    /// bytes starting from the beginning of the file are allocated (see
+    /// ``allocate()``), so if there is not enough space on the filesystem
+    /// you get `ENOSPC` right away rather than in the middle of writing.
+    pub fn allocate<P: AsPath, M: IntoMode>(&self, path: P, len: u64, mode: M)
+        -> io::Result<File>
+    {
+        let file = self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_CREAT|libc::O_RDWR,
+            mode.into_mode())?;
+        allocate(&file, 0, len)?;
+        Ok(file)
+    }
+
+    /// Returns `true` if the filesystem of this directory is mounted
+    /// read-only
+    ///
+    /// Useful to fail early with a clear message instead of getting `EROFS`
+    /// in the middle of an operation. Note that the filesystem may be
+    /// remounted any time, so errors must be handled anyway.
+    pub fn is_read_only(&self) -> io::Result<bool> {
+        Ok(statvfs(self)?.f_flag & libc::ST_RDONLY != 0)
+    }
+
+    /// Returns the number of bytes available on the filesystem of this
+    /// directory for unprivileged users
+    ///
+    /// This doesn't include the space reserved for root, so it's what
+    /// `df` shows as available. Like `is_read_only()`, it's only a hint:
+    /// the space can be consumed by other processes before you write. Use
+    /// `allocate()` to reserve the space.
+    // fsblkcnt_t and c_ulong are u32 on some systems
+    #[allow(clippy::useless_conversion)]
+    pub fn available_space(&self) -> io::Result<u64> {
+        let stat = statvfs(self)?;
+        Ok(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+    }
+}
+
+fn statvfs(dir: &Dir) -> io::Result<libc::statvfs> {
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::fstatvfs(dir.0, &mut stat) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat)
+    }
+}
+
+/// Preallocate space for a range of the file
+///
+/// This is a wrapper around `posix_fallocate`. After successful call
+/// subsequent writes into the range `offset..offset+len` are guaranteed
+/// not to fail because of lack of disk space. If the range extends past
+/// the end of the file, file size is increased (new bytes read as zeros).
+///
+/// Note: if filesystem doesn't support allocation natively, glibc falls
+/// back to writing zeros, which may be slow for large files.
+///
+/// Supported on Linux, Android and FreeBSD.
+#[cfg(any(target_os="linux", target_os="android", target_os="freebsd"))]
+pub fn allocate<F: AsRawFd>(file: &F, offset: u64, len: u64)
+    -> io::Result<()>
+{
+    // Note: posix_fallocate returns error instead of setting errno
+    let res = unsafe {
+        libc::posix_fallocate(file.as_raw_fd(),
+            offset as libc::off_t, len as libc::off_t)
+    };
+    if res != 0 {
+        Err(io::Error::from_raw_os_error(res))
+    } else {
+        Ok(())
+    }
+}
+
+/// Preallocate space for a range of the file
+///
+/// This is a wrapper around `posix_fallocate`. After successful call
+/// subsequent writes into the range `offset..offset+len` are guaranteed
+/// not to fail because of lack of disk space. If the range extends past
+/// the end of the file, file size is increased (new bytes read as zeros).
+///
+/// Supported on Linux, Android and FreeBSD, on other systems always returns
+/// an error.
+#[cfg(not(any(target_os="linux", target_os="android",
+              target_os="freebsd")))]
+pub fn allocate<F: AsRawFd>(_file: &F, _offset: u64, _len: u64)
+    -> io::Result<()>
+{
+    Err(io::Error::new(io::ErrorKind::Other,
+        "preallocating file space is not supported on this platform"))
+}
+
+/// Deallocate a range of the file, making it a hole
+///
+/// Reading from the range returns zeros afterwards, but it doesn't occupy
+/// disk space. File size is never changed, even if range extends past
+/// the end of the file.
+///
+/// Only supported on Linux, and not on all filesystems.
+#[cfg(target_os="linux")]
+pub fn punch_hole<F: AsRawFd>(file: &F, offset: u64, len: u64)
+    -> io::Result<()>
+{
+    _fallocate(file, libc::FALLOC_FL_PUNCH_HOLE|libc::FALLOC_FL_KEEP_SIZE,
+               offset, len)
+}
+
+/// Zero a range of the file
+///
+/// Unlike ``punch_hole`` the space remains allocated (as unwritten extents,
+/// so this is usually much faster than writing zeros). If the range extends
+/// past the end of the file, file size is increased.
+///
+/// Only supported on Linux, and not on all filesystems.
+#[cfg(target_os="linux")]
+pub fn zero_range<F: AsRawFd>(file: &F, offset: u64, len: u64)
+    -> io::Result<()>
+{
+    _fallocate(file, libc::FALLOC_FL_ZERO_RANGE, offset, len)
+}
+
+#[cfg(target_os="linux")]
+fn _fallocate<F: AsRawFd>(file: &F, mode: libc::c_int, offset: u64, len: u64)
+    -> io::Result<()>
+{
+    let res = unsafe {
+        libc::fallocate(file.as_raw_fd(), mode,
+            offset as libc::off_t, len as libc::off_t)
+    };
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, any(target_os="linux", target_os="android",
+                    target_os="freebsd")))]
+mod test {
+    use std::io::{Read, Write};
+    use crate::Dir;
+
+    #[test]
+    fn allocate() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut file = dir.allocate("file", 1 << 20, 0o644).unwrap();
+        let meta = dir.metadata("file").unwrap();
+        assert_eq!(meta.len(), 1 << 20);
+        assert!(meta.stat().st_blocks >= 2048);
+        // doesn't truncate existing file
+        file.write_all(b"hello").unwrap();
+        dir.allocate("file", 10, 0o644).unwrap();
+        assert_eq!(dir.metadata("file").unwrap().len(), 1 << 20);
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn punch_hole() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut file = dir.write_file("file", 0o644).unwrap();
+        file.write_all(&vec![1u8; 1 << 20]).unwrap();
+        file.sync_all().unwrap();
+        let before = dir.metadata("file").unwrap().stat().st_blocks;
+        super::punch_hole(&file, 0, 1 << 19).unwrap();
+        let meta = dir.metadata("file").unwrap();
+        assert_eq!(meta.len(), 1 << 20);
+        assert!(meta.stat().st_blocks < before);
+        let mut buf = Vec::new();
+        dir.open_file("file").unwrap().read_to_end(&mut buf).unwrap();
+        assert!(buf[..1 << 19].iter().all(|&x| x == 0));
+        assert!(buf[1 << 19..].iter().all(|&x| x == 1));
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn zero_range() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut file = dir.write_file("file", 0o644).unwrap();
+        file.write_all(&vec![1u8; 8192]).unwrap();
+        match super::zero_range(&file, 4096, 8192) {
+            // not all filesystems support this
+            Err(ref e) if e.raw_os_error() == Some(libc::EOPNOTSUPP) => return,
+            res => res.unwrap(),
+        }
+        let mut buf = Vec::new();
+        dir.open_file("file").unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf.len(), 12288);
+        assert!(buf[..4096].iter().all(|&x| x == 1));
+        assert!(buf[4096..].iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn filesystem_info() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        assert!(!dir.is_read_only().unwrap());
+        assert!(dir.available_space().unwrap() > 0);
+    }
+}
@@ -0,0 +1,154 @@
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::dir::fstat;
+
+
+/// Iterator over data and hole segments of a file
+///
+/// Created using `segments()`
+#[derive(Debug)]
+pub struct Segments<'a> {
+    fd: RawFd,
+    pos: u64,
+    size: u64,
+    phantom: PhantomData<&'a ()>,
+}
+
+/// A contiguous region of a file which is either data or a hole
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    start: u64,
+    end: u64,
+    data: bool,
+}
+
+impl Segment {
+    /// Returns offset of the start of the segment
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+    /// Returns offset just past the end of the segment
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+    /// Returns `true` if segment contains data
+    pub fn is_data(&self) -> bool {
+        self.data
+    }
+    /// Returns `true` if segment is a hole (reads as zeros)
+    pub fn is_hole(&self) -> bool {
+        !self.data
+    }
+}
+
+/// Iterate over data and hole segments of a file
+///
+/// This uses `lseek` with `SEEK_DATA` and `SEEK_HOLE`, so copy and backup
+/// code can skip holes instead of reading zeros. Filesystems which don't
+/// support holes report whole file as data. Segments are reported up to
+/// the file size at the time this function is called.
+///
+/// Note: this changes the file position, so seek the file explicitly
+/// after iteration if you're going to read it sequentially.
+///
+/// Supported on Linux, Android, FreeBSD and macOS.
+pub fn segments<F: AsRawFd>(file: &F) -> io::Result<Segments<'_>> {
+    let fd = file.as_raw_fd();
+    Ok(Segments {
+        fd,
+        pos: 0,
+        size: fstat(fd)?.len(),
+        phantom: PhantomData,
+    })
+}
+
+impl<'a> Segments<'a> {
+    fn seek(&self, whence: libc::c_int) -> io::Result<Option<u64>> {
+        let res = unsafe {
+            libc::lseek(self.fd, self.pos as libc::off_t, whence)
+        };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENXIO) {
+                // no more data after offset
+                Ok(None)
+            } else {
+                Err(err)
+            }
+        } else {
+            Ok(Some(res as u64))
+        }
+    }
+    fn next_segment(&mut self) -> io::Result<Segment> {
+        let start = self.pos;
+        let data = self.seek(libc::SEEK_DATA)?
+            .unwrap_or(self.size).min(self.size);
+        if data > start {
+            self.pos = data;
+            return Ok(Segment { start, end: data, data: false });
+        }
+        let hole = self.seek(libc::SEEK_HOLE)?
+            .unwrap_or(self.size).min(self.size);
+        self.pos = hole;
+        Ok(Segment { start, end: hole, data: true })
+    }
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = io::Result<Segment>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.size {
+            return None;
+        }
+        match self.next_segment() {
+            Ok(seg) => Some(Ok(seg)),
+            Err(e) => {
+                // stop iteration on error
+                self.pos = self.size;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Seek, SeekFrom, Write};
+    use crate::Dir;
+    use super::segments;
+
+    #[test]
+    fn empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let file = dir.write_file("file", 0o644).unwrap();
+        assert_eq!(segments(&file).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn sparse() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut file = dir.write_file("file", 0o644).unwrap();
+        file.seek(SeekFrom::Start(1 << 20)).unwrap();
+        file.write_all(&[1; 4096]).unwrap();
+        file.set_len(3 << 20).unwrap();
+        let segs = segments(&file).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        // some filesystems don't support holes
+        if segs.len() == 1 {
+            assert!(segs[0].is_data());
+            return;
+        }
+        assert_eq!(segs.len(), 3);
+        assert!(segs[0].is_hole());
+        assert_eq!(segs[0].start(), 0);
+        assert!(segs[1].is_data());
+        assert!(segs[1].start() <= 1 << 20);
+        assert!(segs[1].end() >= (1 << 20) + 4096);
+        assert!(segs[2].is_hole());
+        assert_eq!(segs[2].end(), 3 << 20);
+    }
+}
@@ -0,0 +1,184 @@
+use std::io;
+use std::mem;
+use std::ffi::CStr;
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::{Dir, AsPath, Metadata};
+use crate::dir::to_cstr;
+use crate::error::raw_os_error;
+use crate::metadata;
+use crate::probe::{Feature, is_supported, note_error};
+use crate::syscall::syscall;
+
+
+/// Set of metadata fields to request from `statx`
+///
+/// The filesystem may provide more fields than requested, or fewer if it
+/// doesn't support them; use `Metadata::provided` to check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StatxMask(u32);
+
+impl StatxMask {
+    /// File type (part of the `st_mode`)
+    pub const TYPE: StatxMask = StatxMask(libc::STATX_TYPE);
+    /// Permission bits (part of the `st_mode`)
+    pub const MODE: StatxMask = StatxMask(libc::STATX_MODE);
+    /// Number of hard links
+    pub const NLINK: StatxMask = StatxMask(libc::STATX_NLINK);
+    /// Owner user id
+    pub const UID: StatxMask = StatxMask(libc::STATX_UID);
+    /// Owner group id
+    pub const GID: StatxMask = StatxMask(libc::STATX_GID);
+    /// Last access time
+    pub const ATIME: StatxMask = StatxMask(libc::STATX_ATIME);
+    /// Last modification time
+    pub const MTIME: StatxMask = StatxMask(libc::STATX_MTIME);
+    /// Last status change time
+    pub const CTIME: StatxMask = StatxMask(libc::STATX_CTIME);
+    /// Inode number
+    pub const INO: StatxMask = StatxMask(libc::STATX_INO);
+    /// File size
+    pub const SIZE: StatxMask = StatxMask(libc::STATX_SIZE);
+    /// Number of allocated blocks
+    pub const BLOCKS: StatxMask = StatxMask(libc::STATX_BLOCKS);
+    /// All of the above, i.e. everything `stat` returns
+    pub const BASIC_STATS: StatxMask = StatxMask(libc::STATX_BASIC_STATS);
+    /// Birth (creation) time, see `Metadata::btime`
+    pub const BTIME: StatxMask = StatxMask(libc::STATX_BTIME);
+
+    /// No fields (only the fields that are free to fetch are returned)
+    pub fn empty() -> StatxMask {
+        StatxMask(0)
+    }
+    /// Returns raw `STATX_*` bits
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+    /// Returns `true` if all fields of `other` are set
+    pub fn contains(&self, other: StatxMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for StatxMask {
+    type Output = StatxMask;
+    fn bitor(self, other: StatxMask) -> StatxMask {
+        StatxMask(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for StatxMask {
+    fn bitor_assign(&mut self, other: StatxMask) {
+        self.0 |= other.0;
+    }
+}
+
+/// How `statx` synchronizes attributes with the remote server
+///
+/// Only matters for network filesystems (NFS, CIFS, FUSE, ...)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum StatxSync {
+    /// Do whatever `stat` does (the default)
+    #[default]
+    AsStat,
+    /// Always fetch attributes from the server
+    Force,
+    /// Use cached attributes, they may be stale (`AT_STATX_DONT_SYNC`)
+    DontSync,
+}
+
+impl Dir {
+    /// Returns specified metadata fields of an entry in this directory
+    ///
+    /// Unlike `metadata()`, only the fields in `mask` are requested, so
+    /// the filesystem may skip expensive ones, and with
+    /// `StatxSync::DontSync` network filesystems may return cached
+    /// attributes without a round-trip to the server. Birth time is
+    /// returned if requested and supported. Symlinks are not followed.
+    ///
+    /// Use `Metadata::provided` to check which fields were filled in.
+    /// On kernels older than 4.11 (see `is_supported()`) this falls back
+    /// to `fstatat`, which returns all the basic fields (but no birth time)
+    /// regardless of `mask` and `sync`.
+    pub fn statx<P: AsPath>(&self, path: P, mask: StatxMask,
+        sync: StatxSync)
+        -> io::Result<Metadata>
+    {
+        self._statx(to_cstr(path)?.as_ref(), mask, sync)
+    }
+
+    fn _statx(&self, path: &CStr, mask: StatxMask, sync: StatxSync)
+        -> io::Result<Metadata>
+    {
+        if !is_supported(Feature::Statx) {
+            return self._stat(path, libc::AT_SYMLINK_NOFOLLOW);
+        }
+        let sync = match sync {
+            StatxSync::AsStat => libc::AT_STATX_SYNC_AS_STAT,
+            StatxSync::Force => libc::AT_STATX_FORCE_SYNC,
+            StatxSync::DontSync => libc::AT_STATX_DONT_SYNC,
+        };
+        let call = syscall("statx", self.0, path)?;
+        unsafe {
+            let mut buf: libc::statx = mem::zeroed();
+            let res = libc::statx(self.0, path.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW|sync, mask.0, &mut buf);
+            if res < 0 {
+                let err = call.error();
+                if raw_os_error(&err) == Some(libc::ENOSYS) {
+                    note_error(Feature::Statx, &err);
+                    drop(call);
+                    return self._stat(path, libc::AT_SYMLINK_NOFOLLOW);
+                }
+                Err(err)
+            } else {
+                Ok(metadata::from_statx(&buf))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use crate::{Dir, StatxMask, StatxSync};
+
+    #[test]
+    fn statx() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.write_file("file", 0o644).unwrap().set_len(10).unwrap();
+        let mask = StatxMask::BASIC_STATS | StatxMask::BTIME;
+        let meta = dir.statx("file", mask, StatxSync::DontSync).unwrap();
+        let real = dir.metadata("file").unwrap();
+        assert!(meta.provided(StatxMask::BASIC_STATS));
+        assert!(real.provided(StatxMask::BASIC_STATS));
+        assert!(!real.provided(StatxMask::BTIME));
+        assert_eq!(meta.len(), 10);
+        assert_eq!(meta.stat().st_ino, real.stat().st_ino);
+        assert_eq!(meta.modified().unwrap(), real.modified().unwrap());
+        assert_eq!(meta.btime().is_some(),
+                   meta.provided(StatxMask::BTIME));
+        assert!(real.btime().is_none());
+        let err = dir.statx("missing", StatxMask::SIZE, StatxSync::AsStat)
+            .err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn not_provided() {
+        // the kernel fills in what it has anyway, so fake the mask
+        let mut stx: libc::statx = unsafe { std::mem::zeroed() };
+        stx.stx_mask = libc::STATX_TYPE | libc::STATX_SIZE;
+        stx.stx_size = 5;
+        let meta = crate::metadata::from_statx(&stx);
+        assert!(meta.provided(StatxMask::SIZE));
+        assert!(!meta.provided(StatxMask::MTIME));
+        assert_eq!(meta.len(), 5);
+        assert_eq!(meta.modified().unwrap_err().kind(),
+                   io::ErrorKind::Unsupported);
+        assert!(meta.accessed().is_err());
+        assert!(meta.btime().is_none());
+    }
+}
@@ -0,0 +1,199 @@
+use std::io;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::{Dir, AsPath, DirIter, Metadata, IntoMode};
+use crate::dir::to_cstr;
+use crate::list::open_dir;
+
+
+/// A view of `Dir` which only accepts single-component names
+///
+/// Created by `Dir::strict()`. Every name passed to its methods must be
+/// a single path component: non-empty, without slashes, and not `.` or
+/// `..`. Otherwise the error of kind `InvalidInput` is returned before any
+/// system call is made. Combined with `O_NOFOLLOW` that every method uses,
+/// this guarantees that no file outside of the directory is touched.
+#[derive(Debug, Clone, Copy)]
+pub struct StrictDir<'a> {
+    dir: &'a Dir,
+}
+
+/// Validate that `name` is a single path component
+///
+/// Returns error of kind `InvalidInput` if the name is empty, `.`, `..`,
+/// or contains a slash or a nul byte.
+pub fn validate_name(name: &[u8]) -> io::Result<()> {
+    let reason = if name.is_empty() {
+        "empty file name"
+    } else if name == b"." || name == b".." {
+        "file name can't be `.` or `..`"
+    } else if name.contains(&b'/') {
+        "file name can't contain a slash"
+    } else if name.contains(&0) {
+        "nul byte in file name"
+    } else {
+        return Ok(());
+    };
+    Err(io::Error::new(io::ErrorKind::InvalidInput, reason))
+}
+
+impl Dir {
+    /// Returns a view of this directory that only accepts plain file names
+    ///
+    /// See `StrictDir` for more info.
+    pub fn strict(&self) -> StrictDir<'_> {
+        StrictDir { dir: self }
+    }
+}
+
+fn name<P: AsPath>(path: P) -> io::Result<P::Buffer> {
+    let buf = to_cstr(path)?;
+    validate_name(buf.as_ref().to_bytes())?;
+    Ok(buf)
+}
+
+impl<'a> StrictDir<'a> {
+    /// Returns the underlying directory
+    pub fn dir(&self) -> &'a Dir {
+        self.dir
+    }
+    /// Open file for reading, see `Dir::open_file`
+    pub fn open_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self.dir.open_file(name(path)?.as_ref())
+    }
+    /// Open file for writing and truncate it, see `Dir::write_file`
+    pub fn write_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<File>
+    {
+        self.dir.write_file(name(path)?.as_ref(), mode)
+    }
+    /// Open file for append, see `Dir::append_file`
+    pub fn append_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<File>
+    {
+        self.dir.append_file(name(path)?.as_ref(), mode)
+    }
+    /// Create a new file, fail if exists, see `Dir::new_file`
+    pub fn new_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<File>
+    {
+        self.dir.new_file(name(path)?.as_ref(), mode)
+    }
+    /// Open file for reading and writing, see `Dir::update_file`
+    pub fn update_file<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<File>
+    {
+        self.dir.update_file(name(path)?.as_ref(), mode)
+    }
+    /// Open subdirectory, see `Dir::sub_dir`
+    pub fn sub_dir<P: AsPath>(&self, path: P) -> io::Result<Dir> {
+        self.dir.sub_dir(name(path)?.as_ref())
+    }
+    /// List subdirectory, see `Dir::list_dir`
+    ///
+    /// Unlike `Dir::list_dir` this fails if `path` is a symlink.
+    pub fn list_dir<P: AsPath>(&self, path: P) -> io::Result<DirIter> {
+        open_dir(self.dir, name(path)?.as_ref(), libc::O_NOFOLLOW)
+    }
+    /// Returns metadata of an entry, see `Dir::metadata`
+    pub fn metadata<P: AsPath>(&self, path: P) -> io::Result<Metadata> {
+        self.dir.metadata(name(path)?.as_ref())
+    }
+    /// Read a symlink, see `Dir::read_link`
+    pub fn read_link<P: AsPath>(&self, path: P) -> io::Result<PathBuf> {
+        self.dir.read_link(name(path)?.as_ref())
+    }
+    /// Create a subdirectory, see `Dir::create_dir`
+    pub fn create_dir<P: AsPath, M: IntoMode>(&self, path: P, mode: M)
+        -> io::Result<()>
+    {
+        self.dir.create_dir(name(path)?.as_ref(), mode)
+    }
+    /// Make a symlink, see `Dir::symlink`
+    ///
+    /// Only the name of the symlink is checked, the `value` can be anything.
+    pub fn symlink<P: AsPath, R: AsPath>(&self, path: P, value: R)
+        -> io::Result<()>
+    {
+        self.dir.symlink(name(path)?.as_ref(), value)
+    }
+    /// Rename an entry within this directory, see `Dir::local_rename`
+    pub fn local_rename<P: AsPath, R: AsPath>(&self, old: P, new: R)
+        -> io::Result<()>
+    {
+        let old = name(old)?;
+        let new = name(new)?;
+        self.dir.local_rename(old.as_ref(), new.as_ref())
+    }
+    /// Remove a file, see `Dir::remove_file`
+    pub fn remove_file<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self.dir.remove_file(name(path)?.as_ref())
+    }
+    /// Remove an empty directory, see `Dir::remove_dir`
+    pub fn remove_dir<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self.dir.remove_dir(name(path)?.as_ref())
+    }
+    /// Remove a directory with its contents, see `Dir::remove_recursive`
+    pub fn remove_recursive<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self.dir.remove_recursive(name(path)?.as_ref())
+    }
+}
+
+impl<'a> AsRef<Dir> for StrictDir<'a> {
+    fn as_ref(&self) -> &Dir {
+        self.dir
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use crate::Dir;
+    use super::validate_name;
+
+    #[test]
+    fn names() {
+        assert!(validate_name(b"file.txt").is_ok());
+        assert!(validate_name(b"..file").is_ok());
+        assert!(validate_name(b"...").is_ok());
+        for name in &[&b""[..], b".", b"..", b"a/b", b"/etc", b"dir/", b"a\0"]
+        {
+            assert_eq!(validate_name(name).unwrap_err().kind(),
+                       io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn strict_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let strict = dir.strict();
+        strict.create_dir("sub", 0o755).unwrap();
+        strict.write_file("file", 0o644).unwrap();
+        strict.local_rename("file", "renamed").unwrap();
+        assert!(strict.metadata("renamed").unwrap().is_file());
+        assert_eq!(strict.write_file("sub/file", 0o644).unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        assert_eq!(strict.sub_dir("..").unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        assert_eq!(strict.local_rename("renamed", "sub/x").unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        strict.sub_dir("sub").unwrap().strict()
+            .write_file("file", 0o644).unwrap();
+        strict.remove_recursive("sub").unwrap();
+    }
+
+    #[test]
+    fn list_symlink() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let strict = dir.strict();
+        strict.create_dir("sub", 0o755).unwrap();
+        strict.symlink("link", "sub").unwrap();
+        assert_eq!(strict.list_dir("sub").unwrap().count(), 0);
+        let err = strict.list_dir("link").unwrap_err();
+        assert!(matches!(crate::raw_os_error(&err),
+                         Some(libc::ELOOP) | Some(libc::ENOTDIR)));
+    }
+}
@@ -0,0 +1,225 @@
+use std::io;
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::capability::check_path;
+use crate::error::record_failure;
+
+
+static OBSERVED: AtomicBool = AtomicBool::new(false);
+static OBSERVER: RwLock<Option<Arc<dyn Observer>>> = RwLock::new(None);
+
+/// Receives a notification after every filesystem system call
+///
+/// This can be used to count system calls and record their latencies
+/// (e.g. to find out that an indexer does too many `fstatat` calls). Install
+/// it with `set_observer()`. Closures with the same signature as
+/// `syscall()` method implement this trait.
+///
+/// The observer is called synchronously in the thread doing the system
+/// call, so it should be fast.
+pub trait Observer: Send + Sync {
+    /// The system call `operation` (e.g. `openat`) has finished
+    fn syscall(&self, operation: &'static str, elapsed: Duration,
+               failed: bool);
+}
+
+impl<F> Observer for F
+    where F: Fn(&'static str, Duration, bool) + Send + Sync
+{
+    fn syscall(&self, operation: &'static str, elapsed: Duration,
+               failed: bool)
+    {
+        self(operation, elapsed, failed)
+    }
+}
+
+/// Install a global system call observer, replacing the previous one
+///
+/// Note: only calls that operate on paths relative to a directory (open,
+/// stat, unlink, rename and similar) are reported.
+pub fn set_observer<O: Observer + 'static>(observer: O) {
+    let mut guard = OBSERVER.write().unwrap_or_else(|e| e.into_inner());
+    *guard = Some(Arc::new(observer));
+    OBSERVED.store(true, Ordering::SeqCst);
+}
+
+/// Remove the global system call observer
+pub fn clear_observer() {
+    let mut guard = OBSERVER.write().unwrap_or_else(|e| e.into_inner());
+    OBSERVED.store(false, Ordering::SeqCst);
+    *guard = None;
+}
+
+/// A guard that is alive for the duration of a filesystem system call
+///
+/// Created by `syscall()` right before the call, and dropped when the
+/// wrapping function returns (so errors are reported within it).
+#[derive(Debug)]
+pub struct Syscall<'a> {
+    operation: &'static str,
+    path: &'a CStr,
+    started: Option<Instant>,
+    failed: Cell<bool>,
+    #[cfg(feature="tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+/// Start a system call `operation` on the `path` relative to `dirfd`
+///
+/// Returns an error if the path is not allowed (see `check_path`).
+#[inline]
+pub fn syscall<'a>(operation: &'static str, dirfd: RawFd, path: &'a CStr)
+    -> io::Result<Syscall<'a>>
+{
+    check_path(dirfd, path)?;
+    Ok(unchecked_syscall(operation, dirfd, path))
+}
+
+/// Start a system call `operation` on the `path` relative to `dirfd`
+///
+/// With the `tracing` feature enabled this enters a `TRACE` level span
+/// named `syscall` with `operation`, `dirfd` and `path` fields.
+#[inline]
+pub fn unchecked_syscall<'a>(operation: &'static str, dirfd: RawFd,
+    path: &'a CStr)
+    -> Syscall<'a>
+{
+    #[cfg(not(feature="tracing"))]
+    let _ = dirfd;
+    Syscall {
+        operation,
+        path,
+        started: if OBSERVED.load(Ordering::Relaxed) {
+            Some(Instant::now())
+        } else {
+            None
+        },
+        failed: Cell::new(false),
+        #[cfg(feature="tracing")]
+        _span: tracing::trace_span!("syscall",
+            operation, dirfd, path = ?path).entered(),
+    }
+}
+
+impl<'a> Syscall<'a> {
+    /// Returns the last OS error and remembers it for `ErrorContext`
+    ///
+    /// With the `tracing` feature the error is also logged at `DEBUG` level.
+    pub fn error(&self) -> io::Error {
+        let err = io::Error::last_os_error();
+        self.failed.set(true);
+        #[cfg(feature="tracing")]
+        tracing::debug!(error = %err, "syscall failed");
+        if let Some(code) = err.raw_os_error() {
+            record_failure(self.operation, self.path, code);
+        }
+        err
+    }
+}
+
+impl<'a> Drop for Syscall<'a> {
+    fn drop(&mut self) {
+        if let Some(started) = self.started {
+            let observer = OBSERVER.read()
+                .unwrap_or_else(|e| e.into_inner()).clone();
+            if let Some(observer) = observer {
+                observer.syscall(self.operation, started.elapsed(),
+                                 self.failed.get());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::Dir;
+
+    #[test]
+    fn observer() {
+        let stats = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+        let counter = stats.clone();
+        super::set_observer(move |op: &'static str, _, failed: bool| {
+            if op == "fstatat" {
+                counter.0.fetch_add(1, Ordering::SeqCst);
+                if failed {
+                    counter.1.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        let dir = Dir::open(".").unwrap();
+        for _ in 0..10 {
+            dir.metadata("Cargo.toml").unwrap();
+        }
+        assert!(dir.metadata("nonexistent").is_err());
+        super::clear_observer();
+        dir.metadata("Cargo.toml").unwrap();
+        // other tests may run in parallel, so only check lower bound
+        assert!(stats.0.load(Ordering::SeqCst) >= 11);
+        assert!(stats.1.load(Ordering::SeqCst) >= 1);
+    }
+}
+
+#[cfg(all(test, feature="tracing"))]
+mod test_tracing {
+    use std::sync::{Arc, Mutex};
+    use tracing::{Event, Id, Metadata, Subscriber};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Record};
+    use crate::Dir;
+
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    struct Fields<'a>(&'a mut String);
+
+    impl<'a> Visit for Fields<'a> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug)
+        {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata) -> bool { true }
+        fn new_span(&self, attrs: &Attributes) -> Id {
+            let mut line = attrs.metadata().name().to_string();
+            attrs.record(&mut Fields(&mut line));
+            let mut spans = self.0.lock().unwrap();
+            spans.push(line);
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event) {
+            let mut line = String::from("event");
+            event.record(&mut Fields(&mut line));
+            self.0.lock().unwrap().push(line);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn spans() {
+        let recorder = Recorder::default();
+        let log = recorder.0.clone();
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        tracing::subscriber::with_default(recorder, || {
+            dir.create_dir("sub", 0o755).unwrap();
+            dir.open_file("nonexistent").unwrap_err();
+        });
+        let log = log.lock().unwrap();
+        assert!(log[0].starts_with("syscall operation=\"mkdirat\" dirfd="));
+        assert!(log[0].ends_with(" path=\"sub\""));
+        assert!(log[1].starts_with("syscall operation=\"openat\""));
+        assert!(log[2].starts_with("event message=syscall failed error="));
+    }
+}
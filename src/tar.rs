@@ -0,0 +1,124 @@
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+
+use ::tar::{Builder, Header, EntryType};
+
+use crate::{Dir, AsPath, Metadata, SimpleType};
+use crate::dir::fstat;
+
+
+impl Dir {
+    /// Write a subtree into a tar archive
+    ///
+    /// Entries are added in sorted order with paths relative to `path`
+    /// (the directory itself is not added). Permissions, owners and
+    /// modification times are preserved, symlinks are stored as symlinks.
+    /// Sockets, fifos and devices are skipped.
+    ///
+    /// Every file is opened relative to its parent directory descriptor
+    /// (see `Dir::walk`), so renaming directories while archiving can't
+    /// make it include files from outside of the subtree. Metadata of a
+    /// regular file is taken from the opened file and exactly that many
+    /// bytes are written, so the archive stays valid even if the file is
+    /// modified concurrently: data appended after opening is not included,
+    /// and if the file is truncated the missing tail is filled with zeros.
+    ///
+    /// Requires `tar` feature:
+    ///
+    /// ```rust,no_run
+    /// # use openat::Dir;
+    /// # let dir = Dir::open(".").unwrap();
+    /// let file = std::fs::File::create("/tmp/src.tar").unwrap();
+    /// let mut builder = tar::Builder::new(file);
+    /// dir.export_tar("src", &mut builder).unwrap();
+    /// builder.finish().unwrap();
+    /// ```
+    pub fn export_tar<P: AsPath, W: Write>(&self, path: P,
+        builder: &mut Builder<W>)
+        -> io::Result<()>
+    {
+        for entry in self.walk(path)?.sorted() {
+            let entry = entry?;
+            let mut header = Header::new_gnu();
+            match entry.simple_type() {
+                Some(SimpleType::File) => {
+                    let file = entry.open_file()?;
+                    let meta = fstat(file.as_raw_fd())?;
+                    fill_header(&mut header, &meta, EntryType::Regular);
+                    let len = meta.len();
+                    let data = (&file).take(len).chain(io::repeat(0)).take(len);
+                    builder.append_data(&mut header, entry.path(), data)?;
+                }
+                Some(SimpleType::Dir) => {
+                    let meta = entry.metadata()?;
+                    fill_header(&mut header, &meta, EntryType::Directory);
+                    builder.append_data(&mut header, entry.path(),
+                                        io::empty())?;
+                }
+                Some(SimpleType::Symlink) => {
+                    let meta = entry.metadata()?;
+                    let target = entry.read_link()?;
+                    fill_header(&mut header, &meta, EntryType::Symlink);
+                    header.set_size(0);
+                    builder.append_link(&mut header, entry.path(), target)?;
+                }
+                Some(_) | None => continue,
+            }
+        }
+        Ok(())
+    }
+}
+
+// mode_t is u16 on some systems
+#[allow(clippy::useless_conversion)]
+fn fill_header(header: &mut Header, meta: &Metadata, typ: EntryType) {
+    let stat = meta.stat();
+    header.set_entry_type(typ);
+    header.set_mode(u32::from(stat.st_mode & 0o7777));
+    header.set_uid(stat.st_uid.into());
+    header.set_gid(stat.st_gid.into());
+    header.set_mtime(stat.st_mtime.max(0) as u64);
+    header.set_size(if typ == EntryType::Regular { meta.len() } else { 0 });
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use crate::Dir;
+
+    #[test]
+    fn export() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("src", 0o755).unwrap();
+        dir.create_dir("src/sub", 0o700).unwrap();
+        dir.write_file("src/sub/file", 0o640).unwrap()
+            .write_all(b"hello").unwrap();
+        dir.symlink("src/link", "sub/file").unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        dir.export_tar("src", &mut builder).unwrap();
+        let data = builder.into_inner().unwrap();
+
+        let mut archive = tar::Archive::new(&data[..]);
+        let mut items = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().into_owned();
+            let header = entry.header().clone();
+            let mut buf = String::new();
+            entry.read_to_string(&mut buf).unwrap();
+            items.push((path, header, buf));
+        }
+        let paths = items.iter().map(|x| x.0.clone()).collect::<Vec<_>>();
+        assert_eq!(paths, ["link", "sub", "sub/file"].iter()
+                   .map(PathBuf::from).collect::<Vec<_>>());
+        assert_eq!(items[0].1.link_name().unwrap().unwrap().to_str(),
+                   Some("sub/file"));
+        assert!(items[1].1.entry_type().is_dir());
+        assert_eq!(items[1].1.mode().unwrap(), 0o700);
+        assert_eq!(items[2].1.mode().unwrap(), 0o640);
+        assert_eq!(items[2].2, "hello");
+    }
+}
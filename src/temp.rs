@@ -0,0 +1,360 @@
+use std::io;
+use std::fs::File;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Deref;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Dir, AsPath, IntoMode};
+use crate::dir::{to_cstr, same_file};
+
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A named temporary file created by `Dir::tempfile()`
+///
+/// The file is removed when this object is dropped, unless it's persisted
+/// using `persist()` or `keep()`.
+#[derive(Debug)]
+pub struct TempFile<'a> {
+    dir: &'a Dir,
+    // both are `None` only after file is persisted
+    name: Option<CString>,
+    file: Option<File>,
+}
+
+/// A directory which is removed with all its contents on drop
+///
+/// It holds an open handle to both the directory and its parent, so it
+/// can be removed regardless of the lifetime of the original handles.
+/// Contents are removed through the directory handle, and the name is
+/// unlinked only if it still refers to that directory. Call `keep()` to
+/// disarm the guard.
+#[derive(Debug)]
+pub struct ScopedDir {
+    parent: Dir,
+    // `None` only after directory is kept
+    name: Option<CString>,
+    dir: Option<Dir>,
+}
+
+/// A temporary directory created by `Dir::tempdir()`
+pub type TempDir = ScopedDir;
+
+impl Dir {
+    /// Create a temporary directory in this directory
+    ///
+    /// The name of the directory is `prefix` followed by random characters.
+    /// It is created with `0o700` permissions and is removed with all its
+    /// contents when returned object is dropped.
+    pub fn tempdir<P: AsRef<OsStr>>(&self, prefix: P) -> io::Result<TempDir> {
+        loop {
+            let name = random_name(prefix.as_ref(), OsStr::new(""))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput,
+                                   "nul byte in file name")
+                })?;
+            match ScopedDir::create(self, &*name, 0o700) {
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    continue;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Create a named temporary file in this directory
+    ///
+    /// The name of the file is `prefix`, followed by random characters,
+    /// followed by `suffix`. The file is opened for reading and writing with
+    /// `O_EXCL` flag, so it's guaranteed that the file is newly created (a new
+    /// name is tried if there is a conflict).
+    ///
+    /// Unlike ``new_unnamed_file()`` this works on every system and every
+    /// filesystem. Common workflow is to write the file, then `persist()` it
+    /// under the final name (rename is atomic).
+    pub fn tempfile<P, S, M>(&self, prefix: P, suffix: S, mode: M)
+        -> io::Result<TempFile<'_>>
+        where P: AsRef<OsStr>, S: AsRef<OsStr>, M: IntoMode,
+    {
+        let (file, name) = create_temp(self,
+            prefix.as_ref(), suffix.as_ref(), mode.into_mode())?;
+        Ok(TempFile {
+            dir: self,
+            name: Some(name),
+            file: Some(file),
+        })
+    }
+}
+
+impl<'a> TempFile<'a> {
+    /// Returns the name of the file (relative to the directory)
+    pub fn name(&self) -> &OsStr {
+        OsStr::from_bytes(self.cname().to_bytes())
+    }
+    /// Returns the file
+    pub fn file(&self) -> &File {
+        self.file.as_ref().expect("file is not persisted")
+    }
+    /// Returns the file as mutable reference (for writing)
+    pub fn file_mut(&mut self) -> &mut File {
+        self.file.as_mut().expect("file is not persisted")
+    }
+    /// Atomically rename the file to a new name in the same directory
+    ///
+    /// If there is an error, the temporary file is removed on drop as usual.
+    pub fn persist<P: AsPath>(mut self, path: P) -> io::Result<File> {
+        self.dir.local_rename(self.cname(), path)?;
+        self.name.take();
+        Ok(self.file.take().expect("file is not persisted"))
+    }
+    /// Keep the file with the temporary name and return its name
+    pub fn keep(mut self) -> (File, OsString) {
+        let name = self.name.take().expect("file is not persisted");
+        let file = self.file.take().expect("file is not persisted");
+        (file, OsString::from_vec(name.into_bytes()))
+    }
+    fn cname(&self) -> &CStr {
+        self.name.as_ref().expect("file is not persisted")
+    }
+}
+
+impl<'a> Drop for TempFile<'a> {
+    fn drop(&mut self) {
+        if let Some(ref name) = self.name {
+            self.dir.remove_file(&**name).ok();
+        }
+    }
+}
+
+impl ScopedDir {
+    /// Guard an existing directory `name` in the `parent` directory
+    ///
+    /// Note: if the directory can't be opened it's not removed.
+    pub fn new<P: AsPath>(parent: Dir, name: P) -> io::Result<ScopedDir> {
+        let name = to_cstr(name)?.as_ref().to_owned();
+        let dir = parent.sub_dir(&*name)?;
+        Ok(ScopedDir {
+            parent,
+            name: Some(name),
+            dir: Some(dir),
+        })
+    }
+    /// Create a directory `name` in the `parent` and guard it
+    ///
+    /// The `parent` handle is cloned.
+    pub fn create<P: AsPath, M: IntoMode>(parent: &Dir, name: P, mode: M)
+        -> io::Result<ScopedDir>
+    {
+        let name = to_cstr(name)?;
+        let parent = parent.try_clone()?;
+        parent.create_dir(name.as_ref(), mode)?;
+        match ScopedDir::new(parent.try_clone()?, name.as_ref()) {
+            Ok(dir) => Ok(dir),
+            Err(e) => {
+                parent.remove_dir(name.as_ref()).ok();
+                Err(e)
+            }
+        }
+    }
+    /// Returns the handle of the guarded directory
+    pub fn dir(&self) -> &Dir {
+        self.dir.as_ref().expect("directory is not kept")
+    }
+    /// Returns the name of the directory (relative to the parent)
+    pub fn name(&self) -> &OsStr {
+        let name = self.name.as_ref().expect("directory is not kept");
+        OsStr::from_bytes(name.to_bytes())
+    }
+    /// Returns the handle of the parent directory
+    pub fn parent(&self) -> &Dir {
+        &self.parent
+    }
+    /// Don't remove the directory and return its handle and name
+    pub fn keep(mut self) -> (Dir, OsString) {
+        let name = self.name.take().expect("directory is not kept");
+        let dir = self.dir.take().expect("directory is not kept");
+        (dir, OsString::from_vec(name.into_bytes()))
+    }
+}
+
+impl Deref for ScopedDir {
+    type Target = Dir;
+    fn deref(&self) -> &Dir {
+        self.dir()
+    }
+}
+
+impl Drop for ScopedDir {
+    fn drop(&mut self) {
+        if let (Some(name), Some(dir)) = (&self.name, &self.dir) {
+            // clear through our own handle and only unlink the name if it
+            // still refers to the same directory (it may have been replaced)
+            if dir.clear().is_err() {
+                return;
+            }
+            match (self.parent.metadata(&**name), dir.self_metadata()) {
+                (Ok(ref a), Ok(ref b)) if same_file(a, b) => {
+                    self.parent.remove_dir(&**name).ok();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Create a file with random name, retrying on conflicts
+pub fn create_temp(dir: &Dir, prefix: &OsStr, suffix: &OsStr,
+    mode: libc::mode_t)
+    -> io::Result<(File, CString)>
+{
+    loop {
+        let name = random_name(prefix, suffix)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput,
+                               "nul byte in file name")
+            })?;
+        match dir._open_file(&name,
+            libc::O_CREAT|libc::O_EXCL|libc::O_RDWR, mode)
+        {
+            Ok(file) => return Ok((file, name)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Generate a random file name with the specified prefix and suffix
+///
+/// Returns `None` if prefix or suffix contain a nul byte.
+pub fn random_name(prefix: &OsStr, suffix: &OsStr) -> Option<CString> {
+    // RandomState is seeded randomly for each process, so we only need
+    // to make sure that names differ within the process
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+    if let Ok(time) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        hasher.write_u32(time.subsec_nanos());
+    }
+    let mut name = prefix.as_bytes().to_vec();
+    name.extend(format!("{:016x}", hasher.finish()).bytes());
+    name.extend(suffix.as_bytes());
+    CString::new(name).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+    use std::io::{Read, Write};
+    use crate::Dir;
+    use super::{random_name, ScopedDir};
+
+    #[test]
+    fn prefix_suffix() {
+        let name = random_name(OsStr::new(".tmp"), OsStr::new(".txt"))
+            .unwrap().into_string().unwrap();
+        assert!(name.starts_with(".tmp"));
+        assert!(name.ends_with(".txt"));
+        assert_eq!(name.len(), 4 + 16 + 4);
+    }
+
+    #[test]
+    fn unique() {
+        let a = random_name(OsStr::new(""), OsStr::new(""));
+        let b = random_name(OsStr::new(""), OsStr::new(""));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn tempfile_removed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut file = dir.tempfile("x-", ".tmp", 0o644).unwrap();
+        file.file_mut().write_all(b"hello").unwrap();
+        assert!(dir.metadata(file.name()).is_ok());
+        drop(file);
+        assert_eq!(dir.list_dir(".").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn tempfile_persist() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let mut file = dir.tempfile(".", ".tmp", 0o644).unwrap();
+        file.file_mut().write_all(b"hello").unwrap();
+        file.persist("result.txt").unwrap();
+        let mut buf = String::new();
+        dir.open_file("result.txt").unwrap()
+            .read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+        assert_eq!(dir.list_dir(".").unwrap().count(), 1);
+    }
+
+    #[test]
+    fn tempdir_removed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let parent = Dir::open(tmp.path()).unwrap();
+        let dir = parent.tempdir("test-").unwrap();
+        assert!(dir.name().to_str().unwrap().starts_with("test-"));
+        dir.dir().create_dir("sub", 0o755).unwrap();
+        dir.dir().write_file("sub/file", 0o644).unwrap();
+        drop(parent);
+        drop(dir);
+        assert_eq!(tmp.path().read_dir().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn tempdir_keep() {
+        let tmp = tempfile::tempdir().unwrap();
+        let parent = Dir::open(tmp.path()).unwrap();
+        let (_, name) = parent.tempdir("").unwrap().keep();
+        assert!(parent.metadata(name.as_os_str()).unwrap().is_dir());
+    }
+
+    #[test]
+    fn scoped_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let parent = Dir::open(tmp.path()).unwrap();
+        let dir = ScopedDir::create(&parent, "stage", 0o755).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        assert!(parent.metadata("stage/file").is_ok());
+        drop(dir);
+        assert_eq!(parent.list_dir(".").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn scoped_dir_replaced() {
+        let tmp = tempfile::tempdir().unwrap();
+        let parent = Dir::open(tmp.path()).unwrap();
+        let dir = ScopedDir::create(&parent, "stage", 0o755).unwrap();
+        dir.write_file("file", 0o644).unwrap();
+        parent.local_rename("stage", "moved").unwrap();
+        parent.create_dir("stage", 0o755).unwrap();
+        parent.write_file("stage/other", 0o644).unwrap();
+        drop(dir);
+        assert!(parent.metadata("stage/other").is_ok());
+        assert_eq!(parent.list_dir("moved").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn scoped_dir_create_existing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let parent = Dir::open(tmp.path()).unwrap();
+        parent.create_dir("stage", 0o755).unwrap();
+        let err = ScopedDir::create(&parent, "stage", 0o755).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(parent.metadata("stage").is_ok());
+    }
+
+    #[test]
+    fn tempfile_keep() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        let (_, name) = dir.tempfile("", "", 0o644).unwrap().keep();
+        assert!(dir.metadata(name.as_os_str()).unwrap().is_file());
+    }
+}
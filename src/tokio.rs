@@ -0,0 +1,227 @@
+//! Asynchronous wrapper of `Dir` for tokio
+//!
+//! This is `openat::unblock::AsyncDir` with `TokioSpawner`, i.e. every
+//! method runs the respective blocking method of `Dir` in
+//! `tokio::task::spawn_blocking`, so it must be called within tokio
+//! runtime. Opened files are returned as `tokio::fs::File`:
+//!
+//! ```rust,no_run
+//! # async fn x() -> std::io::Result<()> {
+//! use tokio::io::AsyncReadExt;
+//! use openat::tokio::AsyncDir;
+//!
+//! let dir = AsyncDir::open("/etc").await?;
+//! let mut buf = String::new();
+//! dir.open_file("hostname").await?.read_to_string(&mut buf).await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use ::tokio::fs::File;
+
+use crate::{Dir, Entry, AsPath, Metadata, IntoMode};
+use crate::unblock::{self, Spawner, BoxFuture};
+
+
+/// Spawner that uses `tokio::task::spawn_blocking`
+///
+/// Can be used with `openat::unblock::AsyncDir` if you need
+/// `std::fs::File` rather than `tokio::fs::File`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioSpawner;
+
+impl Spawner for TokioSpawner {
+    fn spawn_blocking<F, T>(&self, f: F) -> BoxFuture<io::Result<T>>
+        where F: FnOnce() -> io::Result<T> + Send + 'static,
+              T: Send + 'static,
+    {
+        let task = ::tokio::task::spawn_blocking(f);
+        Box::pin(async move {
+            match task.await {
+                Ok(res) => res,
+                Err(e) if e.is_panic() => {
+                    std::panic::resume_unwind(e.into_panic())
+                }
+                Err(e) => Err(io::Error::other(e)),
+            }
+        })
+    }
+}
+
+/// Asynchronous wrapper around `Dir`
+///
+/// Cloning is cheap: clones share the same file descriptor.
+#[derive(Debug, Clone)]
+pub struct AsyncDir {
+    inner: unblock::AsyncDir<TokioSpawner>,
+}
+
+impl AsyncDir {
+    /// Open a directory, see `Dir::open`
+    pub async fn open<P: AsPath>(path: P) -> io::Result<AsyncDir> {
+        unblock::AsyncDir::open(path, TokioSpawner).await
+            .map(|inner| AsyncDir { inner })
+    }
+    /// Returns the underlying blocking `Dir`
+    pub fn dir(&self) -> &Dir {
+        self.inner.dir()
+    }
+    /// Open subdirectory, see `Dir::sub_dir`
+    pub async fn sub_dir<P: AsPath>(&self, path: P) -> io::Result<AsyncDir> {
+        self.inner.sub_dir(path).await.map(|inner| AsyncDir { inner })
+    }
+    /// List subdirectory, see `Dir::list_dir`
+    ///
+    /// All entries are read in a single blocking task.
+    pub async fn list_dir<P: AsPath>(&self, path: P)
+        -> io::Result<Vec<Entry>>
+    {
+        self.inner.list_dir(path).await
+    }
+    /// Returns metadata of an entry, see `Dir::metadata`
+    pub async fn metadata<P: AsPath>(&self, path: P) -> io::Result<Metadata> {
+        self.inner.metadata(path).await
+    }
+    /// Read a symlink, see `Dir::read_link`
+    pub async fn read_link<P: AsPath>(&self, path: P) -> io::Result<PathBuf> {
+        self.inner.read_link(path).await
+    }
+    /// Open file for reading, see `Dir::open_file`
+    pub async fn open_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self.inner.open_file(path).await.map(File::from_std)
+    }
+    /// Open file for writing and truncate it, see `Dir::write_file`
+    pub async fn write_file<P, M>(&self, path: P, mode: M) -> io::Result<File>
+        where P: AsPath, M: IntoMode,
+    {
+        self.inner.write_file(path, mode).await.map(File::from_std)
+    }
+    /// Open file for append, see `Dir::append_file`
+    pub async fn append_file<P, M>(&self, path: P, mode: M)
+        -> io::Result<File>
+        where P: AsPath, M: IntoMode,
+    {
+        self.inner.append_file(path, mode).await.map(File::from_std)
+    }
+    /// Create a new file, fail if exists, see `Dir::new_file`
+    pub async fn new_file<P, M>(&self, path: P, mode: M) -> io::Result<File>
+        where P: AsPath, M: IntoMode,
+    {
+        self.inner.new_file(path, mode).await.map(File::from_std)
+    }
+    /// Open file for reading and writing, see `Dir::update_file`
+    pub async fn update_file<P, M>(&self, path: P, mode: M)
+        -> io::Result<File>
+        where P: AsPath, M: IntoMode,
+    {
+        self.inner.update_file(path, mode).await.map(File::from_std)
+    }
+    /// Create a subdirectory, see `Dir::create_dir`
+    pub async fn create_dir<P, M>(&self, path: P, mode: M) -> io::Result<()>
+        where P: AsPath, M: IntoMode,
+    {
+        self.inner.create_dir(path, mode).await
+    }
+    /// Make a symlink, see `Dir::symlink`
+    pub async fn symlink<P: AsPath, R: AsPath>(&self, path: P, value: R)
+        -> io::Result<()>
+    {
+        self.inner.symlink(path, value).await
+    }
+    /// Rename an entry within this directory, see `Dir::local_rename`
+    pub async fn local_rename<P: AsPath, R: AsPath>(&self, old: P, new: R)
+        -> io::Result<()>
+    {
+        self.inner.local_rename(old, new).await
+    }
+    /// Remove a file, see `Dir::remove_file`
+    pub async fn remove_file<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self.inner.remove_file(path).await
+    }
+    /// Remove an empty directory, see `Dir::remove_dir`
+    pub async fn remove_dir<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self.inner.remove_dir(path).await
+    }
+    /// Remove a directory with its contents, see `Dir::remove_recursive`
+    pub async fn remove_recursive<P: AsPath>(&self, path: P)
+        -> io::Result<()>
+    {
+        self.inner.remove_recursive(path).await
+    }
+    /// Wait until an entry exists, see `Dir::wait_for`
+    ///
+    /// Occupies a blocking task until the entry appears or timeout
+    /// expires.
+    pub async fn wait_for<P: AsPath>(&self, path: P, timeout: Duration)
+        -> io::Result<()>
+    {
+        self.inner.wait_for(path, timeout).await
+    }
+    /// Wait until an entry doesn't exist, see `Dir::wait_for_removal`
+    pub async fn wait_for_removal<P: AsPath>(&self, path: P,
+        timeout: Duration)
+        -> io::Result<()>
+    {
+        self.inner.wait_for_removal(path, timeout).await
+    }
+}
+
+impl From<Dir> for AsyncDir {
+    fn from(dir: Dir) -> AsyncDir {
+        AsyncDir { inner: unblock::AsyncDir::new(dir, TokioSpawner) }
+    }
+}
+
+impl AsRef<Dir> for AsyncDir {
+    fn as_ref(&self) -> &Dir {
+        self.inner.dir()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use ::tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use super::AsyncDir;
+
+    #[::tokio::test]
+    async fn files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = AsyncDir::open(tmp.path()).await.unwrap();
+        dir.create_dir("sub", 0o755).await.unwrap();
+        let sub = dir.sub_dir("sub").await.unwrap();
+        let mut file = sub.write_file("file", 0o644).await.unwrap();
+        file.write_all(b"hello").await.unwrap();
+        file.flush().await.unwrap();
+        let mut buf = String::new();
+        dir.open_file("sub/file").await.unwrap()
+            .read_to_string(&mut buf).await.unwrap();
+        assert_eq!(buf, "hello");
+        assert_eq!(dir.metadata("sub/file").await.unwrap().len(), 5);
+        let names = sub.list_dir(".").await.unwrap().iter()
+            .map(|e| e.file_name().to_owned()).collect::<Vec<_>>();
+        assert_eq!(names, ["file"]);
+        dir.symlink("link", "sub").await.unwrap();
+        assert_eq!(dir.read_link("link").await.unwrap().to_str(), Some("sub"));
+        dir.local_rename("link", "link2").await.unwrap();
+        dir.remove_file("link2").await.unwrap();
+        dir.remove_recursive("sub").await.unwrap();
+        assert!(dir.metadata("sub").await.is_err());
+    }
+
+    #[::tokio::test]
+    async fn wait_for() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = AsyncDir::open(tmp.path()).await.unwrap();
+        let writer = dir.dir().try_clone().unwrap();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            writer.write_file("file", 0o644).unwrap();
+        });
+        dir.wait_for("file", Duration::from_secs(10)).await.unwrap();
+        handle.join().unwrap();
+    }
+}
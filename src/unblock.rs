@@ -0,0 +1,337 @@
+//! Runtime-agnostic asynchronous wrapper of `Dir`
+//!
+//! Every method of `AsyncDir` runs the respective blocking method of `Dir`
+//! using a `Spawner`, which is the only thing that depends on the async
+//! runtime. For example, for smol (or async-std) it's:
+//!
+//! ```rust,ignore
+//! use openat::unblock::{AsyncDir, Spawner, BoxFuture};
+//!
+//! #[derive(Clone)]
+//! struct Smol;
+//!
+//! impl Spawner for Smol {
+//!     fn spawn_blocking<F, T>(&self, f: F) -> BoxFuture<std::io::Result<T>>
+//!         where F: FnOnce() -> std::io::Result<T> + Send + 'static,
+//!               T: Send + 'static,
+//!     {
+//!         Box::pin(blocking::unblock(f))
+//!     }
+//! }
+//!
+//! let dir = AsyncDir::open("/etc", Smol).await?;
+//! ```
+//!
+//! `ThreadSpawner` which runs each call in a new thread is provided for
+//! the cases where performance doesn't matter. For tokio see
+//! `openat::tokio` (requires `tokio` feature).
+use std::io;
+use std::fs::File;
+use std::pin::Pin;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::ffi::CString;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::task::{Context, Poll, Waker};
+
+use crate::{Dir, Entry, AsPath, Metadata, IntoMode};
+use crate::dir::to_cstr;
+
+
+/// A boxed future returned by `Spawner`
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output=T> + Send + 'static>>;
+
+/// Runs blocking functions outside of the async executor
+pub trait Spawner: Clone + Send + Sync + 'static {
+    /// Run function in a thread pool (or a thread) and return its result
+    fn spawn_blocking<F, T>(&self, f: F) -> BoxFuture<io::Result<T>>
+        where F: FnOnce() -> io::Result<T> + Send + 'static,
+              T: Send + 'static;
+}
+
+/// A spawner that runs every function in a new thread
+///
+/// If the function panics, the future resolves to an error.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadSpawner;
+
+struct Slot<T> {
+    result: Option<io::Result<T>>,
+    waker: Option<Waker>,
+}
+
+struct ThreadFuture<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Future for ThreadFuture<T> {
+    type Output = io::Result<T>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<T>> {
+        let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Spawner for ThreadSpawner {
+    fn spawn_blocking<F, T>(&self, f: F) -> BoxFuture<io::Result<T>>
+        where F: FnOnce() -> io::Result<T> + Send + 'static,
+              T: Send + 'static,
+    {
+        let slot = Arc::new(Mutex::new(Slot { result: None, waker: None }));
+        let thread_slot = slot.clone();
+        let spawned = std::thread::Builder::new()
+            .name("openat-unblock".into())
+            .spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(f))
+                    .unwrap_or_else(|_| {
+                        Err(io::Error::other("blocking operation panicked"))
+                    });
+                let mut slot = thread_slot.lock()
+                    .unwrap_or_else(|e| e.into_inner());
+                slot.result = Some(result);
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            });
+        match spawned {
+            Ok(_) => Box::pin(ThreadFuture { slot }),
+            Err(e) => Box::pin(async move { Err(e) }),
+        }
+    }
+}
+
+/// Asynchronous wrapper around `Dir`
+///
+/// Cloning is cheap: clones share the same file descriptor.
+#[derive(Debug, Clone)]
+pub struct AsyncDir<S: Spawner = ThreadSpawner> {
+    dir: Arc<Dir>,
+    spawner: S,
+}
+
+fn owned<P: AsPath>(path: P) -> io::Result<CString> {
+    Ok(to_cstr(path)?.as_ref().to_owned())
+}
+
+impl<S: Spawner> AsyncDir<S> {
+    /// Wrap a directory
+    pub fn new(dir: Dir, spawner: S) -> AsyncDir<S> {
+        AsyncDir { dir: Arc::new(dir), spawner }
+    }
+    /// Open a directory, see `Dir::open`
+    pub async fn open<P: AsPath>(path: P, spawner: S) -> io::Result<AsyncDir<S>>
+    {
+        let path = owned(path)?;
+        let dir = spawner.spawn_blocking(move || Dir::open(&*path)).await?;
+        Ok(AsyncDir::new(dir, spawner))
+    }
+    /// Returns the underlying blocking `Dir`
+    pub fn dir(&self) -> &Dir {
+        &self.dir
+    }
+    async fn run<F, T>(&self, f: F) -> io::Result<T>
+        where F: FnOnce(&Dir) -> io::Result<T> + Send + 'static,
+              T: Send + 'static,
+    {
+        let dir = self.dir.clone();
+        self.spawner.spawn_blocking(move || f(&dir)).await
+    }
+    /// Open subdirectory, see `Dir::sub_dir`
+    pub async fn sub_dir<P: AsPath>(&self, path: P)
+        -> io::Result<AsyncDir<S>>
+    {
+        let path = owned(path)?;
+        let dir = self.run(move |d| d.sub_dir(&*path)).await?;
+        Ok(AsyncDir::new(dir, self.spawner.clone()))
+    }
+    /// List subdirectory, see `Dir::list_dir`
+    ///
+    /// All entries are read in a single blocking call.
+    pub async fn list_dir<P: AsPath>(&self, path: P)
+        -> io::Result<Vec<Entry>>
+    {
+        let path = owned(path)?;
+        self.run(move |d| d.list_dir(&*path)?.collect()).await
+    }
+    /// Returns metadata of an entry, see `Dir::metadata`
+    pub async fn metadata<P: AsPath>(&self, path: P) -> io::Result<Metadata> {
+        let path = owned(path)?;
+        self.run(move |d| d.metadata(&*path)).await
+    }
+    /// Read a symlink, see `Dir::read_link`
+    pub async fn read_link<P: AsPath>(&self, path: P) -> io::Result<PathBuf> {
+        let path = owned(path)?;
+        self.run(move |d| d.read_link(&*path)).await
+    }
+    /// Open file for reading, see `Dir::open_file`
+    pub async fn open_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        let path = owned(path)?;
+        self.run(move |d| d.open_file(&*path)).await
+    }
+    /// Open file for writing and truncate it, see `Dir::write_file`
+    pub async fn write_file<P, M>(&self, path: P, mode: M) -> io::Result<File>
+        where P: AsPath, M: IntoMode,
+    {
+        let (path, mode) = (owned(path)?, mode.into_mode());
+        self.run(move |d| d.write_file(&*path, mode)).await
+    }
+    /// Open file for append, see `Dir::append_file`
+    pub async fn append_file<P, M>(&self, path: P, mode: M)
+        -> io::Result<File>
+        where P: AsPath, M: IntoMode,
+    {
+        let (path, mode) = (owned(path)?, mode.into_mode());
+        self.run(move |d| d.append_file(&*path, mode)).await
+    }
+    /// Create a new file, fail if exists, see `Dir::new_file`
+    pub async fn new_file<P, M>(&self, path: P, mode: M) -> io::Result<File>
+        where P: AsPath, M: IntoMode,
+    {
+        let (path, mode) = (owned(path)?, mode.into_mode());
+        self.run(move |d| d.new_file(&*path, mode)).await
+    }
+    /// Open file for reading and writing, see `Dir::update_file`
+    pub async fn update_file<P, M>(&self, path: P, mode: M)
+        -> io::Result<File>
+        where P: AsPath, M: IntoMode,
+    {
+        let (path, mode) = (owned(path)?, mode.into_mode());
+        self.run(move |d| d.update_file(&*path, mode)).await
+    }
+    /// Create a subdirectory, see `Dir::create_dir`
+    pub async fn create_dir<P, M>(&self, path: P, mode: M) -> io::Result<()>
+        where P: AsPath, M: IntoMode,
+    {
+        let (path, mode) = (owned(path)?, mode.into_mode());
+        self.run(move |d| d.create_dir(&*path, mode)).await
+    }
+    /// Make a symlink, see `Dir::symlink`
+    pub async fn symlink<P: AsPath, R: AsPath>(&self, path: P, value: R)
+        -> io::Result<()>
+    {
+        let (path, value) = (owned(path)?, owned(value)?);
+        self.run(move |d| d.symlink(&*path, &*value)).await
+    }
+    /// Rename an entry within this directory, see `Dir::local_rename`
+    pub async fn local_rename<P: AsPath, R: AsPath>(&self, old: P, new: R)
+        -> io::Result<()>
+    {
+        let (old, new) = (owned(old)?, owned(new)?);
+        self.run(move |d| d.local_rename(&*old, &*new)).await
+    }
+    /// Remove a file, see `Dir::remove_file`
+    pub async fn remove_file<P: AsPath>(&self, path: P) -> io::Result<()> {
+        let path = owned(path)?;
+        self.run(move |d| d.remove_file(&*path)).await
+    }
+    /// Remove an empty directory, see `Dir::remove_dir`
+    pub async fn remove_dir<P: AsPath>(&self, path: P) -> io::Result<()> {
+        let path = owned(path)?;
+        self.run(move |d| d.remove_dir(&*path)).await
+    }
+    /// Remove a directory with its contents, see `Dir::remove_recursive`
+    pub async fn remove_recursive<P: AsPath>(&self, path: P)
+        -> io::Result<()>
+    {
+        let path = owned(path)?;
+        self.run(move |d| d.remove_recursive(&*path)).await
+    }
+    /// Wait until an entry exists, see `Dir::wait_for`
+    ///
+    /// Occupies a blocking thread until the entry appears or timeout
+    /// expires.
+    pub async fn wait_for<P: AsPath>(&self, path: P, timeout: Duration)
+        -> io::Result<()>
+    {
+        let path = owned(path)?;
+        self.run(move |d| d.wait_for(&*path, timeout)).await
+    }
+    /// Wait until an entry doesn't exist, see `Dir::wait_for_removal`
+    pub async fn wait_for_removal<P: AsPath>(&self, path: P,
+        timeout: Duration)
+        -> io::Result<()>
+    {
+        let path = owned(path)?;
+        self.run(move |d| d.wait_for_removal(&*path, timeout)).await
+    }
+}
+
+impl From<Dir> for AsyncDir<ThreadSpawner> {
+    fn from(dir: Dir) -> AsyncDir<ThreadSpawner> {
+        AsyncDir::new(dir, ThreadSpawner)
+    }
+}
+
+impl<S: Spawner> AsRef<Dir> for AsyncDir<S> {
+    fn as_ref(&self) -> &Dir {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::future::Future;
+    use std::io::{self, Read, Write};
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+
+    use super::{AsyncDir, Spawner, ThreadSpawner};
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // minimal executor, so tests don't depend on any runtime
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let waker = Arc::new(Unpark(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match Pin::as_mut(&mut fut).poll(&mut cx) {
+                Poll::Ready(res) => return res,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn thread_spawner() {
+        let tmp = tempfile::tempdir().unwrap();
+        block_on(async {
+            let dir = AsyncDir::open(tmp.path(), ThreadSpawner).await.unwrap();
+            dir.create_dir("sub", 0o755).await.unwrap();
+            let sub = dir.sub_dir("sub").await.unwrap();
+            sub.write_file("file", 0o644).await.unwrap()
+                .write_all(b"hello").unwrap();
+            let mut buf = String::new();
+            dir.open_file("sub/file").await.unwrap()
+                .read_to_string(&mut buf).unwrap();
+            assert_eq!(buf, "hello");
+            assert_eq!(sub.list_dir(".").await.unwrap().len(), 1);
+            dir.remove_recursive("sub").await.unwrap();
+            assert!(dir.metadata("sub").await.is_err());
+        });
+    }
+    #[test]
+    fn thread_spawner_panic() {
+        let fut = ThreadSpawner.spawn_blocking(|| -> io::Result<()> {
+            panic!("blocking function failed");
+        });
+        let err = block_on(fut).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+}
@@ -0,0 +1,184 @@
+use std::io;
+use std::ffi::CStr;
+use std::time::{Duration, Instant};
+
+use crate::{Dir, AsPath};
+use crate::dir::to_cstr;
+use crate::error::raw_os_error;
+use self::watcher::watch_parent;
+
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl Dir {
+    /// Wait until the entry at `path` exists
+    ///
+    /// Returns immediately if it already exists, and `TimedOut` error if it
+    /// doesn't appear within `timeout`. Any kind of entry counts (a
+    /// dangling symlink too), so write the file under a temporary name and
+    /// rename it if it must be complete when noticed.
+    ///
+    /// With `inotify` (Linux) or `kqueue` (macOS, FreeBSD) feature the
+    /// parent directory is watched for changes, otherwise (or if the
+    /// parent doesn't exist yet) the entry is checked every 50 ms.
+    pub fn wait_for<P: AsPath>(&self, path: P, timeout: Duration)
+        -> io::Result<()>
+    {
+        self._wait_for(to_cstr(path)?.as_ref(), true, timeout)
+    }
+
+    /// Wait until the entry at `path` doesn't exist
+    ///
+    /// The counterpart of `wait_for`, e.g. for waiting until a lock or pid
+    /// file is removed.
+    pub fn wait_for_removal<P: AsPath>(&self, path: P, timeout: Duration)
+        -> io::Result<()>
+    {
+        self._wait_for(to_cstr(path)?.as_ref(), false, timeout)
+    }
+
+    fn _wait_for(&self, path: &CStr, present: bool, timeout: Duration)
+        -> io::Result<()>
+    {
+        let deadline = Instant::now() + timeout;
+        // watch is set up before checking, so no change is missed
+        let mut watcher = watch_parent(self, path);
+        loop {
+            if exists(self, path)? == present {
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut,
+                    if present {
+                        "timed out waiting for entry to appear"
+                    } else {
+                        "timed out waiting for entry to be removed"
+                    }));
+            }
+            watcher.wait(deadline - now)?;
+        }
+    }
+}
+
+fn exists(dir: &Dir, path: &CStr) -> io::Result<bool> {
+    match dir._stat(path, libc::AT_SYMLINK_NOFOLLOW) {
+        Ok(_) => Ok(true),
+        Err(e) => match raw_os_error(&e) {
+            Some(libc::ENOENT) | Some(libc::ENOTDIR) => Ok(false),
+            _ => Err(e),
+        },
+    }
+}
+
+#[cfg(any(all(feature="inotify", target_os="linux"),
+          all(feature="kqueue",
+              any(target_os="macos", target_os="freebsd"))))]
+mod watcher {
+    use std::io;
+    use std::ffi::{CStr, OsStr};
+    use std::time::Duration;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+
+    use crate::{Dir, Watcher};
+    use crate::watch::wait_readable;
+    use super::POLL_INTERVAL;
+
+    // recheck once in a while anyway, in case the parent directory is
+    // replaced and the watch doesn't see changes any more
+    const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub struct Waiter(Option<Watcher>);
+
+    pub fn watch_parent(dir: &Dir, path: &CStr) -> Waiter {
+        let bytes = path.to_bytes();
+        let watcher = match bytes.iter().rposition(|&b| b == b'/') {
+            Some(0) => Dir::open("/").and_then(|d| d.watch()),
+            Some(pos) => dir.sub_dir(OsStr::from_bytes(&bytes[..pos]))
+                .and_then(|d| d.watch()),
+            None => dir.watch(),
+        };
+        Waiter(watcher.ok())
+    }
+
+    impl Waiter {
+        pub fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+            let watcher = match self.0 {
+                Some(ref mut watcher) => watcher,
+                None => {
+                    std::thread::sleep(timeout.min(POLL_INTERVAL));
+                    return Ok(());
+                }
+            };
+            let fd = watcher.as_raw_fd();
+            if wait_readable(fd, Some(timeout.min(WATCH_INTERVAL)))? {
+                watcher.read_events()?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(any(all(feature="inotify", target_os="linux"),
+              all(feature="kqueue",
+                  any(target_os="macos", target_os="freebsd")))))]
+mod watcher {
+    use std::io;
+    use std::ffi::CStr;
+    use std::time::Duration;
+
+    use crate::Dir;
+    use super::POLL_INTERVAL;
+
+    pub struct Waiter;
+
+    pub fn watch_parent(_dir: &Dir, _path: &CStr) -> Waiter {
+        Waiter
+    }
+
+    impl Waiter {
+        pub fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+            std::thread::sleep(timeout.min(POLL_INTERVAL));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use crate::Dir;
+
+    #[test]
+    fn wait_for() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.wait_for("sub", Duration::from_secs(0)).unwrap();
+        let err = dir.wait_for("sub/file", Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        let writer = dir.try_clone().unwrap();
+        let start = Instant::now();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            writer.write_file("sub/file", 0o644).unwrap();
+        });
+        dir.wait_for("sub/file", Duration::from_secs(10)).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        handle.join().unwrap();
+
+        let remover = dir.try_clone().unwrap();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            remover.remove_file("sub/file").unwrap();
+        });
+        dir.wait_for_removal("sub/file", Duration::from_secs(10)).unwrap();
+        handle.join().unwrap();
+        dir.wait_for_removal("missing/file", Duration::from_secs(0)).unwrap();
+    }
+}
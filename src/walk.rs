@@ -0,0 +1,595 @@
+use std::io;
+use std::fs::File;
+use std::sync::{Arc, Weak};
+use std::ffi::{CStr, OsStr};
+use std::path::{Path, PathBuf};
+
+use crate::{Dir, Entry, AsPath, DirIter, Metadata, SimpleType, SortBy};
+use crate::PathStack;
+use crate::dir::to_cstr;
+use crate::list::open_dir;
+use crate::cancel::{CancelToken, cancelled};
+
+const DOT: &[u8] = b".\0";
+
+
+/// Recursive iterator over a directory tree, created by `Dir::walk()`
+///
+/// Entries are returned in pre-order: each directory is returned before
+/// its contents. Symlinks are never followed. Every directory is opened
+/// relative to its parent's file descriptor, so renaming directories
+/// above the one being traversed doesn't affect the walk.
+///
+/// The path of the current directory is kept in a single `PathStack`,
+/// only paths of the returned entries are allocated.
+#[derive(Debug)]
+pub struct Walk {
+    root: Arc<Dir>,
+    started: bool,
+    stack: Vec<Frame>,
+    // path of the directory of the top frame
+    path: PathStack,
+    error: Option<io::Error>,
+    max_depth: usize,
+    order: Option<SortBy>,
+    cancel: Option<CancelToken>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    dir: Arc<Dir>,
+    entries: Listing,
+    depth: usize,
+}
+
+#[derive(Debug)]
+enum Listing {
+    Unsorted(DirIter),
+    Sorted(std::vec::IntoIter<Entry>),
+}
+
+/// Entry returned by `Walk`
+///
+/// Entries don't keep directories open. While the walk is still in the
+/// containing directory, methods like `open_file` use its file
+/// descriptor, otherwise (e.g. for the results of `par_collect` and
+/// `Dir::find`) they resolve `path()` relative to the walked directory.
+#[derive(Debug)]
+pub struct WalkEntry {
+    root: Arc<Dir>,
+    dir: Weak<Dir>,
+    entry: Entry,
+    pub(crate) path: PathBuf,
+    // number of leading components of `path` not under the `root`
+    prefix: usize,
+    depth: usize,
+}
+
+impl Dir {
+    /// Walk the directory tree recursively
+    ///
+    /// The directory `path` itself is not returned, paths of the entries
+    /// are relative to it:
+    ///
+    /// ```rust,no_run
+    /// # use openat::{Dir, SimpleType};
+    /// # let dir = Dir::open(".").unwrap();
+    /// for entry in dir.walk("src").unwrap().sorted() {
+    ///     let entry = entry.unwrap();
+    ///     if entry.simple_type() == Some(SimpleType::File) {
+    ///         println!("{:?}", entry.path());  // e.g. "bin/main.rs"
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// Errors of reading a directory are returned as items of the iterator
+    /// and the walk continues with the next entry.
+    pub fn walk<P: AsPath>(&self, path: P) -> io::Result<Walk> {
+        let root = self._sub_dir(to_cstr(path)?.as_ref(), libc::O_DIRECTORY)?;
+        Ok(Walk {
+            root: Arc::new(root),
+            started: false,
+            stack: Vec::new(),
+            path: PathStack::new(),
+            error: None,
+            max_depth: usize::MAX,
+            order: None,
+            cancel: None,
+        })
+    }
+}
+
+impl Dir {
+    /// Find all entries in the tree matching the predicate
+    ///
+    /// This is a shortcut for `walk(path)` filtered by `predicate`, so
+    /// paths are relative to `path`. Unlike `walk` this stops on the first
+    /// error.
+    pub fn find<P, F>(&self, path: P, mut predicate: F)
+        -> io::Result<Vec<WalkEntry>>
+        where P: AsPath, F: FnMut(&WalkEntry) -> bool,
+    {
+        let mut result = Vec::new();
+        for entry in self.walk(path)? {
+            let entry = entry?;
+            if predicate(&entry) {
+                result.push(entry);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Find first entry in the tree matching the predicate
+    ///
+    /// Same as `find` but stops walking as soon as a match is found.
+    pub fn find_first<P, F>(&self, path: P, mut predicate: F)
+        -> io::Result<Option<WalkEntry>>
+        where P: AsPath, F: FnMut(&WalkEntry) -> bool,
+    {
+        for entry in self.walk(path)? {
+            let entry = entry?;
+            if predicate(&entry) {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn dot() -> &'static CStr {
+    CStr::from_bytes_with_nul(DOT).expect("valid C string")
+}
+
+fn list(dir: &Dir, order: Option<SortBy>) -> io::Result<Listing> {
+    match order {
+        Some(by) => Ok(Listing::Sorted(dir.list_sorted(dot(), by)?
+                                       .into_iter())),
+        None => Ok(Listing::Unsorted(open_dir(dir, dot(), 0)?)),
+    }
+}
+
+// Fills in the type if `d_type` is not supported by the filesystem
+fn fill_type(dir: &Dir, mut entry: Entry) -> Entry {
+    if entry.file_type.is_none() {
+        if let Ok(meta) = dir._stat(&entry.name, libc::AT_SYMLINK_NOFOLLOW) {
+            entry.file_type = Some(meta.simple_type());
+        }
+    }
+    entry
+}
+
+// Entries of `WalkEntry` must not keep directory streams open
+fn unbind(mut entry: Entry) -> Entry {
+    entry.dir = None;
+    entry
+}
+
+impl Walk {
+    /// Don't descend deeper than `depth` levels
+    ///
+    /// Entries of the walked directory have depth 1, so `max_depth(1)`
+    /// is like `list_dir()`.
+    pub fn max_depth(mut self, depth: usize) -> Walk {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Return entries of every directory sorted by name
+    ///
+    /// This makes the order deterministic, at the cost of reading every
+    /// directory fully before returning its first entry.
+    pub fn sorted(self) -> Walk {
+        self.sort_by(SortBy::Name)
+    }
+
+    /// Return entries of every directory in the specified order
+    ///
+    /// See `Dir::list_sorted`.
+    pub fn sort_by(mut self, order: SortBy) -> Walk {
+        self.order = Some(order);
+        self
+    }
+
+    /// Return entries of every directory ordered by inode number
+    ///
+    /// Use this if you're going to stat or open most of the entries:
+    /// on ext4 and XFS this is much faster with cold caches than the
+    /// order of `readdir`, as inodes are read from disk sequentially.
+    pub fn inode_order(self) -> Walk {
+        self.sort_by(SortBy::Inode)
+    }
+
+    /// Stop the walk when the token is cancelled
+    ///
+    /// The iterator returns a single `ErrorKind::Interrupted` error after
+    /// cancellation and then finishes.
+    pub fn cancel_on(mut self, token: CancelToken) -> Walk {
+        self.cancel = Some(token);
+        self
+    }
+
+    fn push(&mut self, dir: Arc<Dir>, name: Option<&OsStr>, depth: usize) {
+        let listed = list(&dir, self.order).and_then(|entries| {
+            if let Some(name) = name {
+                self.path.push(name)?;
+            }
+            Ok(entries)
+        });
+        match listed {
+            Ok(entries) => self.stack.push(Frame { dir, entries, depth }),
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+impl Iterator for Listing {
+    type Item = io::Result<Entry>;
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        match self {
+            Listing::Unsorted(iter) => iter.next(),
+            Listing::Sorted(iter) => iter.next().map(Ok),
+        }
+    }
+}
+
+impl Iterator for Walk {
+    type Item = io::Result<WalkEntry>;
+    fn next(&mut self) -> Option<io::Result<WalkEntry>> {
+        if !self.started {
+            self.started = true;
+            if self.max_depth > 0 {
+                self.push(self.root.clone(), None, 0);
+            }
+        }
+        loop {
+            if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                if self.stack.is_empty() && self.error.is_none() {
+                    return None;
+                }
+                self.stack.clear();
+                self.path.clear();
+                self.error = None;
+                return Some(Err(cancelled()));
+            }
+            if let Some(err) = self.error.take() {
+                return Some(Err(err));
+            }
+            let frame = self.stack.last_mut()?;
+            let entry = match frame.entries.next() {
+                None => {
+                    self.stack.pop();
+                    self.path.pop();
+                    continue;
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                Some(Ok(entry)) => fill_type(&frame.dir, entry),
+            };
+            let dir = frame.dir.clone();
+            let path = self.path.as_path().join(entry.file_name());
+            let depth = frame.depth + 1;
+            if entry.file_type == Some(SimpleType::Dir)
+                && depth < self.max_depth
+            {
+                match dir._sub_dir(&entry.name, libc::O_DIRECTORY) {
+                    Ok(sub) => {
+                        self.push(Arc::new(sub), Some(entry.file_name()),
+                                  depth)
+                    }
+                    Err(e) => self.error = Some(e),
+                }
+            }
+            return Some(Ok(WalkEntry {
+                root: self.root.clone(),
+                dir: Arc::downgrade(&dir),
+                entry: unbind(entry),
+                path,
+                prefix: 0,
+                depth,
+            }));
+        }
+    }
+}
+
+impl WalkEntry {
+    /// Returns path relative to the walked directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Returns the file name of this entry
+    pub fn file_name(&self) -> &OsStr {
+        self.entry.file_name()
+    }
+    /// Returns the simplified type of this entry
+    ///
+    /// Unlike `Entry::simple_type()` it's only `None` if the type can't
+    /// be determined even by `fstatat`.
+    pub fn simple_type(&self) -> Option<SimpleType> {
+        self.entry.simple_type()
+    }
+    /// Returns depth of the entry, 1 for entries of the walked directory
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+    /// Returns the entry of the containing directory
+    ///
+    /// The entry doesn't keep the directory open, so its `open_file` and
+    /// similar methods fail, use methods of `WalkEntry` instead.
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+    /// Returns metadata of this entry (symlinks are not followed)
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        self.relative(|dir, path| dir.metadata(path))
+    }
+    /// Open this entry as a file for reading, see `Dir::open_file`
+    pub fn open_file(&self) -> io::Result<File> {
+        self.relative(|dir, path| dir.open_file(path))
+    }
+    /// Open this entry as a directory, see `Dir::sub_dir`
+    pub fn sub_dir(&self) -> io::Result<Dir> {
+        self.relative(|dir, path| dir.sub_dir(path))
+    }
+    /// Read this entry as a symlink, see `Dir::read_link`
+    pub fn read_link(&self) -> io::Result<PathBuf> {
+        self.relative(|dir, path| dir.read_link(path))
+    }
+    // Calls `f` with the containing directory and the name if it's still
+    // open, or with the walked directory and the path otherwise
+    fn relative<T, F>(&self, f: F) -> io::Result<T>
+        where F: FnOnce(&Dir, &CStr) -> io::Result<T>,
+    {
+        match self.dir.upgrade() {
+            Some(dir) => f(&dir, &self.entry.name),
+            None => {
+                let path = self.path.components().skip(self.prefix)
+                    .collect::<PathBuf>();
+                f(&self.root, to_cstr(&path)?.as_ref())
+            }
+        }
+    }
+    /// Prepends `prefix` to the path returned by `path()`
+    #[cfg(feature="glob")]
+    pub(crate) fn add_prefix(&mut self, prefix: &Path) {
+        self.prefix += prefix.components().count();
+        self.path = prefix.join(&self.path);
+    }
+}
+
+#[cfg(feature="rayon")]
+mod parallel {
+    use std::io;
+    use std::sync::Arc;
+    use std::path::PathBuf;
+
+    use rayon::prelude::*;
+
+    use crate::{Dir, SimpleType, SortBy, CancelToken};
+    use crate::cancel::cancelled;
+    use super::{Walk, WalkEntry, Listing, list, fill_type, unbind};
+
+    impl Walk {
+        /// Walk the tree in parallel using rayon's thread pool
+        ///
+        /// Subdirectories are traversed concurrently, each one using its
+        /// own file descriptor. The result is in the same order as the
+        /// sequential iteration would return (so use `sorted()` for fully
+        /// deterministic order). Errors are returned in place of the
+        /// entries that couldn't be read.
+        ///
+        /// Only entries that weren't returned by the iterator yet are
+        /// walked, so usually this is called on a fresh `Walk`. When
+        /// cancelled (see `cancel_on`), every branch still being walked
+        /// ends with an `ErrorKind::Interrupted` error.
+        pub fn par_collect(self) -> Vec<io::Result<WalkEntry>> {
+            let mut result = Vec::new();
+            if !self.started {
+                if self.max_depth > 0 {
+                    let opts = Options {
+                        root: &self.root,
+                        max_depth: self.max_depth,
+                        order: self.order,
+                        cancel: self.cancel.as_ref(),
+                    };
+                    result.extend(visit(self.root.clone(), PathBuf::new(), 0,
+                                        &opts));
+                }
+                return result;
+            }
+            // continue partially consumed walk sequentially for simplicity
+            result.extend(self);
+            result
+        }
+    }
+
+    struct Options<'a> {
+        root: &'a Arc<Dir>,
+        max_depth: usize,
+        order: Option<SortBy>,
+        cancel: Option<&'a CancelToken>,
+    }
+
+    fn visit(dir: Arc<Dir>, path: PathBuf, depth: usize, opts: &Options)
+        -> Vec<io::Result<WalkEntry>>
+    {
+        let entries = match list(&dir, opts.order) {
+            // unbound, so the stream is closed before descending
+            Ok(Listing::Sorted(iter)) => {
+                iter.map(|e| Ok(unbind(e))).collect::<Vec<_>>()
+            }
+            Ok(Listing::Unsorted(iter)) => {
+                iter.map(|e| e.map(unbind)).collect::<Vec<_>>()
+            }
+            Err(e) => return vec![Err(e)],
+        };
+        entries.into_par_iter().map(|entry| {
+            if opts.cancel.is_some_and(|c| c.is_cancelled()) {
+                return vec![Err(cancelled())];
+            }
+            let entry = match entry {
+                Ok(entry) => fill_type(&dir, entry),
+                Err(e) => return vec![Err(e)],
+            };
+            let path = path.join(entry.file_name());
+            let depth = depth + 1;
+            let sub = if entry.file_type == Some(SimpleType::Dir)
+                && depth < opts.max_depth
+            {
+                Some(dir._sub_dir(&entry.name, libc::O_DIRECTORY))
+            } else {
+                None
+            };
+            let mut result = vec![Ok(WalkEntry {
+                root: opts.root.clone(),
+                dir: Arc::downgrade(&dir),
+                entry,
+                path: path.clone(),
+                prefix: 0,
+                depth,
+            })];
+            match sub {
+                Some(Ok(sub)) => {
+                    result.extend(visit(Arc::new(sub), path, depth, opts))
+                }
+                Some(Err(e)) => result.push(Err(e)),
+                None => {}
+            }
+            result
+        }).collect::<Vec<_>>().into_iter().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::path::Path;
+    use crate::{Dir, SimpleType, CancelToken};
+
+    fn tree() -> (tempfile::TempDir, Dir) {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("b", 0o755).unwrap();
+        dir.create_dir("b/d", 0o755).unwrap();
+        dir.write_file("b/d/e", 0o644).unwrap();
+        dir.write_file("b/c", 0o644).unwrap();
+        dir.write_file("a", 0o644).unwrap();
+        dir.symlink("f", "b").unwrap();
+        (tmp, dir)
+    }
+
+    #[test]
+    fn sorted() {
+        let (_tmp, dir) = tree();
+        let entries = dir.walk(".").unwrap().sorted()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        let paths = entries.iter().map(|e| e.path()).collect::<Vec<_>>();
+        assert_eq!(paths, ["a", "b", "b/c", "b/d", "b/d/e", "f"].iter()
+                   .map(Path::new).collect::<Vec<_>>());
+        let depths = entries.iter().map(|e| e.depth()).collect::<Vec<_>>();
+        assert_eq!(depths, [1, 1, 2, 2, 3, 1]);
+        assert_eq!(entries[5].simple_type(), Some(SimpleType::Symlink));
+        assert!(entries[4].metadata().unwrap().is_file());
+        entries[4].open_file().unwrap();
+        entries[3].sub_dir().unwrap();
+        assert_eq!(entries[5].read_link().unwrap(), Path::new("b"));
+    }
+
+    #[test]
+    fn max_depth() {
+        let (_tmp, dir) = tree();
+        assert_eq!(dir.walk(".").unwrap().max_depth(1).count(), 3);
+        assert_eq!(dir.walk(".").unwrap().max_depth(2).count(), 5);
+        assert_eq!(dir.walk("b").unwrap().count(), 3);
+        assert_eq!(dir.walk(".").unwrap().max_depth(0).count(), 0);
+    }
+
+    #[test]
+    fn find() {
+        let (_tmp, dir) = tree();
+        let found = dir.find(".", |e| e.file_name() != "a"
+                                      && e.metadata().unwrap().is_file())
+            .unwrap();
+        let mut paths = found.iter().map(|e| e.path()).collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(paths, [Path::new("b/c"), Path::new("b/d/e")]);
+        // directories are closed after the walk, the path is used instead
+        assert!(found.iter().all(|e| e.dir.upgrade().is_none()));
+        assert!(found.iter().all(|e| e.open_file().is_ok()));
+        assert!(found.iter().all(|e| e.entry().open_file().is_err()));
+        let mut visited = 0;
+        let first = dir.find_first(".", |e| {
+            visited += 1;
+            e.depth() == 1
+        }).unwrap().unwrap();
+        assert_eq!(first.depth(), 1);
+        assert_eq!(visited, 1);
+        assert!(dir.find_first("b", |_| false).unwrap().is_none());
+    }
+
+    #[cfg(target_os="linux")]
+    fn open_fds() -> usize {
+        std::fs::read_dir("/proc/self/fd").unwrap().count()
+    }
+
+    #[test]
+    #[cfg(target_os="linux")]
+    fn results_dont_keep_fds() {
+        let (_tmp, dir) = tree();
+        for i in 0..200 {
+            dir.create_dir(format!("b/d/{}", i), 0o755).unwrap();
+            dir.write_file(format!("b/d/{}/x", i), 0o644).unwrap();
+        }
+        let before = open_fds();
+        let found = dir.find(".", |e| e.file_name() == "x").unwrap();
+        assert_eq!(found.len(), 200);
+        // other tests run in parallel and may open a few descriptors
+        assert!(open_fds() < before + 50);
+        #[cfg(feature="rayon")]
+        {
+            let all = dir.walk(".").unwrap().par_collect();
+            assert_eq!(all.len(), 406);
+            assert!(open_fds() < before + 50);
+        }
+        assert!(found.iter().all(|e| e.open_file().is_ok()));
+    }
+
+    #[test]
+    fn inode_order() {
+        let (_tmp, dir) = tree();
+        let entries = dir.walk(".").unwrap().inode_order()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries.len(), 6);
+        let top = entries.iter().filter(|e| e.depth() == 1)
+            .map(|e| e.entry().inode()).collect::<Vec<_>>();
+        assert!(top.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn cancel() {
+        let (_tmp, dir) = tree();
+        let token = CancelToken::new();
+        let mut walk = dir.walk(".").unwrap().cancel_on(token.clone());
+        assert!(walk.next().unwrap().is_ok());
+        token.cancel();
+        assert_eq!(walk.next().unwrap().unwrap_err().kind(),
+                   io::ErrorKind::Interrupted);
+        assert!(walk.next().is_none());
+    }
+
+    #[test]
+    #[cfg(feature="rayon")]
+    fn parallel() {
+        let (_tmp, dir) = tree();
+        for i in 0..10 {
+            dir.create_dir(format!("b/d/{}", i), 0o755).unwrap();
+            dir.write_file(format!("b/d/{}/x", i), 0o644).unwrap();
+        }
+        let seq = dir.walk(".").unwrap().sorted()
+            .map(|e| e.unwrap().path().to_path_buf()).collect::<Vec<_>>();
+        let par = dir.walk(".").unwrap().sorted().par_collect().into_iter()
+            .map(|e| e.unwrap().path().to_path_buf()).collect::<Vec<_>>();
+        assert_eq!(seq.len(), 26);
+        assert_eq!(seq, par);
+        let par = dir.walk("b").unwrap().par_collect();
+        assert!(par.iter().all(|e| e.as_ref().unwrap().metadata().is_ok()));
+    }
+}
@@ -0,0 +1,554 @@
+use std::io;
+use std::fs::File;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::os::wasi::ffi::{OsStrExt, OsStringExt};
+use std::os::wasi::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+
+
+// WASI has neither `O_PATH` nor `O_CLOEXEC`, a directory opened for reading
+// has the rights to open paths relative to it
+const DIR_FLAGS: libc::c_int = libc::O_RDONLY|libc::O_DIRECTORY;
+
+/// A safe wrapper around directory file descriptor
+///
+/// This is the WASI implementation of the core part of the API. `path_open`
+/// is always relative to a directory in WASI, so the semantics are the same
+/// as on unix. Absolute paths passed to `Dir::open` are resolved against
+/// the preopened directories (by wasi-libc), relative paths passed to other
+/// methods are resolved against the directory itself.
+#[derive(Debug)]
+pub struct Dir(RawFd);
+
+/// Entry returned by iterating over `DirIter` iterator
+#[derive(Debug, Clone)]
+pub struct Entry {
+    name: CString,
+    file_type: Option<SimpleType>,
+    ino: u64,
+}
+
+/// Iterator over directory entries, created by `Dir::list_dir`
+///
+/// Entries `.` and `..` are skipped.
+#[derive(Debug)]
+pub struct DirIter {
+    dir: *mut libc::DIR,
+}
+
+/// Metadata of a file, returned by `Dir::metadata`
+pub struct Metadata {
+    stat: libc::stat,
+}
+
+/// This is a simplified file type enum that is easy to match
+///
+/// Same as on unix, WASI reports all of these types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SimpleType {
+    /// Entry is a symlink
+    Symlink,
+    /// Entry is a directory
+    Dir,
+    /// Entry is a regular file
+    File,
+    /// Entry is a socket
+    Socket,
+    /// Entry is a named pipe
+    Fifo,
+    /// Entry is a character device
+    CharDevice,
+    /// Entry is a block device
+    BlockDevice,
+    /// Entry is of some other (system-specific) type
+    Other,
+}
+
+/// The purpose of this is similar to `AsRef<Path>` but it's optimized for
+/// things that can be directly used as `CStr` (which is type passed to
+/// the underlying system call).
+pub trait AsPath {
+    /// The return value of the `to_path` that holds data copied from the
+    /// original path (if copy is needed, otherwise it's just a reference)
+    type Buffer: AsRef<CStr>;
+    /// Returns `None` when path contains a zero byte
+    fn to_path(self) -> Option<Self::Buffer>;
+}
+
+impl<'a> AsPath for &'a Path {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_os_str().as_bytes()).ok()
+    }
+}
+
+impl<'a> AsPath for &'a PathBuf {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_os_str().as_bytes()).ok()
+    }
+}
+
+impl<'a> AsPath for &'a OsStr {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_bytes()).ok()
+    }
+}
+
+impl<'a> AsPath for &'a str {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_bytes()).ok()
+    }
+}
+
+impl<'a> AsPath for &'a String {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self.as_bytes()).ok()
+    }
+}
+
+impl AsPath for String {
+    type Buffer = CString;
+    fn to_path(self) -> Option<CString> {
+        CString::new(self).ok()
+    }
+}
+
+impl<'a> AsPath for &'a CStr {
+    type Buffer = &'a CStr;
+    fn to_path(self) -> Option<&'a CStr> {
+        Some(self)
+    }
+}
+
+impl<'a> AsPath for &'a Entry {
+    type Buffer = &'a CStr;
+    fn to_path(self) -> Option<&'a CStr> {
+        Some(&self.name)
+    }
+}
+
+fn to_cstr<P: AsPath>(path: P) -> io::Result<P::Buffer> {
+    path.to_path()
+    .ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput,
+                       "nul byte in file name")
+    })
+}
+
+fn check(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+impl Dir {
+    /// Open a directory descriptor at specified path
+    pub fn open<P: AsPath>(path: P) -> io::Result<Dir> {
+        let path = to_cstr(path)?;
+        let fd = unsafe { libc::open(path.as_ref().as_ptr(), DIR_FLAGS) };
+        Ok(Dir(check(fd)?))
+    }
+
+    /// List subdirectory of this dir
+    pub fn list_dir<P: AsPath>(&self, path: P) -> io::Result<DirIter> {
+        let path = to_cstr(path)?;
+        let fd = check(unsafe {
+            libc::openat(self.0, path.as_ref().as_ptr(), DIR_FLAGS)
+        })?;
+        let dir = unsafe { libc::fdopendir(fd) };
+        if dir.is_null() {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        Ok(DirIter { dir })
+    }
+
+    /// List this dir
+    pub fn list_self(&self) -> io::Result<DirIter> {
+        self.list_dir(".")
+    }
+
+    /// Open subdirectory
+    ///
+    /// Symlinks are not followed for the last component of the path.
+    pub fn sub_dir<P: AsPath>(&self, path: P) -> io::Result<Dir> {
+        let path = to_cstr(path)?;
+        let fd = unsafe {
+            libc::openat(self.0, path.as_ref().as_ptr(),
+                         DIR_FLAGS|libc::O_NOFOLLOW)
+        };
+        Ok(Dir(check(fd)?))
+    }
+
+    /// Read link in this directory
+    pub fn read_link<P: AsPath>(&self, path: P) -> io::Result<PathBuf> {
+        let path = to_cstr(path)?;
+        let mut buf = vec![0u8; 4096];
+        let res = unsafe {
+            libc::readlinkat(self.0, path.as_ref().as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_char, buf.len())
+        };
+        if res < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            buf.truncate(res as usize);
+            Ok(OsString::from_vec(buf).into())
+        }
+    }
+
+    /// Open file for reading in this directory
+    ///
+    /// Symlinks are not followed for the last component of the path.
+    pub fn open_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self._open_file(to_cstr(path)?.as_ref(), libc::O_RDONLY, 0)
+    }
+
+    /// Open file for writing, create if necessary, truncate on open
+    ///
+    /// WASI has no permissions, so `mode` is ignored.
+    pub fn write_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+        -> io::Result<File>
+    {
+        self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_CREAT|libc::O_WRONLY|libc::O_TRUNC, mode)
+    }
+
+    /// Open file for append, create if necessary
+    ///
+    /// WASI has no permissions, so `mode` is ignored.
+    pub fn append_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+        -> io::Result<File>
+    {
+        self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_CREAT|libc::O_WRONLY|libc::O_APPEND, mode)
+    }
+
+    /// Create file if not exists, fail if exists
+    ///
+    /// WASI has no permissions, so `mode` is ignored.
+    pub fn new_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+        -> io::Result<File>
+    {
+        self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_CREAT|libc::O_EXCL|libc::O_WRONLY, mode)
+    }
+
+    /// Open file for reading and writing without truncation, create if needed
+    ///
+    /// WASI has no permissions, so `mode` is ignored.
+    pub fn update_file<P: AsPath>(&self, path: P, mode: libc::mode_t)
+        -> io::Result<File>
+    {
+        self._open_file(to_cstr(path)?.as_ref(),
+            libc::O_CREAT|libc::O_RDWR, mode)
+    }
+
+    fn _open_file(&self, path: &CStr, flags: libc::c_int,
+        mode: libc::mode_t)
+        -> io::Result<File>
+    {
+        let fd = unsafe {
+            libc::openat(self.0, path.as_ptr(), flags|libc::O_NOFOLLOW,
+                         mode as libc::c_uint)
+        };
+        Ok(unsafe { File::from_raw_fd(check(fd)?) })
+    }
+
+    /// Make a symlink in this directory
+    ///
+    /// Note: the order of arguments differ from `symlinkat`
+    pub fn symlink<P: AsPath, R: AsPath>(&self, path: P, value: R)
+        -> io::Result<()>
+    {
+        let path = to_cstr(path)?;
+        let value = to_cstr(value)?;
+        check(unsafe {
+            libc::symlinkat(value.as_ref().as_ptr(),
+                self.0, path.as_ref().as_ptr())
+        })?;
+        Ok(())
+    }
+
+    /// Create a subdirectory in this directory
+    ///
+    /// WASI has no permissions, so `mode` is ignored.
+    pub fn create_dir<P: AsPath>(&self, path: P, mode: libc::mode_t)
+        -> io::Result<()>
+    {
+        let path = to_cstr(path)?;
+        check(unsafe {
+            libc::mkdirat(self.0, path.as_ref().as_ptr(), mode)
+        })?;
+        Ok(())
+    }
+
+    /// Rename a file in this directory to another name (keeping same dir)
+    pub fn local_rename<P: AsPath, R: AsPath>(&self, old: P, new: R)
+        -> io::Result<()>
+    {
+        rename(self, old, self, new)
+    }
+
+    /// Remove a subdirectory in this directory
+    ///
+    /// Note only empty directory may be removed
+    pub fn remove_dir<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self._unlink(to_cstr(path)?.as_ref(), libc::AT_REMOVEDIR)
+    }
+
+    /// Remove a file in this directory
+    pub fn remove_file<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self._unlink(to_cstr(path)?.as_ref(), 0)
+    }
+
+    fn _unlink(&self, path: &CStr, flags: libc::c_int) -> io::Result<()> {
+        check(unsafe { libc::unlinkat(self.0, path.as_ptr(), flags) })?;
+        Ok(())
+    }
+
+    /// Returns metadata of an entry in this directory
+    ///
+    /// Symlinks are not followed.
+    pub fn metadata<P: AsPath>(&self, path: P) -> io::Result<Metadata> {
+        let path = to_cstr(path)?;
+        unsafe {
+            let mut stat = std::mem::zeroed();
+            check(libc::fstatat(self.0, path.as_ref().as_ptr(),
+                &mut stat, libc::AT_SYMLINK_NOFOLLOW))?;
+            Ok(Metadata { stat })
+        }
+    }
+
+    /// Returns metadata of the directory itself
+    pub fn self_metadata(&self) -> io::Result<Metadata> {
+        unsafe {
+            let mut stat = std::mem::zeroed();
+            check(libc::fstat(self.0, &mut stat))?;
+            Ok(Metadata { stat })
+        }
+    }
+}
+
+/// Rename (move) a file between directories
+///
+/// Files must be on a single filesystem anyway. This funtion does **not**
+/// fallback to copying if needed.
+pub fn rename<P, R>(old_dir: &Dir, old: P, new_dir: &Dir, new: R)
+    -> io::Result<()>
+    where P: AsPath, R: AsPath,
+{
+    let old = to_cstr(old)?;
+    let new = to_cstr(new)?;
+    check(unsafe {
+        libc::renameat(old_dir.0, old.as_ref().as_ptr(),
+            new_dir.0, new.as_ref().as_ptr())
+    })?;
+    Ok(())
+}
+
+/// Create a hardlink to a file
+///
+/// Files must be on a single filesystem even if they are in different
+/// directories. Symlinks are not followed.
+pub fn hardlink<P, R>(old_dir: &Dir, old: P, new_dir: &Dir, new: R)
+    -> io::Result<()>
+    where P: AsPath, R: AsPath,
+{
+    let old = to_cstr(old)?;
+    let new = to_cstr(new)?;
+    check(unsafe {
+        libc::linkat(old_dir.0, old.as_ref().as_ptr(),
+            new_dir.0, new.as_ref().as_ptr(), 0)
+    })?;
+    Ok(())
+}
+
+impl AsRawFd for Dir {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl FromRawFd for Dir {
+    /// The file descriptor must refer to a directory, e.g. a preopened one
+    #[inline]
+    unsafe fn from_raw_fd(fd: RawFd) -> Dir {
+        Dir(fd)
+    }
+}
+
+impl IntoRawFd for Dir {
+    #[inline]
+    fn into_raw_fd(self) -> RawFd {
+        let result = self.0;
+        std::mem::forget(self);
+        result
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+impl Entry {
+    /// Returns the file name of this entry
+    pub fn file_name(&self) -> &OsStr {
+        OsStr::from_bytes(self.name.to_bytes())
+    }
+    /// Returns the simplified type of this entry
+    pub fn simple_type(&self) -> Option<SimpleType> {
+        self.file_type
+    }
+    /// Returns the inode number of this entry
+    pub fn inode(&self) -> u64 {
+        self.ino
+    }
+}
+
+impl Iterator for DirIter {
+    type Item = io::Result<Entry>;
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        loop {
+            unsafe {
+                // readdir returns null both at the end and on error
+                *libc::__errno_location() = 0;
+                let ent = libc::readdir(self.dir);
+                if ent.is_null() {
+                    return match *libc::__errno_location() {
+                        0 => None,
+                        errno => Some(Err(io::Error::from_raw_os_error(errno))),
+                    };
+                }
+                let name = CStr::from_ptr((*ent).d_name.as_ptr());
+                if name.to_bytes() == b"." || name.to_bytes() == b".." {
+                    continue;
+                }
+                return Some(Ok(Entry {
+                    name: name.to_owned(),
+                    file_type: match (*ent).d_type {
+                        libc::DT_UNKNOWN => None,
+                        libc::DT_REG => Some(SimpleType::File),
+                        libc::DT_DIR => Some(SimpleType::Dir),
+                        libc::DT_LNK => Some(SimpleType::Symlink),
+                        libc::DT_SOCK => Some(SimpleType::Socket),
+                        libc::DT_FIFO => Some(SimpleType::Fifo),
+                        libc::DT_CHR => Some(SimpleType::CharDevice),
+                        libc::DT_BLK => Some(SimpleType::BlockDevice),
+                        _ => Some(SimpleType::Other),
+                    },
+                    ino: (*ent).d_ino,
+                }));
+            }
+        }
+    }
+}
+
+impl Drop for DirIter {
+    fn drop(&mut self) {
+        unsafe {
+            libc::closedir(self.dir);
+        }
+    }
+}
+
+impl Metadata {
+    /// Returns simplified type of the directory entry
+    pub fn simple_type(&self) -> SimpleType {
+        match self.stat.st_mode & libc::S_IFMT {
+            libc::S_IFLNK => SimpleType::Symlink,
+            libc::S_IFDIR => SimpleType::Dir,
+            libc::S_IFREG => SimpleType::File,
+            libc::S_IFSOCK => SimpleType::Socket,
+            libc::S_IFIFO => SimpleType::Fifo,
+            libc::S_IFCHR => SimpleType::CharDevice,
+            libc::S_IFBLK => SimpleType::BlockDevice,
+            _ => SimpleType::Other,
+        }
+    }
+    /// Returns underlying stat structure
+    pub fn stat(&self) -> &libc::stat {
+        &self.stat
+    }
+    /// Returns `true` if the entry is a regular file
+    pub fn is_file(&self) -> bool {
+        self.simple_type() == SimpleType::File
+    }
+    /// Returns `true` if the entry is a directory
+    pub fn is_dir(&self) -> bool {
+        self.simple_type() == SimpleType::Dir
+    }
+    /// Returns `true` if the entry is a symlink
+    pub fn is_symlink(&self) -> bool {
+        self.simple_type() == SimpleType::Symlink
+    }
+    /// Returns length of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.stat.st_size as u64
+    }
+    /// Returns last access time
+    pub fn accessed(&self) -> io::Result<SystemTime> {
+        Ok(to_time(&self.stat.st_atim))
+    }
+    /// Returns last modification time
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        Ok(to_time(&self.stat.st_mtim))
+    }
+}
+
+fn to_time(ts: &libc::timespec) -> SystemTime {
+    UNIX_EPOCH + Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use crate::{Dir, SimpleType};
+
+    #[test]
+    fn basic() {
+        // the test runner must preopen the current directory
+        let path = "target/openat-wasi-test";
+        std::fs::remove_dir_all(path).ok();
+        std::fs::create_dir_all(path).unwrap();
+        let dir = Dir::open(path).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("sub/file", 0o644).unwrap()
+            .write_all(b"hello").unwrap();
+        dir.symlink("link", "sub/file").unwrap();
+        assert!(dir.open_file("link").is_err());
+        let mut buf = String::new();
+        dir.sub_dir("sub").unwrap().open_file("file").unwrap()
+            .read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+        assert_eq!(dir.metadata("sub/file").unwrap().len(), 5);
+        assert!(dir.metadata("link").unwrap().is_symlink());
+        let mut names = dir.list_self().unwrap()
+            .map(|e| e.unwrap().file_name().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["link", "sub"]);
+        let entry = dir.list_dir("sub").unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.simple_type(), Some(SimpleType::File));
+        dir.local_rename("sub/file", "file").unwrap();
+        dir.remove_file(&entry).unwrap_err();
+        dir.remove_file("file").unwrap();
+        dir.remove_file("link").unwrap();
+        dir.remove_dir("sub").unwrap();
+        assert_eq!(dir.list_self().unwrap().count(), 0);
+        std::fs::remove_dir(path).unwrap();
+    }
+}
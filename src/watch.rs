@@ -0,0 +1,76 @@
+use std::io;
+use std::time::Duration;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+
+/// Kind of the change reported by `Watcher`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EventKind {
+    /// Entry was created
+    Created,
+    /// Entry was removed
+    Removed,
+    /// File was written to (inotify only)
+    Modified,
+    /// Permissions, owner, timestamps or link count changed
+    MetadataChanged,
+    /// Entry was renamed from this name, the matching `MovedTo` event has
+    /// the same cookie (inotify only, kqueue reports `Removed`)
+    MovedFrom(u32),
+    /// Entry was renamed to this name (inotify only, kqueue reports
+    /// `Created`)
+    MovedTo(u32),
+    /// Watched directory itself was removed
+    WatchedRemoved,
+    /// Event queue overflowed, some events were lost (path is empty)
+    Overflow,
+}
+
+/// A change in the watched directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub(crate) kind: EventKind,
+    pub(crate) path: PathBuf,
+    pub(crate) is_dir: bool,
+}
+
+impl Event {
+    /// Returns the kind of the change
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+    /// Returns path of the entry relative to the watched directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Returns `true` if the entry is a directory
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+}
+
+/// Wait until `fd` is readable, returns `false` on timeout or signal
+///
+/// `None` waits indefinitely.
+pub(crate) fn wait_readable(fd: RawFd, timeout: Option<Duration>)
+    -> io::Result<bool>
+{
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let millis = match timeout {
+        // round up, so we don't spin with zero timeout
+        Some(timeout) => timeout.as_micros().div_ceil(1000)
+            .min(libc::c_int::MAX as u128) as libc::c_int,
+        None => -1,
+    };
+    let res = unsafe { libc::poll(&mut pollfd, 1, millis) };
+    if res < 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(err);
+    }
+    Ok(res > 0)
+}
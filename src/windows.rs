@@ -0,0 +1,651 @@
+use std::io;
+use std::mem;
+use std::ptr;
+use std::fs::{self, File};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::fs::{MetadataExt, OpenOptionsExt};
+use std::os::windows::io::{AsRawHandle, FromRawHandle, IntoRawHandle};
+use std::os::windows::io::RawHandle;
+
+use windows_sys::Wdk::Foundation::OBJECT_ATTRIBUTES;
+use windows_sys::Wdk::Storage::FileSystem::NtCreateFile;
+use windows_sys::Wdk::Storage::FileSystem::{FILE_OPEN, FILE_CREATE};
+use windows_sys::Wdk::Storage::FileSystem::FILE_OPEN_IF;
+use windows_sys::Wdk::Storage::FileSystem::FILE_DIRECTORY_FILE;
+use windows_sys::Wdk::Storage::FileSystem::FILE_NON_DIRECTORY_FILE;
+use windows_sys::Wdk::Storage::FileSystem::FILE_OPEN_REPARSE_POINT;
+use windows_sys::Wdk::Storage::FileSystem::FILE_SYNCHRONOUS_IO_NONALERT;
+use windows_sys::Win32::Foundation as win;
+use windows_sys::Win32::Foundation::{HANDLE, NTSTATUS, UNICODE_STRING};
+use windows_sys::Win32::Storage::FileSystem as fs_sys;
+use windows_sys::Win32::System::IO::IO_STATUS_BLOCK;
+
+
+// Access rights of the directory handle: enough to list it and to open
+// paths relative to it
+const DIR_ACCESS: u32 = fs_sys::FILE_LIST_DIRECTORY|fs_sys::FILE_TRAVERSE|
+    fs_sys::FILE_READ_ATTRIBUTES|fs_sys::SYNCHRONIZE;
+// Same sharing mode as `std::fs::OpenOptions` uses by default
+const SHARE_ALL: u32 = fs_sys::FILE_SHARE_READ|fs_sys::FILE_SHARE_WRITE|
+    fs_sys::FILE_SHARE_DELETE;
+const LIST_BUFFER: usize = 64 << 10;
+
+/// A safe wrapper around directory handle
+///
+/// This is the Windows implementation of the core part of the API, it
+/// requires `windows` feature. Paths are opened relative to the directory
+/// handle with `NtCreateFile`, so just like on unix the directory may be
+/// renamed while the `Dir` is open.
+///
+/// Differences from unix:
+///
+/// * Paths passed to methods must be relative, `..` is not allowed, both
+///   `/` and `\` separators are accepted
+/// * Names are case-insensitive (as elsewhere on Windows)
+/// * `mode` arguments are ignored
+/// * Symlinks are never followed by `open_file`, `sub_dir` and friends
+///   (error `ERROR_STOPPED_ON_SYMLINK` is returned), but there is no way
+///   to read or create them yet
+#[derive(Debug)]
+pub struct Dir(File);
+
+/// Entry returned by iterating over `DirIter` iterator
+#[derive(Debug, Clone)]
+pub struct Entry {
+    name: OsString,
+    file_type: Option<SimpleType>,
+    ino: u64,
+}
+
+/// Iterator over directory entries, created by `Dir::list_dir`
+///
+/// Entries `.` and `..` are skipped.
+#[derive(Debug)]
+pub struct DirIter {
+    file: File,
+    // u64 for the alignment of `FILE_ID_BOTH_DIR_INFO`
+    buf: Vec<u64>,
+    // offset of the next record in the `buf`, `None` if refill is needed
+    offset: Option<usize>,
+    restart: bool,
+    done: bool,
+}
+
+/// Metadata of a file, returned by `Dir::metadata`
+#[derive(Debug, Clone)]
+pub struct Metadata(fs::Metadata);
+
+/// This is a simplified file type enum that is easy to match
+///
+/// On Windows all reparse points (symlinks and junctions) are reported as
+/// `Symlink`, there are no other special files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SimpleType {
+    /// Entry is a symlink
+    Symlink,
+    /// Entry is a directory
+    Dir,
+    /// Entry is a regular file
+    File,
+    /// Entry is a socket
+    Socket,
+    /// Entry is a named pipe
+    Fifo,
+    /// Entry is a character device
+    CharDevice,
+    /// Entry is a block device
+    BlockDevice,
+    /// Entry is of some other (system-specific) type
+    Other,
+}
+
+/// The purpose of this is similar to `AsRef<Path>`, but unlike unix the
+/// path is converted to UTF-16 for the system call
+pub trait AsPath {
+    /// The return value of the `to_path` holding UTF-16 path
+    type Buffer: AsRef<[u16]>;
+    /// Returns `None` when path contains a zero character
+    fn to_path(self) -> Option<Self::Buffer>;
+}
+
+fn encode(path: &OsStr) -> Option<Vec<u16>> {
+    let buf = path.encode_wide().collect::<Vec<_>>();
+    if buf.contains(&0) {
+        return None;
+    }
+    Some(buf)
+}
+
+impl<'a> AsPath for &'a Path {
+    type Buffer = Vec<u16>;
+    fn to_path(self) -> Option<Vec<u16>> {
+        encode(self.as_os_str())
+    }
+}
+
+impl<'a> AsPath for &'a PathBuf {
+    type Buffer = Vec<u16>;
+    fn to_path(self) -> Option<Vec<u16>> {
+        encode(self.as_os_str())
+    }
+}
+
+impl<'a> AsPath for &'a OsStr {
+    type Buffer = Vec<u16>;
+    fn to_path(self) -> Option<Vec<u16>> {
+        encode(self)
+    }
+}
+
+impl<'a> AsPath for &'a str {
+    type Buffer = Vec<u16>;
+    fn to_path(self) -> Option<Vec<u16>> {
+        encode(self.as_ref())
+    }
+}
+
+impl<'a> AsPath for &'a String {
+    type Buffer = Vec<u16>;
+    fn to_path(self) -> Option<Vec<u16>> {
+        encode(self.as_ref())
+    }
+}
+
+impl AsPath for String {
+    type Buffer = Vec<u16>;
+    fn to_path(self) -> Option<Vec<u16>> {
+        encode(self.as_ref())
+    }
+}
+
+impl<'a> AsPath for &'a Entry {
+    type Buffer = Vec<u16>;
+    fn to_path(self) -> Option<Vec<u16>> {
+        encode(&self.name)
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+/// Converts path to the form accepted by `NtCreateFile` relative to
+/// a directory: backslash-separated, no `.` and empty components
+///
+/// Empty result refers to the directory itself.
+fn to_nt_path<P: AsPath>(path: P) -> io::Result<Vec<u16>> {
+    let buf = path.to_path()
+        .ok_or_else(|| invalid("nul character in file name"))?;
+    let buf = buf.as_ref();
+    if buf.get(1) == Some(&(b':' as u16)) {
+        return Err(invalid("path must be relative"));
+    }
+    let mut result = Vec::with_capacity(buf.len());
+    for (idx, part) in buf.split(|&c| c == b'/' as u16 || c == b'\\' as u16)
+        .enumerate()
+    {
+        match part {
+            [] if idx == 0 => return Err(invalid("path must be relative")),
+            [] => {}
+            [dot] if *dot == b'.' as u16 => {}
+            [dot1, dot2] if *dot1 == b'.' as u16 && *dot2 == b'.' as u16 => {
+                return Err(invalid("`..` is not allowed in path"));
+            }
+            _ => {
+                if !result.is_empty() {
+                    result.push(b'\\' as u16);
+                }
+                result.extend_from_slice(part);
+            }
+        }
+    }
+    if result.len() * 2 > u16::MAX as usize {
+        return Err(invalid("path is too long"));
+    }
+    Ok(result)
+}
+
+fn nt_error(status: NTSTATUS) -> io::Error {
+    let code = unsafe { win::RtlNtStatusToDosError(status) };
+    io::Error::from_raw_os_error(code as i32)
+}
+
+fn stopped_on_symlink() -> io::Error {
+    io::Error::from_raw_os_error(win::ERROR_STOPPED_ON_SYMLINK as i32)
+}
+
+impl Dir {
+    /// Open a directory handle at specified path
+    ///
+    /// Unlike other methods, this one accepts any path supported by
+    /// `std::fs`, including absolute ones.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Dir> {
+        let file = fs::OpenOptions::new()
+            .access_mode(DIR_ACCESS)
+            .share_mode(SHARE_ALL)
+            .custom_flags(fs_sys::FILE_FLAG_BACKUP_SEMANTICS)
+            .open(path)?;
+        if !file.metadata()?.is_dir() {
+            return Err(io::Error::from_raw_os_error(
+                win::ERROR_DIRECTORY as i32));
+        }
+        Ok(Dir(file))
+    }
+
+    /// Opens `path` relative to this directory, without following
+    /// the reparse point at the last component
+    fn nt_open(&self, path: &[u16], access: u32, disposition: u32,
+        options: u32)
+        -> io::Result<File>
+    {
+        let len = (path.len() * 2) as u16;
+        let name = UNICODE_STRING {
+            Length: len,
+            MaximumLength: len,
+            Buffer: path.as_ptr() as *mut u16,
+        };
+        let attributes = OBJECT_ATTRIBUTES {
+            Length: mem::size_of::<OBJECT_ATTRIBUTES>() as u32,
+            RootDirectory: self.0.as_raw_handle() as HANDLE,
+            ObjectName: &name,
+            Attributes: win::OBJ_CASE_INSENSITIVE,
+            SecurityDescriptor: ptr::null(),
+            SecurityQualityOfService: ptr::null(),
+        };
+        let mut handle = ptr::null_mut();
+        let mut status_block = IO_STATUS_BLOCK::default();
+        let status = unsafe {
+            NtCreateFile(&mut handle, access|fs_sys::SYNCHRONIZE,
+                &attributes, &mut status_block, ptr::null(),
+                fs_sys::FILE_ATTRIBUTE_NORMAL, SHARE_ALL, disposition,
+                options|FILE_SYNCHRONOUS_IO_NONALERT|FILE_OPEN_REPARSE_POINT,
+                ptr::null(), 0)
+        };
+        if status < 0 {
+            return Err(nt_error(status));
+        }
+        Ok(unsafe { File::from_raw_handle(handle as RawHandle) })
+    }
+
+    /// Same as `nt_open` but returns an error if the last component is
+    /// a reparse point (a symlink or a junction)
+    fn nt_open_nofollow(&self, path: &[u16], access: u32, disposition: u32,
+        options: u32)
+        -> io::Result<File>
+    {
+        let file = self.nt_open(path, access|fs_sys::FILE_READ_ATTRIBUTES,
+            disposition, options)?;
+        let attrs = file.metadata()?.file_attributes();
+        if attrs & fs_sys::FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+            return Err(stopped_on_symlink());
+        }
+        Ok(file)
+    }
+
+    /// List subdirectory of this dir
+    pub fn list_dir<P: AsPath>(&self, path: P) -> io::Result<DirIter> {
+        let file = self.nt_open_nofollow(&to_nt_path(path)?,
+            DIR_ACCESS, FILE_OPEN, FILE_DIRECTORY_FILE)?;
+        Ok(DirIter {
+            file,
+            buf: vec![0; LIST_BUFFER / 8],
+            offset: None,
+            restart: true,
+            done: false,
+        })
+    }
+
+    /// List this dir
+    pub fn list_self(&self) -> io::Result<DirIter> {
+        self.list_dir(".")
+    }
+
+    /// Open subdirectory
+    pub fn sub_dir<P: AsPath>(&self, path: P) -> io::Result<Dir> {
+        Ok(Dir(self.nt_open_nofollow(&to_nt_path(path)?,
+            DIR_ACCESS, FILE_OPEN, FILE_DIRECTORY_FILE)?))
+    }
+
+    /// Open file for reading in this directory
+    pub fn open_file<P: AsPath>(&self, path: P) -> io::Result<File> {
+        self.nt_open_nofollow(&to_nt_path(path)?,
+            fs_sys::FILE_GENERIC_READ, FILE_OPEN, FILE_NON_DIRECTORY_FILE)
+    }
+
+    /// Open file for writing, create if necessary, truncate on open
+    pub fn write_file<P: AsPath>(&self, path: P, _mode: u32)
+        -> io::Result<File>
+    {
+        // truncate only after checking that the file is not a symlink
+        let file = self.nt_open_nofollow(&to_nt_path(path)?,
+            fs_sys::FILE_GENERIC_WRITE, FILE_OPEN_IF,
+            FILE_NON_DIRECTORY_FILE)?;
+        file.set_len(0)?;
+        Ok(file)
+    }
+
+    /// Open file for append, create if necessary
+    pub fn append_file<P: AsPath>(&self, path: P, _mode: u32)
+        -> io::Result<File>
+    {
+        // appending is `FILE_APPEND_DATA` without `FILE_WRITE_DATA`
+        self.nt_open_nofollow(&to_nt_path(path)?,
+            (fs_sys::FILE_GENERIC_WRITE & !fs_sys::FILE_WRITE_DATA)
+                |fs_sys::FILE_APPEND_DATA,
+            FILE_OPEN_IF, FILE_NON_DIRECTORY_FILE)
+    }
+
+    /// Create file if not exists, fail if exists
+    pub fn new_file<P: AsPath>(&self, path: P, _mode: u32)
+        -> io::Result<File>
+    {
+        self.nt_open(&to_nt_path(path)?,
+            fs_sys::FILE_GENERIC_WRITE, FILE_CREATE, FILE_NON_DIRECTORY_FILE)
+    }
+
+    /// Open file for reading and writing without truncation, create if needed
+    pub fn update_file<P: AsPath>(&self, path: P, _mode: u32)
+        -> io::Result<File>
+    {
+        self.nt_open_nofollow(&to_nt_path(path)?,
+            fs_sys::FILE_GENERIC_READ|fs_sys::FILE_GENERIC_WRITE,
+            FILE_OPEN_IF, FILE_NON_DIRECTORY_FILE)
+    }
+
+    /// Create a subdirectory in this directory
+    pub fn create_dir<P: AsPath>(&self, path: P, _mode: u32)
+        -> io::Result<()>
+    {
+        self.nt_open(&to_nt_path(path)?,
+            fs_sys::FILE_LIST_DIRECTORY, FILE_CREATE, FILE_DIRECTORY_FILE)?;
+        Ok(())
+    }
+
+    /// Rename a file in this directory to another name (keeping same dir)
+    pub fn local_rename<P: AsPath, R: AsPath>(&self, old: P, new: R)
+        -> io::Result<()>
+    {
+        rename(self, old, self, new)
+    }
+
+    /// Remove a subdirectory in this directory
+    ///
+    /// Note only empty directory may be removed
+    pub fn remove_dir<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self._remove(to_nt_path(path)?, FILE_DIRECTORY_FILE)
+    }
+
+    /// Remove a file in this directory
+    ///
+    /// Symlinks are removed themselves, not their targets.
+    pub fn remove_file<P: AsPath>(&self, path: P) -> io::Result<()> {
+        self._remove(to_nt_path(path)?, FILE_NON_DIRECTORY_FILE)
+    }
+
+    fn _remove(&self, path: Vec<u16>, options: u32) -> io::Result<()> {
+        let file = self.nt_open(&path, fs_sys::DELETE, FILE_OPEN, options)?;
+        let handle = file.as_raw_handle() as HANDLE;
+        // POSIX semantics make the name disappear immediately even if
+        // the file is open elsewhere, it's not supported by older systems
+        // and by some filesystems
+        let info = fs_sys::FILE_DISPOSITION_INFO_EX {
+            Flags: fs_sys::FILE_DISPOSITION_FLAG_DELETE|
+                fs_sys::FILE_DISPOSITION_FLAG_POSIX_SEMANTICS,
+        };
+        let ok = unsafe {
+            fs_sys::SetFileInformationByHandle(handle,
+                fs_sys::FileDispositionInfoEx,
+                &info as *const _ as *const _,
+                mem::size_of_val(&info) as u32)
+        };
+        if ok != 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(code) if code == win::ERROR_INVALID_PARAMETER as i32
+                || code == win::ERROR_NOT_SUPPORTED as i32 => {}
+            _ => return Err(err),
+        }
+        let info = fs_sys::FILE_DISPOSITION_INFO { DeleteFile: true };
+        let ok = unsafe {
+            fs_sys::SetFileInformationByHandle(handle,
+                fs_sys::FileDispositionInfo,
+                &info as *const _ as *const _,
+                mem::size_of_val(&info) as u32)
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Returns metadata of an entry in this directory
+    ///
+    /// Symlinks are not followed.
+    pub fn metadata<P: AsPath>(&self, path: P) -> io::Result<Metadata> {
+        let file = self.nt_open(&to_nt_path(path)?,
+            fs_sys::FILE_READ_ATTRIBUTES, FILE_OPEN, 0)?;
+        Ok(Metadata(file.metadata()?))
+    }
+
+    /// Returns metadata of the directory itself
+    pub fn self_metadata(&self) -> io::Result<Metadata> {
+        Ok(Metadata(self.0.metadata()?))
+    }
+}
+
+/// Rename (move) a file between directories
+///
+/// Files must be on a single filesystem anyway. This funtion does **not**
+/// fallback to copying if needed. The target is replaced if exists (unless
+/// it's a directory).
+pub fn rename<P, R>(old_dir: &Dir, old: P, new_dir: &Dir, new: R)
+    -> io::Result<()>
+    where P: AsPath, R: AsPath,
+{
+    let new = to_nt_path(new)?;
+    let file = old_dir.nt_open(&to_nt_path(old)?, fs_sys::DELETE,
+        FILE_OPEN, 0)?;
+    // the name is stored past the end of `FILE_RENAME_INFO`, use u64
+    // for alignment
+    let size = mem::size_of::<fs_sys::FILE_RENAME_INFO>() + new.len() * 2;
+    let mut buf = vec![0u64; (size + 7) / 8];
+    unsafe {
+        let info = buf.as_mut_ptr() as *mut fs_sys::FILE_RENAME_INFO;
+        (*info).Anonymous.ReplaceIfExists = true;
+        (*info).RootDirectory = new_dir.0.as_raw_handle() as HANDLE;
+        (*info).FileNameLength = (new.len() * 2) as u32;
+        ptr::copy_nonoverlapping(new.as_ptr(),
+            ptr::addr_of_mut!((*info).FileName) as *mut u16, new.len());
+        let ok = fs_sys::SetFileInformationByHandle(
+            file.as_raw_handle() as HANDLE, fs_sys::FileRenameInfo,
+            info as *const _, size as u32);
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl AsRawHandle for Dir {
+    #[inline]
+    fn as_raw_handle(&self) -> RawHandle {
+        self.0.as_raw_handle()
+    }
+}
+
+impl FromRawHandle for Dir {
+    /// The handle must refer to a directory opened with
+    /// `FILE_FLAG_BACKUP_SEMANTICS` and `FILE_TRAVERSE` access
+    #[inline]
+    unsafe fn from_raw_handle(handle: RawHandle) -> Dir {
+        Dir(File::from_raw_handle(handle))
+    }
+}
+
+impl IntoRawHandle for Dir {
+    #[inline]
+    fn into_raw_handle(self) -> RawHandle {
+        self.0.into_raw_handle()
+    }
+}
+
+impl Entry {
+    /// Returns the file name of this entry
+    pub fn file_name(&self) -> &OsStr {
+        &self.name
+    }
+    /// Returns the simplified type of this entry
+    pub fn simple_type(&self) -> Option<SimpleType> {
+        self.file_type
+    }
+    /// Returns the file id (an analog of inode number) of this entry
+    pub fn inode(&self) -> u64 {
+        self.ino
+    }
+}
+
+fn simple_type(attrs: u32) -> SimpleType {
+    if attrs & fs_sys::FILE_ATTRIBUTE_REPARSE_POINT != 0 {
+        SimpleType::Symlink
+    } else if attrs & fs_sys::FILE_ATTRIBUTE_DIRECTORY != 0 {
+        SimpleType::Dir
+    } else {
+        SimpleType::File
+    }
+}
+
+impl Iterator for DirIter {
+    type Item = io::Result<Entry>;
+    fn next(&mut self) -> Option<io::Result<Entry>> {
+        loop {
+            if let Some(offset) = self.offset {
+                let entry = unsafe {
+                    let info = (self.buf.as_ptr() as *const u8).add(offset)
+                        as *const fs_sys::FILE_ID_BOTH_DIR_INFO;
+                    let next = (*info).NextEntryOffset as usize;
+                    self.offset = if next == 0 { None }
+                                  else { Some(offset + next) };
+                    let name = std::slice::from_raw_parts(
+                        ptr::addr_of!((*info).FileName) as *const u16,
+                        (*info).FileNameLength as usize / 2);
+                    if name == [b'.' as u16] || name == [b'.' as u16; 2] {
+                        continue;
+                    }
+                    Entry {
+                        name: OsString::from_wide(name),
+                        file_type: Some(simple_type((*info).FileAttributes)),
+                        ino: (*info).FileId as u64,
+                    }
+                };
+                return Some(Ok(entry));
+            }
+            if self.done {
+                return None;
+            }
+            let class = if self.restart {
+                fs_sys::FileIdBothDirectoryRestartInfo
+            } else {
+                fs_sys::FileIdBothDirectoryInfo
+            };
+            self.restart = false;
+            let ok = unsafe {
+                fs_sys::GetFileInformationByHandleEx(
+                    self.file.as_raw_handle() as HANDLE, class,
+                    self.buf.as_mut_ptr() as *mut _,
+                    (self.buf.len() * 8) as u32)
+            };
+            if ok == 0 {
+                self.done = true;
+                let err = io::Error::last_os_error();
+                if err.raw_os_error() == Some(win::ERROR_NO_MORE_FILES as i32) {
+                    return None;
+                }
+                return Some(Err(err));
+            }
+            self.offset = Some(0);
+        }
+    }
+}
+
+impl Metadata {
+    /// Returns simplified type of the directory entry
+    pub fn simple_type(&self) -> SimpleType {
+        simple_type(self.0.file_attributes())
+    }
+    /// Returns underlying metadata of the standard library
+    pub fn std(&self) -> &fs::Metadata {
+        &self.0
+    }
+    /// Returns `true` if the entry is a regular file
+    pub fn is_file(&self) -> bool {
+        self.simple_type() == SimpleType::File
+    }
+    /// Returns `true` if the entry is a directory
+    pub fn is_dir(&self) -> bool {
+        self.simple_type() == SimpleType::Dir
+    }
+    /// Returns `true` if the entry is a symlink (or other reparse point)
+    pub fn is_symlink(&self) -> bool {
+        self.simple_type() == SimpleType::Symlink
+    }
+    /// Returns length of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.0.len()
+    }
+    /// Returns last access time
+    pub fn accessed(&self) -> io::Result<SystemTime> {
+        self.0.accessed()
+    }
+    /// Returns last modification time
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        self.0.modified()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read, Write};
+    use crate::{Dir, SimpleType};
+
+    #[test]
+    fn basic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = Dir::open(tmp.path()).unwrap();
+        dir.create_dir("sub", 0o755).unwrap();
+        dir.write_file("sub/file", 0o644).unwrap()
+            .write_all(b"hello").unwrap();
+        let mut buf = String::new();
+        dir.sub_dir("sub").unwrap().open_file("file").unwrap()
+            .read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+        dir.append_file("sub\\file", 0o644).unwrap()
+            .write_all(b"!").unwrap();
+        assert_eq!(dir.metadata("sub/file").unwrap().len(), 6);
+        assert!(dir.new_file("sub/file", 0o644).is_err());
+        assert_eq!(dir.open_file("../x").unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+        assert_eq!(dir.open_file("C:\\x").unwrap_err().kind(),
+                   io::ErrorKind::InvalidInput);
+
+        let entries = dir.list_self().unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file_name(), "sub");
+        assert_eq!(entries[0].simple_type(), Some(SimpleType::Dir));
+
+        let sub = dir.sub_dir("sub").unwrap();
+        super::rename(&sub, "file", &dir, "moved").unwrap();
+        assert!(dir.metadata("moved").unwrap().is_file());
+        assert_eq!(sub.list_self().unwrap().count(), 0);
+        drop(sub);
+        dir.remove_dir("sub").unwrap();
+        dir.remove_file("moved").unwrap();
+        assert_eq!(dir.list_self().unwrap().count(), 0);
+    }
+}